@@ -1,11 +1,13 @@
-use std::{fmt::Display, time::Duration};
+use std::{fmt::Display, net::SocketAddr, time::Duration};
 
 use camino::Utf8PathBuf;
 use corro_types::{
+    actor::ActorId,
     agent::{Agent, LockKind, LockMeta, LockState},
+    base::Version,
     broadcast::{FocaCmd, FocaInput},
     sqlite::SqlitePoolError,
-    sync::generate_sync,
+    sync::{generate_sync, SyncRangeRequest},
 };
 use futures::{SinkExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
@@ -13,7 +15,7 @@ use spawn::spawn_counted;
 use time::OffsetDateTime;
 use tokio::{
     net::{UnixListener, UnixStream},
-    sync::mpsc,
+    sync::{mpsc, oneshot},
 };
 use tokio_serde::{formats::Json, Framed};
 use tokio_util::codec::LengthDelimitedCodec;
@@ -90,6 +92,12 @@ pub enum Command {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum SyncCommand {
     Generate,
+    Range {
+        actor_id: ActorId,
+        start: Version,
+        end: Version,
+        peer: SocketAddr,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -183,6 +191,47 @@ async fn handle_conn(
                     }
                     send_success(&mut stream).await;
                 }
+                Command::Sync(SyncCommand::Range {
+                    actor_id,
+                    start,
+                    end,
+                    peer,
+                }) => {
+                    if start > end {
+                        send_error(&mut stream, "range start is after its end").await;
+                        continue;
+                    }
+
+                    info_log(
+                        &mut stream,
+                        format!("syncing versions {start}..={end} for {actor_id} from {peer}"),
+                    )
+                    .await;
+
+                    let (res_tx, res_rx) = oneshot::channel();
+                    if let Err(e) = agent
+                        .tx_sync_range()
+                        .send(SyncRangeRequest {
+                            actor_id,
+                            versions: start..=end,
+                            peer,
+                            res_tx,
+                        })
+                        .await
+                    {
+                        send_error(&mut stream, e).await;
+                        continue;
+                    }
+
+                    match res_rx.await {
+                        Ok(Ok(count)) => {
+                            info_log(&mut stream, format!("received {count} changes")).await;
+                            send_success(&mut stream).await;
+                        }
+                        Ok(Err(e)) => send_error(&mut stream, e).await,
+                        Err(e) => send_error(&mut stream, e).await,
+                    }
+                }
                 Command::Locks { top } => {
                     info_log(&mut stream, "gathering top locks").await;
                     let bookie = agent.bookie();
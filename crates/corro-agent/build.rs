@@ -0,0 +1,31 @@
+use std::{path::Path, process::Command};
+
+// exposes the git commit as CORRO_GIT_SHA, unless it's already set (e.g. when
+// building from a source tarball without the .git directory)
+fn main() {
+    println!("cargo:rerun-if-env-changed=CORRO_GIT_SHA");
+
+    let git_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../.git");
+    for path in ["HEAD", "refs"] {
+        let path = git_dir.join(path);
+        if path.exists() {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
+
+    if std::env::var_os("CORRO_GIT_SHA").is_some() {
+        return;
+    }
+
+    let sha = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|sha| sha.trim().to_string())
+        .filter(|sha| !sha.is_empty())
+        .unwrap_or_else(|| "unknown".into());
+
+    println!("cargo:rustc-env=CORRO_GIT_SHA={sha}");
+}
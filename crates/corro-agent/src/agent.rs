@@ -2,6 +2,7 @@ use std::{
     cmp,
     collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     convert::Infallible,
+    future::Future,
     net::SocketAddr,
     ops::RangeInclusive,
     sync::{atomic::AtomicI64, Arc},
@@ -10,13 +11,22 @@ use std::{
 
 use crate::{
     api::{
-        peer::{gossip_server_endpoint, parallel_sync, serve_sync, SyncError},
+        peer::{gossip_server_endpoint, parallel_sync, range_sync, serve_sync, SyncError},
         public::{
-            api_v1_db_schema, api_v1_queries, api_v1_transactions,
-            pubsub::{api_v1_sub_by_id, api_v1_subs, process_sub_channel, MatcherBroadcastCache},
+            api_v1_admin_bookkeeping, api_v1_admin_config, api_v1_admin_config_max_change_size,
+            api_v1_backup, api_v1_cluster_members, api_v1_db_explain, api_v1_db_schema,
+            api_v1_db_versions, api_v1_drain, api_v1_export, api_v1_health, api_v1_import,
+            api_v1_info, api_v1_queries, api_v1_queries_named, api_v1_ready, api_v1_sync_now,
+            api_v1_sync_range, api_v1_transactions, execute_schema_locked,
+            gzip::decompress_gzip,
+            pubsub::{
+                api_v1_sub_by_id, api_v1_sub_delete, api_v1_subs, api_v1_subs_get, api_v1_subs_sse,
+                api_v1_subs_test, process_sub_channel, MatcherBroadcastCache,
+            },
         },
     },
-    broadcast::runtime_loop,
+    broadcast::{runtime_loop, try_send_broadcast, SeenCache},
+    mdns::MdnsDiscovery,
     transport::{Transport, TransportError},
 };
 
@@ -24,9 +34,10 @@ use arc_swap::ArcSwap;
 use corro_types::{
     actor::{Actor, ActorId},
     agent::{
-        migrate, Agent, AgentConfig, BookedVersions, Bookie, ChangeError, CurrentVersion,
-        KnownDbVersion, PartialVersion, SplitPool,
+        check_db_version, migrate, Agent, AgentConfig, Booked, BookedVersions, Bookie, ChangeError,
+        CurrentVersion, KnownDbVersion, PartialVersion, SplitPool, SCHEMA_CHANGES_TABLE,
     },
+    api::{ExecResponse, ExecResult},
     base::{CrsqlDbVersion, CrsqlSeq, Version},
     broadcast::{
         BiPayload, BiPayloadV1, BroadcastInput, BroadcastV1, ChangeSource, ChangeV1, Changeset,
@@ -35,34 +46,47 @@ use corro_types::{
     config::{AuthzConfig, Config, DEFAULT_GOSSIP_PORT},
     members::Members,
     pubsub::{Matcher, SubsManager},
-    schema::init_schema,
+    schema::{init_schema, parse_renames, parse_sql, repair_crrs, Schema, SchemaError},
+    signing::GossipSigner,
     sqlite::{CrConn, SqlitePoolError},
-    sync::{generate_sync, SyncMessageDecodeError, SyncMessageEncodeError},
+    sync::{
+        generate_sync, SyncMessageDecodeError, SyncMessageEncodeError, SyncNowRequest,
+        SyncRangeRequest,
+    },
 };
 
 use axum::{
     error_handling::HandleErrorLayer,
     extract::DefaultBodyLimit,
     headers::{authorization::Bearer, Authorization},
-    routing::{get, post},
+    routing::{get, post, put},
     BoxError, Extension, Router, TypedHeader,
 };
 use bytes::Bytes;
+use camino::{Utf8Path, Utf8PathBuf};
 use foca::{Member, Notification};
 use futures::{FutureExt, StreamExt, TryFutureExt};
-use hyper::{server::conn::AddrIncoming, StatusCode};
+use hyper::{
+    header::{self, HeaderValue},
+    server::conn::AddrIncoming,
+    Method, StatusCode,
+};
 use itertools::Itertools;
 use metrics::{counter, gauge, histogram, increment_counter};
+use notify::RecursiveMode;
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult};
 use parking_lot::RwLock;
-use rand::{rngs::StdRng, seq::IteratorRandom, SeedableRng};
+use rand::seq::{IteratorRandom, SliceRandom};
 use rangemap::{RangeInclusiveMap, RangeInclusiveSet};
 use rusqlite::{
     named_params, params, params_from_iter, Connection, OptionalExtension, ToSql, Transaction,
 };
 use spawn::spawn_counted;
-use speedy::Readable;
+use speedy::{IsEof, Readable};
+use subtle::ConstantTimeEq;
 use tokio::{
-    net::TcpListener,
+    net::{TcpListener, UnixListener, UnixStream},
+    signal::unix::{signal, SignalKind},
     sync::{
         mpsc::{channel, Receiver, Sender},
         Semaphore,
@@ -73,30 +97,34 @@ use tokio::{
 use tokio_stream::{wrappers::ReceiverStream, StreamExt as TokioStreamExt};
 use tokio_util::codec::{FramedRead, LengthDelimitedCodec};
 use tower::{limit::ConcurrencyLimitLayer, load_shed::LoadShedLayer};
-use tower_http::trace::TraceLayer;
+use tower_http::{
+    cors::{AllowOrigin, CorsLayer},
+    trace::TraceLayer,
+};
 use tracing::{debug, debug_span, error, info, trace, warn, Instrument};
 use tripwire::{Outcome, PreemptibleFutureExt, TimeoutFutureExt, Tripwire};
 use trust_dns_resolver::{
-    error::ResolveErrorKind,
+    error::{ResolveError, ResolveErrorKind},
     proto::rr::{RData, RecordType},
 };
 
-const MAX_SYNC_BACKOFF: Duration = Duration::from_secs(15); // 1 minute oughta be enough, we're constantly getting broadcasts randomly + targetted
-const RANDOM_NODES_CHOICES: usize = 10;
 const COMPACT_BOOKED_INTERVAL: Duration = Duration::from_secs(300);
-const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(300);
 
 pub struct AgentOptions {
     pub actor_id: ActorId,
     pub gossip_server_endpoint: quinn::Endpoint,
     pub transport: Transport,
     pub api_listener: TcpListener,
+    pub api_uds_listener: Option<UnixListener>,
     pub rx_bcast: Receiver<BroadcastInput>,
     pub rx_apply: Receiver<(ActorId, Version)>,
     pub rx_empty: Receiver<(ActorId, RangeInclusive<Version>)>,
     pub rx_clear_buf: Receiver<(ActorId, RangeInclusive<Version>)>,
     pub rx_changes: Receiver<(ChangeV1, ChangeSource)>,
     pub rx_foca: Receiver<FocaInput>,
+    pub rx_sync_range: Receiver<SyncRangeRequest>,
+    pub rx_sync_now: Receiver<SyncNowRequest>,
+    pub rx_schema_change: Receiver<()>,
     pub rtt_rx: Receiver<(SocketAddr, Duration)>,
     pub subs_manager: SubsManager,
     pub tripwire: Tripwire,
@@ -123,7 +151,15 @@ pub async fn setup(conf: Config, tripwire: Tripwire) -> eyre::Result<(Agent, Age
 
     let write_sema = Arc::new(Semaphore::new(1));
 
-    let pool = SplitPool::create(&conf.db.path, write_sema.clone()).await?;
+    let pool = SplitPool::create(
+        &conf.db.path,
+        write_sema.clone(),
+        conf.db.rw_pool_size,
+        conf.db.ro_pool_size,
+        &conf.db.pragmas,
+        Duration::from_millis(conf.db.pool_acquire_timeout_ms),
+    )
+    .await?;
 
     let schema = {
         let mut conn = pool.write_priority().await?;
@@ -131,6 +167,12 @@ pub async fn setup(conf: Config, tripwire: Tripwire) -> eyre::Result<(Agent, Age
         let mut schema = init_schema(&conn)?;
         schema.constrain()?;
 
+        let repaired = repair_crrs(&conn, &schema)?;
+        if !repaired.is_empty() {
+            warn!("Repaired CRR artifacts for tables: {repaired:?}");
+        }
+        counter!("corro.schema.crr.repaired", repaired.len() as u64);
+
         info!("Ensuring clock table indexes for fast compaction");
         let start = Instant::now();
         for table in schema.tables.keys() {
@@ -141,6 +183,12 @@ pub async fn setup(conf: Config, tripwire: Tripwire) -> eyre::Result<(Agent, Age
         schema
     };
 
+    let last_clock_ts: Option<Timestamp> = {
+        let conn = pool.read().await?;
+        conn.query_row("SELECT ts FROM __corro_clock", [], |row| row.get(0))
+            .optional()?
+    };
+
     let (tx_apply, rx_apply) = channel(20480);
     let (tx_clear_buf, rx_clear_buf) = channel(10240);
 
@@ -271,7 +319,7 @@ pub async fn setup(conf: Config, tripwire: Tripwire) -> eyre::Result<(Agent, Age
 
     let bookie = Bookie::new(bk);
 
-    let gossip_server_endpoint = gossip_server_endpoint(&conf.gossip).await?;
+    let gossip_server_endpoint = gossip_server_endpoint(&conf.gossip, &conf.limits).await?;
     let gossip_addr = gossip_server_endpoint.local_addr()?;
 
     let (rtt_tx, rtt_rx) = channel(128);
@@ -281,6 +329,20 @@ pub async fn setup(conf: Config, tripwire: Tripwire) -> eyre::Result<(Agent, Age
     let api_listener = TcpListener::bind(conf.api.bind_addr).await?;
     let api_addr = api_listener.local_addr()?;
 
+    let api_uds_listener = match conf.api.uds_path.as_ref() {
+        Some(path) => {
+            // remove a socket file possibly left behind by a previous run
+            _ = std::fs::remove_file(path);
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            Some(UnixListener::bind(path)?)
+        }
+        None => None,
+    };
+
+    let signer = GossipSigner::from_config(&conf.gossip)?;
+
     let clock = Arc::new(
         uhlc::HLCBuilder::default()
             .with_id(actor_id.try_into().unwrap())
@@ -288,10 +350,33 @@ pub async fn setup(conf: Config, tripwire: Tripwire) -> eyre::Result<(Agent, Age
             .build(),
     );
 
-    let (tx_bcast, rx_bcast) = channel(10240);
+    // don't mint timestamps preceding the ones we issued before restarting.
+    // the clock can't be advanced further than its max delta past the wall
+    // clock, so wait for the wall clock to catch up with the persisted timestamp
+    if let Some(last_ts) = last_clock_ts {
+        let now = uhlc::system_time_clock();
+        if last_ts.to_ntp64() > now {
+            let behind = (last_ts.to_ntp64() - now).to_duration();
+            if behind > MAX_CLOCK_WAIT {
+                eyre::bail!("wall clock is {behind:?} behind the last persisted timestamp {last_ts}, refusing to start until it's fixed");
+            }
+            info!("waiting {behind:?} for the wall clock to pass the last persisted timestamp {last_ts}");
+            tokio::time::sleep(behind).await;
+        }
+        clock
+            .update_with_timestamp(&uhlc::Timestamp::new(last_ts.to_ntp64(), *clock.get_id()))
+            .map_err(|e| {
+                eyre::eyre!("could not advance clock past last persisted timestamp {last_ts}: {e}")
+            })?;
+    }
+
+    let (tx_bcast, rx_bcast) = channel(conf.gossip.broadcast_buffer);
     let (tx_empty, rx_empty) = channel(10240);
     let (tx_changes, rx_changes) = channel(5192);
     let (tx_foca, rx_foca) = channel(10240);
+    let (tx_sync_range, rx_sync_range) = channel(16);
+    let (tx_sync_now, rx_sync_now) = channel(16);
+    let (tx_schema_change, rx_schema_change) = channel(1);
 
     let subs_manager = SubsManager::default();
 
@@ -300,12 +385,16 @@ pub async fn setup(conf: Config, tripwire: Tripwire) -> eyre::Result<(Agent, Age
         gossip_server_endpoint,
         transport,
         api_listener,
+        api_uds_listener,
         rx_bcast,
         rx_apply,
         rx_empty,
         rx_clear_buf,
         rx_changes,
         rx_foca,
+        rx_sync_range,
+        rx_sync_now,
+        rx_schema_change,
         rtt_rx,
         subs_manager: subs_manager.clone(),
         tripwire: tripwire.clone(),
@@ -326,6 +415,10 @@ pub async fn setup(conf: Config, tripwire: Tripwire) -> eyre::Result<(Agent, Age
         tx_clear_buf,
         tx_changes,
         tx_foca,
+        tx_sync_range,
+        tx_sync_now,
+        tx_schema_change,
+        signer,
         write_sema,
         schema: RwLock::new(schema),
         subs_manager,
@@ -357,6 +450,7 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
         gossip_server_endpoint,
         transport,
         api_listener,
+        api_uds_listener,
         mut tripwire,
         rx_bcast,
         rx_apply,
@@ -364,6 +458,9 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
         rx_clear_buf,
         rx_changes,
         rx_foca,
+        rx_sync_range,
+        rx_sync_now,
+        rx_schema_change,
         subs_manager,
         rtt_rx,
     } = opts;
@@ -432,7 +529,7 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
     let (to_send_tx, to_send_rx) = channel(10240);
     let (notifications_tx, notifications_rx) = channel(10240);
 
-    let (bcast_msg_tx, bcast_rx) = channel::<BroadcastV1>(10240);
+    let (bcast_msg_tx, bcast_rx) = channel::<BroadcastV1>(agent.config().gossip.broadcast_buffer);
 
     let gossip_addr = gossip_server_endpoint.local_addr()?;
 
@@ -516,6 +613,13 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
                         }
                     };
 
+                    if !agent.config().gossip.is_peer_allowed(remote_addr.ip()) {
+                        debug!("refusing connection from denied peer {remote_addr}");
+                        increment_counter!("corro.peer.denied", "type" => "connection");
+                        conn.close(0u32.into(), b"denied");
+                        return;
+                    }
+
                     increment_counter!("corro.peer.connection.accept.total");
 
                     debug!("accepted a QUIC conn from {remote_addr}");
@@ -524,6 +628,7 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
                         let conn = conn.clone();
                         let mut tripwire = tripwire.clone();
                         let foca_tx = agent.tx_foca().clone();
+                        let signer = agent.signer().cloned();
                         async move {
                             loop {
                                 let b = tokio::select! {
@@ -544,6 +649,18 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
                                     }
                                 };
 
+                                let b = match &signer {
+                                    Some(signer) => match signer.verify(b) {
+                                        Ok(b) => b,
+                                        Err(e) => {
+                                            warn!("dropping datagram from {remote_addr}: {e}");
+                                            increment_counter!("corro.payload.signature.fail", "reason" => e.as_str());
+                                            continue;
+                                        }
+                                    },
+                                    None => b,
+                                };
+
                                 if let Err(e) = foca_tx.send(FocaInput::Data(b)).await {
                                     error!("could not send data foca input: {e}");
                                 }
@@ -554,6 +671,7 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
                     tokio::spawn({
                         let conn = conn.clone();
                         let mut tripwire = tripwire.clone();
+                        let signer = agent.signer().cloned();
                         async move {
                             loop {
                                 let rx = tokio::select! {
@@ -579,6 +697,8 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
 
                                 tokio::spawn({
                                     let process_uni_tx = process_uni_tx.clone();
+                                    let signer = signer.clone();
+                                    let remote_addr = conn.remote_address();
                                     async move {
                                         let mut framed =
                                             FramedRead::new(rx, LengthDelimitedCodec::new());
@@ -587,6 +707,19 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
                                             match StreamExt::next(&mut framed).await {
                                                 Some(Ok(b)) => {
                                                     counter!("corro.peer.stream.bytes.recv.total", b.len() as u64, "type" => "uni");
+                                                    let b = match &signer {
+                                                        Some(signer) => {
+                                                            match signer.verify(b.freeze()) {
+                                                                Ok(b) => b,
+                                                                Err(e) => {
+                                                                    warn!("dropping broadcast from {remote_addr}: {e}");
+                                                                    increment_counter!("corro.payload.signature.fail", "reason" => e.as_str());
+                                                                    continue;
+                                                                }
+                                                            }
+                                                        }
+                                                        None => b.freeze(),
+                                                    };
                                                     match UniPayload::read_from_buffer(&b) {
                                                         Ok(payload) => {
                                                             trace!("parsed a payload: {payload:?}");
@@ -600,15 +733,23 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
                                                             }
                                                         }
                                                         Err(e) => {
-                                                            error!(
-                                                                "could not decode UniPayload: {e}"
+                                                            // frames are length-delimited, so a bad
+                                                            // payload only costs us this frame
+                                                            warn!(
+                                                                "could not decode broadcast from {remote_addr} (len: {}): {e}",
+                                                                b.len()
                                                             );
+                                                            increment_counter!("corro.broadcast.decode.error", "kind" => payload_decode_error_kind(&e));
                                                             continue;
                                                         }
                                                     }
                                                 }
                                                 Some(Err(e)) => {
-                                                    error!("decode error: {e}");
+                                                    // the frame boundaries are lost, there's no
+                                                    // telling where the next frame starts
+                                                    warn!("could not read broadcast frame from {remote_addr}, dropping the stream: {e}");
+                                                    increment_counter!("corro.broadcast.decode.error", "kind" => "frame");
+                                                    break;
                                                 }
                                                 None => break,
                                             }
@@ -643,7 +784,6 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
                                 conn.remote_address()
                             );
 
-                            // TODO: implement concurrency limit for sync requests
                             tokio::spawn({
                                 let agent = agent.clone();
                                 async move {
@@ -673,13 +813,24 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
                                                                     BiPayloadV1::SyncStart {
                                                                         actor_id,
                                                                         trace_ctx,
+                                                                        compression,
                                                                     },
                                                                 ) => {
                                                                     trace!("framed read buffer len: {}", framed.read_buffer().len());
                                                                     // println!("got sync state: {state:?}");
+                                                                    // only so many syncs are served at once, others wait their turn
+                                                                    let _permit = agent
+                                                                        .limits()
+                                                                        .sync
+                                                                        .acquire()
+                                                                        .await;
                                                                     if let Err(e) = serve_sync(
-                                                                        &agent, actor_id,
-                                                                        trace_ctx, framed, tx,
+                                                                        &agent,
+                                                                        actor_id,
+                                                                        trace_ctx,
+                                                                        compression,
+                                                                        framed,
+                                                                        tx,
                                                                     )
                                                                     .await
                                                                     {
@@ -723,6 +874,18 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
 
     info!("Starting peer API on udp/{gossip_addr} (QUIC)");
 
+    let mdns = if agent.config().discovery.mdns {
+        match MdnsDiscovery::start(actor_id, gossip_addr, tripwire.clone()) {
+            Ok(mdns) => Some(mdns),
+            Err(e) => {
+                error!("could not start mDNS discovery: {e}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
     tokio::spawn({
         let agent = agent.clone();
         async move {
@@ -732,17 +895,38 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
             let timer = tokio::time::sleep(Duration::new(0, 0));
             tokio::pin!(timer);
 
+            let mut dns_cache = BootstrapCache::default();
+
             loop {
                 timer.as_mut().await;
 
                 match generate_bootstrap(
                     agent.config().gossip.bootstrap.as_slice(),
                     gossip_addr,
-                    agent.pool(),
+                    &agent,
+                    &mut dns_cache,
                 )
                 .await
                 {
-                    Ok(addrs) => {
+                    Ok(mut addrs) => {
+                        if let Some(ref mdns) = mdns {
+                            for addr in mdns.peers() {
+                                if !addrs.contains(&addr) && is_peer_addr(gossip_addr, &addr) {
+                                    debug!("discovered {addr} via mDNS");
+                                    addrs.push(addr);
+                                }
+                            }
+                        }
+
+                        addrs.retain(|addr| {
+                            let allowed = agent.config().gossip.is_peer_allowed(addr.ip());
+                            if !allowed {
+                                debug!("not bootstrapping with denied peer {addr}");
+                                increment_counter!("corro.peer.denied", "type" => "bootstrap");
+                            }
+                            allowed
+                        });
+
                         for addr in addrs.iter() {
                             debug!("Bootstrapping w/ {addr}");
                             if let Err(e) = agent
@@ -761,7 +945,9 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
                     }
                 }
 
-                let dur = boff.next().unwrap_or(ANNOUNCE_INTERVAL);
+                let dur = boff.next().unwrap_or_else(|| {
+                    Duration::from_secs(agent.config().gossip.bootstrap_interval_secs)
+                });
                 timer.as_mut().reset(tokio::time::Instant::now() + dur);
             }
         }
@@ -842,7 +1028,9 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
                         ))
                     }))
                     .layer(LoadShedLayer::new())
-                    .layer(ConcurrencyLimitLayer::new(128)),
+                    .layer(ConcurrencyLimitLayer::new(
+                        agent.config().limits.db_execute_concurrency,
+                    )),
             ),
         )
         .route(
@@ -856,12 +1044,58 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
                         ))
                     }))
                     .layer(LoadShedLayer::new())
-                    .layer(ConcurrencyLimitLayer::new(128)),
+                    .layer(ConcurrencyLimitLayer::new(
+                        agent.config().limits.db_execute_concurrency,
+                    )),
+            ),
+        )
+        .route(
+            "/v1/queries/named",
+            post(api_v1_queries_named).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                        Ok::<_, Infallible>((
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "max concurrency limit reached".to_string(),
+                        ))
+                    }))
+                    .layer(LoadShedLayer::new())
+                    .layer(ConcurrencyLimitLayer::new(
+                        agent.config().limits.db_execute_concurrency,
+                    )),
             ),
         )
         .route(
             "/v1/subscriptions",
-            post(api_v1_subs).route_layer(
+            post(api_v1_subs).get(api_v1_subs_get).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                        Ok::<_, Infallible>((
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "max concurrency limit reached".to_string(),
+                        ))
+                    }))
+                    .layer(LoadShedLayer::new())
+                    .layer(ConcurrencyLimitLayer::new(128)),
+            ),
+        )
+        .route(
+            "/v1/subscriptions/sse",
+            get(api_v1_subs_sse).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                        Ok::<_, Infallible>((
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "max concurrency limit reached".to_string(),
+                        ))
+                    }))
+                    .layer(LoadShedLayer::new())
+                    .layer(ConcurrencyLimitLayer::new(128)),
+            ),
+        )
+        .route(
+            "/v1/subscriptions/test",
+            post(api_v1_subs_test).route_layer(
                 tower::ServiceBuilder::new()
                     .layer(HandleErrorLayer::new(|_error: BoxError| async {
                         Ok::<_, Infallible>((
@@ -875,7 +1109,21 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
         )
         .route(
             "/v1/subscriptions/:id",
-            get(api_v1_sub_by_id).route_layer(
+            get(api_v1_sub_by_id).delete(api_v1_sub_delete).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                        Ok::<_, Infallible>((
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "max concurrency limit reached".to_string(),
+                        ))
+                    }))
+                    .layer(LoadShedLayer::new())
+                    .layer(ConcurrencyLimitLayer::new(128)),
+            ),
+        )
+        .route(
+            "/v1/db/explain",
+            post(api_v1_db_explain).route_layer(
                 tower::ServiceBuilder::new()
                     .layer(HandleErrorLayer::new(|_error: BoxError| async {
                         Ok::<_, Infallible>((
@@ -887,6 +1135,18 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
                     .layer(ConcurrencyLimitLayer::new(128)),
             ),
         )
+        .route("/v1/cluster/members", get(api_v1_cluster_members))
+        .route("/v1/info", get(api_v1_info))
+        .route("/v1/db/versions", get(api_v1_db_versions))
+        .route("/v1/sync/now", post(api_v1_sync_now))
+        .route("/v1/sync/range", post(api_v1_sync_range))
+        .route("/v1/admin/drain", post(api_v1_drain))
+        .route("/v1/admin/bookkeeping", get(api_v1_admin_bookkeeping))
+        .route("/v1/admin/config", get(api_v1_admin_config))
+        .route(
+            "/v1/admin/config/max_change_size",
+            put(api_v1_admin_config_max_change_size),
+        )
         .route(
             "/v1/migrations",
             post(api_v1_db_schema).route_layer(
@@ -901,7 +1161,55 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
                     .layer(ConcurrencyLimitLayer::new(4)),
             ),
         )
+        .route(
+            "/v1/admin/backup",
+            post(api_v1_backup).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                        Ok::<_, Infallible>((
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "max concurrency limit reached".to_string(),
+                        ))
+                    }))
+                    .layer(LoadShedLayer::new())
+                    .layer(ConcurrencyLimitLayer::new(1)),
+            ),
+        )
+        .route(
+            "/v1/admin/export",
+            post(api_v1_export).route_layer(
+                tower::ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                        Ok::<_, Infallible>((
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "max concurrency limit reached".to_string(),
+                        ))
+                    }))
+                    .layer(LoadShedLayer::new())
+                    .layer(ConcurrencyLimitLayer::new(1)),
+            ),
+        )
+        .route(
+            "/v1/admin/import",
+            post(api_v1_import).route_layer(
+                tower::ServiceBuilder::new()
+                    // change files are legitimately large
+                    .layer(DefaultBodyLimit::disable())
+                    .layer(HandleErrorLayer::new(|_error: BoxError| async {
+                        Ok::<_, Infallible>((
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "max concurrency limit reached".to_string(),
+                        ))
+                    }))
+                    .layer(LoadShedLayer::new())
+                    .layer(ConcurrencyLimitLayer::new(1)),
+            ),
+        )
+        .layer(axum::middleware::from_fn(decompress_gzip))
         .layer(axum::middleware::from_fn(require_authz))
+        // probes are not authenticated
+        .route("/v1/health", get(api_v1_health))
+        .route("/v1/ready", get(api_v1_ready))
         .layer(
             tower::ServiceBuilder::new()
                 .layer(Extension(Arc::new(AtomicI64::new(0))))
@@ -910,9 +1218,15 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
                 .layer(Extension(subs_manager))
                 .layer(Extension(tripwire.clone())),
         )
-        .layer(DefaultBodyLimit::disable())
+        .layer(DefaultBodyLimit::max(agent.config().api.max_body_bytes))
         .layer(TraceLayer::new_for_http());
 
+    // outermost, so preflight requests are answered before authz
+    let api = match cors_layer(&agent.config().api.cors_allow_origins) {
+        Some(cors) => api.layer(cors),
+        None => api,
+    };
+
     let api_addr = api_listener.local_addr()?;
     info!("Starting public API server on tcp/{api_addr}");
     spawn_counted(
@@ -930,8 +1244,53 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
             .inspect(|_| info!("corrosion api is done")),
     );
 
+    if let Some(uds_listener) = api_uds_listener {
+        let uds_path = uds_listener
+            .local_addr()?
+            .as_pathname()
+            .map(|path| path.to_owned());
+        info!("Starting public API server on unix/{uds_path:?}");
+        spawn_counted(
+            axum::Server::builder(UnixIncoming(uds_listener))
+                .executor(CountedExecutor)
+                .serve(api.clone().into_make_service())
+                .with_graceful_shutdown(
+                    tripwire
+                        .clone()
+                        .inspect(|_| info!("corrosion api unix socket tripped")),
+                )
+                .inspect(move |_| {
+                    if let Some(path) = uds_path {
+                        if let Err(e) = std::fs::remove_file(&path) {
+                            warn!("could not remove api socket file {}: {e}", path.display());
+                        }
+                    }
+                    info!("corrosion api unix socket is done")
+                }),
+        );
+    }
+
     spawn_counted(handle_changes(agent.clone(), rx_changes, tripwire.clone()));
 
+    // schema directories have to exist to be watched, and for files to be
+    // dropped in them later
+    for path in agent.config().db.schema_paths.iter() {
+        if let Err(e) = schema_path_metadata(path).await {
+            error!("could not check schema path {path}: {e}");
+        }
+    }
+
+    spawn_counted(handle_schema_reload_signals(
+        agent.clone(),
+        tripwire.clone(),
+    ));
+
+    if agent.config().db.watch_schema {
+        spawn_counted(watch_schema_paths(agent.clone(), tripwire.clone()));
+    }
+
+    spawn_counted(persist_clock_loop(agent.clone(), tripwire.clone()));
+
     spawn_counted(write_empties_loop(
         agent.clone(),
         rx_empty,
@@ -940,14 +1299,57 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
 
     tokio::spawn(clear_buffered_meta_loop(agent.clone(), rx_clear_buf));
 
+    spawn_counted(schema_changes_loop(
+        agent.clone(),
+        rx_schema_change,
+        tripwire.clone(),
+    ));
+
+    spawn_counted(handle_sync_ranges(
+        agent.clone(),
+        transport.clone(),
+        rx_sync_range,
+        tripwire.clone(),
+    ));
+
+    tokio::spawn(handle_sync_now(
+        agent.clone(),
+        transport.clone(),
+        rx_sync_now,
+        tripwire.clone(),
+    ));
+
+    spawn_counted(
+        sync_loop(
+            agent.clone(),
+            transport.clone(),
+            rx_apply,
+            Duration::from_secs(agent.config().sync.min_interval_secs),
+            Duration::from_secs(agent.config().sync.max_interval_secs),
+            tripwire.clone(),
+        )
+        .inspect(|_| info!("corrosion agent sync loop is done")),
+    );
+
     spawn_counted(
-        sync_loop(agent.clone(), transport.clone(), rx_apply, tripwire.clone())
-            .inspect(|_| info!("corrosion agent sync loop is done")),
+        gap_sync_loop(
+            agent.clone(),
+            transport.clone(),
+            Duration::from_secs(agent.config().sync.gap_interval_secs),
+            tripwire.clone(),
+        )
+        .inspect(|_| info!("corrosion agent gap sync loop is done")),
     );
 
-    let mut db_cleanup_interval = tokio::time::interval(Duration::from_secs(60 * 15));
+    let mut db_cleanup_interval = tokio::time::interval(Duration::from_secs(
+        agent.config().db.wal_checkpoint_interval_secs,
+    ));
 
-    tokio::spawn(handle_gossip_to_send(transport.clone(), to_send_rx));
+    tokio::spawn(handle_gossip_to_send(
+        agent.clone(),
+        transport.clone(),
+        to_send_rx,
+    ));
     tokio::spawn(handle_notifications(agent.clone(), notifications_rx));
     tokio::spawn(metrics_loop(agent.clone(), transport));
 
@@ -957,7 +1359,7 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
         tokio::select! {
             biased;
             _ = db_cleanup_interval.tick() => {
-                tokio::spawn(handle_db_cleanup(agent.pool().clone()).preemptible(tripwire.clone()));
+                tokio::spawn(handle_db_cleanup(agent.clone()).preemptible(tripwire.clone()));
             },
             _ = &mut tripwire => {
                 debug!("tripped corrosion");
@@ -969,16 +1371,41 @@ pub async fn run(agent: Agent, opts: AgentOptions) -> eyre::Result<()> {
     Ok(())
 }
 
+fn cors_layer(origins: &[String]) -> Option<CorsLayer> {
+    if origins.is_empty() {
+        return None;
+    }
+
+    let allow_origin = if origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            origins
+                .iter()
+                // validated when loading the config
+                .filter_map(|origin| HeaderValue::from_str(origin).ok()),
+        )
+    };
+
+    Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods([Method::GET, Method::POST])
+            .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION]),
+    )
+}
+
 async fn require_authz<B>(
     Extension(agent): Extension<Agent>,
     maybe_authz_header: Option<TypedHeader<Authorization<Bearer>>>,
     request: axum::http::Request<B>,
     next: axum::middleware::Next<B>,
-) -> Result<axum::response::Response, axum::http::StatusCode> {
+) -> Result<axum::response::Response, (StatusCode, axum::Json<ExecResponse>)> {
     let passed = if let Some(ref authz) = agent.config().api.authorization {
         match authz {
+            // constant-time comparison to avoid leaking the token via timing
             AuthzConfig::BearerToken(token) => maybe_authz_header
-                .map(|h| h.token() == token)
+                .map(|h| h.token().as_bytes().ct_eq(token.as_bytes()).into())
                 .unwrap_or(false),
         }
     } else {
@@ -986,7 +1413,17 @@ async fn require_authz<B>(
     };
 
     if !passed {
-        return Err(axum::http::StatusCode::UNAUTHORIZED);
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            axum::Json(ExecResponse {
+                results: vec![ExecResult::Error {
+                    error: "unauthorized".into(),
+                }],
+                time: 0.0,
+                version: None,
+                changes: 0,
+            }),
+        ));
     }
 
     Ok(next.run(request).await)
@@ -1131,14 +1568,45 @@ async fn metrics_loop(agent: Agent, transport: Transport) {
     loop {
         metrics_interval.tick().await;
 
+        collect_bookie_metrics(&agent).await;
         block_in_place(|| collect_metrics(&agent, &transport));
     }
 }
 
+async fn collect_bookie_metrics(agent: &Agent) {
+    gauge!(
+        "corro.members.total",
+        agent.members().read().states.len() as f64
+    );
+
+    let actors: Vec<(ActorId, Booked)> = {
+        agent
+            .bookie()
+            .read("collect_bookie_metrics")
+            .await
+            .iter()
+            .map(|(k, v)| (*k, v.clone()))
+            .collect()
+    };
+
+    for (actor_id, booked) in actors {
+        let last = booked
+            .read(format!("collect_bookie_metrics:{}", actor_id.as_simple()))
+            .await
+            .last();
+        if let Some(version) = last {
+            gauge!("corro.bookie.head", version.0 as f64, "actor_id" => actor_id.to_string());
+        }
+    }
+}
+
 fn collect_metrics(agent: &Agent, transport: &Transport) {
     agent.pool().emit_metrics();
     transport.emit_metrics();
 
+    let tx_bcast = agent.tx_bcast();
+    gauge!("corro.broadcast.channel.depth", (tx_bcast.max_capacity() - tx_bcast.capacity()) as f64, "channel" => "outgoing");
+
     let schema = agent.schema().read();
 
     let conn = match agent.pool().read_blocking() {
@@ -1239,9 +1707,7 @@ pub async fn handle_change(agent: &Agent, bcast: BroadcastV1, bcast_msg_tx: &Sen
                 histogram!("corro.broadcast.recv.lag.seconds", diff.as_secs_f64());
             }
 
-            if let Err(e) = bcast_msg_tx.send(BroadcastV1::Change(change)).await {
-                error!("could not send change message through broadcast channel: {e}");
-            }
+            try_send_broadcast(bcast_msg_tx, BroadcastV1::Change(change), "incoming");
         }
     }
 }
@@ -1298,11 +1764,28 @@ fn find_cleared_db_versions(
     Ok(cleared_db_versions)
 }
 
-async fn handle_gossip_to_send(transport: Transport, mut to_send_rx: Receiver<(Actor, Bytes)>) {
+async fn handle_gossip_to_send(
+    agent: Agent,
+    transport: Transport,
+    mut to_send_rx: Receiver<(Actor, Bytes)>,
+) {
+    let signer = agent.signer().cloned();
+
     // TODO: use tripwire and drain messages to send when that happens...
     while let Some((actor, data)) = to_send_rx.recv().await {
         trace!("got gossip to send to {actor:?}");
 
+        if !agent.config().gossip.is_peer_allowed(actor.addr().ip()) {
+            trace!("not gossiping with denied peer {}", actor.addr());
+            increment_counter!("corro.peer.denied", "type" => "gossip");
+            continue;
+        }
+
+        let data = match &signer {
+            Some(signer) => signer.sign(&data),
+            None => data,
+        };
+
         let addr = actor.addr();
         let actor_id = actor.id();
 
@@ -1320,11 +1803,34 @@ async fn handle_gossip_to_send(transport: Transport, mut to_send_rx: Receiver<(A
     }
 }
 
+const SEEN_BROADCASTS_CAPACITY: usize = 10_000;
+const SEEN_BROADCASTS_TTL: Duration = Duration::from_secs(10);
+
+fn payload_decode_error_kind(e: &speedy::Error) -> &'static str {
+    if e.is_eof() {
+        "truncated"
+    } else {
+        "invalid"
+    }
+}
+
 async fn handle_broadcasts(agent: Agent, mut bcast_rx: Receiver<BroadcastV1>) {
+    // the same change usually arrives from several peers before it's booked,
+    // skip the copies so we don't process and rebroadcast each of them
+    let mut seen = SeenCache::new(SEEN_BROADCASTS_CAPACITY, SEEN_BROADCASTS_TTL);
+
     while let Some(bcast) = bcast_rx.recv().await {
         increment_counter!("corro.broadcast.recv.count");
         match bcast {
             BroadcastV1::Change(change) => {
+                let key = (change.actor_id, change.versions(), change.seqs().cloned());
+                if seen.check_and_insert(key) {
+                    increment_counter!("corro.broadcast.seen.hit");
+                    continue;
+                }
+                increment_counter!("corro.broadcast.seen.miss");
+                gauge!("corro.broadcast.seen.size", seen.len() as f64);
+
                 if let Err(_e) = agent
                     .tx_changes()
                     .send((change, ChangeSource::Broadcast))
@@ -1410,9 +1916,38 @@ async fn handle_notifications(agent: Agent, mut notification_rx: Receiver<Notifi
     }
 }
 
-async fn handle_db_cleanup(pool: SplitPool) -> eyre::Result<()> {
+async fn handle_db_cleanup(agent: Agent) -> eyre::Result<()> {
+    debug!("handling db_cleanup (cleared versions compaction)");
+    let mut conn = agent.pool().write_low().await?;
+    block_in_place(|| {
+        let start = Instant::now();
+
+        let actor_ids: Vec<ActorId> = conn
+            .prepare_cached(
+                "SELECT DISTINCT actor_id FROM __corro_bookkeeping WHERE end_version IS NOT NULL",
+            )?
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let mut deleted = 0;
+        for actor_id in actor_ids {
+            let tx = conn.immediate_transaction()?;
+            deleted += compact_cleared_versions(&tx, actor_id)?;
+            tx.commit()?;
+        }
+
+        if deleted > 0 {
+            info!(
+                "compacted {deleted} cleared version bookkeeping rows in {:?}",
+                start.elapsed()
+            );
+        }
+        counter!("corro.db.bookkeeping.compacted.rows", deleted as u64);
+
+        Ok::<_, eyre::Report>(())
+    })?;
+
     debug!("handling db_cleanup (WAL truncation)");
-    let conn = pool.write_low().await?;
     block_in_place(move || {
         let start = Instant::now();
 
@@ -1434,25 +1969,444 @@ async fn handle_db_cleanup(pool: SplitPool) -> eyre::Result<()> {
     Ok(())
 }
 
-#[derive(Clone)]
-pub struct CountedExecutor;
+/// Reads all the schema files from the configured schema paths and applies
+/// them, leaving the current schema untouched if anything fails.
+pub async fn reload_schema(agent: &Agent) -> eyre::Result<()> {
+    // held while reading the files too, so an older read can't be applied
+    // after a newer one
+    let guard = agent.lock_schema().await;
 
-impl<F> hyper::rt::Executor<F> for CountedExecutor
-where
-    F: std::future::Future + Send + 'static,
-    F::Output: Send,
-{
-    fn execute(&self, fut: F) {
-        spawn::spawn_counted(fut);
+    let schema_paths = agent.config().db.schema_paths.clone();
+    let statements = read_schema_paths(&schema_paths).await?;
+
+    if statements.is_empty() {
+        info!("no schema files found, nothing to reload");
+        return Ok(());
     }
-}
 
-async fn generate_bootstrap(
-    bootstrap: &[String],
-    our_addr: SocketAddr,
-    pool: &SplitPool,
-) -> eyre::Result<Vec<SocketAddr>> {
-    let mut addrs = match resolve_bootstrap(bootstrap, our_addr).await {
+    let previous = agent.schema().read().clone();
+    execute_schema_locked(agent, &guard, statements, false).await?;
+
+    let summary = {
+        let current = agent.schema().read();
+        schema_changes_summary(&previous, &current)
+    };
+    if summary.is_empty() {
+        info!("reloaded schema from {schema_paths:?}, no changes");
+    } else {
+        info!(
+            "reloaded schema from {schema_paths:?}: {}",
+            summary.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+// describes added tables, columns, views and triggers between two schemas
+fn schema_changes_summary(previous: &Schema, current: &Schema) -> Vec<String> {
+    let mut summary = vec![];
+
+    for (name, table) in current.tables.iter() {
+        match previous.tables.get(name) {
+            None => summary.push(format!(
+                "added table {name} ({})",
+                table.columns.keys().join(", ")
+            )),
+            Some(prev_table) => {
+                for column in table.columns.keys() {
+                    if !prev_table.columns.contains_key(column) {
+                        summary.push(format!("added column {name}.{column}"));
+                    }
+                }
+            }
+        }
+    }
+
+    for name in current.views.keys() {
+        if !previous.views.contains_key(name) {
+            summary.push(format!("added view {name}"));
+        }
+    }
+
+    for name in current.triggers.keys() {
+        if !previous.triggers.contains_key(name) {
+            summary.push(format!("added trigger {name}"));
+        }
+    }
+
+    summary
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SchemaChangeError {
+    #[error("renames are not applied from other nodes")]
+    Rename,
+    #[error("schema change for {kind} '{name}' is not additive")]
+    NotAdditive { kind: &'static str, name: String },
+    #[error("invalid schema change statements: {0}")]
+    Statements(#[from] serde_json::Error),
+    #[error(transparent)]
+    Schema(#[from] SchemaError),
+    #[error("{0}")]
+    Apply(eyre::Report),
+}
+
+impl SchemaChangeError {
+    /// Whether applying the same change again can't succeed either
+    pub fn is_permanent(&self) -> bool {
+        !matches!(self, SchemaChangeError::Apply(_))
+    }
+}
+
+/// Applies schema statements recorded by another node. Only additive changes
+/// (new tables, columns, indexes, views and triggers) are applied so a node with outdated schema
+/// files can't revert or drop anything. Returns false if there was nothing new.
+pub async fn apply_schema_change(
+    agent: &Agent,
+    statements: Vec<String>,
+) -> Result<bool, SchemaChangeError> {
+    let sql = statements.join(";");
+    if !parse_renames(&sql)?.is_empty() {
+        return Err(SchemaChangeError::Rename);
+    }
+    let partial_schema = parse_sql(&sql)?;
+
+    // checked and applied under the same lock, so nothing changes in between
+    let guard = agent.lock_schema().await;
+
+    let changed = {
+        let schema = agent.schema().read();
+        let mut changed = false;
+        for (name, table) in partial_schema.tables.iter() {
+            let current = match schema.tables.get(name) {
+                Some(current) => current,
+                None => {
+                    changed = true;
+                    continue;
+                }
+            };
+            if current == table {
+                continue;
+            }
+
+            let additive = current.pk == table.pk
+                && current
+                    .columns
+                    .iter()
+                    .all(|(col_name, col)| table.columns.get(col_name) == Some(col))
+                && current
+                    .indexes
+                    .iter()
+                    .all(|(idx_name, index)| table.indexes.get(idx_name) == Some(index));
+            if !additive {
+                return Err(SchemaChangeError::NotAdditive {
+                    kind: "table",
+                    name: name.clone(),
+                });
+            }
+            changed = true;
+        }
+
+        for (name, view) in partial_schema.views.iter() {
+            match schema.views.get(name) {
+                None => changed = true,
+                Some(current) if current == view => {}
+                Some(_) => {
+                    return Err(SchemaChangeError::NotAdditive {
+                        kind: "view",
+                        name: name.clone(),
+                    })
+                }
+            }
+        }
+
+        // resubmitted tables (and views) come with all of their triggers
+        for (name, trigger) in schema.triggers.iter() {
+            let resubmitted = partial_schema.tables.contains_key(&trigger.tbl_name)
+                || partial_schema.views.contains_key(&trigger.tbl_name);
+            if resubmitted && partial_schema.triggers.get(name) != Some(trigger) {
+                return Err(SchemaChangeError::NotAdditive {
+                    kind: "trigger",
+                    name: name.clone(),
+                });
+            }
+        }
+        changed |= partial_schema
+            .triggers
+            .keys()
+            .any(|name| !schema.triggers.contains_key(name));
+
+        changed
+    };
+
+    if !changed {
+        return Ok(false);
+    }
+
+    execute_schema_locked(agent, &guard, statements, false)
+        .await
+        .map_err(SchemaChangeError::Apply)?;
+
+    Ok(true)
+}
+
+/// Applies the schema changes other nodes recorded in `__corro_schema_changes`
+/// that weren't applied here yet, oldest first. Changes that can never apply
+/// are logged and skipped, the others stay pending until the next run.
+pub async fn apply_pending_schema_changes(agent: &Agent) -> eyre::Result<()> {
+    let mut pending: Vec<(ActorId, Timestamp, String)> = {
+        let conn = agent.pool().read().await?;
+        block_in_place(|| {
+            conn.prepare_cached(
+                "SELECT actor_id, ts, statements FROM __corro_schema_changes AS c
+                    WHERE NOT EXISTS (
+                        SELECT 1 FROM __corro_schema_changes_applied AS a
+                            WHERE a.actor_id = c.actor_id AND a.ts = c.ts
+                    )",
+            )?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()
+        })?
+    };
+
+    // a change can build on any earlier one, from any node
+    pending.sort_by_key(|(_, ts, _)| ts.0.as_u64());
+
+    for (actor_id, ts, statements) in pending {
+        let res = match serde_json::from_str(&statements) {
+            Ok(statements) => apply_schema_change(agent, statements).await,
+            Err(e) => Err(e.into()),
+        };
+
+        match res {
+            Ok(true) => info!("applied schema change from {actor_id} at {ts}"),
+            Ok(false) => debug!("schema change from {actor_id} at {ts} already applied"),
+            Err(e) if e.is_permanent() => {
+                warn!("ignoring schema change from {actor_id} at {ts}: {e}")
+            }
+            Err(e) => {
+                error!("could not apply schema change from {actor_id} at {ts}, will retry: {e}");
+                continue;
+            }
+        }
+
+        let conn = agent.pool().write_normal().await?;
+        block_in_place(|| {
+            conn.prepare_cached(
+                "INSERT OR IGNORE INTO __corro_schema_changes_applied (actor_id, ts) VALUES (?, ?)",
+            )?
+            .execute(params![actor_id, ts])
+        })?;
+    }
+
+    Ok(())
+}
+
+async fn schema_changes_loop(
+    agent: Agent,
+    mut rx_schema_change: Receiver<()>,
+    mut tripwire: Tripwire,
+) {
+    loop {
+        // the first run picks up changes received before a restart
+        if let Err(e) = apply_pending_schema_changes(&agent).await {
+            error!("could not apply pending schema changes: {e}");
+        }
+
+        tokio::select! {
+            woken = rx_schema_change.recv() => if woken.is_none() {
+                break;
+            },
+            _ = &mut tripwire => break,
+        }
+    }
+
+    debug!("schema changes loop is done");
+}
+
+// a full channel means a run is already pending, which also picks this change up
+fn notify_schema_change(agent: &Agent) {
+    _ = agent.tx_schema_change().try_send(());
+}
+
+// a missing schema directory is created empty instead of failing, so a node
+// can start without a schema and have it pushed (or dropped in) later.
+// returns None for paths that didn't exist.
+async fn schema_path_metadata(path: &Utf8Path) -> std::io::Result<Option<std::fs::Metadata>> {
+    match tokio::fs::metadata(path).await {
+        Ok(meta) => Ok(Some(meta)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            if path.extension() == Some("sql") {
+                warn!("schema file {path} does not exist, skipping it");
+            } else {
+                tokio::fs::create_dir_all(path).await?;
+                warn!("schema directory {path} did not exist, created it empty");
+            }
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// reads .sql files from directories (sorted by path) or individual files
+async fn read_schema_paths(schema_paths: &[Utf8PathBuf]) -> eyre::Result<Vec<String>> {
+    let mut statements = vec![];
+
+    for schema_path in schema_paths {
+        let Some(meta) = schema_path_metadata(schema_path).await? else {
+            continue;
+        };
+        if meta.is_dir() {
+            let mut dir = tokio::fs::read_dir(schema_path).await?;
+            let mut paths = vec![];
+            while let Some(entry) = dir.next_entry().await? {
+                let path = entry.path();
+                if path.extension().map(|ext| ext == "sql").unwrap_or(false) {
+                    paths.push(path);
+                }
+            }
+            if paths.is_empty() {
+                warn!("schema directory {schema_path} has no .sql files");
+            }
+            paths.sort();
+
+            for path in paths {
+                statements.push(tokio::fs::read_to_string(path).await?);
+            }
+        } else {
+            statements.push(tokio::fs::read_to_string(schema_path).await?);
+        }
+    }
+
+    Ok(statements)
+}
+
+async fn handle_schema_reload_signals(agent: Agent, mut tripwire: Tripwire) {
+    let mut sighups = match signal(SignalKind::hangup()) {
+        Ok(sighups) => sighups,
+        Err(e) => {
+            error!("could not listen for SIGHUP, schema won't be reloaded on signal: {e}");
+            return;
+        }
+    };
+
+    loop {
+        tokio::select! {
+            res = sighups.recv() => if res.is_none() {
+                break;
+            },
+            _ = &mut tripwire => break,
+        }
+
+        info!("received SIGHUP, reloading schema");
+        if let Err(e) = reload_schema(&agent).await {
+            error!("could not reload schema, keeping the current one: {e}");
+        }
+    }
+}
+
+// reloads the schema whenever a .sql file in the schema paths changes,
+// debouncing rapid successive writes (e.g. from editors)
+async fn watch_schema_paths(agent: Agent, mut tripwire: Tripwire) {
+    let (tx, mut rx) = channel(1);
+
+    let mut debouncer = match new_debouncer(
+        Duration::from_millis(500),
+        None,
+        move |res: DebounceEventResult| {
+            if let Err(e) = tx.blocking_send(res) {
+                error!("could not send schema file change notifications! {e}");
+            }
+        },
+    ) {
+        Ok(debouncer) => debouncer,
+        Err(e) => {
+            error!("could not create schema file watcher: {e}");
+            return;
+        }
+    };
+
+    for path in agent.config().db.schema_paths.iter() {
+        if let Err(e) = debouncer
+            .watcher()
+            .watch(path.as_std_path(), RecursiveMode::NonRecursive)
+        {
+            error!("could not watch schema path {path}: {e}");
+        }
+    }
+
+    loop {
+        let res = tokio::select! {
+            res = rx.recv() => match res {
+                Some(res) => res,
+                None => break,
+            },
+            _ = &mut tripwire => break,
+        };
+
+        let events = match res {
+            Ok(events) => events,
+            Err(e) => {
+                error!("schema file watcher errors: {e:?}");
+                continue;
+            }
+        };
+
+        if !events.iter().any(|event| {
+            event
+                .path
+                .extension()
+                .map(|ext| ext == "sql")
+                .unwrap_or(false)
+        }) {
+            continue;
+        }
+
+        info!("schema files changed, reloading schema");
+        if let Err(e) = reload_schema(&agent).await {
+            error!("could not reload schema, keeping the current one: {e}");
+        }
+    }
+}
+
+// accepts connections on a unix domain socket for hyper
+pub struct UnixIncoming(pub UnixListener);
+
+impl hyper::server::accept::Accept for UnixIncoming {
+    type Conn = UnixStream;
+    type Error = std::io::Error;
+
+    fn poll_accept(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Result<Self::Conn, Self::Error>>> {
+        self.0
+            .poll_accept(cx)
+            .map(|res| Some(res.map(|(stream, _addr)| stream)))
+    }
+}
+
+#[derive(Clone)]
+pub struct CountedExecutor;
+
+impl<F> hyper::rt::Executor<F> for CountedExecutor
+where
+    F: std::future::Future + Send + 'static,
+    F::Output: Send,
+{
+    fn execute(&self, fut: F) {
+        spawn::spawn_counted(fut);
+    }
+}
+
+async fn generate_bootstrap(
+    bootstrap: &[String],
+    our_addr: SocketAddr,
+    agent: &Agent,
+    dns_cache: &mut BootstrapCache,
+) -> eyre::Result<Vec<SocketAddr>> {
+    let mut addrs = match resolve_bootstrap(bootstrap, our_addr, dns_cache).await {
         Ok(addrs) => addrs,
         Err(e) => {
             warn!("could not resolve bootstraps, falling back to in-db nodes: {e}");
@@ -1462,7 +2416,7 @@ async fn generate_bootstrap(
 
     if addrs.is_empty() {
         // fallback to in-db nodes
-        let conn = pool.read().await?;
+        let conn = agent.pool().read().await?;
         addrs = block_in_place(|| {
             let mut prepped =
                 conn.prepare("SELECT address FROM __corro_members ORDER BY RANDOM() LIMIT 5")?;
@@ -1471,29 +2425,109 @@ async fn generate_bootstrap(
                 node_addrs
                     .flatten()
                     .flat_map(|addr| addr.parse())
-                    .filter(|addr| match (our_addr, addr) {
-                        (SocketAddr::V6(our_ip), SocketAddr::V6(ip)) if our_ip != *ip => true,
-                        (SocketAddr::V4(our_ip), SocketAddr::V4(ip)) if our_ip != *ip => true,
-                        _ => {
+                    .filter(|addr| {
+                        let keep = is_peer_addr(our_addr, addr);
+                        if !keep {
                             debug!("ignore node with addr: {addr}");
-                            false
                         }
+                        keep
                     })
                     .collect(),
             )
         })?;
     }
 
-    let mut rng = StdRng::from_entropy();
+    // sorted so the choice only depends on the agent's rng
+    let mut addrs: Vec<SocketAddr> = addrs.into_iter().collect();
+    addrs.sort();
 
-    Ok(addrs
-        .into_iter()
-        .choose_multiple(&mut rng, RANDOM_NODES_CHOICES))
+    let fanout = agent.config().gossip.announce_fanout;
+    Ok(addrs.into_iter().choose_multiple(&mut *agent.rng(), fanout))
+}
+
+// not ourselves and reachable with the same IP family. IPv6 scope ids only
+// tell addresses apart when both have one (they're not gossiped), flow info
+// never does
+fn is_peer_addr(our_addr: SocketAddr, addr: &SocketAddr) -> bool {
+    match (our_addr, addr) {
+        (SocketAddr::V6(our_ip), SocketAddr::V6(ip)) => {
+            our_ip.ip() != ip.ip()
+                || our_ip.port() != ip.port()
+                || (our_ip.scope_id() != 0
+                    && ip.scope_id() != 0
+                    && our_ip.scope_id() != ip.scope_id())
+        }
+        (SocketAddr::V4(our_ip), SocketAddr::V4(ip)) => our_ip != *ip,
+        _ => false,
+    }
+}
+
+/// Last successful resolution of each hostname bootstrap entry, kept
+/// across bootstrap cycles.
+#[derive(Debug, Default)]
+struct BootstrapCache {
+    entries: HashMap<String, CachedResolution>,
+}
+
+#[derive(Debug)]
+struct CachedResolution {
+    addrs: HashSet<SocketAddr>,
+    valid_until: Instant,
+}
+
+impl BootstrapCache {
+    /// Returns the cached addresses for `entry` until their record TTL
+    /// expires, calls `lookup` otherwise. If the lookup fails or doesn't
+    /// return any address, the last known good addresses are used instead.
+    async fn resolve<F, Fut>(
+        &mut self,
+        entry: &str,
+        lookup: F,
+    ) -> Result<HashSet<SocketAddr>, ResolveError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<(HashSet<SocketAddr>, Instant), ResolveError>>,
+    {
+        if let Some(cached) = self.entries.get(entry) {
+            if cached.valid_until > Instant::now() {
+                debug!("using cached addresses for '{entry}'");
+                return Ok(cached.addrs.clone());
+            }
+        }
+
+        let res = lookup().await;
+        if let Ok((addrs, valid_until)) = &res {
+            if !addrs.is_empty() {
+                self.entries.insert(
+                    entry.to_owned(),
+                    CachedResolution {
+                        addrs: addrs.clone(),
+                        valid_until: *valid_until,
+                    },
+                );
+                return Ok(addrs.clone());
+            }
+        }
+
+        match self.entries.get(entry) {
+            Some(cached) => {
+                match &res {
+                    Ok(_) => warn!("no addresses for '{entry}', using last known addresses"),
+                    Err(e) => {
+                        warn!("could not resolve '{entry}', using last known addresses: {e}")
+                    }
+                }
+                Ok(cached.addrs.clone())
+            }
+            None => res.map(|(addrs, _)| addrs),
+        }
+    }
 }
 
 async fn resolve_bootstrap(
     bootstrap: &[String],
     our_addr: SocketAddr,
+    cache: &mut BootstrapCache,
 ) -> eyre::Result<HashSet<SocketAddr>> {
     use trust_dns_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
     use trust_dns_resolver::{AsyncResolver, TokioAsyncResolver};
@@ -1532,51 +2566,53 @@ async fn resolve_bootstrap(
                 debug!("using resolver: {dns_server}");
             }
             if let Some(hostname) = host_port.next() {
-                debug!("Resolving '{hostname}' to an IP");
-                match resolver
-                    .as_ref()
-                    .unwrap_or(&system_resolver)
-                    .lookup(
-                        hostname,
-                        if our_addr.is_ipv6() {
+                let port: u16 = host_port
+                    .next()
+                    .and_then(|p| p.parse().ok())
+                    .unwrap_or(DEFAULT_GOSSIP_PORT);
+                let resolver = resolver.as_ref().unwrap_or(&system_resolver);
+
+                let resolved = cache
+                    .resolve(s, || async {
+                        debug!("Resolving '{hostname}' to an IP");
+                        let record_type = if our_addr.is_ipv6() {
                             RecordType::AAAA
                         } else {
                             RecordType::A
-                        },
-                    )
-                    .await
-                {
-                    Ok(response) => {
+                        };
+                        let response = resolver.lookup(hostname, record_type).await?;
                         debug!("Successfully resolved things: {response:?}");
-                        let port: u16 = host_port
-                            .next()
-                            .and_then(|p| p.parse().ok())
-                            .unwrap_or(DEFAULT_GOSSIP_PORT);
-                        for addr in response.iter().filter_map(|rdata| match rdata {
-                            RData::A(ip) => Some(SocketAddr::from((*ip, port))),
-                            RData::AAAA(ip) => Some(SocketAddr::from((*ip, port))),
-                            _ => None,
-                        }) {
-                            match (our_addr, addr) {
-                                (SocketAddr::V4(our_ip), SocketAddr::V4(ip)) if our_ip != ip => {}
-                                (SocketAddr::V6(our_ip), SocketAddr::V6(ip)) if our_ip != ip => {}
-                                _ => {
+
+                        let addrs = response
+                            .iter()
+                            .filter_map(|rdata| match rdata {
+                                RData::A(ip) => Some(SocketAddr::from((*ip, port))),
+                                RData::AAAA(ip) => Some(SocketAddr::from((*ip, port))),
+                                _ => None,
+                            })
+                            .filter(|addr| {
+                                let keep = is_peer_addr(our_addr, addr);
+                                if !keep {
                                     debug!("ignore node with addr: {addr}");
-                                    continue;
                                 }
-                            }
-                            addrs.insert(addr);
-                        }
+                                keep
+                            })
+                            .collect();
+
+                        Ok((addrs, response.valid_until()))
+                    })
+                    .await;
+
+                match resolved {
+                    Ok(resolved) => addrs.extend(resolved),
+                    // do nothing, that might be fine!
+                    Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
+                        debug!("no records found for '{hostname}'");
+                    }
+                    Err(e) => {
+                        error!("could not resolve '{hostname}': {e}");
+                        return Err(e.into());
                     }
-                    Err(e) => match e.kind() {
-                        ResolveErrorKind::NoRecordsFound { .. } => {
-                            // do nothing, that might be fine!
-                        }
-                        _ => {
-                            error!("could not resolve '{hostname}': {e}");
-                            return Err(e.into());
-                        }
-                    },
                 }
             }
         }
@@ -1663,6 +2699,71 @@ fn store_empty_changeset(
     Ok(inserted)
 }
 
+// books a version whose changes were applied locally. a sync can re-deliver
+// a version or deliver versions out of order, so this must not fail if the
+// version is already booked: a current version gets the latest db_version,
+// a cleared range is left as-is.
+fn book_current_version(
+    tx: &Transaction,
+    actor_id: ActorId,
+    version: Version,
+    current: &CurrentVersion,
+) -> rusqlite::Result<usize> {
+    tx.prepare_cached(
+        "
+        INSERT INTO __corro_bookkeeping ( actor_id,  start_version,  db_version,  last_seq,  ts)
+                                VALUES  (:actor_id, :start_version, :db_version, :last_seq, :ts)
+            ON CONFLICT (actor_id, start_version) DO UPDATE SET
+                db_version = excluded.db_version,
+                last_seq = excluded.last_seq,
+                ts = excluded.ts
+            WHERE end_version IS NULL;",
+    )?
+    .execute(named_params! {
+        ":actor_id": actor_id,
+        ":start_version": version,
+        ":db_version": current.db_version,
+        ":last_seq": current.last_seq,
+        ":ts": current.ts
+    })
+}
+
+// collapses adjacent or overlapping cleared version ranges of an actor into
+// as few bookkeeping rows as possible, returns how many rows were removed.
+// the in-memory cleared versions are a `RangeInclusiveSet` and are always
+// coalesced, so they don't need to be touched.
+fn compact_cleared_versions(tx: &Transaction, actor_id: ActorId) -> rusqlite::Result<usize> {
+    let rows: Vec<RangeInclusive<Version>> = tx
+        .prepare_cached(
+            "SELECT start_version, end_version FROM __corro_bookkeeping WHERE actor_id = ? AND end_version IS NOT NULL",
+        )?
+        .query_map([actor_id], |row| Ok(row.get(0)?..=row.get(1)?))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let coalesced = RangeInclusiveSet::from_iter(rows.iter().cloned());
+
+    if coalesced.len() >= rows.len() {
+        return Ok(0);
+    }
+
+    tx.prepare_cached(
+        "DELETE FROM __corro_bookkeeping WHERE actor_id = ? AND end_version IS NOT NULL",
+    )?
+    .execute([actor_id])?;
+
+    for range in coalesced.iter() {
+        tx.prepare_cached(
+            "INSERT INTO __corro_bookkeeping (actor_id, start_version, end_version, db_version, last_seq, ts)
+                VALUES (?, ?, ?, NULL, NULL, NULL)",
+        )?
+        .execute(params![actor_id, range.start(), range.end()])?;
+    }
+
+    debug!(%actor_id, "compacted {} cleared version rows into {}", rows.len(), coalesced.len());
+
+    Ok(rows.len() - coalesced.len())
+}
+
 #[tracing::instrument(skip(agent), err)]
 async fn process_fully_buffered_changes(
     agent: &Agent,
@@ -1746,39 +2847,29 @@ async fn process_fully_buffered_changes(
 
         debug!(%actor_id, %version, "rows impacted by buffered changes insertion: {rows_impacted}");
 
+        let mut schema_changed = false;
+
         let known_version = if rows_impacted > 0 {
             let db_version: CrsqlDbVersion =
                 tx.query_row("SELECT crsql_next_db_version()", [], |row| row.get(0))?;
             debug!("db version: {db_version}");
 
-            tx.prepare_cached(
-                "
-                INSERT INTO __corro_bookkeeping (actor_id, start_version, db_version, last_seq, ts)
-                    VALUES (
-                        :actor_id,
-                        :version,
-                        :db_version,
-                        :last_seq,
-                        :ts
-                    );",
-            )?
-            .execute(named_params! {
-                ":actor_id": actor_id,
-                ":version": version,
-                ":db_version": db_version,
-             // ":start_version": 0,
-                ":last_seq": last_seq,
-                ":ts": ts
-            })?;
-
-            debug!(%actor_id, %version, "inserted bookkeeping row after buffered insert");
+            schema_changed = tx
+                .prepare_cached(r#"SELECT EXISTS(SELECT 1 FROM __corro_buffered_changes WHERE site_id = ? AND version = ? AND "table" = ?)"#)?
+                .query_row(params![actor_id.as_bytes(), version, SCHEMA_CHANGES_TABLE], |row| row.get(0))?;
 
-            Some(KnownDbVersion::Current(CurrentVersion {
+            let current = CurrentVersion {
                 db_version,
                 last_seq,
                 ts,
-            }))
-        } else {
+            };
+
+            book_current_version(&tx, actor_id, version, &current)?;
+
+            debug!(%actor_id, %version, "inserted bookkeeping row after buffered insert");
+
+            Some(KnownDbVersion::Current(current))
+        } else {
             if let Err(e) = agent.tx_empty().try_send((actor_id, version..=version)) {
                 error!(%actor_id, "could not schedule empties for clear: {e}");
             }
@@ -1789,6 +2880,10 @@ async fn process_fully_buffered_changes(
 
         tx.commit()?;
 
+        if schema_changed {
+            notify_schema_change(agent);
+        }
+
         let inserted = if let Some(known_version) = known_version {
             bookedw.insert(version, known_version);
 
@@ -1846,158 +2941,11 @@ pub async fn process_multiple_changes(
 
     let mut conn = agent.pool().write_normal().await?;
 
-    let changesets = block_in_place(|| {
-        let start = Instant::now();
-        let tx = conn.immediate_transaction()?;
-
-        let mut knowns: BTreeMap<ActorId, Vec<_>> = BTreeMap::new();
-        let mut changesets = vec![];
-
-        let mut last_db_version = None;
-
-        for (actor_id, changes) in unknown_changes
-            .into_iter()
-            .group_by(|(change, _src)| change.actor_id)
-            .into_iter()
-        {
-            // get a lock on the actor id's booked writer if we didn't already
-            {
-                let booked = {
-                    bookie
-                        .blocking_write(format!(
-                            "process_multiple_changes(for_actor_blocking):{}",
-                            actor_id.as_simple()
-                        ))
-                        .for_actor(actor_id)
-                };
-                let booked_write = booked.blocking_write(format!(
-                    "process_multiple_changes(booked writer):{}",
-                    actor_id.as_simple()
-                ));
-
-                let mut seen = RangeInclusiveMap::new();
-
-                for (change, src) in changes {
-                    trace!("handling a single changeset: {change:?}");
-                    let seqs = change.seqs();
-                    if booked_write.contains_all(change.versions(), change.seqs()) {
-                        trace!(
-                            "previously unknown versions are now deemed known, aborting inserts"
-                        );
-                        continue;
-                    }
-
-                    let versions = change.versions();
-
-                    // check if we've seen this version here...
-                    if versions.clone().all(|version| match seqs {
-                        Some(check_seqs) => match seen.get(&version) {
-                            Some(known) => match known {
-                                KnownDbVersion::Partial(PartialVersion { seqs, .. }) => {
-                                    check_seqs.clone().all(|seq| seqs.contains(&seq))
-                                }
-                                KnownDbVersion::Current { .. } | KnownDbVersion::Cleared => true,
-                            },
-                            None => false,
-                        },
-                        None => seen.contains_key(&version),
-                    }) {
-                        continue;
-                    }
-
-                    // optimizing this, insert later!
-                    let known = if change.is_complete() && change.is_empty() {
-                        // we never want to block here
-                        if let Err(e) = agent.tx_empty().try_send((actor_id, change.versions())) {
-                            error!("could not send empty changed versions into channel: {e}");
-                        }
-
-                        KnownDbVersion::Cleared
-                    } else {
-                        if let Some(seqs) = change.seqs() {
-                            if seqs.end() < seqs.start() {
-                                warn!(%actor_id, versions = ?change.versions(), "received an invalid change, seqs start is greater than seqs end: {seqs:?}");
-                                continue;
-                            }
-                        }
-
-                        let (known, versions) = match process_single_version(
-                            agent,
-                            &tx,
-                            last_db_version,
-                            change,
-                        ) {
-                            Ok((known, changeset)) => {
-                                let versions = changeset.versions();
-                                if let KnownDbVersion::Current(CurrentVersion {
-                                    db_version, ..
-                                }) = &known
-                                {
-                                    last_db_version = Some(*db_version);
-                                    changesets.push((actor_id, changeset, *db_version, src));
-                                }
-                                (known, versions)
-                            }
-                            Err(e) => {
-                                error!(%actor_id, ?versions, "could not process single change: {e}");
-                                continue;
-                            }
-                        };
-                        debug!(%actor_id, self_actor_id = %agent.actor_id(), ?versions, "got known to insert: {known:?}");
-                        known
-                    };
-
-                    seen.insert(versions.clone(), known.clone());
-                    knowns.entry(actor_id).or_default().push((versions, known));
-                }
-            }
-        }
-
-        let mut count = 0;
-
-        for (actor_id, knowns) in knowns.iter_mut() {
-            debug!(%actor_id, self_actor_id = %agent.actor_id(), "processing {} knowns", knowns.len());
-            for (versions, known) in knowns.iter_mut() {
-                match known {
-                    KnownDbVersion::Partial { .. } => {
-                        continue;
-                    }
-                    KnownDbVersion::Current(CurrentVersion {
-                        db_version,
-                        last_seq,
-                        ts,
-                    }) => {
-                        count += 1;
-                        let version = versions.start();
-                        debug!(%actor_id, self_actor_id = %agent.actor_id(), %version, "inserting bookkeeping row db_version: {db_version}, ts: {ts:?}");
-                        tx.prepare_cached("
-                            INSERT INTO __corro_bookkeeping ( actor_id,  start_version,  db_version,  last_seq,  ts)
-                                                    VALUES  (:actor_id, :start_version, :db_version, :last_seq, :ts);")?
-                            .execute(named_params!{
-                                ":actor_id": actor_id,
-                                ":start_version": *version,
-                                ":db_version": *db_version,
-                                ":last_seq": *last_seq,
-                                ":ts": *ts
-                            })?;
-                    }
-                    KnownDbVersion::Cleared => {
-                        debug!(%actor_id, self_actor_id = %agent.actor_id(), ?versions, "inserting CLEARED bookkeeping");
-                        if let Err(e) = agent.tx_empty().try_send((*actor_id, versions.clone())) {
-                            error!("could not schedule version to be cleared: {e}");
-                        }
-                    }
-                }
-                debug!(%actor_id, self_actor_id = %agent.actor_id(), ?versions, "inserted bookkeeping row");
-            }
-        }
-
-        debug!("inserted {count} new changesets");
-
-        tx.commit()?;
-
-        debug!("committed {count} changes in {:?}", start.elapsed());
+    let (changesets, knowns) =
+        retry_busy(|| block_in_place(|| apply_unknown_changes(agent, &mut conn, &unknown_changes)))
+            .await?;
 
+    block_in_place(|| {
         for (actor_id, knowns) in knowns {
             let booked = {
                 bookie
@@ -2033,32 +2981,236 @@ pub async fn process_multiple_changes(
                 booked_write.insert_many(versions, known);
             }
         }
+    });
 
-        Ok::<_, ChangeError>(changesets)
-    })?;
+    let mut schema_changed = false;
 
-    for (actor_id, changeset, db_version, src) in changesets {
+    for (actor_id, changeset, db_version, src, complete) in changesets {
         agent
             .subs_manager()
             .match_changes(changeset.changes(), db_version);
 
-        if matches!(src, ChangeSource::Broadcast) && !changeset.is_empty() {
-            if let Err(_e) =
-                agent
-                    .tx_bcast()
-                    .try_send(BroadcastInput::Rebroadcast(BroadcastV1::Change(ChangeV1 {
-                        actor_id,
-                        changeset,
-                    })))
-            {
-                debug!("broadcasts are full or done!");
-            }
+        schema_changed |= changeset
+            .changes()
+            .iter()
+            .any(|change| change.table.as_str() == SCHEMA_CHANGES_TABLE);
+
+        // peers would book a partial version as complete
+        if complete && matches!(src, ChangeSource::Broadcast) && !changeset.is_empty() {
+            try_send_broadcast(
+                agent.tx_bcast(),
+                BroadcastInput::Rebroadcast(BroadcastV1::Change(ChangeV1 {
+                    actor_id,
+                    changeset,
+                })),
+                "outgoing",
+            );
         }
     }
 
+    if schema_changed {
+        notify_schema_change(agent);
+    }
+
     Ok(())
 }
 
+type AppliedChanges = (
+    Vec<(ActorId, Changeset, CrsqlDbVersion, ChangeSource, bool)>,
+    BTreeMap<ActorId, Vec<(RangeInclusive<Version>, KnownDbVersion)>>,
+);
+
+/// Applies changes in a single transaction, returning the changesets that were
+/// applied and what to book for them once committed. Nothing is booked in
+/// memory here, so a failed attempt can be retried as a whole.
+fn apply_unknown_changes(
+    agent: &Agent,
+    conn: &mut CrConn,
+    unknown_changes: &[(ChangeV1, ChangeSource)],
+) -> Result<AppliedChanges, ChangeError> {
+    let bookie = agent.bookie();
+    let start = Instant::now();
+    let tx = conn.immediate_transaction()?;
+
+    let mut knowns: BTreeMap<ActorId, Vec<_>> = BTreeMap::new();
+    let mut changesets = vec![];
+
+    let mut last_db_version = None;
+
+    for (actor_id, changes) in unknown_changes
+        .iter()
+        .group_by(|(change, _src)| change.actor_id)
+        .into_iter()
+    {
+        // get a lock on the actor id's booked writer if we didn't already
+        {
+            let booked = {
+                bookie
+                    .blocking_write(format!(
+                        "process_multiple_changes(for_actor_blocking):{}",
+                        actor_id.as_simple()
+                    ))
+                    .for_actor(actor_id)
+            };
+            let booked_write = booked.blocking_write(format!(
+                "process_multiple_changes(booked writer):{}",
+                actor_id.as_simple()
+            ));
+
+            let mut seen = RangeInclusiveMap::new();
+
+            for (change, src) in changes {
+                trace!("handling a single changeset: {change:?}");
+                let seqs = change.seqs();
+                if booked_write.contains_all(change.versions(), change.seqs()) {
+                    trace!("previously unknown versions are now deemed known, aborting inserts");
+                    continue;
+                }
+
+                let versions = change.versions();
+
+                // check if we've seen this version here...
+                if versions.clone().all(|version| match seqs {
+                    Some(check_seqs) => match seen.get(&version) {
+                        Some(known) => match known {
+                            KnownDbVersion::Partial(PartialVersion { seqs, .. }) => {
+                                check_seqs.clone().all(|seq| seqs.contains(&seq))
+                            }
+                            KnownDbVersion::Current { .. } | KnownDbVersion::Cleared => true,
+                        },
+                        None => false,
+                    },
+                    None => seen.contains_key(&version),
+                }) {
+                    continue;
+                }
+
+                // optimizing this, insert later!
+                let known = if change.is_complete() && change.is_empty() {
+                    // we never want to block here
+                    if let Err(e) = agent.tx_empty().try_send((actor_id, change.versions())) {
+                        error!("could not send empty changed versions into channel: {e}");
+                    }
+
+                    KnownDbVersion::Cleared
+                } else {
+                    if let Some(seqs) = change.seqs() {
+                        if seqs.end() < seqs.start() {
+                            warn!(%actor_id, versions = ?change.versions(), "received an invalid change, seqs start is greater than seqs end: {seqs:?}");
+                            continue;
+                        }
+                    }
+
+                    let (known, versions) = match process_single_version(
+                        agent,
+                        &tx,
+                        last_db_version,
+                        change.clone(),
+                    ) {
+                        Ok((known, changeset, rejected)) => {
+                            let versions = changeset.versions();
+                            if let KnownDbVersion::Current(CurrentVersion { db_version, .. }) =
+                                &known
+                            {
+                                // never book garbage, abort the whole batch instead
+                                check_db_version(last_db_version, db_version.0 as i64)?;
+                                last_db_version = Some(*db_version);
+                                changesets.push((
+                                    actor_id,
+                                    changeset,
+                                    *db_version,
+                                    *src,
+                                    rejected == 0,
+                                ));
+                            }
+                            if rejected > 0 {
+                                warn!(%actor_id, ?versions, "rejected {rejected} changes, booking the version without them");
+                            }
+                            (known, versions)
+                        }
+                        Err(e) => {
+                            error!(%actor_id, ?versions, "could not process single change: {e}");
+                            continue;
+                        }
+                    };
+                    debug!(%actor_id, self_actor_id = %agent.actor_id(), ?versions, "got known to insert: {known:?}");
+                    known
+                };
+
+                seen.insert(versions.clone(), known.clone());
+                knowns.entry(actor_id).or_default().push((versions, known));
+            }
+        }
+    }
+
+    let mut count = 0;
+
+    for (actor_id, knowns) in knowns.iter_mut() {
+        debug!(%actor_id, self_actor_id = %agent.actor_id(), "processing {} knowns", knowns.len());
+        for (versions, known) in knowns.iter_mut() {
+            match known {
+                KnownDbVersion::Partial { .. } => {
+                    continue;
+                }
+                KnownDbVersion::Current(current) => {
+                    count += 1;
+                    let version = versions.start();
+                    debug!(%actor_id, self_actor_id = %agent.actor_id(), %version, "inserting bookkeeping row db_version: {}, ts: {:?}", current.db_version, current.ts);
+                    book_current_version(&tx, *actor_id, *version, current)?;
+                }
+                KnownDbVersion::Cleared => {
+                    debug!(%actor_id, self_actor_id = %agent.actor_id(), ?versions, "inserting CLEARED bookkeeping");
+                    if let Err(e) = agent.tx_empty().try_send((*actor_id, versions.clone())) {
+                        error!("could not schedule version to be cleared: {e}");
+                    }
+                }
+            }
+            debug!(%actor_id, self_actor_id = %agent.actor_id(), ?versions, "inserted bookkeeping row");
+        }
+    }
+
+    debug!("inserted {count} new changesets");
+
+    tx.commit()?;
+
+    debug!("committed {count} changes in {:?}", start.elapsed());
+
+    Ok((changesets, knowns))
+}
+
+const BUSY_RETRY_MAX_ATTEMPTS: u32 = 5;
+const BUSY_RETRY_BACKOFF: Duration = Duration::from_millis(5);
+
+fn is_busy_error(e: &rusqlite::Error) -> bool {
+    matches!(
+        e.sqlite_error_code(),
+        Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+    )
+}
+
+/// Runs a whole write attempt, from `BEGIN IMMEDIATE` through `COMMIT`,
+/// retrying it a few times with a linear backoff if the database is busy or
+/// locked by another connection. The backoff doesn't block the worker thread.
+async fn retry_busy<T, F>(mut attempt_fn: F) -> Result<T, ChangeError>
+where
+    F: FnMut() -> Result<T, ChangeError>,
+{
+    let mut attempt = 1;
+    loop {
+        match attempt_fn() {
+            Err(ChangeError::Rusqlite(e))
+                if is_busy_error(&e) && attempt < BUSY_RETRY_MAX_ATTEMPTS =>
+            {
+                warn!("database busy, retrying transaction (attempt {attempt}): {e}");
+                increment_counter!("corro.process_msg.busy.retry");
+                tokio::time::sleep(BUSY_RETRY_BACKOFF * attempt).await;
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
 #[tracing::instrument(skip(tx, parts), err)]
 fn process_incomplete_version(
     tx: &Transaction,
@@ -2150,6 +3302,10 @@ fn process_incomplete_version(
     }))
 }
 
+// applies a complete version, along with the number of changes that were
+// rejected. the version is booked regardless: a change that can't apply now
+// (unknown table, failing CHECK, ...) would otherwise be requested again by
+// every sync, forever.
 #[tracing::instrument(skip(tx, last_db_version, parts), err)]
 fn process_complete_version(
     tx: &Transaction,
@@ -2157,7 +3313,7 @@ fn process_complete_version(
     last_db_version: Option<CrsqlDbVersion>,
     versions: RangeInclusive<Version>,
     parts: ChangesetParts,
-) -> rusqlite::Result<(KnownDbVersion, Changeset)> {
+) -> rusqlite::Result<(KnownDbVersion, Changeset, usize)> {
     let ChangesetParts {
         version,
         changes,
@@ -2189,18 +3345,30 @@ fn process_complete_version(
         .prepare_cached("SELECT CASE WHEN COALESCE(?, crsql_db_version()) >= ? THEN crsql_next_db_version(crsql_next_db_version() + 1) END")?
         .query_row(params![last_db_version, max_db_version], |_row| Ok(()))?;
 
+    // prepare these once, outside of the loop, there might be a lot of changes to go through
+    let mut insert_change = tx.prepare_cached(
+        r#"
+            INSERT INTO crsql_changes
+                ("table", pk, cid, val, col_version, db_version, site_id, cl, seq)
+            VALUES
+                (?,       ?,  ?,   ?,   ?,           ?,          ?,       ?,  ?)
+        "#,
+    )?;
+    let mut select_rows_impacted = tx.prepare_cached("SELECT crsql_rows_impacted()")?;
+    let mut savepoint = tx.prepare_cached("SAVEPOINT corro_change")?;
+    let mut rollback = tx.prepare_cached("ROLLBACK TO corro_change")?;
+    let mut release = tx.prepare_cached("RELEASE corro_change")?;
+
+    let mut rejected = 0;
+
     for change in changes {
         trace!("inserting change! {change:?}");
 
-        tx.prepare_cached(
-            r#"
-                INSERT INTO crsql_changes
-                    ("table", pk, cid, val, col_version, db_version, site_id, cl, seq)
-                VALUES
-                    (?,       ?,  ?,   ?,   ?,           ?,          ?,       ?,  ?)
-            "#,
-        )?
-        .execute(params![
+        // a single bad change (unknown table after a schema divergence, bad
+        // site_id, ...) must not cost us every other change in the version,
+        // the savepoint undoes whatever it managed to write before failing
+        savepoint.execute([])?;
+        let res = insert_change.execute(params![
             change.table.as_str(),
             change.pk,
             change.cid.as_str(),
@@ -2211,10 +3379,19 @@ fn process_complete_version(
             change.cl,
             // increment the seq by the start_seq or else we'll have multiple change rows with the same seq
             change.seq,
-        ])?;
-        let rows_impacted: i64 = tx
-            .prepare_cached("SELECT crsql_rows_impacted()")?
-            .query_row((), |row| row.get(0))?;
+        ]);
+        if let Err(e) = res {
+            rollback.execute([])?;
+            release.execute([])?;
+            debug!(%actor_id, %version, table = %change.table.as_str(), pk = ?change.pk, "rejected change: {e}");
+            increment_counter!("corro.process_msg.change.rejected", "table" => change.table.to_string());
+            rejected += 1;
+            continue;
+        }
+        release.execute([])?;
+        // crsql_rows_impacted is cumulative for the transaction, so a change
+        // had an impact if it went up
+        let rows_impacted: i64 = select_rows_impacted.query_row((), |row| row.get(0))?;
 
         if rows_impacted > last_rows_impacted {
             trace!("inserted the change into crsql_changes");
@@ -2257,7 +3434,7 @@ fn process_complete_version(
         counter!("corro.changes.committed", count, "table" => table_name.to_string(), "source" => "remote");
     }
 
-    Ok::<_, rusqlite::Error>((known_version, new_changeset))
+    Ok::<_, rusqlite::Error>((known_version, new_changeset, rejected))
 }
 
 fn check_buffered_meta_to_clear(
@@ -2273,13 +3450,13 @@ fn check_buffered_meta_to_clear(
     conn.prepare_cached("SELECT EXISTS(SELECT 1 FROM __corro_seq_bookkeeping WHERE site_id = ? AND version >= ? AND version <= ?)")?.query_row(params![actor_id, versions.start(), versions.end()], |row| row.get(0))
 }
 
-#[tracing::instrument(skip_all, err)]
+#[tracing::instrument(skip_all, fields(actor_id = %change.actor_id, versions = ?change.versions()), err)]
 fn process_single_version(
     agent: &Agent,
     tx: &Transaction,
     last_db_version: Option<CrsqlDbVersion>,
     change: ChangeV1,
-) -> rusqlite::Result<(KnownDbVersion, Changeset)> {
+) -> rusqlite::Result<(KnownDbVersion, Changeset, usize)> {
     let ChangeV1 {
         actor_id,
         changeset,
@@ -2287,8 +3464,8 @@ fn process_single_version(
 
     let versions = changeset.versions();
 
-    let (known, changeset) = if changeset.is_complete() {
-        let (known, changeset) = process_complete_version(
+    let (known, changeset, rejected) = if changeset.is_complete() {
+        let (known, changeset, rejected) = process_complete_version(
             tx,
             actor_id,
             last_db_version,
@@ -2307,15 +3484,15 @@ fn process_single_version(
             }
         }
 
-        (known, changeset)
+        (known, changeset, rejected)
     } else {
         let parts = changeset.into_parts().unwrap();
         let known = process_incomplete_version(tx, actor_id, &parts)?;
 
-        (known, parts.into())
+        (known, parts.into(), 0)
     };
 
-    Ok((known, changeset))
+    Ok((known, changeset, rejected))
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -2369,8 +3546,32 @@ pub enum SyncRecvError {
     RequestsChannelClosed,
 }
 
-#[tracing::instrument(skip_all, err, level = "debug")]
-async fn handle_sync(agent: &Agent, transport: &Transport) -> Result<(), SyncClientError> {
+// members we haven't measured yet are treated as being on the far side
+const UNKNOWN_RTT_MS: u64 = 200;
+
+/// Weight of a sync candidate when picking who to sync with, inversely
+/// proportional to its round-trip time.
+fn sync_candidate_weight(rtt_ms: Option<u64>) -> f64 {
+    1.0 / (rtt_ms.unwrap_or(UNKNOWN_RTT_MS) + 1) as f64
+}
+
+/// How many members to sync with, and how many to consider before picking
+/// them, out of `candidates` members.
+fn sync_counts(candidates: usize, candidate_pool: Option<usize>) -> (usize, usize) {
+    let desired_count = cmp::max(cmp::min(candidates / 100, 10), 3);
+    match candidate_pool {
+        // can't sync with more members than we considered
+        Some(pool_size) => (cmp::min(desired_count, pool_size), pool_size),
+        None => (desired_count, desired_count * 2),
+    }
+}
+
+#[tracing::instrument(skip_all, fields(actor_id = %agent.actor_id()), err, level = "debug")]
+async fn handle_sync(
+    agent: &Agent,
+    transport: &Transport,
+    tripwire: Tripwire,
+) -> Result<usize, SyncClientError> {
     let sync_state = generate_sync(agent.bookie(), agent.actor_id()).await;
 
     for (actor_id, needed) in sync_state.need.iter() {
@@ -2382,29 +3583,54 @@ async fn handle_sync(agent: &Agent, transport: &Transport) -> Result<(), SyncCli
 
     let chosen: Vec<(ActorId, SocketAddr)> = {
         let candidates = {
+            let config = agent.config();
             let members = agent.members().read();
 
             members
                 .states
                 .iter()
                 .filter(|(id, _state)| **id != agent.actor_id())
-                .map(|(id, state)| (*id, state.ring.unwrap_or(255), state.addr))
-                .collect::<Vec<(ActorId, u8, SocketAddr)>>()
+                .filter(|(_id, state)| {
+                    let allowed = config.gossip.is_peer_allowed(state.addr.ip());
+                    if !allowed {
+                        increment_counter!("corro.peer.denied", "type" => "sync");
+                    }
+                    allowed
+                })
+                .map(|(id, state)| {
+                    (
+                        *id,
+                        state.ring.unwrap_or(255),
+                        state.addr,
+                        sync_candidate_weight(members.avg_rtt(&state.addr)),
+                    )
+                })
+                .collect::<Vec<(ActorId, u8, SocketAddr, f64)>>()
         };
 
         if candidates.is_empty() {
-            return Ok(());
+            return Ok(0);
         }
 
         debug!("found {} candidates to synchronize with", candidates.len());
 
-        let desired_count = cmp::max(cmp::min(candidates.len() / 100, 10), 3);
+        let (desired_count, pool_size) =
+            sync_counts(candidates.len(), agent.config().sync.candidate_pool);
 
-        let mut rng = StdRng::from_entropy();
+        let mut rng = agent.rng();
 
-        let mut choices = candidates
-            .into_iter()
-            .choose_multiple(&mut rng, desired_count * 2);
+        // favor closer members, while still giving a chance to farther ones
+        let mut choices: Vec<(ActorId, u8, SocketAddr)> =
+            match candidates.choose_multiple_weighted(&mut *rng, pool_size, |c| c.3) {
+                Ok(choices) => choices.map(|c| (c.0, c.1, c.2)).collect(),
+                Err(e) => {
+                    warn!("could not choose sync candidates by rtt: {e}");
+                    candidates
+                        .into_iter()
+                        .map(|c| (c.0, c.1, c.2))
+                        .choose_multiple(&mut *rng, pool_size)
+                }
+            };
 
         choices.sort_by(|a, b| {
             // most missing actors first
@@ -2423,11 +3649,11 @@ async fn handle_sync(agent: &Agent, transport: &Transport) -> Result<(), SyncCli
     };
 
     if chosen.is_empty() {
-        return Ok(());
+        return Ok(0);
     }
 
     let start = Instant::now();
-    let n = parallel_sync(agent, transport, chosen.clone(), sync_state).await?;
+    let n = parallel_sync(agent, transport, chosen.clone(), sync_state, tripwire).await?;
 
     let elapsed = start.elapsed();
     if n > 0 {
@@ -2442,11 +3668,9 @@ async fn handle_sync(agent: &Agent, transport: &Transport) -> Result<(), SyncCli
             n as f64 / elapsed.as_secs_f64()
         );
     }
-    Ok(())
+    Ok(n)
 }
 
-const MIN_CHANGES_CHUNK: usize = 1000;
-
 async fn handle_changes(
     agent: Agent,
     mut rx_changes: Receiver<(ChangeV1, ChangeSource)>,
@@ -2463,7 +3687,7 @@ async fn handle_changes(
                 counter!("corro.agent.changes.recv", std::cmp::max(change.len(), 1) as u64); // count empties...
                 count += change.len(); // don't count empties
                 buf.push((change, src));
-                if count < MIN_CHANGES_CHUNK {
+                if count < agent.config().sync.apply_chunk_size {
                     continue;
                 }
             },
@@ -2481,6 +3705,8 @@ async fn handle_changes(
             }
         }
 
+        gauge!("corro.agent.changes.apply.chunk", count as f64);
+
         // drain and process current changes!
         #[allow(clippy::drain_collect)]
         if let Err(e) = process_multiple_changes(&agent, buf.drain(..).collect()).await {
@@ -2499,7 +3725,7 @@ async fn handle_changes(
         counter!("corro.agent.changes.recv", changes_count as u64);
         count += changes_count;
         buf.push((change, src));
-        if count >= MIN_CHANGES_CHUNK {
+        if count >= agent.config().sync.apply_chunk_size {
             // drain and process current changes!
             #[allow(clippy::drain_collect)]
             if let Err(e) = process_multiple_changes(&agent, buf.drain(..).collect()).await {
@@ -2517,6 +3743,47 @@ async fn handle_changes(
     }
 }
 
+const PERSIST_CLOCK_INTERVAL: Duration = Duration::from_secs(1);
+/// How far ahead of the clock the persisted timestamp is, so it stays ahead
+/// of every timestamp issued until the next persist, even if we crash before
+const PERSIST_CLOCK_AHEAD: Duration = Duration::from_secs(2);
+/// How long startup waits for the wall clock to pass the persisted timestamp
+const MAX_CLOCK_WAIT: Duration = Duration::from_secs(30);
+
+async fn persist_clock(agent: &Agent) -> eyre::Result<()> {
+    let conn = agent.pool().write_low().await?;
+    let ts = Timestamp::from(
+        *agent.clock().new_timestamp().get_time() + uhlc::NTP64::from(PERSIST_CLOCK_AHEAD),
+    );
+    block_in_place(|| {
+        conn.prepare_cached(
+            "INSERT INTO __corro_clock (id, ts) VALUES (0, ?) ON CONFLICT (id) DO UPDATE SET ts = excluded.ts",
+        )?
+        .execute([ts])
+    })?;
+    Ok(())
+}
+
+async fn persist_clock_loop(agent: Agent, mut tripwire: Tripwire) {
+    let mut persist_interval = tokio::time::interval(PERSIST_CLOCK_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = persist_interval.tick() => {},
+            _ = &mut tripwire => break,
+        }
+
+        if let Err(e) = persist_clock(&agent).await {
+            error!("could not persist clock: {e}");
+        }
+    }
+
+    info!("Persisting clock before shut down");
+    if let Err(e) = persist_clock(&agent).await {
+        error!("could not persist clock: {e}");
+    }
+}
+
 const CHECK_EMPTIES_TO_INSERT_AFTER: Duration = Duration::from_secs(120);
 
 async fn write_empties_loop(
@@ -2618,16 +3885,68 @@ async fn clear_buffered_meta_loop(
     }
 }
 
+async fn handle_sync_ranges(
+    agent: Agent,
+    transport: Transport,
+    mut rx_sync_range: Receiver<SyncRangeRequest>,
+    mut tripwire: Tripwire,
+) {
+    loop {
+        let req = tokio::select! {
+            req = rx_sync_range.recv() => match req {
+                Some(req) => req,
+                None => break,
+            },
+            _ = &mut tripwire => break,
+        };
+
+        let agent = agent.clone();
+        let transport = transport.clone();
+        spawn_counted(async move {
+            let res = range_sync(&agent, &transport, req.peer, req.actor_id, req.versions)
+                .await
+                .map_err(|e| e.to_string());
+            if let Err(ref e) = res {
+                error!("could not sync range from {}: {e}", req.peer);
+            }
+            _ = req.res_tx.send(res);
+        });
+    }
+
+    debug!("sync ranges loop is done");
+}
+
+// syncs requested out of band, they don't affect the sync loop's backoff
+async fn handle_sync_now(
+    agent: Agent,
+    transport: Transport,
+    mut rx_sync_now: Receiver<SyncNowRequest>,
+    tripwire: Tripwire,
+) {
+    while let Some(req) = rx_sync_now.recv().await {
+        let res = handle_sync(&agent, &transport, tripwire.clone())
+            .await
+            .map_err(|e| e.to_string());
+        if let Err(ref e) = res {
+            error!("could not sync on demand: {e}");
+        }
+        _ = req.res_tx.send(res);
+    }
+}
+
 async fn sync_loop(
     agent: Agent,
     transport: Transport,
     mut rx_apply: Receiver<(ActorId, Version)>,
+    min_sync_interval: Duration,
+    max_sync_interval: Duration,
     mut tripwire: Tripwire,
 ) {
+    // we're constantly getting broadcasts randomly + targetted, so the upper bound can stay low
     let mut sync_backoff = backoff::Backoff::new(0)
-        .timeout_range(Duration::from_secs(1), MAX_SYNC_BACKOFF)
+        .timeout_range(min_sync_interval, max_sync_interval)
         .iter();
-    let next_sync_at = tokio::time::sleep(sync_backoff.next().unwrap());
+    let next_sync_at = tokio::time::sleep(min_sync_interval);
     tokio::pin!(next_sync_at);
 
     loop {
@@ -2658,21 +3977,20 @@ async fn sync_loop(
         match branch {
             Branch::Tick => {
                 // ignoring here, there is trying and logging going on inside
-                match handle_sync(&agent, &transport)
-                    .preemptible(&mut tripwire)
-                    .await
-                {
-                    tripwire::Outcome::Preempted(_) => {
-                        warn!("aborted sync by tripwire");
-                        break;
-                    }
-                    tripwire::Outcome::Completed(res) => {
-                        if let Err(e) = res {
-                            error!("could not sync: {e}");
-                            // keep syncing until we successfully sync
-                            continue;
-                        }
-                    }
+                let res = handle_sync(&agent, &transport, tripwire.clone()).await;
+                // the sync stops receiving changes as soon as the wire trips
+                if tripwire.is_shutting_down() {
+                    warn!("aborted sync by tripwire");
+                    break;
+                }
+                if let Err(e) = res {
+                    error!("could not sync: {e}");
+                    // keep syncing until we successfully sync
+                    continue;
+                }
+                if !agent.is_ready() {
+                    info!("completed first sync, agent is ready");
+                    agent.set_ready();
                 }
                 next_sync_at
                     .as_mut()
@@ -2696,18 +4014,172 @@ async fn sync_loop(
     }
 }
 
-#[tracing::instrument(skip_all, err)]
-async fn process_completed_empties(
-    agent: Agent,
-    empties: BTreeMap<ActorId, RangeInclusiveSet<Version>>,
-) -> eyre::Result<()> {
-    debug!(
-        "processing empty versions (count: {})",
-        empties.values().map(RangeInclusiveSet::len).sum::<usize>()
-    );
-
-    let mut inserted = 0;
-
+// finds holes in between versions we know about, for every actor but ourselves
+async fn find_version_gaps(agent: &Agent) -> HashMap<ActorId, RangeInclusiveSet<Version>> {
+    let actors: Vec<(ActorId, Booked)> = {
+        agent
+            .bookie()
+            .read("find_version_gaps")
+            .await
+            .iter()
+            .filter(|(actor_id, _)| **actor_id != agent.actor_id())
+            .map(|(k, v)| (*k, v.clone()))
+            .collect()
+    };
+
+    let mut gaps = HashMap::new();
+    for (actor_id, booked) in actors {
+        let booked = booked
+            .read(format!("find_version_gaps:{}", actor_id.as_simple()))
+            .await;
+
+        // only holes below a version we have in full, the rest of a
+        // partially received version might still be on its way
+        let max_booked = cmp::max(
+            booked.current.last_key_value().map(|(version, _)| *version),
+            booked.cleared.iter().last().map(|versions| *versions.end()),
+        );
+        let max_booked = match max_booked {
+            Some(max_booked) => max_booked,
+            None => continue,
+        };
+
+        let need: RangeInclusiveSet<Version> = booked
+            .sync_need()
+            .iter()
+            .filter(|versions| *versions.start() < max_booked)
+            .map(|versions| *versions.start()..=cmp::min(*versions.end(), max_booked - 1))
+            .collect();
+        if !need.is_empty() {
+            gaps.insert(actor_id, need);
+        }
+    }
+
+    gaps
+}
+
+// at most this many versions are requested per actor on each tick, what's
+// left is picked up on the next ones
+const MAX_GAP_VERSIONS_PER_TICK: u64 = 100;
+
+fn cap_gap_ranges(ranges: Vec<RangeInclusive<Version>>, max: u64) -> Vec<RangeInclusive<Version>> {
+    let mut left = max;
+    let mut capped = vec![];
+    for range in ranges {
+        if left == 0 {
+            break;
+        }
+        let len = range.end().0 - range.start().0 + 1;
+        if len <= left {
+            left -= len;
+            capped.push(range);
+        } else {
+            capped.push(*range.start()..=*range.start() + (left - 1));
+            left = 0;
+        }
+    }
+    capped
+}
+
+async fn gap_sync_loop(
+    agent: Agent,
+    transport: Transport,
+    gap_interval: Duration,
+    mut tripwire: Tripwire,
+) {
+    let mut interval = tokio::time::interval(gap_interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    // only request holes that were already there on the previous tick,
+    // the missing versions might just be in flight
+    let mut prev_gaps: HashMap<ActorId, RangeInclusiveSet<Version>> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {},
+            _ = &mut tripwire => break,
+        }
+
+        let gaps = find_version_gaps(&agent).await;
+
+        let mut stale: Vec<(ActorId, Vec<RangeInclusive<Version>>)> = vec![];
+        for (actor_id, need) in gaps.iter() {
+            let prev = match prev_gaps.get(actor_id) {
+                Some(prev) => prev,
+                None => continue,
+            };
+            let ranges: Vec<_> = need
+                .iter()
+                .flat_map(|range| {
+                    prev.overlapping(range).map(|overlap| {
+                        cmp::max(*range.start(), *overlap.start())
+                            ..=cmp::min(*range.end(), *overlap.end())
+                    })
+                })
+                .collect();
+            if !ranges.is_empty() {
+                stale.push((*actor_id, ranges));
+            }
+        }
+
+        prev_gaps = gaps;
+
+        for (actor_id, ranges) in stale {
+            let ranges = cap_gap_ranges(ranges, MAX_GAP_VERSIONS_PER_TICK);
+            let count: u64 = ranges.iter().map(|r| r.end().0 - r.start().0 + 1).sum();
+            counter!("corro.sync.gaps.detected", count, "actor_id" => actor_id.to_string());
+
+            // the actor itself has all of its versions, prefer it if it's around
+            let addr = {
+                let members = agent.members().read();
+                members.get(&actor_id).map(|state| state.addr).or_else(|| {
+                    members
+                        .states
+                        .iter()
+                        .filter(|(id, _)| **id != agent.actor_id())
+                        .map(|(_, state)| state.addr)
+                        .choose(&mut *agent.rng())
+                })
+            };
+
+            let addr = match addr {
+                Some(addr) => addr,
+                None => {
+                    debug!(%actor_id, "no member to request version gaps from");
+                    continue;
+                }
+            };
+
+            debug!(%actor_id, %addr, "requesting {count} missing versions: {ranges:?}");
+
+            for versions in ranges {
+                match range_sync(&agent, &transport, addr, actor_id, versions.clone())
+                    .preemptible(&mut tripwire)
+                    .await
+                {
+                    tripwire::Outcome::Preempted(_) => return,
+                    tripwire::Outcome::Completed(Err(e)) => {
+                        warn!(%actor_id, %addr, "could not sync version gap {versions:?}: {e}");
+                    }
+                    tripwire::Outcome::Completed(Ok(_)) => {}
+                }
+            }
+        }
+    }
+}
+
+#[tracing::instrument(skip_all, err)]
+async fn process_completed_empties(
+    agent: Agent,
+    empties: BTreeMap<ActorId, RangeInclusiveSet<Version>>,
+) -> eyre::Result<()> {
+    debug!(
+        "processing empty versions (count: {})",
+        empties.values().map(RangeInclusiveSet::len).sum::<usize>()
+    );
+
+    let mut inserted = 0;
+
     let start = Instant::now();
     for (actor_id, empties) in empties {
         let v = empties.into_iter().collect::<Vec<_>>();
@@ -2755,6 +4227,7 @@ pub mod tests {
     use std::{
         collections::HashMap,
         net::SocketAddr,
+        sync::atomic::Ordering,
         time::{Duration, Instant},
     };
 
@@ -2763,244 +4236,1867 @@ pub mod tests {
         distributions::Uniform, prelude::Distribution, rngs::StdRng, seq::IteratorRandom,
         SeedableRng,
     };
+    use rusqlite::TransactionBehavior;
     use serde::Deserialize;
     use serde_json::json;
     use spawn::wait_for_all_pending_handles;
+    use speedy::Writable;
     use tokio::time::{sleep, timeout, MissedTickBehavior};
+    use tower::ServiceExt;
     use tracing::info_span;
     use tripwire::Tripwire;
 
     use super::*;
 
-    use corro_types::api::{ExecResponse, ExecResult, Statement};
+    use corro_types::api::{Change, ExecResponse, ExecResult, Statement};
+    use corro_types::config::{ConfigError, TlsClientConfig, TlsConfig, DEFAULT_ANNOUNCE_FANOUT};
 
     use corro_tests::*;
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
-    async fn insert_rows_and_gossip() -> eyre::Result<()> {
+    async fn test_api_bearer_authz() -> eyre::Result<()> {
         _ = tracing_subscriber::fmt::try_init();
-        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
-        let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
-        let ta2 = launch_test_agent(
-            |conf| {
-                conf.bootstrap(vec![ta1.agent.gossip_addr().to_string()])
-                    .build()
-            },
-            tripwire.clone(),
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let mut conf = Config::builder()
+            .db_path(dir.path().join("corrosion.db").display().to_string())
+            .gossip_addr("127.0.0.1:0".parse()?)
+            .api_addr("127.0.0.1:0".parse()?)
+            .build()?;
+        conf.api.authorization = Some(AuthzConfig::BearerToken("s3cr3t".into()));
+
+        let (agent, _agent_options) = setup(conf, tripwire).await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![TEST_SCHEMA.into()]),
         )
-        .await?;
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
 
-        let client = hyper::Client::builder()
-            .pool_max_idle_per_host(5)
-            .pool_idle_timeout(Duration::from_secs(300))
-            .build_http::<hyper::Body>();
+        let api = Router::new()
+            .route("/v1/transactions", post(api_v1_transactions))
+            .layer(axum::middleware::from_fn(require_authz))
+            .layer(Extension(agent.clone()));
 
         let req_body: Vec<Statement> = serde_json::from_value(json!([[
             "INSERT INTO tests (id,text) VALUES (?,?)",
             [1, "hello world 1"]
         ],]))?;
 
-        let res = timeout(
-            Duration::from_secs(5),
-            client.request(
-                hyper::Request::builder()
-                    .method(hyper::Method::POST)
-                    .uri(format!("http://{}/v1/transactions", ta1.agent.api_addr()))
-                    .header(hyper::header::CONTENT_TYPE, "application/json")
-                    .body(serde_json::to_vec(&req_body)?.into())?,
-            ),
-        )
-        .await??;
+        let request = |token: Option<&str>| {
+            let mut builder = hyper::Request::builder()
+                .method(hyper::Method::POST)
+                .uri("/v1/transactions")
+                .header(hyper::header::CONTENT_TYPE, "application/json");
+            if let Some(token) = token {
+                builder = builder.header(hyper::header::AUTHORIZATION, format!("Bearer {token}"));
+            }
+            builder.body(hyper::Body::from(serde_json::to_vec(&req_body).unwrap()))
+        };
 
-        let body: ExecResponse =
-            serde_json::from_slice(&hyper::body::to_bytes(res.into_body()).await?)?;
+        for token in [None, Some("wrong"), Some("s3cr3t-but-longer")] {
+            let res = api.clone().oneshot(request(token)?).await?;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
 
-        let db_version: CrsqlDbVersion =
-            ta1.agent
+            let body: ExecResponse =
+                serde_json::from_slice(&hyper::body::to_bytes(res.into_body()).await?)?;
+            assert!(matches!(
+                body.results.as_slice(),
+                [ExecResult::Error { .. }]
+            ));
+        }
+
+        let res = api.clone().oneshot(request(Some("s3cr3t"))?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let count: i64 =
+            agent
                 .pool()
                 .read()
                 .await?
-                .query_row("SELECT crsql_db_version();", (), |row| row.get(0))?;
-        assert_eq!(db_version, CrsqlDbVersion(1));
-
-        println!("body: {body:?}");
-
-        #[derive(Debug, Deserialize)]
-        struct TestRecord {
-            id: i64,
-            text: String,
-        }
+                .query_row("SELECT COUNT(*) FROM tests", (), |row| row.get(0))?;
+        assert_eq!(count, 1);
 
-        let svc: TestRecord = ta1.agent.pool().read().await?.query_row(
-            "SELECT id, text FROM tests WHERE id = 1;",
-            [],
-            |row| {
-                Ok(TestRecord {
-                    id: row.get(0)?,
-                    text: row.get(1)?,
-                })
-            },
-        )?;
+        Ok(())
+    }
 
-        assert_eq!(svc.id, 1);
-        assert_eq!(svc.text, "hello world 1");
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn api_over_unix_socket() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
 
-        sleep(Duration::from_secs(1)).await;
+        let dir = tempfile::tempdir()?;
+        let uds_path = dir.path().join("api.sock");
 
-        let svc: TestRecord = ta2.agent.pool().read().await?.query_row(
-            "SELECT id, text FROM tests WHERE id = 1;",
-            [],
-            |row| {
-                Ok(TestRecord {
-                    id: row.get(0)?,
-                    text: row.get(1)?,
-                })
+        let ta = launch_test_agent(
+            |conf| {
+                let mut conf = conf.build()?;
+                conf.api.uds_path = Some(uds_path.display().to_string().into());
+                Ok(conf)
             },
-        )?;
-
-        assert_eq!(svc.id, 1);
-        assert_eq!(svc.text, "hello world 1");
+            tripwire,
+        )
+        .await?;
 
-        let req_body: Vec<Statement> = serde_json::from_value(json!([[
-            "INSERT INTO tests (id,text) VALUES (?,?)",
-            [2, "hello world 2"]
-        ]]))?;
+        let stream = tokio::net::UnixStream::connect(&uds_path).await?;
+        let (mut sender, conn) = hyper::client::conn::handshake(stream).await?;
+        tokio::spawn(conn);
 
-        let res = client
-            .request(
+        let res = sender
+            .send_request(
                 hyper::Request::builder()
-                    .method(hyper::Method::POST)
-                    .uri(format!("http://{}/v1/transactions", ta1.agent.api_addr()))
-                    .header(hyper::header::CONTENT_TYPE, "application/json")
-                    .body(serde_json::to_vec(&req_body)?.into())?,
+                    .uri("/v1/health")
+                    .body(hyper::Body::empty())?,
             )
             .await?;
+        assert_eq!(res.status(), StatusCode::OK);
 
-        let body: ExecResponse =
+        let body: serde_json::Value =
             serde_json::from_slice(&hyper::body::to_bytes(res.into_body()).await?)?;
+        assert_eq!(body["actor_id"], json!(ta.agent.actor_id()));
 
-        println!("body: {body:?}");
+        drop(sender);
 
-        #[allow(clippy::type_complexity)]
-        let bk: Vec<(ActorId, Version, Option<Version>, CrsqlDbVersion, Option<CrsqlSeq>)> = ta1
-            .agent
-            .pool()
-            .read()
-            .await?
-            .prepare("SELECT actor_id, start_version, end_version, db_version, last_seq FROM __corro_bookkeeping")?
-            .query_map((), |row| {
-                Ok((
-                    row.get::<_, ActorId>(0)?,
-                    row.get::<_, Version>(1)?,
-                    row.get::<_, Option<Version>>(2)?,
-                    row.get::<_, CrsqlDbVersion>(3)?,
-                    row.get::<_, Option<CrsqlSeq>>(4)?,
-                ))
-            })?
-            .collect::<rusqlite::Result<_>>()?;
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
 
-        assert_eq!(
-            bk,
-            vec![
-                (
-                    ta1.agent.actor_id(),
-                    Version(1),
-                    None,
-                    CrsqlDbVersion(1),
-                    Some(CrsqlSeq(0))
-                ),
-                (
-                    ta1.agent.actor_id(),
-                    Version(2),
-                    None,
-                    CrsqlDbVersion(2),
-                    Some(CrsqlSeq(0))
-                )
-            ]
-        );
+        // the socket file is cleaned up on shutdown
+        assert!(!uds_path.exists());
 
-        let svc: TestRecord = ta1.agent.pool().read().await?.query_row(
-            "SELECT id, text FROM tests WHERE id = 2;",
-            [],
-            |row| {
-                Ok(TestRecord {
-                    id: row.get(0)?,
-                    text: row.get(1)?,
-                })
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn api_body_limit() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let ta = launch_test_agent(
+            |conf| {
+                let mut conf = conf.build()?;
+                conf.api.max_body_bytes = 1024;
+                Ok(conf)
             },
-        )?;
+            tripwire,
+        )
+        .await?;
 
-        assert_eq!(svc.id, 2);
-        assert_eq!(svc.text, "hello world 2");
+        let client: hyper::Client<_, hyper::Body> = hyper::Client::builder().build_http();
 
-        sleep(Duration::from_secs(1)).await;
+        let transaction = |text: String| -> eyre::Result<hyper::Request<hyper::Body>> {
+            Ok(hyper::Request::builder()
+                .method(hyper::Method::POST)
+                .uri(format!("http://{}/v1/transactions", ta.agent.api_addr()))
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .body(
+                    serde_json::to_vec(&json!([[
+                        "INSERT INTO tests (id,text) VALUES (?,?)",
+                        [1, text]
+                    ]]))?
+                    .into(),
+                )?)
+        };
 
-        let svc: TestRecord = ta2.agent.pool().read().await?.query_row(
-            "SELECT id, text FROM tests WHERE id = 2;",
-            [],
-            |row| {
-                Ok(TestRecord {
-                    id: row.get(0)?,
-                    text: row.get(1)?,
-                })
+        let res = client.request(transaction("a".repeat(2048))?).await?;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let res = client.request(transaction("a".repeat(512))?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn api_gzip_body() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let ta = launch_test_agent(
+            |conf| {
+                let mut conf = conf.build()?;
+                conf.api.max_body_bytes = 64 * 1024;
+                Ok(conf)
             },
-        )?;
+            tripwire,
+        )
+        .await?;
 
-        assert_eq!(svc.id, 2);
-        assert_eq!(svc.text, "hello world 2");
+        let client: hyper::Client<_, hyper::Body> = hyper::Client::builder().build_http();
 
-        let values: Vec<serde_json::Value> = (3..1000)
-            .map(|id| {
-                serde_json::json!([
+        let gzipped_request = |body: &[u8]| -> eyre::Result<hyper::Request<hyper::Body>> {
+            Ok(hyper::Request::builder()
+                .method(hyper::Method::POST)
+                .uri(format!("http://{}/v1/transactions", ta.agent.api_addr()))
+                .header(hyper::header::CONTENT_TYPE, "application/json")
+                .header(hyper::header::CONTENT_ENCODING, "gzip")
+                .body(crate::api::public::gzip::gzip(body).into())?)
+        };
+
+        let statements = (1..=100)
+            .map(|i| {
+                json!([
                     "INSERT INTO tests (id,text) VALUES (?,?)",
-                    [id, format!("hello world #{id}")],
+                    [i, "hello world"]
                 ])
             })
-            .collect();
+            .collect::<Vec<_>>();
 
-        let req_body: Vec<Statement> = serde_json::from_value(json!(values))?;
+        let res = client
+            .request(gzipped_request(&serde_json::to_vec(&statements)?)?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
 
-        timeout(
-            Duration::from_secs(5),
-            client.request(
-                hyper::Request::builder()
-                    .method(hyper::Method::POST)
-                    .uri(format!("http://{}/v1/transactions", ta1.agent.api_addr()))
+        let count: i64 =
+            ta.agent
+                .pool()
+                .read()
+                .await?
+                .query_row("SELECT COUNT(*) FROM tests", (), |row| row.get(0))?;
+        assert_eq!(count, 100);
+
+        // the limit applies to the decompressed size, which compresses very well here
+        let too_large = serde_json::to_vec(&json!([[
+            "INSERT INTO tests (id,text) VALUES (?,?)",
+            [101, "a".repeat(128 * 1024)]
+        ]]))?;
+        let res = client.request(gzipped_request(&too_large)?).await?;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn api_cors_preflight() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let ta = launch_test_agent(
+            |conf| {
+                let mut conf = conf.build()?;
+                conf.api.cors_allow_origins = vec!["https://dashboard.example".into()];
+                Ok(conf)
+            },
+            tripwire,
+        )
+        .await?;
+
+        // only enabled now, the test agent applies its schema through the API
+        let mut conf = ta.agent.config().as_ref().clone();
+        conf.api.authorization = Some(AuthzConfig::BearerToken("s3cr3t".into()));
+        ta.agent.set_config(conf);
+
+        let client: hyper::Client<_, hyper::Body> = hyper::Client::builder().build_http();
+
+        let preflight = |origin: &str| {
+            hyper::Request::builder()
+                .method(hyper::Method::OPTIONS)
+                .uri(format!("http://{}/v1/queries", ta.agent.api_addr()))
+                .header(hyper::header::ORIGIN, origin)
+                .header(hyper::header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+                .header(
+                    hyper::header::ACCESS_CONTROL_REQUEST_HEADERS,
+                    "content-type,authorization",
+                )
+                .body(hyper::Body::empty())
+        };
+
+        // answered without credentials
+        let res = client
+            .request(preflight("https://dashboard.example")?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers()
+                .get(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .unwrap(),
+            "https://dashboard.example"
+        );
+        let allow_methods = res
+            .headers()
+            .get(hyper::header::ACCESS_CONTROL_ALLOW_METHODS)
+            .unwrap()
+            .to_str()?;
+        assert!(allow_methods.contains("POST"));
+
+        let res = client.request(preflight("https://evil.example")?).await?;
+        assert!(res
+            .headers()
+            .get(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cors_wildcard() -> eyre::Result<()> {
+        assert!(cors_layer(&[]).is_none());
+
+        let api = Router::new()
+            .route("/v1/queries", post(|| async {}))
+            .layer(cors_layer(&["*".into()]).unwrap());
+
+        let res = api
+            .oneshot(
+                hyper::Request::builder()
+                    .method(hyper::Method::OPTIONS)
+                    .uri("/v1/queries")
+                    .header(hyper::header::ORIGIN, "https://anywhere.example")
+                    .header(hyper::header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+                    .body(hyper::Body::empty())?,
+            )
+            .await?;
+        assert_eq!(
+            res.headers()
+                .get(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .unwrap(),
+            "*"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn schema_paths_overlay() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+
+        // redefines the base `tests` table, and sorts before it by filename
+        let overlay = tempfile::tempdir()?;
+        tokio::fs::write(
+            overlay.path().join("a.sql"),
+            TEST_SCHEMA.replacen(
+                "text TEXT NOT NULL DEFAULT \"\"",
+                "text TEXT NOT NULL DEFAULT \"\",\n            extra TEXT",
+                1,
+            ),
+        )
+        .await?;
+
+        let ta = launch_test_agent(
+            |conf| {
+                conf.add_schema_path(overlay.path().display().to_string())
+                    .build()
+            },
+            tripwire,
+        )
+        .await?;
+
+        let statements = read_schema_paths(&ta.agent.config().db.schema_paths).await?;
+        assert_eq!(statements.len(), 2);
+        assert!(statements[1].contains("extra TEXT"));
+
+        // the overlay directory comes last, so its definition wins
+        assert!(ta.agent.schema().read().tables["tests"]
+            .columns
+            .contains_key("extra"));
+
+        reload_schema(&ta.agent).await?;
+        assert!(ta.agent.schema().read().tables["tests"]
+            .columns
+            .contains_key("extra"));
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn schema_paths_missing_or_empty() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let tmpdir = tempfile::tempdir()?;
+        let missing = Utf8PathBuf::try_from(tmpdir.path().join("missing/schema"))?;
+        let missing_file = Utf8PathBuf::try_from(tmpdir.path().join("missing.sql"))?;
+        let empty = Utf8PathBuf::try_from(tmpdir.path().join("empty"))?;
+        tokio::fs::create_dir(&empty).await?;
+
+        let statements =
+            read_schema_paths(&[missing.clone(), missing_file.clone(), empty.clone()]).await?;
+        assert!(statements.is_empty());
+
+        // the missing directory now exists, empty, but files aren't conjured up
+        assert!(tokio::fs::metadata(&missing).await?.is_dir());
+        assert!(tokio::fs::metadata(&missing_file).await.is_err());
+
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+
+        let later = Utf8PathBuf::try_from(tmpdir.path().join("later"))?;
+        let ta =
+            launch_test_agent(|conf| conf.add_schema_path(later.clone()).build(), tripwire).await?;
+
+        // the node starts, with a directory ready for schema files
+        assert!(tokio::fs::metadata(&later).await?.is_dir());
+        assert!(ta.agent.schema().read().tables.contains_key("tests"));
+
+        tokio::fs::write(
+            later.join("other.sql"),
+            "CREATE TABLE other (id INTEGER NOT NULL PRIMARY KEY) WITHOUT ROWID;",
+        )
+        .await?;
+        reload_schema(&ta.agent).await?;
+        assert!(ta.agent.schema().read().tables.contains_key("other"));
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn schema_reload() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+
+        let ta = launch_test_agent(|conf| conf.build(), tripwire).await?;
+
+        assert!(!ta.agent.schema().read().tables["tests"]
+            .columns
+            .contains_key("extra"));
+
+        // add a column to an existing table
+        tokio::fs::write(
+            ta.tmpdir.path().join("schema").join("tests.sql"),
+            TEST_SCHEMA.replacen(
+                "text TEXT NOT NULL DEFAULT \"\"",
+                "text TEXT NOT NULL DEFAULT \"\",\n            extra TEXT",
+                1,
+            ),
+        )
+        .await?;
+
+        // a broken file prevents the whole reload
+        tokio::fs::write(
+            ta.tmpdir.path().join("schema").join("broken.sql"),
+            "CREATE TABLE nope (",
+        )
+        .await?;
+        assert!(reload_schema(&ta.agent).await.is_err());
+        assert!(!ta.agent.schema().read().tables["tests"]
+            .columns
+            .contains_key("extra"));
+
+        tokio::fs::remove_file(ta.tmpdir.path().join("schema").join("broken.sql")).await?;
+        reload_schema(&ta.agent).await?;
+
+        assert!(ta.agent.schema().read().tables["tests"]
+            .columns
+            .contains_key("extra"));
+
+        let has_column: bool = ta.agent.pool().read().await?.query_row(
+            "SELECT EXISTS(SELECT 1 FROM pragma_table_info('tests') WHERE name = 'extra')",
+            [],
+            |row| row.get(0),
+        )?;
+        assert!(has_column);
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn schema_watch_reload() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+
+        let ta = launch_test_agent(
+            |conf| {
+                let mut conf = conf.build()?;
+                conf.db.watch_schema = true;
+                Ok(conf)
+            },
+            tripwire,
+        )
+        .await?;
+
+        // non-.sql files are ignored
+        tokio::fs::write(
+            ta.tmpdir.path().join("schema").join("notes.txt"),
+            "CREATE TABLE nope (",
+        )
+        .await?;
+
+        tokio::fs::write(
+            ta.tmpdir.path().join("schema").join("watched.sql"),
+            "CREATE TABLE watched (id INTEGER NOT NULL PRIMARY KEY, value TEXT) WITHOUT ROWID;",
+        )
+        .await?;
+
+        let mut reloaded = false;
+        for _ in 0..50 {
+            if ta.agent.schema().read().tables.contains_key("watched") {
+                reloaded = true;
+                break;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        assert!(
+            reloaded,
+            "schema was not reloaded after a .sql file changed"
+        );
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn schema_change_propagation() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+
+        let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+        let ta2 = launch_test_agent(
+            |conf| {
+                conf.bootstrap(vec![ta1.agent.gossip_addr().to_string()])
+                    .build()
+            },
+            tripwire.clone(),
+        )
+        .await?;
+
+        // wait for the nodes to know each other, so the change is broadcast
+        timeout(Duration::from_secs(10), async {
+            while ta1.agent.members().read().states.is_empty() {
+                sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await?;
+
+        let res = hyper::Client::new()
+            .request(
+                hyper::Request::builder()
+                    .method(hyper::Method::POST)
+                    .uri(format!("http://{}/v1/migrations", ta1.agent.api_addr()))
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .body(
+                        serde_json::to_vec(&json!([
+                            "CREATE TABLE broadcasted (id INTEGER NOT NULL PRIMARY KEY, value TEXT) WITHOUT ROWID;"
+                        ]))?
+                        .into(),
+                    )?,
+            )
+            .await?;
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+        assert!(ta1.agent.schema().read().tables.contains_key("broadcasted"));
+
+        timeout(Duration::from_secs(10), async {
+            while !ta2.agent.schema().read().tables.contains_key("broadcasted") {
+                sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await?;
+
+        // the table is usable and replicated like any other
+        let has_table: bool = ta2.agent.pool().read().await?.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_schema WHERE type = 'table' AND name = 'broadcasted__crsql_clock')",
+            [],
+            |row| row.get(0),
+        )?;
+        assert!(has_table);
+
+        // re-applying the same schema is a no-op
+        assert!(
+            !apply_schema_change(
+                &ta2.agent,
+                vec![
+                    "CREATE TABLE broadcasted (id INTEGER NOT NULL PRIMARY KEY, value TEXT) WITHOUT ROWID;"
+                        .into()
+                ]
+            )
+            .await?
+        );
+
+        // removing a column isn't additive
+        assert!(matches!(
+            apply_schema_change(
+                &ta2.agent,
+                vec![
+                    "CREATE TABLE broadcasted (id INTEGER NOT NULL PRIMARY KEY) WITHOUT ROWID;"
+                        .into()
+                ]
+            )
+            .await,
+            Err(SchemaChangeError::NotAdditive { kind: "table", .. })
+        ));
+        assert!(ta2.agent.schema().read().tables["broadcasted"]
+            .columns
+            .contains_key("value"));
+
+        // a node joining later gets the change through sync
+        let ta3 = launch_test_agent(
+            |conf| {
+                conf.bootstrap(vec![ta1.agent.gossip_addr().to_string()])
+                    .build()
+            },
+            tripwire.clone(),
+        )
+        .await?;
+
+        timeout(Duration::from_secs(60), async {
+            while !ta3.agent.schema().read().tables.contains_key("broadcasted") {
+                sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await?;
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn pending_schema_changes_apply_in_order() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+
+        let ta = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+        let actor_id = ActorId(uuid::Uuid::new_v4());
+        let superseded = Timestamp::from(ta.agent.clock().new_timestamp());
+        let create = Timestamp::from(ta.agent.clock().new_timestamp());
+        let view = Timestamp::from(ta.agent.clock().new_timestamp());
+
+        let record = |ts: Timestamp, statements: &[&str]| {
+            let agent = ta.agent.clone();
+            let statements = serde_json::to_string(statements).unwrap();
+            async move {
+                let conn = agent.pool().write_priority().await?;
+                conn.execute(
+                    "INSERT INTO __corro_schema_changes (actor_id, ts, statements) VALUES (?, ?, ?)",
+                    params![actor_id, ts, statements],
+                )?;
+                Ok::<_, eyre::Report>(())
+            }
+        };
+
+        // the view needs the table, received the other way around
+        record(
+            view,
+            &["CREATE VIEW ordered_a AS SELECT id, a FROM ordered;"],
+        )
+        .await?;
+        record(
+            create,
+            &["CREATE TABLE ordered (id INTEGER NOT NULL PRIMARY KEY, a TEXT) WITHOUT ROWID;"],
+        )
+        .await?;
+
+        apply_pending_schema_changes(&ta.agent).await?;
+
+        assert!(ta.agent.schema().read().tables.contains_key("ordered"));
+        assert!(ta.agent.schema().read().views.contains_key("ordered_a"));
+
+        let pending = |agent: Agent| async move {
+            let conn = agent.pool().read().await?;
+            let pending: i64 = conn.query_row(
+                "SELECT COUNT(*) FROM __corro_schema_changes AS c WHERE NOT EXISTS (SELECT 1 FROM __corro_schema_changes_applied AS a WHERE a.actor_id = c.actor_id AND a.ts = c.ts)",
+                [],
+                |row| row.get(0),
+            )?;
+            Ok::<_, eyre::Report>(pending)
+        };
+        assert_eq!(pending(ta.agent.clone()).await?, 0);
+
+        // an older change showing up late doesn't undo newer ones
+        record(
+            superseded,
+            &["CREATE TABLE ordered (id INTEGER NOT NULL PRIMARY KEY) WITHOUT ROWID;"],
+        )
+        .await?;
+
+        apply_pending_schema_changes(&ta.agent).await?;
+
+        assert!(ta.agent.schema().read().tables["ordered"]
+            .columns
+            .contains_key("a"));
+        assert_eq!(pending(ta.agent.clone()).await?, 0);
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn mdns_discovery() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+
+        // mDNS doesn't advertise over loopback and peers reply to the
+        // advertised gossip address, so bind to the address of the
+        // interface holding the default route (connecting UDP sends nothing)
+        let local_ip = {
+            let socket = std::net::UdpSocket::bind("0.0.0.0:0")?;
+            socket.connect("203.0.113.1:9")?;
+            socket.local_addr()?.ip()
+        };
+
+        let launch = |tripwire| {
+            launch_test_agent(
+                move |conf| {
+                    conf.gossip_addr(SocketAddr::new(local_ip, 0))
+                        .mdns_discovery(true)
+                        .build()
+                },
+                tripwire,
+            )
+        };
+
+        let ta1 = launch(tripwire.clone()).await?;
+        let ta2 = launch(tripwire.clone()).await?;
+
+        timeout(Duration::from_secs(30), async {
+            loop {
+                let found1 = ta1
+                    .agent
+                    .members()
+                    .read()
+                    .states
+                    .contains_key(&ta2.agent.actor_id());
+                let found2 = ta2
+                    .agent
+                    .members()
+                    .read()
+                    .states
+                    .contains_key(&ta1.agent.actor_id());
+                if found1 && found2 {
+                    break;
+                }
+                sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await?;
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn members_persisted_on_shutdown() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+        let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+        let ta2 = launch_test_agent(
+            |conf| {
+                conf.bootstrap(vec![ta1.agent.gossip_addr().to_string()])
+                    .build()
+            },
+            tripwire.clone(),
+        )
+        .await?;
+
+        timeout(Duration::from_secs(30), async {
+            while !ta1
+                .agent
+                .members()
+                .read()
+                .states
+                .contains_key(&ta2.agent.actor_id())
+            {
+                sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await?;
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        // the periodic diff already ran before ta2 joined, this is written on shutdown
+        let conn = rusqlite::Connection::open(ta1.tmpdir.path().join("corrosion.db"))?;
+        let (address, foca_state): (String, String) = conn.query_row(
+            "SELECT address, foca_state FROM __corro_members WHERE actor_id = ?",
+            [ta2.agent.actor_id()],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        assert_eq!(address, ta2.agent.gossip_addr().to_string());
+        let member: foca::Member<Actor> = serde_json::from_str(&foca_state)?;
+        assert_eq!(member.id().id(), ta2.agent.actor_id());
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn clock_persisted_across_restarts() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+
+        let ta = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+        let issued = Timestamp::from(ta.agent.clock().new_timestamp());
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        let db_path = ta.tmpdir.path().join("corrosion.db");
+
+        let conn = rusqlite::Connection::open(&db_path)?;
+        let persisted: Timestamp =
+            conn.query_row("SELECT ts FROM __corro_clock", [], |row| row.get(0))?;
+        // ahead of anything issued, in case we'd crashed instead
+        assert!(persisted.to_ntp64() >= issued.to_ntp64() + uhlc::NTP64::from(PERSIST_CLOCK_AHEAD));
+
+        let conf = || {
+            Config::builder()
+                .db_path(db_path.display().to_string())
+                .gossip_addr("127.0.0.1:0".parse().unwrap())
+                .api_addr("127.0.0.1:0".parse().unwrap())
+                .build()
+        };
+
+        // a wall clock that went too far back has to be fixed first
+        conn.execute(
+            "UPDATE __corro_clock SET ts = ?",
+            [Timestamp::from(
+                uhlc::system_time_clock() + uhlc::NTP64::from(MAX_CLOCK_WAIT * 2),
+            )],
+        )?;
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+        assert!(setup(conf()?, tripwire).await.is_err());
+
+        conn.execute("UPDATE __corro_clock SET ts = ?", [persisted])?;
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+        let (agent, _agent_options) = setup(conf()?, tripwire).await?;
+
+        // waited for the wall clock to catch up
+        assert!(uhlc::system_time_clock() > persisted.to_ntp64());
+        assert!(Timestamp::from(agent.clock().new_timestamp()) > persisted);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn sync_now() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+        let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+        let client = hyper::Client::builder().build_http::<hyper::Body>();
+
+        // nobody to broadcast to yet, ta2 will only get this by syncing
+        let req_body: Vec<Statement> = serde_json::from_value(json!([[
+            "INSERT INTO tests (id,text) VALUES (?,?)",
+            [1, "hello world 1"]
+        ],]))?;
+        let res = client
+            .request(
+                hyper::Request::builder()
+                    .method(hyper::Method::POST)
+                    .uri(format!("http://{}/v1/transactions", ta1.agent.api_addr()))
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .body(serde_json::to_vec(&req_body)?.into())?,
+            )
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let ta2 = launch_test_agent(
+            |conf| {
+                let mut conf = conf
+                    .bootstrap(vec![ta1.agent.gossip_addr().to_string()])
+                    .build()?;
+                // keep the sync loop out of the way
+                conf.sync.min_interval_secs = 3600;
+                conf.sync.max_interval_secs = 3600;
+                Ok(conf)
+            },
+            tripwire.clone(),
+        )
+        .await?;
+
+        timeout(Duration::from_secs(30), async {
+            while !ta2
+                .agent
+                .members()
+                .read()
+                .states
+                .contains_key(&ta1.agent.actor_id())
+            {
+                sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await?;
+
+        let res = client
+            .request(
+                hyper::Request::builder()
+                    .method(hyper::Method::POST)
+                    .uri(format!("http://{}/v1/sync/now", ta2.agent.api_addr()))
+                    .body(hyper::Body::empty())?,
+            )
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let body: serde_json::Value =
+            serde_json::from_slice(&hyper::body::to_bytes(res.into_body()).await?)?;
+        assert!(body["synced"].as_u64().unwrap_or_default() > 0, "{body}");
+
+        // received changes are applied in the background
+        timeout(Duration::from_secs(5), async {
+            loop {
+                let count: i64 = ta2.agent.pool().read().await?.query_row(
+                    "SELECT COUNT(*) FROM tests",
+                    (),
+                    |row| row.get(0),
+                )?;
+                if count == 1 {
+                    return Ok::<_, eyre::Report>(());
+                }
+                sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await??;
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn sync_range() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+        let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+        // not part of ta1's cluster, it only gets what it asks for
+        let ta2 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+        let client = hyper::Client::builder().build_http::<hyper::Body>();
+
+        let mut versions = vec![];
+        for i in 1..=3 {
+            let req_body: Vec<Statement> = serde_json::from_value(json!([[
+                "INSERT INTO tests (id,text) VALUES (?,?)",
+                [i, format!("hello world {i}")]
+            ],]))?;
+            let res = client
+                .request(
+                    hyper::Request::builder()
+                        .method(hyper::Method::POST)
+                        .uri(format!("http://{}/v1/transactions", ta1.agent.api_addr()))
+                        .header(hyper::header::CONTENT_TYPE, "application/json")
+                        .body(serde_json::to_vec(&req_body)?.into())?,
+                )
+                .await?;
+            assert_eq!(res.status(), StatusCode::OK);
+            let body: ExecResponse =
+                serde_json::from_slice(&hyper::body::to_bytes(res.into_body()).await?)?;
+            versions.push(body.version.unwrap());
+        }
+
+        // only the last 2 transactions
+        let res = client
+            .request(
+                hyper::Request::builder()
+                    .method(hyper::Method::POST)
+                    .uri(format!("http://{}/v1/sync/range", ta2.agent.api_addr()))
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .body(
+                        serde_json::to_vec(&json!({
+                            "actor_id": ta1.agent.actor_id(),
+                            "start": versions[1],
+                            "end": versions[2],
+                            "peer": ta1.agent.gossip_addr(),
+                        }))?
+                        .into(),
+                    )?,
+            )
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let body: serde_json::Value =
+            serde_json::from_slice(&hyper::body::to_bytes(res.into_body()).await?)?;
+        assert_eq!(body["synced"].as_u64(), Some(2), "{body}");
+
+        // received changes are applied in the background
+        timeout(Duration::from_secs(5), async {
+            loop {
+                let ids: Vec<i64> = ta2
+                    .agent
+                    .pool()
+                    .read()
+                    .await?
+                    .prepare("SELECT id FROM tests ORDER BY id")?
+                    .query_map((), |row| row.get(0))?
+                    .collect::<rusqlite::Result<_>>()?;
+                if !ids.is_empty() {
+                    assert_eq!(ids, vec![2, 3]);
+                    return Ok::<_, eyre::Report>(());
+                }
+                sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await??;
+
+        // inverted ranges are rejected up front
+        let res = client
+            .request(
+                hyper::Request::builder()
+                    .method(hyper::Method::POST)
+                    .uri(format!("http://{}/v1/sync/range", ta2.agent.api_addr()))
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .body(
+                        serde_json::to_vec(&json!({
+                            "actor_id": ta1.agent.actor_id(),
+                            "start": versions[2],
+                            "end": versions[1],
+                            "peer": ta1.agent.gossip_addr(),
+                        }))?
+                        .into(),
+                    )?,
+            )
+            .await?;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn gap_sync_fills_holes() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+        let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+        let client = hyper::Client::builder().build_http::<hyper::Body>();
+
+        // nobody to broadcast to yet
+        let mut versions = vec![];
+        for i in 1..=3 {
+            let req_body: Vec<Statement> = serde_json::from_value(json!([[
+                "INSERT INTO tests (id,text) VALUES (?,?)",
+                [i, format!("hello world {i}")]
+            ],]))?;
+            let res = client
+                .request(
+                    hyper::Request::builder()
+                        .method(hyper::Method::POST)
+                        .uri(format!("http://{}/v1/transactions", ta1.agent.api_addr()))
+                        .header(hyper::header::CONTENT_TYPE, "application/json")
+                        .body(serde_json::to_vec(&req_body)?.into())?,
+                )
+                .await?;
+            assert_eq!(res.status(), StatusCode::OK);
+            let body: ExecResponse =
+                serde_json::from_slice(&hyper::body::to_bytes(res.into_body()).await?)?;
+            versions.push(body.version.unwrap());
+        }
+
+        // not part of ta1's cluster, so ta1's broadcasts (and their
+        // retransmissions) never reach it
+        let ta2 = launch_test_agent(
+            |conf| {
+                let mut conf = conf.build()?;
+                // keep the sync loop out of the way, only gap syncs fill the hole
+                conf.sync.min_interval_secs = 3600;
+                conf.sync.max_interval_secs = 3600;
+                conf.sync.gap_interval_secs = 1;
+                Ok(conf)
+            },
+            tripwire.clone(),
+        )
+        .await?;
+
+        // ...but it knows where to ask for missing versions
+        ta2.agent.members().write().add_member(&Actor::new(
+            ta1.agent.actor_id(),
+            ta1.agent.gossip_addr(),
+            ta1.agent.clock().new_timestamp().into(),
+        ));
+
+        // only the last version, leaving a hole before it
+        let res = client
+            .request(
+                hyper::Request::builder()
+                    .method(hyper::Method::POST)
+                    .uri(format!("http://{}/v1/sync/range", ta2.agent.api_addr()))
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .body(
+                        serde_json::to_vec(&json!({
+                            "actor_id": ta1.agent.actor_id(),
+                            "start": versions[2],
+                            "end": versions[2],
+                            "peer": ta1.agent.gossip_addr(),
+                        }))?
+                        .into(),
+                    )?,
+            )
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        timeout(Duration::from_secs(20), async {
+            loop {
+                let ids: Vec<i64> = ta2
+                    .agent
+                    .pool()
+                    .read()
+                    .await?
+                    .prepare("SELECT id FROM tests ORDER BY id")?
+                    .query_map((), |row| row.get(0))?
+                    .collect::<rusqlite::Result<_>>()?;
+                if ids == vec![1, 2, 3] {
+                    return Ok::<_, eyre::Report>(());
+                }
+                sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await??;
+
+        let booked = ta2
+            .agent
+            .bookie()
+            .read("test")
+            .await
+            .get(&ta1.agent.actor_id())
+            .cloned()
+            .unwrap();
+        assert!(booked.read("test").await.sync_need().is_empty());
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        Ok(())
+    }
+
+    #[test]
+    fn gap_ranges_are_capped() {
+        let ranges = vec![Version(1)..=Version(5), Version(10)..=Version(20)];
+        assert_eq!(cap_gap_ranges(ranges.clone(), 100), ranges);
+        assert_eq!(
+            cap_gap_ranges(ranges.clone(), 8),
+            vec![Version(1)..=Version(5), Version(10)..=Version(12)]
+        );
+        assert_eq!(cap_gap_ranges(ranges, 5), vec![Version(1)..=Version(5)]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn insert_rows_and_gossip() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+        let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+        let ta2 = launch_test_agent(
+            |conf| {
+                conf.bootstrap(vec![ta1.agent.gossip_addr().to_string()])
+                    .build()
+            },
+            tripwire.clone(),
+        )
+        .await?;
+
+        let client = hyper::Client::builder()
+            .pool_max_idle_per_host(5)
+            .pool_idle_timeout(Duration::from_secs(300))
+            .build_http::<hyper::Body>();
+
+        let req_body: Vec<Statement> = serde_json::from_value(json!([[
+            "INSERT INTO tests (id,text) VALUES (?,?)",
+            [1, "hello world 1"]
+        ],]))?;
+
+        let res = timeout(
+            Duration::from_secs(5),
+            client.request(
+                hyper::Request::builder()
+                    .method(hyper::Method::POST)
+                    .uri(format!("http://{}/v1/transactions", ta1.agent.api_addr()))
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .body(serde_json::to_vec(&req_body)?.into())?,
+            ),
+        )
+        .await??;
+
+        let body: ExecResponse =
+            serde_json::from_slice(&hyper::body::to_bytes(res.into_body()).await?)?;
+
+        // version 1 is the schema change recorded when the agent started, db
+        // versions depend on when ta2's own schema change comes in
+        assert_eq!(body.version, Some(Version(2)));
+
+        println!("body: {body:?}");
+
+        #[derive(Debug, Deserialize)]
+        struct TestRecord {
+            id: i64,
+            text: String,
+        }
+
+        let svc: TestRecord = ta1.agent.pool().read().await?.query_row(
+            "SELECT id, text FROM tests WHERE id = 1;",
+            [],
+            |row| {
+                Ok(TestRecord {
+                    id: row.get(0)?,
+                    text: row.get(1)?,
+                })
+            },
+        )?;
+
+        assert_eq!(svc.id, 1);
+        assert_eq!(svc.text, "hello world 1");
+
+        sleep(Duration::from_secs(1)).await;
+
+        let svc: TestRecord = ta2.agent.pool().read().await?.query_row(
+            "SELECT id, text FROM tests WHERE id = 1;",
+            [],
+            |row| {
+                Ok(TestRecord {
+                    id: row.get(0)?,
+                    text: row.get(1)?,
+                })
+            },
+        )?;
+
+        assert_eq!(svc.id, 1);
+        assert_eq!(svc.text, "hello world 1");
+
+        let req_body: Vec<Statement> = serde_json::from_value(json!([[
+            "INSERT INTO tests (id,text) VALUES (?,?)",
+            [2, "hello world 2"]
+        ]]))?;
+
+        let res = client
+            .request(
+                hyper::Request::builder()
+                    .method(hyper::Method::POST)
+                    .uri(format!("http://{}/v1/transactions", ta1.agent.api_addr()))
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .body(serde_json::to_vec(&req_body)?.into())?,
+            )
+            .await?;
+
+        let body: ExecResponse =
+            serde_json::from_slice(&hyper::body::to_bytes(res.into_body()).await?)?;
+
+        println!("body: {body:?}");
+
+        #[allow(clippy::type_complexity)]
+        let bk: Vec<(ActorId, Version, Option<Version>, Option<CrsqlSeq>)> = ta1
+            .agent
+            .pool()
+            .read()
+            .await?
+            .prepare("SELECT actor_id, start_version, end_version, last_seq FROM __corro_bookkeeping WHERE actor_id = ?")?
+            .query_map([ta1.agent.actor_id()], |row| {
+                Ok((
+                    row.get::<_, ActorId>(0)?,
+                    row.get::<_, Version>(1)?,
+                    row.get::<_, Option<Version>>(2)?,
+                    row.get::<_, Option<CrsqlSeq>>(3)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        assert_eq!(
+            bk,
+            vec![
+                (ta1.agent.actor_id(), Version(1), None, Some(CrsqlSeq(0))),
+                (ta1.agent.actor_id(), Version(2), None, Some(CrsqlSeq(0))),
+                (ta1.agent.actor_id(), Version(3), None, Some(CrsqlSeq(0)))
+            ]
+        );
+
+        let svc: TestRecord = ta1.agent.pool().read().await?.query_row(
+            "SELECT id, text FROM tests WHERE id = 2;",
+            [],
+            |row| {
+                Ok(TestRecord {
+                    id: row.get(0)?,
+                    text: row.get(1)?,
+                })
+            },
+        )?;
+
+        assert_eq!(svc.id, 2);
+        assert_eq!(svc.text, "hello world 2");
+
+        sleep(Duration::from_secs(1)).await;
+
+        let svc: TestRecord = ta2.agent.pool().read().await?.query_row(
+            "SELECT id, text FROM tests WHERE id = 2;",
+            [],
+            |row| {
+                Ok(TestRecord {
+                    id: row.get(0)?,
+                    text: row.get(1)?,
+                })
+            },
+        )?;
+
+        assert_eq!(svc.id, 2);
+        assert_eq!(svc.text, "hello world 2");
+
+        let values: Vec<serde_json::Value> = (3..1000)
+            .map(|id| {
+                serde_json::json!([
+                    "INSERT INTO tests (id,text) VALUES (?,?)",
+                    [id, format!("hello world #{id}")],
+                ])
+            })
+            .collect();
+
+        let req_body: Vec<Statement> = serde_json::from_value(json!(values))?;
+
+        let res = timeout(
+            Duration::from_secs(5),
+            client.request(
+                hyper::Request::builder()
+                    .method(hyper::Method::POST)
+                    .uri(format!("http://{}/v1/transactions", ta1.agent.api_addr()))
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .body(serde_json::to_vec(&req_body)?.into())?,
+            ),
+        )
+        .await??;
+
+        let body: ExecResponse =
+            serde_json::from_slice(&hyper::body::to_bytes(res.into_body()).await?)?;
+        assert_eq!(body.version, Some(Version(4)));
+
+        let expected_count: i64 =
+            ta1.agent
+                .pool()
+                .read()
+                .await?
+                .query_row("SELECT COUNT(*) FROM tests", (), |row| row.get(0))?;
+
+        sleep(Duration::from_secs(5)).await;
+
+        let got_count: i64 =
+            ta2.agent
+                .pool()
+                .read()
+                .await?
+                .query_row("SELECT COUNT(*) FROM tests", (), |row| row.get(0))?;
+
+        assert_eq!(expected_count, got_count);
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn large_broadcast_propagates() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+        let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+        let ta2 = launch_test_agent(
+            |conf| {
+                conf.bootstrap(vec![ta1.agent.gossip_addr().to_string()])
+                    .build()
+            },
+            tripwire.clone(),
+        )
+        .await?;
+
+        let client: hyper::Client<_, hyper::Body> = hyper::Client::builder().build_http();
+
+        // way bigger than a single datagram
+        let req_body: Vec<Statement> = serde_json::from_value(json!([
+            "INSERT INTO testsblob (id,text) VALUES (x'01', randomblob(256 * 1024))"
+        ]))?;
+
+        let res = timeout(
+            Duration::from_secs(5),
+            client.request(
+                hyper::Request::builder()
+                    .method(hyper::Method::POST)
+                    .uri(format!("http://{}/v1/transactions", ta1.agent.api_addr()))
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .body(serde_json::to_vec(&req_body)?.into())?,
+            ),
+        )
+        .await??;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let expected: Vec<u8> = ta1.agent.pool().read().await?.query_row(
+            "SELECT text FROM testsblob WHERE id = x'01'",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(expected.len(), 256 * 1024);
+
+        let got = timeout(Duration::from_secs(10), async {
+            loop {
+                let got: Option<Vec<u8>> = ta2
+                    .agent
+                    .pool()
+                    .read()
+                    .await?
+                    .query_row("SELECT text FROM testsblob WHERE id = x'01'", [], |row| {
+                        row.get(0)
+                    })
+                    .optional()?;
+                if let Some(got) = got {
+                    return Ok::<_, eyre::Report>(got);
+                }
+                sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await??;
+
+        assert_eq!(got, expected);
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn signed_gossip() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+
+        let key = corro_types::signing::generate_signing_key()?;
+
+        // syncs aren't signed, so configs have to authenticate peers too
+        let conf = || {
+            Config::builder()
+                .db_path("/tmp/unused.db")
+                .api_addr("127.0.0.1:0".parse().unwrap())
+                .gossip_addr("127.0.0.1:0".parse().unwrap())
+                .signing_key(&key)
+        };
+        assert!(matches!(
+            conf().build()?.validate(),
+            Err(ConfigError::SigningWithoutMtls)
+        ));
+        conf()
+            .tls_config(TlsConfig {
+                cert_file: "server.pem".into(),
+                key_file: "server.key".into(),
+                ca_file: Some("ca.pem".into()),
+                insecure: false,
+                client: Some(TlsClientConfig {
+                    cert_file: "client.pem".into(),
+                    key_file: "client.key".into(),
+                }),
+            })
+            .build()?
+            .validate()?;
+
+        let ta1 =
+            launch_test_agent(|conf| conf.signing_key(&key).build(), tripwire.clone()).await?;
+        let ta2 = launch_test_agent(
+            |conf| {
+                conf.bootstrap(vec![ta1.agent.gossip_addr().to_string()])
+                    .signing_key(&key)
+                    .build()
+            },
+            tripwire.clone(),
+        )
+        .await?;
+        // signs with another key, can't join
+        let ta3 = launch_test_agent(
+            |conf| {
+                conf.bootstrap(vec![ta1.agent.gossip_addr().to_string()])
+                    .signing_key(corro_types::signing::generate_signing_key().unwrap())
+                    .build()
+            },
+            tripwire.clone(),
+        )
+        .await?;
+
+        let client: hyper::Client<_, hyper::Body> = hyper::Client::builder().build_http();
+
+        let req_body: Vec<Statement> = serde_json::from_value(json!([[
+            "INSERT INTO tests (id,text) VALUES (?,?)",
+            [1, "hello world 1"]
+        ]]))?;
+
+        let res = timeout(
+            Duration::from_secs(5),
+            client.request(
+                hyper::Request::builder()
+                    .method(hyper::Method::POST)
+                    .uri(format!("http://{}/v1/transactions", ta1.agent.api_addr()))
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .body(serde_json::to_vec(&req_body)?.into())?,
+            ),
+        )
+        .await??;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        timeout(Duration::from_secs(10), async {
+            loop {
+                let count: i64 = ta2.agent.pool().read().await?.query_row(
+                    "SELECT COUNT(*) FROM tests",
+                    [],
+                    |row| row.get(0),
+                )?;
+                if count == 1 {
+                    return Ok::<_, eyre::Report>(());
+                }
+                sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await??;
+
+        {
+            let members = ta1.agent.members().read();
+            assert!(members.states.contains_key(&ta2.agent.actor_id()));
+            assert!(!members.states.contains_key(&ta3.agent.actor_id()));
+        }
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn denied_peer_never_syncs() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+
+        let loopback: ipnet::IpNet = "127.0.0.0/8".parse()?;
+        let ta1 = launch_test_agent(
+            |conf| conf.deny_cidrs(vec![loopback]).build(),
+            tripwire.clone(),
+        )
+        .await?;
+        let ta2 = launch_test_agent(
+            |conf| {
+                conf.bootstrap(vec![ta1.agent.gossip_addr().to_string()])
+                    .build()
+            },
+            tripwire.clone(),
+        )
+        .await?;
+
+        let client: hyper::Client<_, hyper::Body> = hyper::Client::builder().build_http();
+
+        let req_body: Vec<Statement> = serde_json::from_value(json!([[
+            "INSERT INTO tests (id,text) VALUES (?,?)",
+            [1, "hello world 1"]
+        ]]))?;
+
+        let res = timeout(
+            Duration::from_secs(5),
+            client.request(
+                hyper::Request::builder()
+                    .method(hyper::Method::POST)
+                    .uri(format!("http://{}/v1/transactions", ta1.agent.api_addr()))
                     .header(hyper::header::CONTENT_TYPE, "application/json")
                     .body(serde_json::to_vec(&req_body)?.into())?,
             ),
         )
         .await??;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // plenty of time for a few sync rounds
+        sleep(Duration::from_secs(5)).await;
+
+        let count: i64 =
+            ta2.agent
+                .pool()
+                .read()
+                .await?
+                .query_row("SELECT COUNT(*) FROM tests", [], |row| row.get(0))?;
+        assert_eq!(count, 0);
+
+        assert!(!ta1
+            .agent
+            .members()
+            .read()
+            .states
+            .contains_key(&ta2.agent.actor_id()));
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn no_broadcast_tables_stay_local() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+
+        let ta1 = launch_test_agent(
+            |conf| conf.no_broadcast_table("tests2").build(),
+            tripwire.clone(),
+        )
+        .await?;
+        let ta2 = launch_test_agent(
+            |conf| {
+                conf.bootstrap(vec![ta1.agent.gossip_addr().to_string()])
+                    .build()
+            },
+            tripwire.clone(),
+        )
+        .await?;
+
+        let client: hyper::Client<_, hyper::Body> = hyper::Client::builder().build_http();
+
+        let exec = |statements: serde_json::Value| {
+            let client = client.clone();
+            let addr = ta1.agent.api_addr();
+            async move {
+                let req_body: Vec<Statement> = serde_json::from_value(statements)?;
+                let res = timeout(
+                    Duration::from_secs(5),
+                    client.request(
+                        hyper::Request::builder()
+                            .method(hyper::Method::POST)
+                            .uri(format!("http://{addr}/v1/transactions"))
+                            .header(hyper::header::CONTENT_TYPE, "application/json")
+                            .body(serde_json::to_vec(&req_body)?.into())?,
+                    ),
+                )
+                .await??;
+                assert_eq!(res.status(), StatusCode::OK);
+                let body = hyper::body::to_bytes(res.into_body()).await?;
+                Ok::<_, eyre::Report>(serde_json::from_slice::<ExecResponse>(&body)?)
+            }
+        };
+
+        // a write touching only local tables doesn't get a version
+        let res = exec(json!([[
+            "INSERT INTO tests2 (id,text) VALUES (?,?)",
+            [1, "local only"]
+        ]]))
+        .await?;
+        assert_eq!(res.version, None);
+
+        let res = exec(json!([
+            ["INSERT INTO tests (id,text) VALUES (?,?)", [1, "shared"]],
+            [
+                "INSERT INTO tests2 (id,text) VALUES (?,?)",
+                [2, "local too"]
+            ]
+        ]))
+        .await?;
+        // version 1 is the schema change recorded when the agent started
+        assert_eq!(res.version, Some(Version(2)));
+
+        let start = Instant::now();
+        loop {
+            let count: i64 = ta2.agent.pool().read().await?.query_row(
+                "SELECT COUNT(*) FROM tests",
+                [],
+                |row| row.get(0),
+            )?;
+            if count == 1 {
+                break;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "shared row never made it to the peer"
+            );
+            sleep(Duration::from_millis(100)).await;
+        }
+
+        // give sync a few rounds to (not) send the rest
+        sleep(Duration::from_secs(3)).await;
+
+        let count: i64 =
+            ta2.agent
+                .pool()
+                .read()
+                .await?
+                .query_row("SELECT COUNT(*) FROM tests2", [], |row| row.get(0))?;
+        assert_eq!(count, 0);
+
+        let local: i64 =
+            ta1.agent
+                .pool()
+                .read()
+                .await?
+                .query_row("SELECT COUNT(*) FROM tests2", [], |row| row.get(0))?;
+        assert_eq!(local, 2);
+
+        // the peer considers the version complete, with no gaps left to sync
+        let booked = ta2
+            .agent
+            .bookie()
+            .read("test")
+            .await
+            .get(&ta1.agent.actor_id())
+            .cloned()
+            .expect("peer should have booked our actor");
+        assert!(booked.read("test").await.contains_version(&Version(1)));
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn announce_fanout() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+
+        let ta =
+            launch_test_agent(|conf| conf.announce_fanout(3).build(), tripwire.clone()).await?;
+
+        let bootstrap: Vec<String> = (1..=50).map(|n| format!("127.0.1.{n}:8787")).collect();
+        let our_addr: SocketAddr = "127.0.0.1:8787".parse()?;
+
+        let mut cache = BootstrapCache::default();
+        let chosen = generate_bootstrap(&bootstrap, our_addr, &ta.agent, &mut cache).await?;
+        assert_eq!(chosen.len(), 3);
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn seeded_peer_selection() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+
+        let ta1 =
+            launch_test_agent(|conf| conf.sync_rng_seed(42).build(), tripwire.clone()).await?;
+        let ta2 =
+            launch_test_agent(|conf| conf.sync_rng_seed(42).build(), tripwire.clone()).await?;
+        let ta3 = launch_test_agent(|conf| conf.sync_rng_seed(7).build(), tripwire.clone()).await?;
+
+        let bootstrap: Vec<String> = (1..=50).map(|n| format!("127.0.1.{n}:8787")).collect();
+        let our_addr: SocketAddr = "127.0.0.1:8787".parse()?;
+
+        for _ in 0..5 {
+            let mut cache = BootstrapCache::default();
+            let chosen1 = generate_bootstrap(&bootstrap, our_addr, &ta1.agent, &mut cache).await?;
+            let chosen2 = generate_bootstrap(&bootstrap, our_addr, &ta2.agent, &mut cache).await?;
+            let chosen3 = generate_bootstrap(&bootstrap, our_addr, &ta3.agent, &mut cache).await?;
+            assert_eq!(chosen1.len(), DEFAULT_ANNOUNCE_FANOUT);
+            assert_eq!(chosen1, chosen2);
+            assert_ne!(chosen1, chosen3);
+        }
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bootstrap_cache_flapping_dns() -> eyre::Result<()> {
+        let lookups = AtomicI64::new(0);
+        // every other lookup fails, records expire right away
+        let flapping = |addrs: HashSet<SocketAddr>, ttl: Duration| {
+            let lookups = &lookups;
+            move || async move {
+                if lookups.fetch_add(1, Ordering::SeqCst) % 2 == 1 {
+                    Err(ResolveError::from("dns is down"))
+                } else {
+                    Ok((addrs, Instant::now() + ttl))
+                }
+            }
+        };
+
+        let first: HashSet<SocketAddr> = ["10.0.0.1:8787".parse()?].into();
+        let second: HashSet<SocketAddr> = ["10.0.0.2:8787".parse()?].into();
+
+        let mut cache = BootstrapCache::default();
+
+        let addrs = cache
+            .resolve("app.internal", flapping(first.clone(), Duration::ZERO))
+            .await?;
+        assert_eq!(addrs, first);
 
-        let db_version: CrsqlDbVersion =
-            ta1.agent
-                .pool()
-                .read()
-                .await?
-                .query_row("SELECT crsql_db_version();", (), |row| row.get(0))?;
-        assert_eq!(db_version, CrsqlDbVersion(3));
+        // the lookup fails, last known good addresses are used
+        let addrs = cache
+            .resolve("app.internal", flapping(second.clone(), Duration::ZERO))
+            .await?;
+        assert_eq!(addrs, first);
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+
+        // the lookup succeeds again, its TTL is honored
+        let addrs = cache
+            .resolve(
+                "app.internal",
+                flapping(second.clone(), Duration::from_secs(60)),
+            )
+            .await?;
+        assert_eq!(addrs, second);
 
-        let expected_count: i64 =
-            ta1.agent
-                .pool()
-                .read()
-                .await?
-                .query_row("SELECT COUNT(*) FROM tests", (), |row| row.get(0))?;
+        let addrs = cache
+            .resolve("app.internal", flapping(first.clone(), Duration::ZERO))
+            .await?;
+        assert_eq!(addrs, second);
+        assert_eq!(lookups.load(Ordering::SeqCst), 3);
 
-        sleep(Duration::from_secs(5)).await;
+        // nothing to fall back to for entries that never resolved
+        lookups.store(1, Ordering::SeqCst);
+        assert!(cache
+            .resolve("other.internal", flapping(first, Duration::ZERO))
+            .await
+            .is_err());
 
-        let got_count: i64 =
-            ta2.agent
-                .pool()
-                .read()
-                .await?
-                .query_row("SELECT COUNT(*) FROM tests", (), |row| row.get(0))?;
+        Ok(())
+    }
 
-        assert_eq!(expected_count, got_count);
+    #[tokio::test]
+    async fn bootstrap_cache_empty_answer() -> eyre::Result<()> {
+        let addrs: HashSet<SocketAddr> = ["10.0.0.1:8787".parse()?].into();
+        let answer =
+            |addrs: HashSet<SocketAddr>| move || async move { Ok((addrs, Instant::now())) };
 
-        tripwire_tx.send(()).await.ok();
-        tripwire_worker.await;
-        wait_for_all_pending_handles().await;
+        let mut cache = BootstrapCache::default();
+
+        assert_eq!(
+            cache.resolve("app.internal", answer(addrs.clone())).await?,
+            addrs
+        );
+
+        // an empty answer doesn't wipe out the last known good addresses
+        assert_eq!(
+            cache
+                .resolve("app.internal", answer(HashSet::new()))
+                .await?,
+            addrs
+        );
+        assert_eq!(
+            cache
+                .resolve("app.internal", answer(HashSet::new()))
+                .await?,
+            addrs
+        );
+
+        // ...and it's just empty for entries that never resolved
+        assert!(cache
+            .resolve("other.internal", answer(HashSet::new()))
+            .await?
+            .is_empty());
+        assert!(!cache.entries.contains_key("other.internal"));
 
         Ok(())
     }
@@ -3021,7 +6117,8 @@ pub mod tests {
                 async move {
                     for (n, gossip_addr) in to_launch {
                         println!("LAUNCHING AGENT #{n}");
-                        let mut rng = StdRng::from_entropy();
+                        // pinned so that peer selection can be replayed
+                        let mut rng = StdRng::seed_from_u64(n);
                         let bootstrap = agents
                             .iter()
                             .map(|ta| ta.agent.gossip_addr())
@@ -3036,6 +6133,7 @@ pub mod tests {
                                                 .map(SocketAddr::to_string)
                                                 .collect::<Vec<String>>(),
                                         )
+                                        .sync_rng_seed(n)
                                         .build()
                                 },
                                 tripwire.clone(),
@@ -3148,11 +6246,12 @@ pub mod tests {
                 let _entered = span.enter();
 
                 let conn = ta.agent.pool().read().await?;
+                // every node also records the schema change it started with
                 let counts: HashMap<ActorId, i64> = conn
                     .prepare_cached(
-                        "SELECT COALESCE(site_id, crsql_site_id()), count(*) FROM crsql_changes GROUP BY site_id;",
+                        r#"SELECT COALESCE(site_id, crsql_site_id()), count(*) FROM crsql_changes WHERE "table" != ? GROUP BY site_id;"#,
                     )?
-                    .query_map([], |row| {
+                    .query_map([SCHEMA_CHANGES_TABLE], |row| {
                         Ok((
                             row.get(0)?,
                             row.get(1)?,
@@ -3228,10 +6327,10 @@ pub mod tests {
 
         conn.execute_batch(
             "
-            CREATE TABLE foo (a INTEGER NOT NULL PRIMARY KEY, b INTEGER);
+            CREATE TABLE foo (a INTEGER NOT NULL PRIMARY KEY, b INTEGER) WITHOUT ROWID;
             SELECT crsql_as_crr('foo');
 
-            CREATE TABLE foo2 (a INTEGER NOT NULL PRIMARY KEY, b INTEGER);
+            CREATE TABLE foo2 (a INTEGER NOT NULL PRIMARY KEY, b INTEGER) WITHOUT ROWID;
             SELECT crsql_as_crr('foo2');
 
             CREATE INDEX fooclock ON foo__crsql_clock (site_id, db_version);
@@ -3372,7 +6471,8 @@ pub mod tests {
                 .read()
                 .await?
                 .query_row("SELECT crsql_db_version();", (), |row| row.get(0))?;
-        assert_eq!(db_version, CrsqlDbVersion(1));
+        // version 1 is the schema change recorded when the agent started
+        assert_eq!(db_version, CrsqlDbVersion(2));
 
         sleep(Duration::from_secs(2)).await;
 
@@ -3469,6 +6569,68 @@ pub mod tests {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn shutdown_during_sync() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+        let ta1 = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+        let client = hyper::Client::builder().build_http::<hyper::Body>();
+
+        let req_body: Vec<Statement> = serde_json::from_value(json!(["INSERT INTO tests  WITH RECURSIVE    cte(id) AS (       SELECT random()       UNION ALL       SELECT random()         FROM cte        LIMIT 5000  ) SELECT id, \"hello\" as text FROM cte;"]))?;
+
+        for _ in 0..20 {
+            let res = timeout(
+                Duration::from_secs(5),
+                client.request(
+                    hyper::Request::builder()
+                        .method(hyper::Method::POST)
+                        .uri(format!("http://{}/v1/transactions", ta1.agent.api_addr()))
+                        .header(hyper::header::CONTENT_TYPE, "application/json")
+                        .body(serde_json::to_vec(&req_body)?.into())?,
+                ),
+            )
+            .await??;
+            assert_eq!(res.status(), hyper::StatusCode::OK);
+        }
+
+        let ta2 = launch_test_agent(
+            |conf| {
+                conf.bootstrap(vec![ta1.agent.gossip_addr().to_string()])
+                    .build()
+            },
+            tripwire.clone(),
+        )
+        .await?;
+
+        // wait for the sync to be underway
+        timeout(Duration::from_secs(30), async {
+            loop {
+                let count: i64 = ta2.agent.pool().read().await?.query_row(
+                    "SELECT COUNT(*) FROM tests",
+                    (),
+                    |row| row.get(0),
+                )?;
+                if count > 0 {
+                    return Ok::<_, eyre::Report>(count);
+                }
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await??;
+
+        // leaving the cluster alone can take up to 5s, but we should not be
+        // stuck waiting on the 15s sync request timeout
+        tripwire_tx.send(()).await.ok();
+        timeout(Duration::from_secs(10), async {
+            tripwire_worker.await;
+            wait_for_all_pending_handles().await;
+        })
+        .await?;
+
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn many_small_changes() -> eyre::Result<()> {
         _ = tracing_subscriber::fmt::try_init();
@@ -3601,9 +6763,8 @@ pub mod tests {
 
     #[test]
     fn test_store_empty_changeset() -> eyre::Result<()> {
-        let mut conn = Connection::open_in_memory()?;
+        let mut conn = CrConn::init(Connection::open_in_memory()?)?;
 
-        corro_types::sqlite::setup_conn(&mut conn)?;
         migrate(&mut conn)?;
 
         let actor_id = ActorId(uuid::Uuid::new_v4());
@@ -3966,4 +7127,473 @@ pub mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_compact_cleared_versions() -> eyre::Result<()> {
+        let mut conn = CrConn::init(Connection::open_in_memory()?)?;
+
+        migrate(&mut conn)?;
+
+        let actor_id = ActorId(uuid::Uuid::new_v4());
+        let other_actor_id = ActorId(uuid::Uuid::new_v4());
+
+        // lots of single cleared versions, with a current version in the middle
+        for v in (1..=50).chain(52..=100) {
+            conn.execute(
+                "INSERT INTO __corro_bookkeeping (actor_id, start_version, end_version) VALUES (?, ?, ?)",
+                params![actor_id, v, v],
+            )?;
+        }
+        conn.execute(
+            "INSERT INTO __corro_bookkeeping (actor_id, start_version, db_version) VALUES (?, 51, 1)",
+            [actor_id],
+        )?;
+        conn.execute(
+            "INSERT INTO __corro_bookkeeping (actor_id, start_version, end_version) VALUES (?, 1, 10)",
+            [other_actor_id],
+        )?;
+
+        let count = |conn: &Connection| -> rusqlite::Result<usize> {
+            conn.query_row("SELECT COUNT(*) FROM __corro_bookkeeping", [], |row| {
+                row.get(0)
+            })
+        };
+
+        assert_eq!(count(&conn)?, 101);
+
+        {
+            let tx = conn.transaction()?;
+            assert_eq!(compact_cleared_versions(&tx, actor_id)?, 97);
+            assert_eq!(compact_cleared_versions(&tx, other_actor_id)?, 0);
+            tx.commit()?;
+        }
+
+        assert_eq!(count(&conn)?, 4);
+
+        let rows = conn
+            .prepare("SELECT start_version, end_version FROM __corro_bookkeeping WHERE actor_id = ? ORDER BY start_version")?
+            .query_map([actor_id], |row| Ok((row.get(0)?, row.get(1)?)))
+            .and_then(|rows| rows.collect::<rusqlite::Result<Vec<(Version, Option<Version>)>>>())?;
+
+        assert_eq!(
+            rows,
+            vec![
+                (Version(1), Some(Version(50))),
+                (Version(51), None),
+                (Version(52), Some(Version(100)))
+            ]
+        );
+
+        // nothing left to compact
+        {
+            let tx = conn.transaction()?;
+            assert_eq!(compact_cleared_versions(&tx, actor_id)?, 0);
+            tx.commit()?;
+        }
+
+        Ok(())
+    }
+
+    // runs `stmts` against a fresh in-memory db created from `schema`,
+    // returns its site id and every change it produced, in seq order
+    fn crr_changes(schema: &str, stmts: &str) -> eyre::Result<(ActorId, Vec<Change>)> {
+        let conn = CrConn::init(Connection::open_in_memory()?)?;
+        conn.execute_batch(schema)?;
+        conn.execute_batch(stmts)?;
+
+        let actor_id = conn.query_row("SELECT crsql_site_id()", [], |row| row.get(0))?;
+        let changes = conn
+            .prepare(r#"SELECT "table", pk, cid, val, col_version, db_version, seq, COALESCE(site_id, crsql_site_id()), cl FROM crsql_changes ORDER BY seq ASC"#)?
+            .query_map([], corro_types::api::row_to_change)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok((actor_id, changes))
+    }
+
+    // all of `changes` as the first version of their actor
+    fn complete_parts(changes: Vec<Change>) -> ChangesetParts {
+        let last_seq = changes.last().map(|change| change.seq).unwrap_or_default();
+        ChangesetParts {
+            version: Version(1),
+            changes,
+            seqs: CrsqlSeq(0)..=last_seq,
+            last_seq,
+            ts: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_process_complete_version_large_changeset() -> eyre::Result<()> {
+        const ROWS: i64 = 10_000;
+
+        let schema = "CREATE TABLE foo (id INTEGER NOT NULL PRIMARY KEY, text TEXT NOT NULL DEFAULT '') WITHOUT ROWID; SELECT crsql_as_crr('foo');";
+
+        let (actor_id, changes) = crr_changes(
+            schema,
+            &format!("WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < {ROWS}) INSERT INTO foo (id, text) SELECT i, 'row ' || i FROM n"),
+        )?;
+
+        // 1 change per non-pk column
+        assert_eq!(changes.len(), ROWS as usize);
+
+        let mut dst = CrConn::init(Connection::open_in_memory()?)?;
+        dst.execute_batch(schema)?;
+
+        let tx = dst.transaction()?;
+        let (known, changeset, rejected) = process_complete_version(
+            &tx,
+            actor_id,
+            None,
+            Version(1)..=Version(1),
+            complete_parts(changes.clone()),
+        )?;
+        tx.commit()?;
+
+        assert!(matches!(known, KnownDbVersion::Current(_)));
+        assert_eq!(changeset.len(), ROWS as usize);
+        assert_eq!(rejected, 0);
+
+        let (count, last_text): (i64, String) = dst.query_row(
+            "SELECT COUNT(*), (SELECT text FROM foo WHERE id = ?) FROM foo",
+            [ROWS],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        assert_eq!(count, ROWS);
+        assert_eq!(last_text, format!("row {ROWS}"));
+
+        // applying the same changes again has no impact
+        let tx = dst.transaction()?;
+        let (known, changeset, _) = process_complete_version(
+            &tx,
+            actor_id,
+            None,
+            Version(1)..=Version(1),
+            complete_parts(changes),
+        )?;
+        tx.commit()?;
+
+        assert!(matches!(known, KnownDbVersion::Cleared));
+        assert!(changeset.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_complete_version_skips_rejected_changes() -> eyre::Result<()> {
+        let foo = "CREATE TABLE foo (id INTEGER NOT NULL PRIMARY KEY, text TEXT NOT NULL DEFAULT '') WITHOUT ROWID; SELECT crsql_as_crr('foo');";
+        let bar = "CREATE TABLE bar (id INTEGER NOT NULL PRIMARY KEY, text TEXT NOT NULL DEFAULT '') WITHOUT ROWID; SELECT crsql_as_crr('bar');";
+
+        let (actor_id, changes) = crr_changes(
+            &format!("{foo} {bar}"),
+            "BEGIN;
+            INSERT INTO foo (id, text) VALUES (1, 'one');
+            INSERT INTO bar (id, text) VALUES (1, 'unknown');
+            INSERT INTO foo (id, text) VALUES (2, 'two');
+            COMMIT;",
+        )?;
+        assert_eq!(changes.len(), 3);
+
+        // the destination never learned about `bar`
+        let mut dst = CrConn::init(Connection::open_in_memory()?)?;
+        dst.execute_batch(foo)?;
+
+        let tx = dst.transaction()?;
+        let (known, changeset, rejected) = process_complete_version(
+            &tx,
+            actor_id,
+            None,
+            Version(1)..=Version(1),
+            complete_parts(changes),
+        )?;
+        tx.commit()?;
+
+        assert!(matches!(known, KnownDbVersion::Current(_)));
+        assert_eq!(changeset.len(), 2);
+        assert_eq!(rejected, 1);
+
+        let texts = dst
+            .prepare("SELECT text FROM foo ORDER BY id")?
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        assert_eq!(texts, vec!["one", "two"]);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_rejected_changes_still_book_version() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+        let ta = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+        let late = "CREATE TABLE late (id INTEGER NOT NULL PRIMARY KEY, text TEXT NOT NULL DEFAULT '') WITHOUT ROWID";
+        let (actor_id, changes) = crr_changes(
+            &format!(
+                "{TEST_SCHEMA} {late}; SELECT crsql_as_crr('tests'); SELECT crsql_as_crr('late');"
+            ),
+            "BEGIN;
+            INSERT INTO tests (id, text) VALUES (1, 'hello');
+            INSERT INTO late (id, text) VALUES (1, 'late');
+            COMMIT;",
+        )?;
+        let change = ChangeV1 {
+            actor_id,
+            changeset: complete_parts(changes).into(),
+        };
+
+        // the agent doesn't know about `late`, the good change is applied and
+        // the version booked anyway
+        process_multiple_changes(&ta.agent, vec![(change.clone(), ChangeSource::Sync)]).await?;
+
+        let count: i64 =
+            ta.agent
+                .pool()
+                .read()
+                .await?
+                .query_row("SELECT COUNT(*) FROM tests", [], |row| row.get(0))?;
+        assert_eq!(count, 1);
+
+        let booked = ta
+            .agent
+            .bookie()
+            .read("test")
+            .await
+            .get(&actor_id)
+            .cloned()
+            .expect("actor should be booked");
+        assert!(booked.read("test").await.contains_current(&Version(1)));
+
+        // so the version isn't applied again, even once it could be
+        crate::api::public::execute_schema(&ta.agent, vec![late.into()]).await?;
+        process_multiple_changes(&ta.agent, vec![(change, ChangeSource::Sync)]).await?;
+
+        let count: i64 =
+            ta.agent
+                .pool()
+                .read()
+                .await?
+                .query_row("SELECT COUNT(*) FROM late", [], |row| row.get(0))?;
+        assert_eq!(count, 0);
+
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_book_same_version_twice() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
+        let ta = launch_test_agent(|conf| conf.build(), tripwire.clone()).await?;
+
+        let (actor_id, changes) = crr_changes(
+            &format!("{TEST_SCHEMA} SELECT crsql_as_crr('tests');"),
+            "INSERT INTO tests (id, text) VALUES (1, 'hello')",
+        )?;
+
+        let change = ChangeV1 {
+            actor_id,
+            changeset: complete_parts(changes.clone()).into(),
+        };
+
+        // a re-delivered version is not an error
+        for _ in 0..2 {
+            process_multiple_changes(&ta.agent, vec![(change.clone(), ChangeSource::Sync)]).await?;
+        }
+
+        // the same changes under a new version impact nothing locally
+        let mut parts = complete_parts(changes);
+        parts.version = Version(2);
+        process_multiple_changes(
+            &ta.agent,
+            vec![(
+                ChangeV1 {
+                    actor_id,
+                    changeset: parts.into(),
+                },
+                ChangeSource::Sync,
+            )],
+        )
+        .await?;
+
+        let booked = ta
+            .agent
+            .bookie()
+            .read("test")
+            .await
+            .get(&actor_id)
+            .cloned()
+            .expect("actor should be booked");
+        {
+            let booked = booked.read("test").await;
+            assert!(booked.contains_current(&Version(1)));
+            assert!(booked
+                .get(&Version(2))
+                .is_some_and(|known| known.is_cleared()));
+        }
+
+        // cleared versions are written out by the empties loop, at the
+        // latest when shutting down
+        tripwire_tx.send(()).await.ok();
+        tripwire_worker.await;
+        wait_for_all_pending_handles().await;
+
+        let conn = Connection::open(ta.tmpdir.path().join("corrosion.db"))?;
+        let rows: Vec<(Version, Option<Version>, Option<CrsqlDbVersion>)> = conn
+            .prepare("SELECT start_version, end_version, db_version FROM __corro_bookkeeping WHERE actor_id = ? ORDER BY start_version")?
+            .query_map([actor_id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        assert_eq!(rows.len(), 2, "{rows:?}");
+        assert!(matches!(rows[0], (Version(1), None, Some(_))), "{rows:?}");
+        assert_eq!(rows[1], (Version(2), Some(Version(2)), None));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_retry_busy() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("busy.db");
+
+        let mut holder = Connection::open(&path)?;
+        let mut conn = CrConn::init(Connection::open(&path)?)?;
+        // don't let sqlite's own busy handler wait on our behalf
+        conn.busy_timeout(Duration::ZERO)?;
+
+        // hold the write lock for a little while from another connection
+        let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+        let handle = std::thread::spawn(move || -> rusqlite::Result<()> {
+            let tx = holder.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            locked_tx.send(()).unwrap();
+            std::thread::sleep(Duration::from_millis(20));
+            tx.commit()
+        });
+        locked_rx.recv()?;
+
+        assert!(is_busy_error(&conn.immediate_transaction().unwrap_err()));
+
+        let mut attempts = 0;
+        retry_busy(|| {
+            attempts += 1;
+            block_in_place(|| {
+                let tx = conn.immediate_transaction()?;
+                tx.execute_batch("CREATE TABLE IF NOT EXISTS foo (id INTEGER PRIMARY KEY)")?;
+                tx.commit()?;
+                Ok::<_, ChangeError>(())
+            })
+        })
+        .await?;
+        assert!(attempts > 1, "expected at least one retry");
+
+        handle.join().unwrap()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_candidate_weight() {
+        assert!(sync_candidate_weight(Some(1)) > sync_candidate_weight(Some(50)));
+        assert!(sync_candidate_weight(Some(50)) > sync_candidate_weight(Some(300)));
+        assert_eq!(
+            sync_candidate_weight(None),
+            sync_candidate_weight(Some(UNKNOWN_RTT_MS))
+        );
+
+        let candidates = [(Some(1), 0usize), (Some(50), 1), (Some(300), 2), (None, 3)];
+        let mut picks = [0usize; 4];
+
+        let mut rng = StdRng::seed_from_u64(42);
+        for _ in 0..10_000 {
+            let (_, idx) = candidates
+                .choose_weighted(&mut rng, |(rtt, _)| sync_candidate_weight(*rtt))
+                .unwrap();
+            picks[*idx] += 1;
+        }
+
+        // closer members are picked more often, but farther ones still get picked
+        assert!(picks[0] > picks[1]);
+        assert!(picks[1] > picks[2]);
+        assert!(picks[2] > 0);
+    }
+
+    #[test]
+    fn scoped_ipv6_addrs() -> eyre::Result<()> {
+        // numeric scopes parse, and survive the text round-trip through
+        // `__corro_members.address`
+        let scoped: SocketAddr = "[fe80::1%2]:8787".parse()?;
+        match scoped {
+            SocketAddr::V6(addr) => assert_eq!(addr.scope_id(), 2),
+            SocketAddr::V4(_) => panic!("expected an IPv6 address"),
+        }
+        assert_eq!(scoped.to_string(), "[fe80::1%2]:8787");
+        assert_eq!(scoped.to_string().parse::<SocketAddr>()?, scoped);
+
+        let unscoped: SocketAddr = "[fe80::1]:8787".parse()?;
+        let other_scope: SocketAddr = "[fe80::1%3]:8787".parse()?;
+        let other_port: SocketAddr = "[fe80::1%2]:8788".parse()?;
+
+        assert!(!is_peer_addr(scoped, &scoped));
+        // a missing scope can't tell us apart from a peer
+        assert!(!is_peer_addr(scoped, &unscoped));
+        assert!(!is_peer_addr(unscoped, &scoped));
+        // same address on another link
+        assert!(is_peer_addr(scoped, &other_scope));
+        assert!(is_peer_addr(scoped, &other_port));
+        assert!(!is_peer_addr(scoped, &"127.0.0.1:8787".parse()?));
+
+        // interface names aren't supported, and refused upfront
+        let conf = Config::builder()
+            .db_path("/tmp/unused.db")
+            .api_addr("127.0.0.1:0".parse()?)
+            .gossip_addr("[::1]:0".parse()?)
+            .bootstrap(vec!["[fe80::1%eth0]:8787".to_string()])
+            .build()?;
+        assert!(matches!(
+            conf.validate(),
+            Err(ConfigError::BootstrapScope(entry)) if entry == "[fe80::1%eth0]:8787"
+        ));
+
+        let conf = Config::builder()
+            .db_path("/tmp/unused.db")
+            .api_addr("127.0.0.1:0".parse()?)
+            .gossip_addr("[::1]:0".parse()?)
+            .bootstrap(vec!["[fe80::1%2]:8787".to_string()])
+            .build()?;
+        conf.validate()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_counts() {
+        // defaults scale with the cluster, within bounds
+        assert_eq!(sync_counts(5, None), (3, 6));
+        assert_eq!(sync_counts(500, None), (5, 10));
+        assert_eq!(sync_counts(5000, None), (10, 20));
+
+        assert_eq!(sync_counts(500, Some(50)), (5, 50));
+        assert_eq!(sync_counts(500, Some(2)), (2, 2));
+    }
+
+    #[test]
+    fn test_payload_decode_error_kind() -> eyre::Result<()> {
+        let mut buf = UniPayload::V1(UniPayloadV1::Broadcast(BroadcastV1::Change(ChangeV1 {
+            actor_id: ActorId(uuid::Uuid::new_v4()),
+            changeset: Changeset::Empty {
+                versions: Version(1)..=Version(5),
+            },
+        })))
+        .write_to_vec()?;
+
+        let e = UniPayload::read_from_buffer(&buf[..buf.len() - 1]).unwrap_err();
+        assert_eq!(payload_decode_error_kind(&e), "truncated");
+
+        buf[0] = 0xff;
+        let e = UniPayload::read_from_buffer(&buf).unwrap_err();
+        assert_eq!(payload_decode_error_kind(&e), "invalid");
+
+        Ok(())
+    }
 }
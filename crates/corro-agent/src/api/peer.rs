@@ -2,6 +2,8 @@ use std::cmp;
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
@@ -15,15 +17,15 @@ use corro_types::broadcast::{
     BiPayload, BiPayloadV1, ChangeSource, ChangeV1, Changeset, Timestamp,
 };
 use corro_types::change::{row_to_change, Change, ChunkedChanges};
-use corro_types::config::{GossipConfig, TlsClientConfig};
+use corro_types::config::{GossipConfig, LimitsConfig, TlsClientConfig};
 use corro_types::sync::{
-    generate_sync, SyncMessage, SyncMessageEncodeError, SyncMessageV1, SyncNeedV1, SyncRejectionV1,
-    SyncRequestV1, SyncStateV1, SyncTraceContextV1,
+    generate_sync, SyncCompressionV1, SyncMessage, SyncMessageEncodeError, SyncMessageV1,
+    SyncNeedV1, SyncRejectionV1, SyncRequestV1, SyncStateV1, SyncTraceContextV1,
 };
 use futures::stream::FuturesUnordered;
 use futures::{Future, Stream, TryFutureExt, TryStreamExt};
 use itertools::Itertools;
-use metrics::{counter, increment_counter};
+use metrics::{counter, gauge, increment_counter};
 use quinn::{RecvStream, SendStream};
 use rand::seq::SliceRandom;
 use rangemap::RangeInclusiveSet;
@@ -32,15 +34,16 @@ use speedy::Writable;
 use tokio::io::AsyncWriteExt;
 use tokio::sync::mpsc::{self, unbounded_channel, Sender};
 use tokio::task::block_in_place;
-use tokio::time::timeout;
+use tokio::time::{timeout, MissedTickBehavior};
 use tokio_stream::wrappers::{ReceiverStream, UnboundedReceiverStream};
 use tokio_stream::StreamExt as TokioStreamExt;
 // use tokio_stream::StreamExt as TokioStreamExt;
 use tokio_util::codec::{Encoder, FramedRead, LengthDelimitedCodec};
 use tracing::{debug, error, info, info_span, trace, warn, Instrument};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tripwire::{Outcome, PreemptibleFutureExt, Tripwire};
 
-use crate::agent::SyncRecvError;
+use crate::agent::{process_multiple_changes, SyncRecvError};
 use crate::transport::{Transport, TransportError};
 
 use corro_types::{
@@ -129,7 +132,10 @@ fn build_quinn_transport_config(config: &GossipConfig) -> quinn::TransportConfig
     transport_config
 }
 
-async fn build_quinn_server_config(config: &GossipConfig) -> eyre::Result<quinn::ServerConfig> {
+async fn build_quinn_server_config(
+    config: &GossipConfig,
+    limits: &LimitsConfig,
+) -> eyre::Result<quinn::ServerConfig> {
     let mut server_config = if config.plaintext {
         quinn_plaintext::server_config()
     } else {
@@ -205,15 +211,21 @@ async fn build_quinn_server_config(config: &GossipConfig) -> eyre::Result<quinn:
         quinn::ServerConfig::with_crypto(Arc::new(server_crypto.with_single_cert(certs, key)?))
     };
 
-    let transport_config = build_quinn_transport_config(config);
+    let mut transport_config = build_quinn_transport_config(config);
+
+    // broadcasts are sent over unidirectional streams
+    transport_config.max_concurrent_uni_streams(limits.broadcast_concurrency.into());
 
     server_config.transport_config(Arc::new(transport_config));
 
     Ok(server_config)
 }
 
-pub async fn gossip_server_endpoint(config: &GossipConfig) -> eyre::Result<quinn::Endpoint> {
-    let server_config = build_quinn_server_config(config).await?;
+pub async fn gossip_server_endpoint(
+    config: &GossipConfig,
+    limits: &LimitsConfig,
+) -> eyre::Result<quinn::Endpoint> {
+    let server_config = build_quinn_server_config(config, limits).await?;
 
     Ok(quinn::Endpoint::server(server_config, config.bind_addr)?)
 }
@@ -365,6 +377,7 @@ fn handle_known_version(
     seqs_needed: Vec<RangeInclusive<CrsqlSeq>>,
     last_seq: CrsqlSeq,
     ts: Timestamp,
+    skip_tables: &[String],
     sender: &Sender<SyncMessage>,
 ) -> eyre::Result<()> {
     debug!(%actor_id, %version, "handle known version! known: {init_known:?}, seqs_needed: {seqs_needed:?}");
@@ -408,10 +421,15 @@ fn handle_known_version(
                 let start_seq = range_needed.start();
                 let end_seq = range_needed.end();
 
-                let rows = prepped.query_map(
-                    params![site_id, db_version, start_seq, end_seq],
-                    row_to_change,
-                )?;
+                // the seqs of skipped changes are still covered by the chunks sent
+                let rows = prepped
+                    .query_map(
+                        params![site_id, db_version, start_seq, end_seq],
+                        row_to_change,
+                    )?
+                    .filter(|res| {
+                        !matches!(res, Ok(change) if skip_tables.iter().any(|t| t == change.table.as_str()))
+                    });
 
                 // drop write lock!
                 drop(bw);
@@ -506,6 +524,7 @@ fn handle_known_version(
                                 seqs_needed,
                                 last_seq,
                                 ts,
+                                skip_tables,
                                 sender,
                             );
                         }
@@ -570,6 +589,7 @@ async fn process_version(
     known_version: KnownDbVersion,
     booked: &Booked,
     mut seqs_needed: Vec<RangeInclusive<CrsqlSeq>>,
+    skip_tables: &[String],
     sender: &Sender<SyncMessage>,
 ) -> eyre::Result<()> {
     let mut conn = pool.read().await?;
@@ -598,6 +618,7 @@ async fn process_version(
             seqs_needed,
             last_seq,
             ts,
+            skip_tables,
             sender,
         )
     })?;
@@ -669,6 +690,7 @@ async fn process_sync(
     local_actor_id: ActorId,
     pool: SplitPool,
     bookie: Bookie,
+    no_broadcast: Arc<Vec<String>>,
     sender: Sender<SyncMessage>,
     recv: mpsc::Receiver<SyncRequestV1>,
 ) -> eyre::Result<()> {
@@ -718,6 +740,12 @@ async fn process_sync(
             };
 
             let is_local = actor_id == local_actor_id;
+            // only our own changes to no-broadcast tables are kept to ourselves
+            let skip_tables = if is_local {
+                no_broadcast.clone()
+            } else {
+                Arc::default()
+            };
 
             let mut cleared: RangeInclusiveSet<Version> = RangeInclusiveSet::new();
 
@@ -773,6 +801,7 @@ async fn process_sync(
             for (version, known_version) in current_haves.drain(..) {
                 let pool = pool.clone();
                 let booked = booked.clone();
+                let skip_tables = skip_tables.clone();
                 let sender = sender.clone();
                 if job_tx
                     .send(Box::pin(async move {
@@ -784,6 +813,7 @@ async fn process_sync(
                             known_version,
                             &booked,
                             vec![],
+                            &skip_tables,
                             &sender,
                         )
                         .await
@@ -797,6 +827,7 @@ async fn process_sync(
             for (version, known_version, seqs_needed) in partial_needs.drain(..) {
                 let pool = pool.clone();
                 let booked = booked.clone();
+                let skip_tables = skip_tables.clone();
                 let sender = sender.clone();
                 if job_tx
                     .send(Box::pin(async move {
@@ -808,6 +839,7 @@ async fn process_sync(
                             known_version,
                             &booked,
                             seqs_needed,
+                            &skip_tables,
                             &sender,
                         )
                         .await
@@ -830,6 +862,89 @@ async fn process_sync(
     Ok(())
 }
 
+/// Writes the changes of an actor's versions to a file, framed like a sync
+/// response so they can be imported with [import_changes] on another node
+pub async fn export_changes(
+    agent: &Agent,
+    actor_id: ActorId,
+    versions: RangeInclusive<Version>,
+    path: &Path,
+) -> eyre::Result<usize> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .await?;
+
+    let (req_tx, req_rx) = mpsc::channel(1);
+    req_tx
+        .send(vec![(actor_id, vec![SyncNeedV1::Full { versions }])])
+        .await?;
+    drop(req_tx);
+
+    let (msg_tx, mut msg_rx) = mpsc::channel(256);
+
+    // same path as serving a sync request for these versions
+    let process = tokio::spawn(process_sync(
+        agent.actor_id(),
+        agent.pool().clone(),
+        agent.bookie().clone(),
+        Arc::new(agent.config().tables.no_broadcast.clone()),
+        msg_tx,
+        req_rx,
+    ));
+
+    let mut codec = LengthDelimitedCodec::new();
+    let mut encode_buf = BytesMut::new();
+    let mut send_buf = BytesMut::new();
+
+    let mut count = 0;
+    while let Some(msg) = msg_rx.recv().await {
+        if let SyncMessage::V1(SyncMessageV1::Changeset(ref change)) = msg {
+            count += cmp::max(change.len(), 1);
+        }
+        encode_sync_msg(&mut codec, &mut encode_buf, &mut send_buf, msg)?;
+        file.write_all_buf(&mut send_buf).await?;
+    }
+
+    process.await??;
+    file.sync_all().await?;
+
+    Ok(count)
+}
+
+/// Applies changes from a file written by [export_changes], versions we
+/// already know about are skipped
+pub async fn import_changes(agent: &Agent, path: &Path) -> eyre::Result<usize> {
+    const IMPORT_BATCH_SIZE: usize = 100;
+
+    let mut read = FramedRead::new(
+        tokio::fs::File::open(path).await?,
+        LengthDelimitedCodec::new(),
+    );
+
+    let mut count = 0;
+    let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+    while let Some(msg) = read_sync_msg(&mut read).await? {
+        match msg {
+            SyncMessage::V1(SyncMessageV1::Changeset(change)) => {
+                count += cmp::max(change.len(), 1);
+                batch.push((change, ChangeSource::Sync));
+                if batch.len() >= IMPORT_BATCH_SIZE {
+                    process_multiple_changes(agent, std::mem::take(&mut batch)).await?;
+                }
+            }
+            _ => eyre::bail!("unexpected message in changes file, expected a changeset"),
+        }
+    }
+
+    if !batch.is_empty() {
+        process_multiple_changes(agent, batch).await?;
+    }
+
+    Ok(count)
+}
+
 fn chunk_range<T: std::iter::Step + std::ops::Add<u64, Output = T> + std::cmp::Ord + Copy>(
     range: RangeInclusive<T>,
     chunk_size: usize,
@@ -921,12 +1036,43 @@ pub async fn read_sync_msg<R: Stream<Item = std::io::Result<BytesMut>> + Unpin>(
     }
 }
 
-#[tracing::instrument(skip_all, err)]
+fn update_clock_from_peer(agent: &Agent, actor_id: ActorId, ts: Timestamp) {
+    let id = match actor_id.try_into() {
+        Ok(id) => id,
+        Err(e) => {
+            error!("could not convert ActorId to uhlc ID: {e}");
+            return;
+        }
+    };
+
+    // uhlc only refuses timestamps too far ahead of our own clock (max delta)
+    if let Err(e) = agent
+        .clock()
+        .update_with_timestamp(&uhlc::Timestamp::new(ts.to_ntp64(), id))
+    {
+        warn!(%actor_id, "rejected clock from peer, one of our clocks is likely skewed: {e}");
+        increment_counter!("corro.clock.rejected.count", "actor_id" => actor_id.to_string());
+    }
+}
+
+// how often a sync still receiving changes reports how far along it is
+const SYNC_PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Records how much a sync with a peer received so far, so long backfills
+/// can be told apart from stuck ones.
+fn report_sync_progress(actor_id: ActorId, changes: usize, bytes: u64) {
+    let actor_id = actor_id.to_string();
+    gauge!("corro.sync.client.progress", changes as f64, "actor_id" => actor_id.clone(), "type" => "changes");
+    gauge!("corro.sync.client.progress", bytes as f64, "actor_id" => actor_id, "type" => "bytes");
+}
+
+#[tracing::instrument(skip_all, fields(actor_id = %agent.actor_id(), peers = members.len()), err)]
 pub async fn parallel_sync(
     agent: &Agent,
     transport: &Transport,
     members: Vec<(ActorId, SocketAddr)>,
     our_sync_state: SyncStateV1,
+    mut tripwire: Tripwire,
 ) -> Result<usize, SyncError> {
     trace!(
         self_actor_id = %agent.actor_id(),
@@ -962,7 +1108,7 @@ pub async fn parallel_sync(
                         &mut codec,
                         &mut encode_buf,
                         &mut send_buf,
-                        BiPayload::V1(BiPayloadV1::SyncStart {actor_id: agent.actor_id(), trace_ctx}),
+                        BiPayload::V1(BiPayloadV1::SyncStart {actor_id: agent.actor_id(), trace_ctx, compression: Some(SyncCompressionV1::Zstd)}),
                         &mut tx,
                     ).instrument(info_span!("write_sync_start"))
                     .await?;
@@ -994,19 +1140,7 @@ pub async fn parallel_sync(
                     trace!(%actor_id, self_actor_id = %agent.actor_id(), "read state payload: {their_sync_state:?}");
 
                     match timeout(Duration::from_secs(2), read_sync_msg(&mut read)).instrument(info_span!("read_sync_clock")).await.map_err(SyncRecvError::from)??  {
-                        Some(SyncMessage::V1(SyncMessageV1::Clock(ts))) => match actor_id.try_into() {
-                            Ok(id) => {
-                                if let Err(e) = agent
-                                    .clock()
-                                    .update_with_timestamp(&uhlc::Timestamp::new(ts.to_ntp64(), id))
-                                {
-                                    warn!("could not update clock from actor {actor_id}: {e}");
-                                }
-                            }
-                            Err(e) => {
-                                error!("could not convert ActorId to uhlc ID: {e}");
-                            }
-                        },
+                        Some(SyncMessage::V1(SyncMessageV1::Clock(ts))) => update_clock_from_peer(agent, actor_id, ts),
                         Some(_) => return Err(SyncRecvError::ExpectedClockMessage.into()),
                         None => return Err(SyncRecvError::UnexpectedEndOfStream.into()),
                     }
@@ -1024,8 +1158,17 @@ pub async fn parallel_sync(
         }.instrument(info_span!("sync_client_handshake", %actor_id, %addr))
     }))
     .collect::<Vec<(ActorId, SocketAddr, Result<_, SyncError>)>>()
+    .preemptible(&mut tripwire)
     .await;
 
+    let results = match results {
+        Outcome::Completed(results) => results,
+        Outcome::Preempted(_) => {
+            info!("sync interrupted by shutdown during handshake");
+            return Ok(0);
+        }
+    };
+
     debug!("collected member needs and such!");
 
     #[allow(clippy::manual_try_fold)]
@@ -1220,17 +1363,44 @@ pub async fn parallel_sync(
             }
             servers = next_servers;
         }
-    }.instrument(info_span!("send_sync_requests")));
+    }.instrument(info_span!("send_sync_requests")).preemptible(tripwire.clone()));
 
     // now handle receiving changesets!
 
-    let counts = FuturesUnordered::from_iter(readers.into_iter().map(|(actor_id, mut read)| {
+    let counts = FuturesUnordered::from_iter(readers.into_iter().map(|(actor_id, read)| {
         let tx_changes = agent.tx_changes().clone();
+        let mut tripwire = tripwire.clone();
         async move {
             let mut count = 0;
 
+            let bytes = AtomicU64::new(0);
+            let mut read = TokioStreamExt::map(read, |res| {
+                if let Ok(buf) = &res {
+                    bytes.fetch_add(buf.len() as u64, Ordering::Relaxed);
+                }
+                res
+            });
+
+            let start = Instant::now();
+            report_sync_progress(actor_id, 0, 0);
+            let mut progress_interval =
+                tokio::time::interval_at((start + SYNC_PROGRESS_INTERVAL).into(), SYNC_PROGRESS_INTERVAL);
+            progress_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
             loop {
-                match read_sync_msg(&mut read).await {
+                let res = tokio::select! {
+                    res = read_sync_msg(&mut read) => res,
+                    _ = progress_interval.tick() => {
+                        report_sync_progress(actor_id, count, bytes.load(Ordering::Relaxed));
+                        info!(%actor_id, "sync in progress: received {count} changes ({} bytes) in {:?}", bytes.load(Ordering::Relaxed), start.elapsed());
+                        continue;
+                    }
+                    _ = &mut tripwire => {
+                        info!(%actor_id, "sync interrupted by shutdown after receiving {count} changes");
+                        break;
+                    }
+                };
+                match res {
                     Ok(None) => {
                         break;
                     }
@@ -1261,6 +1431,10 @@ pub async fn parallel_sync(
                             warn!("received sync clock message unexpectedly, ignoring");
                             continue;
                         }
+                        SyncMessage::V1(SyncMessageV1::Compressed { .. }) => {
+                            warn!("received nested compressed sync message unexpectedly, ignoring");
+                            continue;
+                        }
                         SyncMessage::V1(SyncMessageV1::Rejection(rejection)) => {
                             return Err(rejection.into())
                         }
@@ -1268,6 +1442,7 @@ pub async fn parallel_sync(
                 }
             }
 
+            report_sync_progress(actor_id, count, bytes.load(Ordering::Relaxed));
             debug!(%actor_id, %count, "done reading sync messages");
 
             Ok(count)
@@ -1285,11 +1460,128 @@ pub async fn parallel_sync(
     Ok(counts.into_iter().flatten().sum::<usize>())
 }
 
+const RANGE_SYNC_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Requests a specific range of versions for an actor from a single peer,
+/// regardless of what we think we already have.
+#[tracing::instrument(skip(agent, transport), err)]
+pub async fn range_sync(
+    agent: &Agent,
+    transport: &Transport,
+    addr: SocketAddr,
+    actor_id: ActorId,
+    versions: RangeInclusive<Version>,
+) -> Result<usize, SyncError> {
+    let mut codec = LengthDelimitedCodec::new();
+    let mut send_buf = BytesMut::new();
+    let mut encode_buf = BytesMut::new();
+
+    let mut trace_ctx = SyncTraceContextV1::default();
+    opentelemetry::global::get_text_map_propagator(|prop| {
+        prop.inject_context(&tracing::Span::current().context(), &mut trace_ctx)
+    });
+
+    let (mut tx, rx) = transport.open_bi(addr).await?;
+    let mut read = FramedRead::new(rx, LengthDelimitedCodec::new());
+
+    encode_write_bipayload_msg(
+        &mut codec,
+        &mut encode_buf,
+        &mut send_buf,
+        BiPayload::V1(BiPayloadV1::SyncStart {
+            actor_id: agent.actor_id(),
+            trace_ctx,
+            compression: Some(SyncCompressionV1::Zstd),
+        }),
+        &mut tx,
+    )
+    .await?;
+
+    encode_write_sync_msg(
+        &mut codec,
+        &mut encode_buf,
+        &mut send_buf,
+        SyncMessage::V1(SyncMessageV1::Clock(agent.clock().new_timestamp().into())),
+        &mut tx,
+    )
+    .await?;
+
+    // the peer always starts by sending its state and clock, we don't need either
+    match timeout(Duration::from_secs(2), read_sync_msg(&mut read))
+        .await
+        .map_err(SyncRecvError::from)??
+    {
+        Some(SyncMessage::V1(SyncMessageV1::State(_))) => {}
+        Some(SyncMessage::V1(SyncMessageV1::Rejection(rejection))) => return Err(rejection.into()),
+        Some(_) => return Err(SyncRecvError::ExpectedSyncState.into()),
+        None => return Err(SyncRecvError::UnexpectedEndOfStream.into()),
+    }
+
+    match timeout(Duration::from_secs(2), read_sync_msg(&mut read))
+        .await
+        .map_err(SyncRecvError::from)??
+    {
+        Some(SyncMessage::V1(SyncMessageV1::Clock(_))) => {}
+        Some(_) => return Err(SyncRecvError::ExpectedClockMessage.into()),
+        None => return Err(SyncRecvError::UnexpectedEndOfStream.into()),
+    }
+
+    let needs: Vec<_> = chunk_range(versions, 10)
+        .map(|versions| SyncNeedV1::Full { versions })
+        .collect();
+
+    for needs in needs.chunks(10) {
+        encode_write_sync_msg(
+            &mut codec,
+            &mut encode_buf,
+            &mut send_buf,
+            SyncMessage::V1(SyncMessageV1::Request(vec![(actor_id, needs.to_vec())])),
+            &mut tx,
+        )
+        .await?;
+    }
+
+    tx.finish().await.map_err(SyncSendError::from)?;
+
+    let tx_changes = agent.tx_changes();
+    let mut count = 0;
+
+    loop {
+        // a peer that stops sending shouldn't hold the request up forever
+        match timeout(RANGE_SYNC_READ_TIMEOUT, read_sync_msg(&mut read))
+            .await
+            .map_err(SyncRecvError::from)??
+        {
+            None => break,
+            Some(SyncMessage::V1(SyncMessageV1::Changeset(change))) => {
+                let changes_len = cmp::max(change.len(), 1);
+                count += changes_len;
+                counter!("corro.sync.changes.recv", changes_len as u64, "actor_id" => actor_id.to_string());
+                tx_changes
+                    .send((change, ChangeSource::Sync))
+                    .await
+                    .map_err(|_| SyncRecvError::ChangesChannelClosed)?;
+            }
+            Some(SyncMessage::V1(SyncMessageV1::Rejection(rejection))) => {
+                return Err(rejection.into())
+            }
+            Some(_) => {
+                warn!("received unexpected sync message during range sync, ignoring");
+            }
+        }
+    }
+
+    debug!(%actor_id, %addr, %count, "done syncing range");
+
+    Ok(count)
+}
+
 #[tracing::instrument(skip(agent, their_actor_id, read, write), fields(actor_id = %their_actor_id), err)]
 pub async fn serve_sync(
     agent: &Agent,
     their_actor_id: ActorId,
     trace_ctx: SyncTraceContextV1,
+    compression: Option<SyncCompressionV1>,
     mut read: FramedRead<RecvStream, LengthDelimitedCodec>,
     mut write: SendStream,
 ) -> Result<usize, SyncError> {
@@ -1307,19 +1599,9 @@ pub async fn serve_sync(
         .instrument(info_span!("read_peer_clock"))
         .await?
     {
-        Some(SyncMessage::V1(SyncMessageV1::Clock(ts))) => match their_actor_id.try_into() {
-            Ok(id) => {
-                if let Err(e) = agent
-                    .clock()
-                    .update_with_timestamp(&uhlc::Timestamp::new(ts.to_ntp64(), id))
-                {
-                    warn!("could not update clock from actor {their_actor_id}: {e}");
-                }
-            }
-            Err(e) => {
-                error!("could not convert ActorId to uhlc ID: {e}");
-            }
-        },
+        Some(SyncMessage::V1(SyncMessageV1::Clock(ts))) => {
+            update_clock_from_peer(agent, their_actor_id, ts)
+        }
         Some(_) => return Err(SyncRecvError::ExpectedClockMessage.into()),
         None => return Err(SyncRecvError::UnexpectedEndOfStream.into()),
     }
@@ -1369,6 +1651,7 @@ pub async fn serve_sync(
             agent.actor_id(),
             agent.pool().clone(),
             agent.bookie().clone(),
+            Arc::new(agent.config().tables.no_broadcast.clone()),
             tx,
             rx_need,
         )
@@ -1402,9 +1685,13 @@ pub async fn serve_sync(
                     },
 
                     maybe_msg = rx.recv() => match maybe_msg {
-                        Some(msg) => {
+                        Some(mut msg) => {
                             if let SyncMessage::V1(SyncMessageV1::Changeset(change)) = &msg {
                                 count += change.len();
+                                // only compress changesets, everything else is tiny
+                                if let Some(compression) = compression {
+                                    msg = msg.compress(compression).map_err(SyncSendError::from)?;
+                                }
                             }
                             encode_sync_msg(&mut codec, &mut encode_buf, &mut send_buf, msg)?;
 
@@ -1479,6 +1766,10 @@ pub async fn serve_sync(
                             warn!(actor_id = %their_actor_id, "received sync clock message more than once, ignoring");
                             continue;
                         }
+                        SyncMessage::V1(SyncMessageV1::Compressed { .. }) => {
+                            warn!(actor_id = %their_actor_id, "received compressed sync message unexpectedly, ignoring");
+                            continue;
+                        }
                         SyncMessage::V1(SyncMessageV1::Rejection(rejection)) => {
                             return Err(rejection.into())
                         }
@@ -1609,14 +1900,15 @@ mod tests {
         )
         .await?;
 
+        // the schema change was recorded locally first
         let known1 = KnownDbVersion::Current(CurrentVersion {
-            db_version: CrsqlDbVersion(1),
+            db_version: CrsqlDbVersion(3),
             last_seq: CrsqlSeq(0),
             ts,
         });
 
         let known2 = KnownDbVersion::Current(CurrentVersion {
-            db_version: CrsqlDbVersion(2),
+            db_version: CrsqlDbVersion(4),
             last_seq: CrsqlSeq(0), // original last seq
             ts,
         });
@@ -1665,6 +1957,7 @@ mod tests {
                     vec![CrsqlSeq(0)..=CrsqlSeq(0)],
                     CrsqlSeq(0),
                     ts,
+                    &[],
                     &tx,
                 )
             })?;
@@ -1676,7 +1969,10 @@ mod tests {
                     actor_id,
                     changeset: Changeset::Full {
                         version: Version(1),
-                        changes: vec![change1],
+                        changes: vec![Change {
+                            db_version: CrsqlDbVersion(3),
+                            ..change1
+                        }],
                         seqs: CrsqlSeq(0)..=CrsqlSeq(0),
                         last_seq: CrsqlSeq(0),
                         ts,
@@ -1695,6 +1991,7 @@ mod tests {
                     vec![CrsqlSeq(0)..=CrsqlSeq(0)],
                     CrsqlSeq(0),
                     ts,
+                    &[],
                     &tx,
                 )
             })?;
@@ -1706,7 +2003,10 @@ mod tests {
                     actor_id,
                     changeset: Changeset::Full {
                         version: Version(2),
-                        changes: vec![change2],
+                        changes: vec![Change {
+                            db_version: CrsqlDbVersion(4),
+                            ..change2
+                        }],
                         seqs: CrsqlSeq(0)..=CrsqlSeq(0),
                         last_seq: CrsqlSeq(0),
                         ts,
@@ -1775,9 +2075,17 @@ mod tests {
             plaintext: false,
             max_mtu: None,
             disable_gso: false,
+            bootstrap_interval_secs: 300,
+            max_datagram_size: 1178,
+            broadcast_buffer: 10240,
+            announce_fanout: 10,
+            signing_key: None,
+            signing_required: true,
+            allow_cidrs: vec![],
+            deny_cidrs: vec![],
         };
 
-        let server = gossip_server_endpoint(&gossip_config).await?;
+        let server = gossip_server_endpoint(&gossip_config, &LimitsConfig::default()).await?;
         let addr = server.local_addr()?;
 
         let client = gossip_client_endpoint(&gossip_config).await?;
@@ -1833,4 +2141,86 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_mutual_tls_untrusted_client() -> eyre::Result<()> {
+        let ca_cert = generate_ca()?;
+        let (server_cert, server_cert_signed) = generate_server_cert(
+            &ca_cert.serialize_pem()?,
+            &ca_cert.serialize_private_key_pem(),
+            "127.0.0.1".parse()?,
+        )?;
+
+        // client cert signed by a CA the server doesn't know about
+        let other_ca_cert = generate_ca()?;
+        let (client_cert, client_cert_signed) = generate_client_cert(
+            &other_ca_cert.serialize_pem()?,
+            &other_ca_cert.serialize_private_key_pem(),
+        )?;
+
+        let tmpdir = TempDir::new()?;
+        let base_path = Utf8PathBuf::from(tmpdir.path().display().to_string());
+
+        let cert_file = base_path.join("cert.pem");
+        let key_file = base_path.join("cert.key");
+        let ca_file = base_path.join("ca.pem");
+
+        let client_cert_file = base_path.join("client-cert.pem");
+        let client_key_file = base_path.join("client-cert.key");
+
+        tokio::fs::write(&cert_file, &server_cert_signed).await?;
+        tokio::fs::write(&key_file, server_cert.serialize_private_key_pem()).await?;
+
+        tokio::fs::write(&ca_file, ca_cert.serialize_pem()?).await?;
+
+        tokio::fs::write(&client_cert_file, &client_cert_signed).await?;
+        tokio::fs::write(&client_key_file, client_cert.serialize_private_key_pem()).await?;
+
+        let gossip_config = GossipConfig {
+            bind_addr: "127.0.0.1:0".parse()?,
+            bootstrap: vec![],
+            tls: Some(TlsConfig {
+                cert_file,
+                key_file,
+                ca_file: Some(ca_file),
+                client: Some(TlsClientConfig {
+                    cert_file: client_cert_file,
+                    key_file: client_key_file,
+                }),
+                insecure: false,
+            }),
+            idle_timeout_secs: 30,
+            plaintext: false,
+            max_mtu: None,
+            disable_gso: false,
+            bootstrap_interval_secs: 300,
+            max_datagram_size: 1178,
+            broadcast_buffer: 10240,
+            announce_fanout: 10,
+            signing_key: None,
+            signing_required: true,
+            allow_cidrs: vec![],
+            deny_cidrs: vec![],
+        };
+
+        let server = gossip_server_endpoint(&gossip_config, &LimitsConfig::default()).await?;
+        let addr = server.local_addr()?;
+
+        let client = gossip_client_endpoint(&gossip_config).await?;
+
+        let (_client_res, server_res) = tokio::join!(
+            async { client.connect(addr, &addr.ip().to_string()).unwrap().await },
+            async {
+                match server.accept().await {
+                    None => eyre::bail!("None accept!"),
+                    Some(connecting) => Ok(connecting.await),
+                }
+            }
+        );
+
+        // the server refuses the handshake
+        assert!(server_res?.is_err());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,229 @@
+use axum::Extension;
+use corro_types::{
+    agent::Agent,
+    api::{ExecResponse, ExecResult},
+};
+use hyper::{body::HttpBody, header, StatusCode};
+use miniz_oxide::inflate::{decompress_to_vec_with_limit, TINFLStatus};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const GZIP_DEFLATE: u8 = 8;
+const GZIP_HEADER_LEN: usize = 10;
+const GZIP_TRAILER_LEN: usize = 8;
+
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+#[derive(Debug, thiserror::Error)]
+pub enum GunzipError {
+    #[error("invalid gzip header")]
+    Header,
+    #[error("decompressed body is larger than {0} bytes")]
+    TooLarge(usize),
+    #[error("invalid gzip data: {0:?}")]
+    Inflate(TINFLStatus),
+    #[error("gzip checksum mismatch")]
+    Checksum,
+}
+
+/// Decompresses a single-member gzip payload (RFC 1952), refusing to
+/// produce more than `limit` bytes.
+pub fn gunzip(input: &[u8], limit: usize) -> Result<Vec<u8>, GunzipError> {
+    if input.len() < GZIP_HEADER_LEN + GZIP_TRAILER_LEN
+        || input[..2] != GZIP_MAGIC
+        || input[2] != GZIP_DEFLATE
+    {
+        return Err(GunzipError::Header);
+    }
+
+    let flags = input[3];
+    let mut pos = GZIP_HEADER_LEN;
+
+    if flags & FEXTRA != 0 {
+        let xlen = input
+            .get(pos..pos + 2)
+            .ok_or(GunzipError::Header)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)?;
+        pos += 2 + xlen;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let nul = input
+                .get(pos..)
+                .and_then(|rest| rest.iter().position(|b| *b == 0))
+                .ok_or(GunzipError::Header)?;
+            pos += nul + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+
+    let trailer_start = input
+        .len()
+        .checked_sub(GZIP_TRAILER_LEN)
+        .filter(|start| *start >= pos)
+        .ok_or(GunzipError::Header)?;
+
+    let output = decompress_to_vec_with_limit(&input[pos..trailer_start], limit).map_err(|e| {
+        match e.status {
+            TINFLStatus::HasMoreOutput => GunzipError::TooLarge(limit),
+            status => GunzipError::Inflate(status),
+        }
+    })?;
+
+    let trailer = &input[trailer_start..];
+    let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    let isize = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+
+    if crc32fast::hash(&output) != crc || output.len() as u32 != isize {
+        return Err(GunzipError::Checksum);
+    }
+
+    Ok(output)
+}
+
+fn error_response(status: StatusCode, error: String) -> (StatusCode, axum::Json<ExecResponse>) {
+    (
+        status,
+        axum::Json(ExecResponse {
+            results: vec![ExecResult::Error { error }],
+            time: 0.0,
+            version: None,
+            changes: 0,
+        }),
+    )
+}
+
+/// Transparently decompresses `Content-Encoding: gzip` request bodies. Both
+/// the compressed and the decompressed body are held to `api.max_body_bytes`.
+pub async fn decompress_gzip(
+    Extension(agent): Extension<Agent>,
+    request: axum::http::Request<hyper::Body>,
+    next: axum::middleware::Next<hyper::Body>,
+) -> Result<axum::response::Response, (StatusCode, axum::Json<ExecResponse>)> {
+    let encoding = match request.headers().get(header::CONTENT_ENCODING) {
+        None => return Ok(next.run(request).await),
+        Some(encoding) => encoding
+            .to_str()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase(),
+    };
+
+    match encoding.as_str() {
+        "identity" => return Ok(next.run(request).await),
+        "gzip" | "x-gzip" => {}
+        _ => {
+            return Err(error_response(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                format!("unsupported content-encoding: {encoding}"),
+            ))
+        }
+    }
+
+    let limit = agent.config().api.max_body_bytes;
+    let (mut parts, mut body) = request.into_parts();
+
+    let mut compressed = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| error_response(StatusCode::BAD_REQUEST, e.to_string()))?;
+        if compressed.len() + chunk.len() > limit {
+            return Err(error_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("request body is larger than {limit} bytes"),
+            ));
+        }
+        compressed.extend_from_slice(&chunk);
+    }
+
+    let decompressed = gunzip(&compressed, limit).map_err(|e| {
+        let status = match e {
+            GunzipError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        error_response(status, e.to_string())
+    })?;
+
+    parts.headers.remove(header::CONTENT_ENCODING);
+    parts
+        .headers
+        .insert(header::CONTENT_LENGTH, decompressed.len().into());
+
+    Ok(next
+        .run(axum::http::Request::from_parts(
+            parts,
+            hyper::Body::from(decompressed),
+        ))
+        .await)
+}
+
+/// Minimal gzip encoder, only used to build request bodies in tests
+#[cfg(test)]
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![
+        GZIP_MAGIC[0],
+        GZIP_MAGIC[1],
+        GZIP_DEFLATE,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0xff,
+    ];
+    out.extend(miniz_oxide::deflate::compress_to_vec(data, 6));
+    out.extend(crc32fast::hash(data).to_le_bytes());
+    out.extend((data.len() as u32).to_le_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gunzip_roundtrip() {
+        let data = b"INSERT INTO tests (id, text) VALUES (1, 'hello')".repeat(100);
+        let compressed = gzip(&data);
+        assert!(compressed.len() < data.len());
+
+        assert_eq!(gunzip(&compressed, data.len()).unwrap(), data);
+        assert!(matches!(
+            gunzip(&compressed, data.len() - 1),
+            Err(GunzipError::TooLarge(_))
+        ));
+
+        let mut corrupted = compressed.clone();
+        let crc_pos = corrupted.len() - GZIP_TRAILER_LEN;
+        corrupted[crc_pos] ^= 0xff;
+        assert!(matches!(
+            gunzip(&corrupted, data.len()),
+            Err(GunzipError::Checksum)
+        ));
+
+        assert!(matches!(
+            gunzip(&data, data.len()),
+            Err(GunzipError::Header)
+        ));
+    }
+
+    #[test]
+    fn gunzip_optional_header_fields() {
+        let data = b"[\"SELECT 1\"]";
+        let plain = gzip(data);
+
+        // same member, with FEXTRA, FNAME and FCOMMENT set
+        let mut with_fields = plain[..GZIP_HEADER_LEN].to_vec();
+        with_fields[3] = FEXTRA | FNAME | FCOMMENT;
+        with_fields.extend([3, 0, b'a', b'b', b'c']);
+        with_fields.extend(b"body.json\0");
+        with_fields.extend(b"a comment\0");
+        with_fields.extend(&plain[GZIP_HEADER_LEN..]);
+
+        assert_eq!(gunzip(&with_fields, 1024).unwrap(), data);
+    }
+}
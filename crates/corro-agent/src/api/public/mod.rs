@@ -1,21 +1,42 @@
-use std::time::{Duration, Instant};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    net::SocketAddr,
+    ops::RangeInclusive,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use axum::{response::IntoResponse, Extension};
-use bytes::{BufMut, BytesMut};
+use axum::{extract::FromRequest, response::IntoResponse, BoxError, Extension};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use bytes::{BufMut, Bytes, BytesMut};
 use compact_str::ToCompactString;
 use corro_types::{
-    agent::{Agent, ChangeError, CurrentVersion, KnownDbVersion},
-    api::{row_to_change, ColumnName, ExecResponse, ExecResult, QueryEvent, Statement},
-    base::{CrsqlDbVersion, CrsqlSeq},
+    actor::ActorId,
+    agent::{
+        check_db_version, Agent, ChangeError, CurrentVersion, KnownDbVersion, SCHEMA_CHANGES_TABLE,
+    },
+    api::{row_to_change, ColumnName, ExecResponse, ExecResult, NamedQuery, QueryEvent, Statement},
+    base::{CrsqlDbVersion, CrsqlSeq, Version},
     broadcast::{ChangeV1, Changeset, Timestamp},
     change::{ChunkedChanges, SqliteValue, MAX_CHANGES_BYTE_SIZE},
-    schema::{apply_schema, parse_sql},
+    config::LimitsConfig,
+    schema::{
+        apply_schema, parse_renames, parse_sql, ApplySchemaError, ApplySchemaOptions,
+        ConstrainedSchemaError, SchemaError,
+    },
     sqlite::SqlitePoolError,
+    sync::{generate_sync, SyncNowRequest, SyncRangeRequest},
 };
-use hyper::StatusCode;
+use hyper::{body::HttpBody, header, HeaderMap, StatusCode};
 use itertools::Itertools;
 use metrics::counter;
-use rusqlite::{named_params, params_from_iter, ToSql, Transaction};
+use parking_lot::Mutex;
+use rusqlite::{
+    named_params, params, params_from_iter, Connection, InterruptHandle, ToSql, Transaction,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use spawn::spawn_counted;
 use tokio::{
     sync::{
@@ -24,21 +45,25 @@ use tokio::{
     },
     task::block_in_place,
 };
-use tracing::{debug, error, info, trace};
+use tracing::{debug, error, info, trace, warn};
 
 use corro_types::broadcast::{BroadcastInput, BroadcastV1};
 
+use crate::api::peer::{export_changes, import_changes};
+
+pub mod gzip;
 pub mod pubsub;
 
+#[tracing::instrument(skip_all, fields(actor_id = %agent.actor_id(), version = tracing::field::Empty))]
 pub async fn make_broadcastable_changes<F, T>(
     agent: &Agent,
     f: F,
-) -> Result<(T, Duration), ChangeError>
+) -> Result<(T, Option<Version>, usize, Duration), ChangeError>
 where
     F: Fn(&Transaction) -> Result<T, ChangeError>,
 {
     trace!("getting conn...");
-    let mut conn = agent.pool().write_priority().await?;
+    let mut conn = agent.pool().write_priority_timeout().await?;
     trace!("got conn");
 
     let actor_id = agent.actor_id();
@@ -55,6 +80,8 @@ where
         .write("make_broadcastable_changes(booked writer)")
         .await;
 
+    let tables = agent.config().tables.clone();
+
     let start = Instant::now();
     block_in_place(move || {
         let tx = conn.immediate_transaction()?;
@@ -64,25 +91,46 @@ where
 
         let ts = Timestamp::from(agent.clock().new_timestamp());
 
-        let db_version: CrsqlDbVersion = tx
+        let db_version: i64 = tx
             .prepare_cached("SELECT crsql_next_db_version()")?
             .query_row((), |row| row.get(0))?;
 
-        let has_changes: bool = tx
-        .prepare_cached(
-            "SELECT EXISTS(SELECT 1 FROM crsql_changes WHERE site_id IS NULL AND db_version = ?);",
-        )?
-        .query_row([db_version], |row| row.get(0))?;
+        let changes: usize = tx
+            .prepare_cached(
+                "SELECT COUNT(*) FROM crsql_changes WHERE site_id IS NULL AND db_version = ?",
+            )?
+            .query_row([db_version], |row| row.get(0))?;
+
+        // changes to no-broadcast tables alone don't get a version: they're
+        // never booked, so never broadcast or synced either
+        let has_changes: bool = if changes == 0 || tables.no_broadcast.is_empty() {
+            changes > 0
+        } else {
+            tx.prepare_cached(&format!(
+                "SELECT EXISTS(SELECT 1 FROM crsql_changes WHERE site_id IS NULL AND db_version = ? AND \"table\" NOT IN ({}));",
+                vec!["?"; tables.no_broadcast.len()].join(",")
+            ))?
+            .query_row(
+                params_from_iter(
+                    std::iter::once(&db_version as &dyn ToSql)
+                        .chain(tables.no_broadcast.iter().map(|t| t as &dyn ToSql)),
+                ),
+                |row| row.get(0),
+            )?
+        };
 
         if !has_changes {
             tx.commit()?;
-            return Ok((ret, start.elapsed()));
+            return Ok((ret, None, changes, start.elapsed()));
         }
 
+        let db_version = check_db_version(book_writer.last_db_version(), db_version)?;
+
         let last_version = book_writer.last().unwrap_or_default();
         trace!("last_version: {last_version}");
         let version = last_version + 1;
         trace!("version: {version}");
+        tracing::Span::current().record("version", tracing::field::display(version));
 
         let last_seq: CrsqlSeq = tx
             .prepare_cached(
@@ -142,17 +190,24 @@ where
                     ChunkedChanges::new(rows, CrsqlSeq(0), last_seq, MAX_CHANGES_BYTE_SIZE);
                 for changes_seqs in chunked {
                     match changes_seqs {
-                        Ok((changes, seqs)) => {
+                        Ok((mut changes, seqs)) => {
                             for (table_name, count) in
                                 changes.iter().counts_by(|change| &change.table)
                             {
                                 counter!("corro.changes.committed", count as u64, "table" => table_name.to_string(), "source" => "local");
                             }
 
-                            trace!("broadcasting changes: {changes:?} for seq: {seqs:?}");
-
                             agent.subs_manager().match_changes(&changes, db_version);
 
+                            // peers missing these seqs get them through sync, which
+                            // also leaves out no-broadcast tables
+                            changes.retain(|change| tables.broadcasts(change.table.as_str()));
+                            if changes.is_empty() {
+                                continue;
+                            }
+
+                            trace!("broadcasting changes: {changes:?} for seq: {seqs:?}");
+
                             let tx_bcast = agent.tx_bcast().clone();
                             tokio::spawn(async move {
                                 if let Err(e) = tx_bcast
@@ -186,59 +241,250 @@ where
             Ok::<_, eyre::Report>(())
         });
 
-        Ok::<_, ChangeError>((ret, elapsed))
+        Ok::<_, ChangeError>((ret, Some(version), changes, elapsed))
     })
 }
 
+/// Replaces literals with `?` so values embedded in the SQL text don't end
+/// up in the logs.
+fn redact_sql(sql: &str) -> String {
+    use sqlite3_parser::{
+        dialect::TokenType,
+        lexer::{sql::Tokenizer, Scanner},
+    };
+
+    let input = sql.as_bytes();
+    let mut scanner = Scanner::new(Tokenizer::new());
+    let mut redacted = String::with_capacity(sql.len());
+
+    loop {
+        match scanner.scan(input) {
+            Ok((start, Some((_, token_type)), end)) => {
+                if !redacted.is_empty() {
+                    redacted.push(' ');
+                }
+                match token_type {
+                    TokenType::TK_STRING
+                    | TokenType::TK_BLOB
+                    | TokenType::TK_INTEGER
+                    | TokenType::TK_FLOAT => redacted.push('?'),
+                    _ => redacted.push_str(&String::from_utf8_lossy(&input[start..end])),
+                }
+            }
+            Ok((_, None, _)) => break,
+            // don't risk logging whatever we couldn't tokenize
+            Err(_) => return "<unparseable sql>".into(),
+        }
+    }
+
+    redacted
+}
+
+/// Whether `sql` starts with a transaction control statement (`BEGIN`,
+/// `COMMIT`, `SAVEPOINT`, ...). Only the first statement of a string is ever
+/// executed, so the leading keyword is all that matters.
+fn is_transaction_control(sql: &str) -> bool {
+    use sqlite3_parser::{
+        dialect::TokenType,
+        lexer::{sql::Tokenizer, Scanner},
+    };
+
+    let input = sql.as_bytes();
+    let mut scanner = Scanner::new(Tokenizer::new());
+
+    loop {
+        match scanner.scan(input) {
+            // empty statements are skipped by sqlite
+            Ok((_, Some((_, TokenType::TK_SEMI)), _)) => continue,
+            Ok((_, Some((_, token_type)), _)) => {
+                return matches!(
+                    token_type,
+                    TokenType::TK_BEGIN
+                        | TokenType::TK_COMMIT
+                        | TokenType::TK_END
+                        | TokenType::TK_ROLLBACK
+                        | TokenType::TK_SAVEPOINT
+                        | TokenType::TK_RELEASE
+                )
+            }
+            // let sqlite report on anything we can't tokenize
+            Ok((_, None, _)) | Err(_) => return false,
+        }
+    }
+}
+
+fn log_slow_query(
+    threshold: Option<Duration>,
+    endpoint: &'static str,
+    sql: &str,
+    elapsed: Duration,
+) {
+    if threshold.map_or(false, |threshold| elapsed > threshold) {
+        warn!(
+            "slow query on {endpoint} took {elapsed:?}: {}",
+            redact_sql(sql)
+        );
+        counter!("corro.db.slow_query.count", 1, "endpoint" => endpoint);
+    }
+}
+
+struct StatementOutput {
+    rows_affected: usize,
+    // only set if the statement returns rows
+    columns: Option<Vec<ColumnName>>,
+    rows: Vec<Vec<SqliteValue>>,
+}
+
 #[tracing::instrument(skip_all, err)]
-fn execute_statement(tx: &Transaction, stmt: &Statement) -> rusqlite::Result<usize> {
+fn execute_statement(tx: &Transaction, stmt: &Statement) -> rusqlite::Result<StatementOutput> {
     let mut prepped = tx.prepare(stmt.query())?;
 
-    match stmt {
+    let col_count = prepped.column_count();
+    let columns = (col_count > 0).then(|| {
+        prepped
+            .columns()
+            .into_iter()
+            .map(|col| ColumnName(col.name().to_compact_string()))
+            .collect()
+    });
+    let readonly = prepped.readonly();
+
+    // querying instead of executing lets statements with a RETURNING clause hand back their rows
+    let mut query = match stmt {
         Statement::Simple(_)
         | Statement::Verbose {
             params: None,
             named_params: None,
             ..
-        } => prepped.execute([]),
+        } => prepped.query([]),
         Statement::WithParams(_, params)
         | Statement::Verbose {
             params: Some(params),
             ..
-        } => prepped.execute(params_from_iter(params)),
+        } => prepped.query(params_from_iter(params)),
         Statement::WithNamedParams(_, params)
         | Statement::Verbose {
             named_params: Some(params),
             ..
-        } => prepped.execute(
+        } => prepped.query(
             params
                 .iter()
                 .map(|(k, v)| (k.as_str(), v as &dyn ToSql))
                 .collect::<Vec<(&str, &dyn ToSql)>>()
                 .as_slice(),
         ),
+    }?;
+
+    let mut rows = vec![];
+    while let Some(row) = query.next()? {
+        rows.push(
+            (0..col_count)
+                .map(|i| row.get::<_, SqliteValue>(i))
+                .collect::<rusqlite::Result<Vec<_>>>()?,
+        );
     }
+
+    Ok(StatementOutput {
+        rows_affected: if readonly { 0 } else { tx.changes() as usize },
+        columns,
+        rows,
+    })
 }
 
 #[tracing::instrument(skip_all)]
 pub async fn api_v1_transactions(
     // axum::extract::RawQuery(raw_query): axum::extract::RawQuery,
     Extension(agent): Extension<Agent>,
-    axum::extract::Json(statements): axum::extract::Json<Vec<Statement>>,
-) -> (StatusCode, axum::Json<ExecResponse>) {
+    headers: HeaderMap,
+    ApiBody(statements): ApiBody<Vec<Statement>>,
+) -> (StatusCode, ApiResponse<ExecResponse>) {
+    let format = BodyFormat::accepted(&headers);
+
+    if agent.is_draining() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            ApiResponse(
+                ExecResponse {
+                    results: vec![ExecResult::Error {
+                        error: "node is draining, not accepting writes".into(),
+                    }],
+                    time: 0.0,
+                    version: None,
+                    changes: 0,
+                },
+                format,
+            ),
+        );
+    }
+
+    if agent.config().node.read_only {
+        return (
+            StatusCode::FORBIDDEN,
+            ApiResponse(
+                ExecResponse {
+                    results: vec![ExecResult::Error {
+                        error: "node is read-only, not accepting writes".into(),
+                    }],
+                    time: 0.0,
+                    version: None,
+                    changes: 0,
+                },
+                format,
+            ),
+        );
+    }
+
     if statements.is_empty() {
         return (
             StatusCode::BAD_REQUEST,
-            axum::Json(ExecResponse {
+            ApiResponse(
+                ExecResponse {
+                    results: vec![ExecResult::Error {
+                        error: "at least 1 statement is required".into(),
+                    }],
+                    time: 0.0,
+                    version: None,
+                    changes: 0,
+                },
+                format,
+            ),
+        );
+    }
+
+    if let Some(i) = statements
+        .iter()
+        .position(|stmt| is_transaction_control(stmt.query()))
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            ApiResponse(
+                ExecResponse {
                 results: vec![ExecResult::Error {
-                    error: "at least 1 statement is required".into(),
+                    error: format!("statement #{i} is a transaction control statement, statements are already run in a single transaction"),
                 }],
                 time: 0.0,
-            }),
+                version: None,
+                changes: 0,
+            },
+            format,
+            ),
         );
     }
 
+    let slow_query = agent.config().db.slow_query_ms.map(Duration::from_millis);
+    let max_change_size = agent.config().limits.max_change_size;
+    let agent_draining = agent.clone();
+
     let res = make_broadcastable_changes(&agent, move |tx| {
+        // draining might have started while waiting for the write connection
+        if agent_draining.is_draining() {
+            return Err(ChangeError::Draining);
+        }
+
+        let changes_before: i64 = tx
+            .prepare_cached("SELECT total_changes()")?
+            .query_row((), |row| row.get(0))?;
+
         let mut total_rows_affected = 0;
 
         let results = statements
@@ -246,13 +492,20 @@ pub async fn api_v1_transactions(
             .map(|stmt| {
                 let start = Instant::now();
                 let res = execute_statement(tx, stmt);
+                log_slow_query(slow_query, "transactions", stmt.query(), start.elapsed());
 
                 match res {
-                    Ok(rows_affected) => {
+                    Ok(StatementOutput {
+                        rows_affected,
+                        columns,
+                        rows,
+                    }) => {
                         total_rows_affected += rows_affected;
                         ExecResult::Execute {
                             rows_affected,
                             time: start.elapsed().as_secs_f64(),
+                            rows: columns.is_some().then_some(rows),
+                            columns,
                         }
                     }
                     Err(e) => ExecResult::Error {
@@ -262,32 +515,102 @@ pub async fn api_v1_transactions(
             })
             .collect::<Vec<ExecResult>>();
 
+        if let Some(max) = max_change_size {
+            let rows: i64 = tx
+                .prepare_cached("SELECT total_changes()")?
+                .query_row((), |row| row.get(0))?;
+            let rows = rows - changes_before;
+            if rows > max {
+                // dropping the transaction rolls it back
+                return Err(ChangeError::TooLarge { rows, max });
+            }
+        }
+
         Ok(results)
     })
     .await;
 
-    let (results, elapsed) = match res {
+    let (results, version, changes, elapsed) = match res {
         Ok(res) => res,
+        Err(ChangeError::Pool(e)) if e.is_timeout() => {
+            warn!("could not execute statement(s): {e}");
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                ApiResponse(
+                    ExecResponse {
+                        results: vec![ExecResult::Error {
+                            error: e.to_string(),
+                        }],
+                        time: 0.0,
+                        version: None,
+                        changes: 0,
+                    },
+                    format,
+                ),
+            );
+        }
+        Err(e @ ChangeError::Draining) => {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                ApiResponse(
+                    ExecResponse {
+                        results: vec![ExecResult::Error {
+                            error: e.to_string(),
+                        }],
+                        time: 0.0,
+                        version: None,
+                        changes: 0,
+                    },
+                    format,
+                ),
+            );
+        }
+        Err(e @ ChangeError::TooLarge { .. }) => {
+            return (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                ApiResponse(
+                    ExecResponse {
+                        results: vec![ExecResult::Error {
+                            error: e.to_string(),
+                        }],
+                        time: 0.0,
+                        version: None,
+                        changes: 0,
+                    },
+                    format,
+                ),
+            );
+        }
         Err(e) => {
             error!("could not execute statement(s): {e}");
             return (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                axum::Json(ExecResponse {
-                    results: vec![ExecResult::Error {
-                        error: e.to_string(),
-                    }],
-                    time: 0.0,
-                }),
+                ApiResponse(
+                    ExecResponse {
+                        results: vec![ExecResult::Error {
+                            error: e.to_string(),
+                        }],
+                        time: 0.0,
+                        version: None,
+                        changes: 0,
+                    },
+                    format,
+                ),
             );
         }
     };
 
     (
         StatusCode::OK,
-        axum::Json(ExecResponse {
-            results,
-            time: elapsed.as_secs_f64(),
-        }),
+        ApiResponse(
+            ExecResponse {
+                results,
+                time: elapsed.as_secs_f64(),
+                version,
+                changes,
+            },
+            format,
+        ),
     )
 }
 
@@ -299,21 +622,75 @@ pub enum QueryError {
     Rusqlite(#[from] rusqlite::Error),
 }
 
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+pub struct QueryParams {
+    /// Interrupt the query if it's still running after this many milliseconds
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+}
+
+/// Interrupts a running query once its deadline passes. Dropping the guard
+/// disarms the watchdog, so it must be dropped before its connection is
+/// returned to the pool.
+struct QueryTimeoutGuard {
+    handle: Arc<Mutex<Option<InterruptHandle>>>,
+    watchdog: tokio::task::JoinHandle<()>,
+}
+
+impl QueryTimeoutGuard {
+    fn new(conn: &Connection, timeout: Duration) -> Self {
+        let handle = Arc::new(Mutex::new(Some(conn.get_interrupt_handle())));
+        let watchdog = tokio::spawn({
+            let handle = handle.clone();
+            async move {
+                tokio::time::sleep(timeout).await;
+                if let Some(handle) = handle.lock().take() {
+                    debug!("query timed out after {timeout:?}, interrupting");
+                    handle.interrupt();
+                }
+            }
+        });
+        Self { handle, watchdog }
+    }
+}
+
+impl Drop for QueryTimeoutGuard {
+    fn drop(&mut self) {
+        self.handle.lock().take();
+        self.watchdog.abort();
+    }
+}
+
+fn query_error_string(e: &rusqlite::Error) -> String {
+    if e.sqlite_error_code() == Some(rusqlite::ErrorCode::OperationInterrupted) {
+        "query timed out".into()
+    } else {
+        e.to_string()
+    }
+}
+
 async fn build_query_rows_response(
     agent: &Agent,
     data_tx: mpsc::Sender<QueryEvent>,
     stmt: Statement,
+    timeout: Option<Duration>,
 ) -> Result<(), (StatusCode, ExecResult)> {
     let (res_tx, res_rx) = oneshot::channel();
 
     let pool = agent.pool().clone();
+    let slow_query = agent.config().db.slow_query_ms.map(Duration::from_millis);
 
     tokio::spawn(async move {
-        let conn = match pool.read().await {
+        let conn = match pool.read_timeout().await {
             Ok(conn) => conn,
             Err(e) => {
+                let status = if matches!(e, SqlitePoolError::Timeout(_)) {
+                    StatusCode::SERVICE_UNAVAILABLE
+                } else {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                };
                 _ = res_tx.send(Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
+                    status,
                     ExecResult::Error {
                         error: e.to_string(),
                     },
@@ -322,6 +699,9 @@ async fn build_query_rows_response(
             }
         };
 
+        // declared after `conn` so it's dropped (and disarmed) first
+        let _timeout_guard = timeout.map(|timeout| QueryTimeoutGuard::new(&conn, timeout));
+
         let prepped_res = block_in_place(|| conn.prepare(stmt.query()));
 
         let mut prepped = match prepped_res {
@@ -364,7 +744,7 @@ async fn build_query_rows_response(
 
             let start = Instant::now();
 
-            let query = match stmt {
+            let query = match &stmt {
                 Statement::Simple(_)
                 | Statement::Verbose {
                     params: None,
@@ -395,13 +775,14 @@ async fn build_query_rows_response(
                     _ = res_tx.send(Err((
                         StatusCode::INTERNAL_SERVER_ERROR,
                         ExecResult::Error {
-                            error: e.to_string(),
+                            error: query_error_string(&e),
                         },
                     )));
                     return;
                 }
             };
             let elapsed = start.elapsed();
+            log_slow_query(slow_query, "queries", stmt.query(), elapsed);
 
             if let Err(_e) = res_tx.send(Ok(())) {
                 error!("could not send back response through oneshot channel, aborting");
@@ -440,7 +821,7 @@ async fn build_query_rows_response(
                         break;
                     }
                     Err(e) => {
-                        _ = data_tx.blocking_send(QueryEvent::Error(e.to_compact_string()));
+                        _ = data_tx.blocking_send(QueryEvent::Error(query_error_string(&e).into()));
                         return;
                     }
                 }
@@ -464,189 +845,2965 @@ async fn build_query_rows_response(
     }
 }
 
-pub async fn api_v1_queries(
-    Extension(agent): Extension<Agent>,
-    axum::extract::Json(stmt): axum::extract::Json<Statement>,
-) -> impl IntoResponse {
-    let (mut tx, body) = hyper::Body::channel();
-
-    // TODO: timeout on data send instead of infinitely waiting for channel space.
-    let (data_tx, mut data_rx) = channel(512);
-
-    tokio::spawn(async move {
-        let mut buf = BytesMut::new();
-
-        while let Some(row_res) = data_rx.recv().await {
-            {
-                let mut writer = (&mut buf).writer();
-                if let Err(e) = serde_json::to_writer(&mut writer, &row_res) {
-                    _ = tx
-                        .send_data(
-                            serde_json::to_vec(&serde_json::json!(QueryEvent::Error(
-                                e.to_compact_string()
-                            )))
-                            .expect("could not serialize error json")
-                            .into(),
-                        )
-                        .await;
-                    return;
-                }
-            }
-
-            buf.extend_from_slice(b"\n");
+/// Streams query events as newline-delimited JSON
+async fn write_json_body(mut tx: hyper::body::Sender, mut data_rx: mpsc::Receiver<QueryEvent>) {
+    let mut buf = BytesMut::new();
 
-            if let Err(e) = tx.send_data(buf.split().freeze()).await {
-                error!("could not send data through body's channel: {e}");
+    while let Some(row_res) = data_rx.recv().await {
+        {
+            let mut writer = (&mut buf).writer();
+            if let Err(e) = serde_json::to_writer(&mut writer, &row_res) {
+                _ = tx
+                    .send_data(
+                        serde_json::to_vec(&serde_json::json!(QueryEvent::Error(
+                            e.to_compact_string()
+                        )))
+                        .expect("could not serialize error json")
+                        .into(),
+                    )
+                    .await;
                 return;
             }
         }
-        debug!("query body channel done");
-    });
 
-    trace!("building query rows response...");
+        buf.extend_from_slice(b"\n");
 
-    match build_query_rows_response(&agent, data_tx, stmt).await {
-        Ok(_) => {
-            #[allow(clippy::needless_return)]
-            return hyper::Response::builder()
-                .status(StatusCode::OK)
-                .body(body)
-                .expect("could not build query response body");
+        if let Err(e) = tx.send_data(buf.split().freeze()).await {
+            error!("could not send data through body's channel: {e}");
+            return;
         }
-        Err((status, res)) => {
-            #[allow(clippy::needless_return)]
-            return hyper::Response::builder()
-                .status(status)
-                .body(
-                    serde_json::to_vec(&res)
-                        .expect("could not serialize query error response")
-                        .into(),
-                )
-                .expect("could not build query response body");
+    }
+    debug!("query body channel done");
+}
+
+/// Streams query events as consecutive MessagePack values
+async fn write_msgpack_body(mut tx: hyper::body::Sender, mut data_rx: mpsc::Receiver<QueryEvent>) {
+    while let Some(row_res) = data_rx.recv().await {
+        let buf = match rmp_serde::to_vec_named(&row_res) {
+            Ok(buf) => buf,
+            Err(e) => {
+                _ = tx
+                    .send_data(
+                        rmp_serde::to_vec_named(&QueryEvent::Error(e.to_compact_string()))
+                            .expect("could not serialize error msgpack")
+                            .into(),
+                    )
+                    .await;
+                return;
+            }
+        };
+
+        if let Err(e) = tx.send_data(buf.into()).await {
+            error!("could not send data through body's channel: {e}");
+            return;
         }
     }
+    debug!("query body channel done");
 }
 
-async fn execute_schema(agent: &Agent, statements: Vec<String>) -> eyre::Result<()> {
-    let new_sql: String = statements.join(";");
+pub const MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
 
-    let partial_schema = parse_sql(&new_sql)?;
+fn accepts(headers: &HeaderMap, media_type: &str) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media| media.split(';').next().map(str::trim) == Some(media_type))
+}
 
-    let mut conn = agent.pool().write_priority().await?;
+fn accepts_csv(headers: &HeaderMap) -> bool {
+    accepts(headers, "text/csv")
+}
 
-    // hold onto this lock so nothing else makes changes
-    let mut schema_write = agent.schema().write();
+/// How a response body is encoded, as negotiated through the `Accept` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyFormat {
+    Json,
+    Msgpack,
+}
 
-    // clone the previous schema and apply
-    let mut new_schema = {
-        let mut schema = schema_write.clone();
-        for (name, def) in partial_schema.tables.iter() {
-            // overwrite table because users are expected to return a full table def
-            schema.tables.insert(name.clone(), def.clone());
+impl BodyFormat {
+    pub fn accepted(headers: &HeaderMap) -> Self {
+        if accepts(headers, MSGPACK_CONTENT_TYPE) {
+            BodyFormat::Msgpack
+        } else {
+            BodyFormat::Json
         }
-        schema
-    };
+    }
 
-    new_schema.constrain()?;
+    fn encode<T: Serialize>(self, value: &T) -> Vec<u8> {
+        match self {
+            BodyFormat::Json => serde_json::to_vec(value).expect("could not serialize json"),
+            BodyFormat::Msgpack => {
+                rmp_serde::to_vec_named(value).expect("could not serialize msgpack")
+            }
+        }
+    }
+}
 
-    block_in_place(|| {
-        let tx = conn.immediate_transaction()?;
+/// Request body, decoded from MessagePack when sent with
+/// `Content-Type: application/msgpack` and from JSON otherwise
+#[derive(Debug)]
+pub struct ApiBody<T>(pub T);
+
+#[axum::async_trait]
+impl<T, S, B> FromRequest<S, B> for ApiBody<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = axum::response::Response;
+
+    async fn from_request(req: hyper::Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let msgpack = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(str::trim)
+            == Some(MSGPACK_CONTENT_TYPE);
+
+        if !msgpack {
+            return axum::Json::<T>::from_request(req, state)
+                .await
+                .map(|axum::Json(body)| ApiBody(body))
+                .map_err(IntoResponse::into_response);
+        }
 
-        apply_schema(&tx, &schema_write, &mut new_schema)?;
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
 
-        for tbl_name in partial_schema.tables.keys() {
-            tx.execute("DELETE FROM __corro_schema WHERE tbl_name = ?", [tbl_name])?;
+        rmp_serde::from_slice(&body).map(ApiBody).map_err(|e| {
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("could not deserialize msgpack body: {e}"),
+            )
+                .into_response()
+        })
+    }
+}
 
-            let n = tx.execute("INSERT INTO __corro_schema SELECT tbl_name, type, name, sql, 'api' AS source FROM sqlite_schema WHERE tbl_name = ? AND type IN ('table', 'index') AND name IS NOT NULL AND sql IS NOT NULL", [tbl_name])?;
-            info!("Updated {n} rows in __corro_schema for table {tbl_name}");
+/// Response body, encoded as negotiated by the request
+#[derive(Debug)]
+pub struct ApiResponse<T>(pub T, pub BodyFormat);
+
+impl<T: Serialize> IntoResponse for ApiResponse<T> {
+    fn into_response(self) -> axum::response::Response {
+        match self.1 {
+            BodyFormat::Json => axum::Json(self.0).into_response(),
+            BodyFormat::Msgpack => match rmp_serde::to_vec_named(&self.0) {
+                Ok(buf) => ([(header::CONTENT_TYPE, MSGPACK_CONTENT_TYPE)], buf).into_response(),
+                Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+            },
         }
+    }
+}
 
-        tx.commit()?;
+// parse errors are left for sqlite to report when preparing the statement
+fn statement_count(sql: &str) -> usize {
+    use fallible_iterator::FallibleIterator;
 
-        Ok::<_, eyre::Report>(())
-    })?;
+    let mut parser = sqlite3_parser::lexer::sql::Parser::new(sql.as_bytes());
+    let mut count = 0;
+    while let Ok(Some(_)) = parser.next() {
+        count += 1;
+    }
+    count
+}
 
-    *schema_write = new_schema;
+fn csv_field(value: &SqliteValue) -> Cow<'_, [u8]> {
+    match value {
+        SqliteValue::Null => Cow::Borrowed(b""),
+        SqliteValue::Integer(i) => Cow::Owned(i.to_string().into_bytes()),
+        SqliteValue::Real(f) => Cow::Owned(f.to_string().into_bytes()),
+        SqliteValue::Text(t) => Cow::Borrowed(t.as_bytes()),
+        SqliteValue::Blob(b) => Cow::Owned(BASE64.encode(b).into_bytes()),
+    }
+}
 
-    Ok(())
+/// Streams query events as RFC 4180 CSV, with a header row. Errors can't be
+/// represented in CSV, the body is aborted instead.
+async fn write_csv_body(mut tx: hyper::body::Sender, mut data_rx: mpsc::Receiver<QueryEvent>) {
+    let mut builder = csv::WriterBuilder::new();
+    builder.terminator(csv::Terminator::CRLF);
+
+    let mut buf = vec![];
+
+    while let Some(event) = data_rx.recv().await {
+        let mut writer = builder.from_writer(&mut buf);
+        let res = match event {
+            QueryEvent::Columns(cols) => writer.write_record(&cols),
+            QueryEvent::Row(_, cells) => writer.write_record(cells.iter().map(csv_field)),
+            QueryEvent::EndOfQuery { .. } => break,
+            QueryEvent::Error(e) => {
+                error!("query failed while streaming csv: {e}");
+                tx.abort();
+                return;
+            }
+            QueryEvent::Change(..) | QueryEvent::Old(..) => continue,
+        };
+
+        if let Err(e) = res
+            .map_err(std::io::Error::from)
+            .and_then(|_| writer.flush())
+        {
+            error!("could not write csv record: {e}");
+            tx.abort();
+            return;
+        }
+        drop(writer);
+
+        if let Err(e) = tx.send_data(std::mem::take(&mut buf).into()).await {
+            error!("could not send data through body's channel: {e}");
+            return;
+        }
+    }
+    debug!("query csv body channel done");
 }
 
-pub async fn api_v1_db_schema(
+pub async fn api_v1_queries(
     Extension(agent): Extension<Agent>,
-    axum::extract::Json(statements): axum::extract::Json<Vec<String>>,
-) -> (StatusCode, axum::Json<ExecResponse>) {
-    if statements.is_empty() {
-        return (
-            StatusCode::BAD_REQUEST,
-            axum::Json(ExecResponse {
-                results: vec![ExecResult::Error {
-                    error: "at least 1 statement is required".into(),
-                }],
-                time: 0.0,
-            }),
-        );
+    axum::extract::Query(params): axum::extract::Query<QueryParams>,
+    headers: HeaderMap,
+    ApiBody(stmt): ApiBody<Statement>,
+) -> impl IntoResponse {
+    let (tx, body) = hyper::Body::channel();
+
+    // TODO: timeout on data send instead of infinitely waiting for channel space.
+    let (data_tx, data_rx) = channel(512);
+
+    let csv = accepts_csv(&headers);
+    let format = BodyFormat::accepted(&headers);
+
+    if csv && statement_count(stmt.query()) > 1 {
+        return hyper::Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(
+                serde_json::to_vec(&ExecResult::Error {
+                    error: "csv output only supports a single statement".into(),
+                })
+                .expect("could not serialize query error response")
+                .into(),
+            )
+            .expect("could not build query response body");
+    }
+
+    if csv {
+        tokio::spawn(write_csv_body(tx, data_rx));
+    } else if format == BodyFormat::Msgpack {
+        tokio::spawn(write_msgpack_body(tx, data_rx));
+    } else {
+        tokio::spawn(write_json_body(tx, data_rx));
+    }
+
+    trace!("building query rows response...");
+
+    match build_query_rows_response(
+        &agent,
+        data_tx,
+        stmt,
+        params.timeout_ms.map(Duration::from_millis),
+    )
+    .await
+    {
+        Ok(_) => {
+            let mut res = hyper::Response::builder().status(StatusCode::OK);
+            if csv {
+                res = res.header(header::CONTENT_TYPE, "text/csv; charset=utf-8");
+            } else if format == BodyFormat::Msgpack {
+                res = res.header(header::CONTENT_TYPE, MSGPACK_CONTENT_TYPE);
+            }
+            #[allow(clippy::needless_return)]
+            return res.body(body).expect("could not build query response body");
+        }
+        Err((status, res)) => {
+            let mut builder = hyper::Response::builder().status(status);
+            if format == BodyFormat::Msgpack {
+                builder = builder.header(header::CONTENT_TYPE, MSGPACK_CONTENT_TYPE);
+            }
+            #[allow(clippy::needless_return)]
+            return builder
+                .body(format.encode(&res).into())
+                .expect("could not build query response body");
+        }
+    }
+}
+
+/// Runs a query template registered under `[queries]` in the config, exactly
+/// like `/v1/queries` would run it.
+pub async fn api_v1_queries_named(
+    Extension(agent): Extension<Agent>,
+    params: axum::extract::Query<QueryParams>,
+    headers: HeaderMap,
+    axum::extract::Json(named): axum::extract::Json<NamedQuery>,
+) -> axum::response::Response {
+    let query = match agent.config().queries.get(&named.name) {
+        Some(query) => query.clone(),
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                axum::Json(ExecResult::Error {
+                    error: format!("unknown query: {}", named.name),
+                }),
+            )
+                .into_response()
+        }
+    };
+
+    let stmt = Statement::Verbose {
+        query,
+        params: named.params,
+        named_params: named.named_params,
+    };
+
+    api_v1_queries(Extension(agent), params, headers, ApiBody(stmt))
+        .await
+        .into_response()
+}
+
+fn explain_query_plan(stmt: Statement) -> Statement {
+    let explain = |query: String| format!("EXPLAIN QUERY PLAN {query}");
+    match stmt {
+        Statement::Simple(query) => Statement::Simple(explain(query)),
+        Statement::WithParams(query, params) => Statement::WithParams(explain(query), params),
+        Statement::WithNamedParams(query, params) => {
+            Statement::WithNamedParams(explain(query), params)
+        }
+        Statement::Verbose {
+            query,
+            params,
+            named_params,
+        } => Statement::Verbose {
+            query: explain(query),
+            params,
+            named_params,
+        },
+    }
+}
+
+/// Reports the query plan sqlite picks for a statement, params included,
+/// without ever running it.
+pub async fn api_v1_db_explain(
+    agent: Extension<Agent>,
+    params: axum::extract::Query<QueryParams>,
+    headers: HeaderMap,
+    axum::extract::Json(stmt): axum::extract::Json<Statement>,
+) -> impl IntoResponse {
+    // only the first statement would be explained, don't let the rest go unnoticed
+    if statement_count(stmt.query()) > 1 {
+        return (
+            StatusCode::BAD_REQUEST,
+            axum::Json(ExecResult::Error {
+                error: "only a single statement can be explained".into(),
+            }),
+        )
+            .into_response();
+    }
+
+    api_v1_queries(agent, params, headers, ApiBody(explain_query_plan(stmt)))
+        .await
+        .into_response()
+}
+
+pub async fn execute_schema(agent: &Agent, statements: Vec<String>) -> eyre::Result<()> {
+    let guard = agent.lock_schema().await;
+    execute_schema_locked(agent, &guard, statements, true).await
+}
+
+/// Same as [`execute_schema`], for callers already holding the schema lock.
+/// New rowid tables are only warned about unless `require_without_rowid`
+/// is set, schema files and changes from other nodes may still have them.
+pub(crate) async fn execute_schema_locked(
+    agent: &Agent,
+    _lock: &tokio::sync::MutexGuard<'_, ()>,
+    statements: Vec<String>,
+    require_without_rowid: bool,
+) -> eyre::Result<()> {
+    let new_sql: String = statements.join(";");
+
+    let partial_schema = parse_sql(&new_sql)?;
+    let renames = parse_renames(&new_sql)?;
+
+    let mut conn = agent.pool().write_priority().await?;
+
+    // hold onto this lock so nothing else makes changes
+    let mut schema_write = agent.schema().write();
+
+    // clone the previous schema and apply
+    let mut new_schema = {
+        let mut schema = schema_write.clone();
+        for (name, def) in partial_schema.tables.iter() {
+            // overwrite table because users are expected to return a full table def
+            schema.tables.insert(name.clone(), def.clone());
+        }
+        // renamed tables are only defined under their new name
+        for (old_name, _) in renames.tables.iter() {
+            if !partial_schema.tables.contains_key(old_name) {
+                schema.tables.shift_remove(old_name);
+            }
+        }
+        for (name, def) in partial_schema.views.iter() {
+            schema.views.insert(name.clone(), def.clone());
+        }
+        // like indexes, triggers are defined along with their table (or view)
+        schema.triggers.retain(|_, trigger| {
+            !partial_schema.tables.contains_key(&trigger.tbl_name)
+                && !partial_schema.views.contains_key(&trigger.tbl_name)
+                && !renames
+                    .tables
+                    .iter()
+                    .any(|(old_name, _)| *old_name == trigger.tbl_name)
+        });
+        for (name, def) in partial_schema.triggers.iter() {
+            schema.triggers.insert(name.clone(), def.clone());
+        }
+        schema
+    };
+
+    new_schema.constrain()?;
+
+    block_in_place(|| {
+        let tx = conn.immediate_transaction()?;
+
+        apply_schema(
+            &tx,
+            &schema_write,
+            &mut new_schema,
+            ApplySchemaOptions {
+                allow_destructive: agent.config().db.allow_destructive_schema,
+                renames: renames.clone(),
+                require_without_rowid,
+            },
+        )?;
+
+        for (old_name, _) in renames.tables.iter() {
+            tx.execute("DELETE FROM __corro_schema WHERE tbl_name = ?", [old_name])?;
+        }
+
+        for tbl_name in partial_schema
+            .tables
+            .keys()
+            .chain(partial_schema.views.keys())
+        {
+            tx.execute("DELETE FROM __corro_schema WHERE tbl_name = ?", [tbl_name])?;
+
+            // cr-sqlite's own triggers are recreated along with the table
+            let n = tx.execute("INSERT INTO __corro_schema SELECT tbl_name, type, name, sql, 'api' AS source FROM sqlite_schema WHERE tbl_name = ? AND type IN ('table', 'index', 'view', 'trigger') AND name NOT LIKE '%crsql%' AND name IS NOT NULL AND sql IS NOT NULL", [tbl_name])?;
+            info!("Updated {n} rows in __corro_schema for table {tbl_name}");
+        }
+
+        tx.commit()?;
+
+        Ok::<_, eyre::Report>(())
+    })?;
+
+    *schema_write = new_schema;
+
+    Ok(())
+}
+
+pub async fn api_v1_db_schema(
+    Extension(agent): Extension<Agent>,
+    axum::extract::Json(statements): axum::extract::Json<Vec<String>>,
+) -> (StatusCode, axum::Json<ExecResponse>) {
+    if agent.is_draining() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            axum::Json(ExecResponse {
+                results: vec![ExecResult::Error {
+                    error: "node is draining, not accepting schema changes".into(),
+                }],
+                time: 0.0,
+                version: None,
+                changes: 0,
+            }),
+        );
+    }
+
+    if agent.config().node.read_only {
+        return (
+            StatusCode::FORBIDDEN,
+            axum::Json(ExecResponse {
+                results: vec![ExecResult::Error {
+                    error: "node is read-only, not accepting schema changes".into(),
+                }],
+                time: 0.0,
+                version: None,
+                changes: 0,
+            }),
+        );
+    }
+
+    if statements.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            axum::Json(ExecResponse {
+                results: vec![ExecResult::Error {
+                    error: "at least 1 statement is required".into(),
+                }],
+                time: 0.0,
+                version: None,
+                changes: 0,
+            }),
+        );
     }
 
     let start = Instant::now();
 
-    if let Err(e) = execute_schema(&agent, statements).await {
+    // held until the change is recorded, so nothing else changes the schema
+    // in between and what's recorded is exactly what was applied here
+    let guard = agent.lock_schema().await;
+
+    let previous = agent.schema().read().clone();
+
+    if let Err(e) = execute_schema_locked(&agent, &guard, statements.clone(), true).await {
+        // the statements were refused, as opposed to failing to apply
+        let invalid = e
+            .downcast_ref::<SchemaError>()
+            .is_some_and(SchemaError::is_invalid_schema)
+            || e.downcast_ref::<ConstrainedSchemaError>().is_some()
+            || e.downcast_ref::<ApplySchemaError>()
+                .is_some_and(ApplySchemaError::is_invalid_schema);
         error!("could not merge schemas: {e}");
         return (
-            StatusCode::INTERNAL_SERVER_ERROR,
+            if invalid {
+                StatusCode::BAD_REQUEST
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            },
             axum::Json(ExecResponse {
                 results: vec![ExecResult::Error {
                     error: e.to_string(),
                 }],
                 time: 0.0,
+                version: None,
+                changes: 0,
             }),
         );
     }
 
+    // recorded like any other change, so other nodes get it through broadcasts
+    // and sync (even when they join later) and apply it on their end
+    let changed = *agent.schema().read() != previous;
+    if changed {
+        if let Err(e) = record_schema_change(&agent, &statements).await {
+            error!("could not record schema change for other nodes: {e}");
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                axum::Json(ExecResponse {
+                    results: vec![ExecResult::Error {
+                        error: format!(
+                            "schema change was applied on this node but could not be recorded for other nodes: {e}"
+                        ),
+                    }],
+                    time: 0.0,
+                    version: None,
+                    changes: 0,
+                }),
+            );
+        }
+    }
+
+    drop(guard);
+
     (
         StatusCode::OK,
         axum::Json(ExecResponse {
             results: vec![],
             time: start.elapsed().as_secs_f64(),
+            version: None,
+            changes: 0,
         }),
     )
 }
 
-#[cfg(test)]
-mod tests {
-    use bytes::Bytes;
-    use corro_types::{api::RowId, config::Config, schema::SqliteType, base::Version};
-    use futures::Stream;
-    use http_body::{combinators::UnsyncBoxBody, Body};
-    use tokio::sync::mpsc::error::TryRecvError;
-    use tokio_util::codec::{Decoder, LinesCodec};
-    use tripwire::Tripwire;
+async fn record_schema_change(agent: &Agent, statements: &[String]) -> eyre::Result<()> {
+    let statements = serde_json::to_string(statements)?;
+    let actor_id = agent.actor_id();
+
+    make_broadcastable_changes(agent, |tx| {
+        let ts = Timestamp::from(agent.clock().new_timestamp());
+        tx.prepare_cached(&format!(
+            "INSERT INTO {SCHEMA_CHANGES_TABLE} (actor_id, ts, statements) VALUES (?, ?, ?)"
+        ))?
+        .execute(params![actor_id, ts, statements])?;
+        // applied here already
+        tx.prepare_cached(&format!(
+            "INSERT INTO {SCHEMA_CHANGES_TABLE}_applied (actor_id, ts) VALUES (?, ?)"
+        ))?
+        .execute(params![actor_id, ts])?;
+        Ok(())
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthResponse {
+    pub actor_id: ActorId,
+    pub db_version: Option<CrsqlDbVersion>,
+    pub ready: bool,
+    pub draining: bool,
+}
+
+async fn health_response(agent: &Agent) -> HealthResponse {
+    let db_version = match agent.pool().read().await {
+        Ok(conn) => {
+            block_in_place(|| conn.query_row("SELECT crsql_db_version()", [], |row| row.get(0)))
+                .map_err(|e| error!("could not query db version: {e}"))
+                .ok()
+        }
+        Err(e) => {
+            error!("could not acquire read connection: {e}");
+            None
+        }
+    };
+
+    HealthResponse {
+        actor_id: agent.actor_id(),
+        db_version,
+        ready: agent.is_ready() && !agent.is_draining(),
+        draining: agent.is_draining(),
+    }
+}
+
+pub async fn api_v1_health(
+    Extension(agent): Extension<Agent>,
+) -> (StatusCode, axum::Json<HealthResponse>) {
+    (StatusCode::OK, axum::Json(health_response(&agent).await))
+}
+
+pub async fn api_v1_ready(
+    Extension(agent): Extension<Agent>,
+) -> (StatusCode, axum::Json<HealthResponse>) {
+    let res = health_response(&agent).await;
+    let status = if res.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, axum::Json(res))
+}
+
+#[derive(Debug, Serialize)]
+pub struct DbVersionsResponse {
+    pub db_version: CrsqlDbVersion,
+    pub heads: HashMap<ActorId, Version>,
+}
+
+pub async fn api_v1_db_versions(
+    Extension(agent): Extension<Agent>,
+) -> Result<axum::Json<DbVersionsResponse>, (StatusCode, String)> {
+    let db_version = {
+        let conn = agent.pool().read().await.map_err(|e| {
+            error!("could not acquire read connection: {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        })?;
+        block_in_place(|| conn.query_row("SELECT crsql_db_version()", [], |row| row.get(0)))
+            .map_err(|e| {
+                error!("could not query db version: {e}");
+                (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+            })?
+    };
+
+    let heads = generate_sync(agent.bookie(), agent.actor_id()).await.heads;
+
+    Ok(axum::Json(DbVersionsResponse { db_version, heads }))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct BookkeepingParams {
+    /// Only return this actor's bookkeeping, in full
+    #[serde(default)]
+    actor_id: Option<ActorId>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BookkeepingHeadsResponse {
+    pub heads: HashMap<ActorId, Version>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BookkeepingCurrent {
+    pub version: Version,
+    pub db_version: CrsqlDbVersion,
+    pub last_seq: CrsqlSeq,
+    pub ts: Timestamp,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BookkeepingPartial {
+    pub version: Version,
+    pub seqs: Vec<RangeInclusive<CrsqlSeq>>,
+    pub last_seq: CrsqlSeq,
+    pub ts: Timestamp,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ActorBookkeepingResponse {
+    pub actor_id: ActorId,
+    pub last: Option<Version>,
+    pub cleared: Vec<RangeInclusive<Version>>,
+    pub current: Vec<BookkeepingCurrent>,
+    pub partials: Vec<BookkeepingPartial>,
+    pub needed: Vec<RangeInclusive<Version>>,
+}
+
+/// Versions booked in memory, for every actor (heads only) or a single
+/// actor (everything), so they can be inspected without SQL access to
+/// `__corro_bookkeeping`.
+pub async fn api_v1_admin_bookkeeping(
+    Extension(agent): Extension<Agent>,
+    axum::extract::Query(params): axum::extract::Query<BookkeepingParams>,
+) -> Result<axum::response::Response, (StatusCode, String)> {
+    let Some(actor_id) = params.actor_id else {
+        let heads = generate_sync(agent.bookie(), agent.actor_id()).await.heads;
+        return Ok(axum::Json(BookkeepingHeadsResponse { heads }).into_response());
+    };
+
+    let booked = agent
+        .bookie()
+        .read("api_v1_admin_bookkeeping")
+        .await
+        .get(&actor_id)
+        .cloned()
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                format!("no bookkeeping for actor {actor_id}"),
+            )
+        })?;
+
+    let booked = booked
+        .read(format!("api_v1_admin_bookkeeping:{}", actor_id.as_simple()))
+        .await;
+
+    Ok(axum::Json(ActorBookkeepingResponse {
+        actor_id,
+        last: booked.last(),
+        cleared: booked.cleared.iter().cloned().collect(),
+        current: booked
+            .current
+            .iter()
+            .map(|(version, current)| BookkeepingCurrent {
+                version: *version,
+                db_version: current.db_version,
+                last_seq: current.last_seq,
+                ts: current.ts,
+            })
+            .collect(),
+        partials: booked
+            .partials
+            .iter()
+            .map(|(version, partial)| BookkeepingPartial {
+                version: *version,
+                seqs: partial.seqs.iter().cloned().collect(),
+                last_seq: partial.last_seq,
+                ts: partial.ts,
+            })
+            .collect(),
+        needed: booked.sync_need().iter().cloned().collect(),
+    })
+    .into_response())
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncNowResponse {
+    Synced(usize),
+    Error(String),
+}
+
+pub async fn api_v1_sync_now(
+    Extension(agent): Extension<Agent>,
+) -> (StatusCode, axum::Json<SyncNowResponse>) {
+    let (res_tx, res_rx) = oneshot::channel();
+    if let Err(e) = agent.tx_sync_now().send(SyncNowRequest { res_tx }).await {
+        error!("could not request sync: {e}");
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(SyncNowResponse::Error(e.to_string())),
+        );
+    }
+
+    match res_rx.await {
+        Ok(Ok(n)) => (StatusCode::OK, axum::Json(SyncNowResponse::Synced(n))),
+        Ok(Err(e)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(SyncNowResponse::Error(e)),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(SyncNowResponse::Error(e.to_string())),
+        ),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SyncRangeBody {
+    pub actor_id: ActorId,
+    pub start: Version,
+    pub end: Version,
+    /// gossip address of the peer to sync from
+    pub peer: SocketAddr,
+}
+
+pub async fn api_v1_sync_range(
+    Extension(agent): Extension<Agent>,
+    axum::extract::Json(body): axum::extract::Json<SyncRangeBody>,
+) -> (StatusCode, axum::Json<SyncNowResponse>) {
+    if body.start > body.end {
+        return (
+            StatusCode::BAD_REQUEST,
+            axum::Json(SyncNowResponse::Error(
+                "range start is after its end".into(),
+            )),
+        );
+    }
+
+    let (res_tx, res_rx) = oneshot::channel();
+    if let Err(e) = agent
+        .tx_sync_range()
+        .send(SyncRangeRequest {
+            actor_id: body.actor_id,
+            versions: body.start..=body.end,
+            peer: body.peer,
+            res_tx,
+        })
+        .await
+    {
+        error!("could not request range sync: {e}");
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(SyncNowResponse::Error(e.to_string())),
+        );
+    }
+
+    match res_rx.await {
+        Ok(Ok(n)) => (StatusCode::OK, axum::Json(SyncNowResponse::Synced(n))),
+        Ok(Err(e)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(SyncNowResponse::Error(e)),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            axum::Json(SyncNowResponse::Error(e.to_string())),
+        ),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BackupRequest {
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackupResponse {
+    pub size: u64,
+    pub time: f64,
+}
+
+// only plain relative paths are accepted and joined onto `api.backup_dir`, so
+// API clients can't have files read or written anywhere else on the node
+fn backup_dir_path(agent: &Agent, path: &Path) -> Result<PathBuf, (StatusCode, String)> {
+    let dir = match agent.config().api.backup_dir.clone() {
+        Some(dir) => dir,
+        None => return Err((StatusCode::FORBIDDEN, "api.backup_dir is not set".into())),
+    };
+
+    if path.as_os_str().is_empty()
+        || !path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "{} must be a relative path without '..', within api.backup_dir",
+                path.display()
+            ),
+        ));
+    }
+
+    Ok(dir.as_std_path().join(path))
+}
+
+pub async fn api_v1_backup(
+    Extension(agent): Extension<Agent>,
+    axum::extract::Json(req): axum::extract::Json<BackupRequest>,
+) -> Result<axum::Json<BackupResponse>, (StatusCode, String)> {
+    let path = backup_dir_path(&agent, &req.path)?;
+
+    if path.exists() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("{} already exists", req.path.display()),
+        ));
+    }
+
+    let start = Instant::now();
+
+    let conn = agent.pool().read().await.map_err(|e| {
+        error!("could not acquire read connection: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    })?;
+
+    // VACUUM INTO runs in a read transaction, the copy is a consistent snapshot
+    block_in_place(|| conn.execute("VACUUM INTO ?", [path.display().to_string()])).map_err(
+        |e| {
+            error!("could not backup database to {}: {e}", path.display());
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        },
+    )?;
+
+    let size = tokio::fs::metadata(&path)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .len();
+
+    info!("Backed up database to {} ({size} bytes)", path.display());
+
+    Ok(axum::Json(BackupResponse {
+        size,
+        time: start.elapsed().as_secs_f64(),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportRequest {
+    /// defaults to this node's actor id
+    #[serde(default)]
+    pub actor_id: Option<ActorId>,
+    pub from_version: Version,
+    pub to_version: Version,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportRequest {
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChangesFileResponse {
+    pub changes: usize,
+    pub time: f64,
+}
+
+pub async fn api_v1_export(
+    Extension(agent): Extension<Agent>,
+    axum::extract::Json(req): axum::extract::Json<ExportRequest>,
+) -> Result<axum::Json<ChangesFileResponse>, (StatusCode, String)> {
+    if req.from_version > req.to_version {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "from_version is after to_version".into(),
+        ));
+    }
+    let path = backup_dir_path(&agent, &req.path)?;
+
+    if path.exists() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("{} already exists", req.path.display()),
+        ));
+    }
+
+    let start = Instant::now();
+    let actor_id = req.actor_id.unwrap_or_else(|| agent.actor_id());
+
+    let changes = export_changes(&agent, actor_id, req.from_version..=req.to_version, &path)
+        .await
+        .map_err(|e| {
+            error!("could not export changes to {}: {e}", path.display());
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        })?;
+
+    info!(
+        "Exported {changes} changes for {actor_id} to {}",
+        path.display()
+    );
+
+    Ok(axum::Json(ChangesFileResponse {
+        changes,
+        time: start.elapsed().as_secs_f64(),
+    }))
+}
+
+pub async fn api_v1_import(
+    Extension(agent): Extension<Agent>,
+    axum::extract::Json(req): axum::extract::Json<ImportRequest>,
+) -> Result<axum::Json<ChangesFileResponse>, (StatusCode, String)> {
+    if agent.is_draining() {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "node is draining, not accepting writes".into(),
+        ));
+    }
+
+    let path = backup_dir_path(&agent, &req.path)?;
+
+    let start = Instant::now();
+
+    let changes = import_changes(&agent, &path).await.map_err(|e| {
+        error!("could not import changes from {}: {e}", path.display());
+        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    })?;
+
+    info!("Imported {changes} changes from {}", path.display());
+
+    Ok(axum::Json(ChangesFileResponse {
+        changes,
+        time: start.elapsed().as_secs_f64(),
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct DrainResponse {
+    pub synced: usize,
+    pub time: f64,
+}
+
+const DRAIN_BROADCASTS_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub async fn api_v1_drain(
+    Extension(agent): Extension<Agent>,
+) -> Result<axum::Json<DrainResponse>, (StatusCode, String)> {
+    if !agent.set_draining() {
+        return Err((StatusCode::CONFLICT, "node is already draining".into()));
+    }
+
+    info!("Draining: refusing new writes");
+    let start = Instant::now();
+
+    // writes that got in before the flag was set hold the write connection
+    // until they're committed (and their broadcasts queued), later ones
+    // check the flag again once they get it
+    drop(agent.pool().write_priority().await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("could not wait for in-flight writes: {e}"),
+        )
+    })?);
+
+    // let queued broadcasts get picked up before the final sync
+    let tx_bcast = agent.tx_bcast();
+    let flushed = tokio::time::timeout(DRAIN_BROADCASTS_TIMEOUT, async {
+        while tx_bcast.capacity() < tx_bcast.max_capacity() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await;
+    if flushed.is_err() {
+        warn!("Draining: broadcast queue didn't flush within {DRAIN_BROADCASTS_TIMEOUT:?}");
+    }
+
+    let (res_tx, res_rx) = oneshot::channel();
+    agent
+        .tx_sync_now()
+        .send(SyncNowRequest { res_tx })
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let synced = match res_rx.await {
+        Ok(Ok(n)) => n,
+        Ok(Err(e)) => {
+            error!("Draining: final sync failed: {e}");
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("final sync failed: {e}"),
+            ));
+        }
+        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    };
+
+    info!("Drained in {:?}, ready to shut down", start.elapsed());
+
+    Ok(axum::Json(DrainResponse {
+        synced,
+        time: start.elapsed().as_secs_f64(),
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct RuntimeConfigResponse {
+    pub limits: LimitsConfig,
+}
+
+pub async fn api_v1_admin_config(
+    Extension(agent): Extension<Agent>,
+) -> axum::Json<RuntimeConfigResponse> {
+    axum::Json(RuntimeConfigResponse {
+        limits: agent.config().limits.clone(),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetConfigValue<T> {
+    pub value: T,
+}
+
+pub async fn api_v1_admin_config_max_change_size(
+    Extension(agent): Extension<Agent>,
+    axum::extract::Json(req): axum::extract::Json<SetConfigValue<i64>>,
+) -> Result<axum::Json<RuntimeConfigResponse>, (StatusCode, String)> {
+    if req.value <= 0 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "max_change_size must be greater than 0".into(),
+        ));
+    }
+
+    let previous = agent.update_config(|conf| {
+        conf.limits.max_change_size = Some(req.value);
+    });
+
+    info!(
+        "Updated limits.max_change_size from {:?} to {}",
+        previous.limits.max_change_size, req.value
+    );
+
+    Ok(axum::Json(RuntimeConfigResponse {
+        limits: agent.config().limits.clone(),
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClusterMember {
+    pub actor_id: ActorId,
+    pub addr: SocketAddr,
+    pub ring: Option<u8>,
+    pub rtt_ms: Option<u64>,
+}
+
+pub async fn api_v1_cluster_members(
+    Extension(agent): Extension<Agent>,
+) -> (StatusCode, axum::Json<Vec<ClusterMember>>) {
+    let members = agent.members().read();
+
+    let members = members
+        .states
+        .iter()
+        .map(|(actor_id, state)| ClusterMember {
+            actor_id: *actor_id,
+            addr: state.addr,
+            ring: state.ring,
+            rtt_ms: members.avg_rtt(&state.addr),
+        })
+        .collect();
+
+    (StatusCode::OK, axum::Json(members))
+}
+
+#[derive(Debug, Serialize)]
+pub struct InfoResponse {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    pub actor_id: ActorId,
+    pub gossip_addr: SocketAddr,
+    pub api_addr: SocketAddr,
+    pub uptime_secs: u64,
+}
+
+pub async fn api_v1_info(Extension(agent): Extension<Agent>) -> axum::Json<InfoResponse> {
+    axum::Json(InfoResponse {
+        version: env!("CARGO_PKG_VERSION"),
+        git_sha: env!("CORRO_GIT_SHA"),
+        actor_id: agent.actor_id(),
+        gossip_addr: agent.gossip_addr(),
+        api_addr: agent.api_addr(),
+        uptime_secs: agent.uptime().as_secs(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use corro_types::{
+        api::RowId,
+        base::Version,
+        config::Config,
+        schema::{init_schema, SqliteType},
+    };
+    use futures::Stream;
+    use http_body::{combinators::UnsyncBoxBody, Body};
+    use hyper::header::HeaderValue;
+    use tokio::sync::mpsc::error::TryRecvError;
+    use tokio_util::codec::{Decoder, LinesCodec};
+    use tripwire::Tripwire;
+
+    use super::*;
+
+    use crate::agent::setup;
+
+    struct UnsyncBodyStream(std::pin::Pin<Box<UnsyncBoxBody<Bytes, axum::Error>>>);
+
+    impl Stream for UnsyncBodyStream {
+        type Item = Result<Bytes, axum::Error>;
+
+        fn poll_next(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Option<Self::Item>> {
+            self.0.as_mut().poll_data(cx)
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_db_execute() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, mut agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let rx_bcast = &mut agent_options.rx_bcast;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        // the schema change is recorded as version 1, for other nodes to apply
+        assert!(matches!(
+            rx_bcast.recv().await,
+            Some(BroadcastInput::AddBroadcast(BroadcastV1::Change(ChangeV1 {
+                changeset: Changeset::Full {
+                    version: Version(1),
+                    ref changes,
+                    ..
+                },
+                ..
+            }))) if changes.iter().all(|change| change.table.as_str() == SCHEMA_CHANGES_TABLE)
+        ));
+
+        let (status_code, body) = api_v1_transactions(
+            Extension(agent.clone()),
+            HeaderMap::new(),
+            ApiBody(vec![Statement::WithParams(
+                "insert into tests (id, text) values (?,?)".into(),
+                vec!["service-id".into(), "service-name".into()],
+            )]),
+        )
+        .await;
+
+        println!("{body:?}");
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        assert!(body.0.results.len() == 1);
+
+        let msg = rx_bcast
+            .recv()
+            .await
+            .expect("not msg received on bcast channel");
+
+        assert!(matches!(
+            msg,
+            BroadcastInput::AddBroadcast(BroadcastV1::Change(ChangeV1 {
+                changeset: Changeset::Full {
+                    version: Version(2),
+                    ..
+                },
+                ..
+            }))
+        ));
+
+        let last = agent
+            .bookie()
+            .write("test")
+            .await
+            .for_actor(agent.actor_id())
+            .read("test")
+            .await
+            .last();
+        assert_eq!(last, Some(Version(2)));
+
+        // the response carries the version that was assigned to the changes
+        assert_eq!(body.0.version, last);
+
+        println!("second req...");
+
+        let (status_code, body) = api_v1_transactions(
+            Extension(agent.clone()),
+            HeaderMap::new(),
+            ApiBody(vec![Statement::WithParams(
+                "update tests SET text = ? where id = ?".into(),
+                vec!["service-name".into(), "service-id".into()],
+            )]),
+        )
+        .await;
+
+        println!("{body:?}");
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        assert!(body.0.results.len() == 1);
+
+        // no actual changes!
+        assert!(matches!(rx_bcast.try_recv(), Err(TryRecvError::Empty)));
+        assert_eq!(body.0.version, None);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_db_execute_returning() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, mut agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let rx_bcast = &mut agent_options.rx_bcast;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        // the schema change is recorded as version 1, for other nodes to apply
+        assert!(matches!(
+            rx_bcast.recv().await,
+            Some(BroadcastInput::AddBroadcast(BroadcastV1::Change(ChangeV1 {
+                changeset: Changeset::Full {
+                    version: Version(1),
+                    ref changes,
+                    ..
+                },
+                ..
+            }))) if changes.iter().all(|change| change.table.as_str() == SCHEMA_CHANGES_TABLE)
+        ));
+
+        let (status_code, body) = api_v1_transactions(
+            Extension(agent.clone()),
+            HeaderMap::new(),
+            ApiBody(vec![
+                Statement::WithParams(
+                    "insert into tests (id, text) values (?,?) returning id, text".into(),
+                    vec!["service-id".into(), "service-name".into()],
+                ),
+                Statement::Simple(
+                    "insert into tests (id, text) values ('service-id-2', 'service-name-2')".into(),
+                ),
+            ]),
+        )
+        .await;
+
+        println!("{body:?}");
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        assert!(matches!(
+            &body.0.results[0],
+            ExecResult::Execute {
+                rows_affected: 1,
+                columns: Some(columns),
+                rows: Some(rows),
+                ..
+            } if columns == &vec![ColumnName("id".into()), ColumnName("text".into())]
+                && rows == &vec![vec![SqliteValue::from("service-id"), SqliteValue::from("service-name")]]
+        ));
+
+        // statements without a RETURNING clause don't return rows
+        assert!(matches!(
+            &body.0.results[1],
+            ExecResult::Execute {
+                rows_affected: 1,
+                columns: None,
+                rows: None,
+                ..
+            }
+        ));
+
+        // changes are still broadcast
+        let msg = rx_bcast
+            .recv()
+            .await
+            .expect("not msg received on bcast channel");
+
+        assert!(matches!(
+            msg,
+            BroadcastInput::AddBroadcast(BroadcastV1::Change(ChangeV1 {
+                changeset: Changeset::Full {
+                    version: Version(2),
+                    ..
+                },
+                ..
+            }))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_db_query() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        let (status_code, body) = api_v1_transactions(
+            Extension(agent.clone()),
+            HeaderMap::new(),
+            ApiBody(vec![
+                Statement::WithParams(
+                    "insert into tests (id, text) values (?,?)".into(),
+                    vec!["service-id".into(), "service-name".into()],
+                ),
+                Statement::WithParams(
+                    "insert into tests (id, text) values (?,?)".into(),
+                    vec!["service-id-2".into(), "service-name-2".into()],
+                ),
+            ]),
+        )
+        .await;
+
+        // println!("{body:?}");
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        assert!(body.0.results.len() == 2);
+
+        println!("transaction body: {body:?}");
+
+        let res = api_v1_queries(
+            Extension(agent.clone()),
+            axum::extract::Query(QueryParams::default()),
+            HeaderMap::new(),
+            ApiBody(Statement::Simple("select * from tests".into())),
+        )
+        .await
+        .into_response();
+
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut body = res.into_body();
+
+        let mut lines = LinesCodec::new();
+
+        let mut buf = BytesMut::new();
+
+        buf.extend_from_slice(&body.data().await.unwrap()?);
+
+        let s = lines.decode(&mut buf).unwrap().unwrap();
+
+        let cols: QueryEvent = serde_json::from_str(&s).unwrap();
+
+        assert_eq!(cols, QueryEvent::Columns(vec!["id".into(), "text".into()]));
+
+        buf.extend_from_slice(&body.data().await.unwrap()?);
+
+        let s = lines.decode(&mut buf).unwrap().unwrap();
+
+        let row: QueryEvent = serde_json::from_str(&s).unwrap();
+
+        assert_eq!(
+            row,
+            QueryEvent::Row(RowId(1), vec!["service-id".into(), "service-name".into()])
+        );
+
+        buf.extend_from_slice(&body.data().await.unwrap()?);
+
+        let s = lines.decode(&mut buf).unwrap().unwrap();
+
+        let row: QueryEvent = serde_json::from_str(&s).unwrap();
+
+        assert_eq!(
+            row,
+            QueryEvent::Row(
+                RowId(2),
+                vec!["service-id-2".into(), "service-name-2".into()]
+            )
+        );
+
+        buf.extend_from_slice(&body.data().await.unwrap()?);
+
+        let s = lines.decode(&mut buf).unwrap().unwrap();
+
+        let query_evt: QueryEvent = serde_json::from_str(&s).unwrap();
+
+        assert!(matches!(query_evt, QueryEvent::EndOfQuery { .. }));
+
+        assert!(body.data().await.is_none());
+
+        // a query with empty params should return the same shape as a simple one
+        let res = api_v1_queries(
+            Extension(agent.clone()),
+            axum::extract::Query(QueryParams::default()),
+            HeaderMap::new(),
+            ApiBody(Statement::WithParams(
+                "select * from tests where id = 'service-id'".into(),
+                vec![],
+            )),
+        )
+        .await
+        .into_response();
+
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut body = res.into_body();
+
+        let mut buf = BytesMut::new();
+
+        buf.extend_from_slice(&body.data().await.unwrap()?);
+
+        let s = lines.decode(&mut buf).unwrap().unwrap();
+
+        let cols: QueryEvent = serde_json::from_str(&s).unwrap();
+
+        assert_eq!(cols, QueryEvent::Columns(vec!["id".into(), "text".into()]));
+
+        buf.extend_from_slice(&body.data().await.unwrap()?);
+
+        let s = lines.decode(&mut buf).unwrap().unwrap();
+
+        let row: QueryEvent = serde_json::from_str(&s).unwrap();
+
+        assert_eq!(
+            row,
+            QueryEvent::Row(RowId(1), vec!["service-id".into(), "service-name".into()])
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_blob_roundtrip() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let statements: Vec<Statement> = serde_json::from_str(
+            r#"[["insert into testsblob (id, text) values (?, ?)", [{"$blob": "AAH/"}, "binary"]]]"#,
+        )?;
+
+        let (status_code, _body) = api_v1_transactions(
+            Extension(agent.clone()),
+            HeaderMap::new(),
+            ApiBody(statements),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let stored: Vec<u8> =
+            agent
+                .pool()
+                .read()
+                .await?
+                .query_row("SELECT id FROM testsblob", [], |row| row.get(0))?;
+        assert_eq!(stored, vec![0x00, 0x01, 0xff]);
+
+        let res = api_v1_queries(
+            Extension(agent.clone()),
+            axum::extract::Query(QueryParams::default()),
+            HeaderMap::new(),
+            ApiBody(Statement::Simple("select id, text from testsblob".into())),
+        )
+        .await
+        .into_response();
+
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(res.into_body()).await?;
+        let mut lines = std::str::from_utf8(&body)?.lines();
+
+        // skip columns
+        lines.next();
+
+        let row = lines.next().unwrap();
+        assert_eq!(row, r#"{"row":[1,[{"$blob":"AAH/"},"binary"]]}"#);
+
+        let row: QueryEvent = serde_json::from_str(row)?;
+        assert_eq!(
+            row,
+            QueryEvent::Row(
+                RowId(1),
+                vec![
+                    SqliteValue::Blob(vec![0x00, 0x01, 0xff].into()),
+                    "binary".into()
+                ]
+            )
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_msgpack() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::ACCEPT,
+            HeaderValue::from_static(MSGPACK_CONTENT_TYPE),
+        );
+
+        let req = hyper::Request::builder()
+            .header(header::CONTENT_TYPE, MSGPACK_CONTENT_TYPE)
+            .body(hyper::Body::from(rmp_serde::to_vec_named(&vec![
+                Statement::WithParams(
+                    "insert into testsblob (id, text) values (?, ?)".into(),
+                    vec![vec![0x00, 0x01, 0xff].into(), "binary".into()],
+                ),
+            ])?))?;
+        let body = ApiBody::<Vec<Statement>>::from_request(req, &())
+            .await
+            .unwrap();
+
+        let res = api_v1_transactions(Extension(agent.clone()), headers.clone(), body)
+            .await
+            .into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE),
+            Some(&HeaderValue::from_static(MSGPACK_CONTENT_TYPE))
+        );
+
+        let body = hyper::body::to_bytes(res.into_body()).await?;
+        let res: ExecResponse = rmp_serde::from_slice(&body)?;
+        assert!(matches!(
+            res.results[..],
+            [ExecResult::Execute {
+                rows_affected: 1,
+                ..
+            }]
+        ));
+
+        let req = hyper::Request::builder()
+            .header(header::CONTENT_TYPE, MSGPACK_CONTENT_TYPE)
+            .body(hyper::Body::from(b"not msgpack".to_vec()))?;
+        let rejection = ApiBody::<Vec<Statement>>::from_request(req, &())
+            .await
+            .unwrap_err();
+        assert_eq!(rejection.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let res = api_v1_queries(
+            Extension(agent.clone()),
+            axum::extract::Query(QueryParams::default()),
+            headers,
+            ApiBody(Statement::Simple("select id, text from testsblob".into())),
+        )
+        .await
+        .into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE),
+            Some(&HeaderValue::from_static(MSGPACK_CONTENT_TYPE))
+        );
+
+        let body = hyper::body::to_bytes(res.into_body()).await?;
+        let mut de = rmp_serde::Deserializer::new(&body[..]);
+
+        assert!(matches!(
+            QueryEvent::deserialize(&mut de)?,
+            QueryEvent::Columns(_)
+        ));
+        assert_eq!(
+            QueryEvent::deserialize(&mut de)?,
+            QueryEvent::Row(
+                RowId(1),
+                vec![
+                    SqliteValue::Blob(vec![0x00, 0x01, 0xff].into()),
+                    "binary".into()
+                ]
+            )
+        );
+        assert!(matches!(
+            QueryEvent::deserialize(&mut de)?,
+            QueryEvent::EndOfQuery { .. }
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_db_query_csv() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let (status_code, _body) = api_v1_transactions(
+            Extension(agent.clone()),
+            HeaderMap::new(),
+            ApiBody(vec![
+                Statement::WithParams(
+                    "insert into tests (id, text) values (?,?)".into(),
+                    vec![1i64.into(), "plain".into()],
+                ),
+                Statement::WithParams(
+                    "insert into tests (id, text) values (?,?)".into(),
+                    vec![2i64.into(), "with, a \"quote\"\nand a newline".into()],
+                ),
+            ]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, "text/csv".parse()?);
+
+        let res = api_v1_queries(
+            Extension(agent.clone()),
+            axum::extract::Query(QueryParams::default()),
+            headers.clone(),
+            ApiBody(Statement::WithParams(
+                "select id, text, null as missing from tests where id >= ?".into(),
+                vec![1i64.into()],
+            )),
+        )
+        .await
+        .into_response();
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/csv; charset=utf-8"
+        );
+
+        let body = hyper::body::to_bytes(res.into_body()).await?;
+        assert_eq!(
+            std::str::from_utf8(&body)?,
+            "id,text,missing\r\n1,plain,\r\n2,\"with, a \"\"quote\"\"\nand a newline\",\r\n"
+        );
+
+        let res = api_v1_queries(
+            Extension(agent.clone()),
+            axum::extract::Query(QueryParams::default()),
+            headers,
+            ApiBody(Statement::Simple(
+                "select * from tests; select * from tests2".into(),
+            )),
+        )
+        .await
+        .into_response();
+
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_queries_named() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let mut conf = Config::builder()
+            .db_path(dir.path().join("corrosion.db").display().to_string())
+            .gossip_addr("127.0.0.1:0".parse()?)
+            .api_addr("127.0.0.1:0".parse()?)
+            .build()?;
+        conf.queries.insert(
+            "text_by_id".into(),
+            "SELECT text FROM tests WHERE id = ?".into(),
+        );
+
+        let (agent, _agent_options) = setup(conf, tripwire).await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let (status_code, _body) = api_v1_transactions(
+            Extension(agent.clone()),
+            HeaderMap::new(),
+            ApiBody(vec![Statement::Simple(
+                "INSERT INTO tests (id, text) VALUES (1, 'one'), (2, 'two')".into(),
+            )]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let res = api_v1_queries_named(
+            Extension(agent.clone()),
+            axum::extract::Query(QueryParams::default()),
+            HeaderMap::new(),
+            axum::Json(NamedQuery {
+                name: "text_by_id".into(),
+                params: Some(vec![2i64.into()]),
+                named_params: None,
+            }),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(res.into_body()).await?;
+        let events = body
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(serde_json::from_slice)
+            .collect::<Result<Vec<QueryEvent>, _>>()?;
+
+        assert_eq!(events[0], QueryEvent::Columns(vec!["text".into()]));
+        assert_eq!(events[1], QueryEvent::Row(RowId(1), vec!["two".into()]));
+        assert!(matches!(events[2], QueryEvent::EndOfQuery { .. }));
+        assert_eq!(events.len(), 3);
+
+        let res = api_v1_queries_named(
+            Extension(agent.clone()),
+            axum::extract::Query(QueryParams::default()),
+            HeaderMap::new(),
+            axum::Json(NamedQuery {
+                name: "nope".into(),
+                params: None,
+                named_params: None,
+            }),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_redact_sql() {
+        assert_eq!(
+            redact_sql("SELECT * FROM users WHERE token = 'hunter2' AND id > 10 LIMIT ?"),
+            "SELECT * FROM users WHERE token = ? AND id > ? LIMIT ?"
+        );
+        assert_eq!(
+            redact_sql("INSERT INTO blobs (id, data) VALUES (:id, x'deadbeef'), (1.5, \"col\")"),
+            "INSERT INTO blobs ( id , data ) VALUES ( :id , ? ) , ( ? , \"col\" )"
+        );
+        assert_eq!(redact_sql("SELECT 'unterminated"), "<unparseable sql>");
+    }
+
+    #[test]
+    fn test_is_transaction_control() {
+        for sql in [
+            "BEGIN",
+            "begin immediate transaction",
+            "COMMIT",
+            "END TRANSACTION",
+            "ROLLBACK TO sp1",
+            "SAVEPOINT sp1",
+            "RELEASE sp1",
+            "  -- a comment\n/* another */ BEGIN",
+            "; COMMIT",
+        ] {
+            assert!(is_transaction_control(sql), "{sql}");
+        }
+
+        for sql in [
+            "INSERT INTO tests (id, text) VALUES (1, 'BEGIN')",
+            "SELECT CASE WHEN 1 THEN 2 END",
+            "UPDATE tests SET text = 'COMMIT'",
+            "",
+        ] {
+            assert!(!is_transaction_control(sql), "{sql}");
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_transactions_changes_count() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![
+                "CREATE TABLE wide (id INTEGER NOT NULL PRIMARY KEY, a TEXT NOT NULL DEFAULT '', b TEXT NOT NULL DEFAULT '', c TEXT NOT NULL DEFAULT '') WITHOUT ROWID;".into(),
+            ]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let exec = |sql: &str| {
+            api_v1_transactions(
+                Extension(agent.clone()),
+                HeaderMap::new(),
+                ApiBody(vec![Statement::Simple(sql.into())]),
+            )
+        };
+
+        let (status_code, body) =
+            exec("INSERT INTO wide (id, a, b, c) VALUES (1, 'a', 'b', 'c')").await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert!(matches!(
+            body.0.results[..],
+            [ExecResult::Execute {
+                rows_affected: 1,
+                ..
+            }]
+        ));
+        // one change per column
+        assert_eq!(body.0.changes, 3);
+
+        let (status_code, body) = exec("UPDATE wide SET a = 'aa', b = 'bb' WHERE id = 1").await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert!(matches!(
+            body.0.results[..],
+            [ExecResult::Execute {
+                rows_affected: 1,
+                ..
+            }]
+        ));
+        assert_eq!(body.0.changes, 2);
+
+        // a no-op update affects the row, but changes nothing
+        let (status_code, body) = exec("UPDATE wide SET c = 'c' WHERE id = 1").await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert!(matches!(
+            body.0.results[..],
+            [ExecResult::Execute {
+                rows_affected: 1,
+                ..
+            }]
+        ));
+        assert_eq!(body.0.changes, 0);
+        assert_eq!(body.0.version, None);
+
+        // deletes are recorded as a single tombstone
+        let (status_code, body) = exec("DELETE FROM wide WHERE id = 1").await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert!(matches!(
+            body.0.results[..],
+            [ExecResult::Execute {
+                rows_affected: 1,
+                ..
+            }]
+        ));
+        assert_eq!(body.0.changes, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_transactions_rejects_transaction_control() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let (status_code, body) = api_v1_transactions(
+            Extension(agent.clone()),
+            HeaderMap::new(),
+            ApiBody(vec![
+                Statement::Simple("INSERT INTO tests (id, text) VALUES (1, 'one')".into()),
+                Statement::Simple("BEGIN".into()),
+            ]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::BAD_REQUEST);
+        assert!(matches!(
+            &body.0.results[..],
+            [ExecResult::Error { error }] if error.contains("statement #1")
+        ));
+
+        // nothing ran, and the write connection is not left in a transaction
+        assert!(agent.pool().write_priority().await?.is_autocommit());
+
+        let (status_code, body) = api_v1_transactions(
+            Extension(agent.clone()),
+            HeaderMap::new(),
+            ApiBody(vec![Statement::Simple(
+                "INSERT INTO tests (id, text) VALUES (2, 'two')".into(),
+            )]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        // version 1 is the schema change
+        assert_eq!(body.0.version, Some(Version(2)));
+
+        let count: i64 =
+            agent
+                .pool()
+                .read()
+                .await?
+                .query_row("SELECT COUNT(*) FROM tests", [], |row| row.get(0))?;
+        assert_eq!(count, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_db_explain() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![format!(
+                "{}\nCREATE INDEX IF NOT EXISTS tests_text ON tests (text);",
+                corro_tests::TEST_SCHEMA
+            )]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let res = api_v1_db_explain(
+            Extension(agent.clone()),
+            axum::extract::Query(QueryParams::default()),
+            HeaderMap::new(),
+            axum::Json(Statement::WithParams(
+                "select id from tests where text = ?".into(),
+                vec!["hello".into()],
+            )),
+        )
+        .await
+        .into_response();
+
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(res.into_body()).await?;
+        let events = body
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(serde_json::from_slice)
+            .collect::<Result<Vec<QueryEvent>, _>>()?;
+
+        assert!(
+            events.iter().any(|event| matches!(
+                event,
+                QueryEvent::Row(_, cells) if cells.iter().any(|cell| matches!(
+                    cell,
+                    SqliteValue::Text(detail) if detail.contains("USING COVERING INDEX tests_text")
+                ))
+            )),
+            "plan does not use the index: {events:?}"
+        );
+
+        // writes go through the same readonly check as regular queries
+        let res = api_v1_db_explain(
+            Extension(agent.clone()),
+            axum::extract::Query(QueryParams::default()),
+            HeaderMap::new(),
+            axum::Json(Statement::Simple(
+                "insert into tests (id, text) values (1, 'hello')".into(),
+            )),
+        )
+        .await
+        .into_response();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let count: i64 =
+            agent
+                .pool()
+                .read()
+                .await?
+                .query_row("SELECT count(*) FROM tests", [], |row| row.get(0))?;
+        assert_eq!(count, 0);
+
+        let res = api_v1_db_explain(
+            Extension(agent.clone()),
+            axum::extract::Query(QueryParams::default()),
+            HeaderMap::new(),
+            axum::Json(Statement::Simple(
+                "select * from tests; delete from tests".into(),
+            )),
+        )
+        .await
+        .into_response();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_max_change_size() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .max_change_size(2)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let large_write = || {
+            ApiBody(vec![
+                Statement::Simple("insert into tests (id, text) values (1, 'one')".into()),
+                Statement::Simple(
+                    "insert into tests (id, text) values (2, 'two'), (3, 'three')".into(),
+                ),
+            ])
+        };
+
+        let (status_code, body) =
+            api_v1_transactions(Extension(agent.clone()), HeaderMap::new(), large_write()).await;
+        assert_eq!(status_code, StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(matches!(
+            &body.0.results[..],
+            [ExecResult::Error { error }] if error.contains("max_change_size")
+        ));
+        assert_eq!(body.0.version, None);
+
+        let count: i64 =
+            agent
+                .pool()
+                .read()
+                .await?
+                .query_row("SELECT count(*) FROM tests", [], |row| row.get(0))?;
+        assert_eq!(count, 0);
+
+        let res = api_v1_admin_config_max_change_size(
+            Extension(agent.clone()),
+            axum::Json(SetConfigValue { value: 0 }),
+        )
+        .await;
+        assert!(matches!(res, Err((StatusCode::BAD_REQUEST, _))));
+
+        let res = api_v1_admin_config_max_change_size(
+            Extension(agent.clone()),
+            axum::Json(SetConfigValue { value: 10 }),
+        )
+        .await;
+        assert!(res.is_ok());
+
+        let axum::Json(runtime) = api_v1_admin_config(Extension(agent.clone())).await;
+        assert_eq!(runtime.limits.max_change_size, Some(10));
+
+        let (status_code, body) =
+            api_v1_transactions(Extension(agent.clone()), HeaderMap::new(), large_write()).await;
+        assert_eq!(status_code, StatusCode::OK);
+        // version 1 is the schema change
+        assert_eq!(body.0.version, Some(Version(2)));
+
+        let count: i64 =
+            agent
+                .pool()
+                .read()
+                .await?
+                .query_row("SELECT count(*) FROM tests", [], |row| row.get(0))?;
+        assert_eq!(count, 3);
+
+        // concurrent config updates don't overwrite each other
+        let schema_paths = agent.config().db.schema_paths.len();
+        let mut tasks = tokio::task::JoinSet::new();
+        for i in 1..=20 {
+            let agent = agent.clone();
+            tasks.spawn(async move {
+                assert!(api_v1_admin_config_max_change_size(
+                    Extension(agent.clone()),
+                    axum::Json(SetConfigValue { value: i }),
+                )
+                .await
+                .is_ok());
+                agent.update_config(|conf| conf.db.schema_paths.push(format!("/tmp/{i}").into()));
+            });
+        }
+        while let Some(res) = tasks.join_next().await {
+            res?;
+        }
+        assert_eq!(agent.config().db.schema_paths.len(), schema_paths + 20);
+        assert!(agent.config().limits.max_change_size.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_pool_acquire_timeout() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .pool_acquire_timeout_ms(100)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let write = || {
+            ApiBody(vec![Statement::Simple(
+                "insert into tests (id, text) values (1, 'one')".into(),
+            )])
+        };
+
+        // hold the only write connection
+        let conn = agent.pool().write_priority().await?;
+
+        let start = Instant::now();
+        let (status_code, body) =
+            api_v1_transactions(Extension(agent.clone()), HeaderMap::new(), write()).await;
+        assert_eq!(status_code, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(matches!(
+            &body.0.results[..],
+            [ExecResult::Error { error }] if error.contains("timed out")
+        ));
+        assert_eq!(body.0.version, None);
+
+        // internal writers keep waiting past the timeout
+        let normal = tokio::spawn({
+            let agent = agent.clone();
+            async move { agent.pool().write_normal().await.map(drop) }
+        });
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(!normal.is_finished());
+
+        drop(conn);
+        normal.await??;
+
+        let (status_code, body) =
+            api_v1_transactions(Extension(agent.clone()), HeaderMap::new(), write()).await;
+        assert_eq!(status_code, StatusCode::OK);
+        // version 1 is the schema change
+        assert_eq!(body.0.version, Some(Version(2)));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_read_only() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let (status_code, _body) = api_v1_transactions(
+            Extension(agent.clone()),
+            HeaderMap::new(),
+            ApiBody(vec![Statement::Simple(
+                "insert into tests (id, text) values (1, 'hello')".into(),
+            )]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let mut conf = agent.config().as_ref().clone();
+        conf.node.read_only = true;
+        agent.set_config(conf);
+
+        let (status_code, body) = api_v1_transactions(
+            Extension(agent.clone()),
+            HeaderMap::new(),
+            ApiBody(vec![Statement::Simple(
+                "insert into tests (id, text) values (2, 'world')".into(),
+            )]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::FORBIDDEN);
+        assert!(matches!(
+            &body.0.results[..],
+            [ExecResult::Error { error }] if error.contains("read-only")
+        ));
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![
+                "CREATE TABLE other (id INTEGER NOT NULL PRIMARY KEY) WITHOUT ROWID;".into(),
+            ]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::FORBIDDEN);
+
+        let res = api_v1_queries(
+            Extension(agent.clone()),
+            axum::extract::Query(QueryParams::default()),
+            HeaderMap::new(),
+            ApiBody(Statement::Simple("select id, text from tests".into())),
+        )
+        .await
+        .into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(res.into_body()).await?;
+        let rows = body
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(serde_json::from_slice)
+            .collect::<Result<Vec<QueryEvent>, _>>()?
+            .into_iter()
+            .filter(|event| matches!(event, QueryEvent::Row(..)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            vec![QueryEvent::Row(RowId(1), vec![1i64.into(), "hello".into()])]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_db_query_timeout() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        // never terminates on its own
+        let res = api_v1_queries(
+            Extension(agent.clone()),
+            axum::extract::Query(QueryParams {
+                timeout_ms: Some(100),
+            }),
+            HeaderMap::new(),
+            ApiBody(Statement::Simple(
+                "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c) SELECT count(*) FROM c"
+                    .into(),
+            )),
+        )
+        .await
+        .into_response();
+
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut body = res.into_body();
+
+        let mut lines = LinesCodec::new();
+
+        let mut buf = BytesMut::new();
+
+        buf.extend_from_slice(&body.data().await.unwrap()?);
+
+        let s = lines.decode(&mut buf).unwrap().unwrap();
+
+        let cols: QueryEvent = serde_json::from_str(&s).unwrap();
+
+        assert_eq!(cols, QueryEvent::Columns(vec!["count(*)".into()]));
+
+        buf.extend_from_slice(
+            &tokio::time::timeout(Duration::from_secs(5), body.data())
+                .await?
+                .unwrap()?,
+        );
+
+        let s = lines.decode(&mut buf).unwrap().unwrap();
+
+        let evt: QueryEvent = serde_json::from_str(&s).unwrap();
+
+        assert_eq!(evt, QueryEvent::Error("query timed out".into()));
+
+        assert!(body.data().await.is_none());
+
+        // queries finishing before their deadline are unaffected
+        let res = api_v1_queries(
+            Extension(agent.clone()),
+            axum::extract::Query(QueryParams {
+                timeout_ms: Some(5000),
+            }),
+            HeaderMap::new(),
+            ApiBody(Statement::Simple("SELECT 1".into())),
+        )
+        .await
+        .into_response();
+
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut body = res.into_body();
+
+        let mut buf = BytesMut::new();
+
+        while let Some(data) = body.data().await {
+            buf.extend_from_slice(&data?);
+        }
+
+        let evts = std::iter::from_fn(|| lines.decode(&mut buf).unwrap())
+            .map(|s| serde_json::from_str::<QueryEvent>(&s))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        assert_eq!(evts.len(), 3);
+        assert_eq!(evts[1], QueryEvent::Row(RowId(1), vec![1i64.into()]));
+        assert!(matches!(evts[2], QueryEvent::EndOfQuery { .. }));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_db_schema() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![
+                "CREATE TABLE tests (id BIGINT NOT NULL PRIMARY KEY, foo TEXT) WITHOUT ROWID;"
+                    .into(),
+            ]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        // scope the schema reader in here
+        {
+            let schema = agent.schema().read();
+            let tests = schema
+                .tables
+                .get("tests")
+                .expect("no tests table in schema");
+
+            let id_col = tests.columns.get("id").unwrap();
+            assert_eq!(id_col.name, "id");
+            assert_eq!(id_col.sql_type(), (SqliteType::Integer, Some("BIGINT")));
+            assert!(!id_col.nullable);
+            assert!(id_col.primary_key);
+
+            let foo_col = tests.columns.get("foo").unwrap();
+            assert_eq!(foo_col.name, "foo");
+            assert_eq!(foo_col.sql_type(), (SqliteType::Text, Some("TEXT")));
+            assert!(foo_col.nullable);
+            assert!(!foo_col.primary_key);
+        }
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![
+                "CREATE TABLE tests2 (id BIGINT NOT NULL PRIMARY KEY, foo TEXT) WITHOUT ROWID;"
+                    .into(),
+                "CREATE TABLE tests (id BIGINT NOT NULL PRIMARY KEY, foo TEXT) WITHOUT ROWID;"
+                    .into(),
+            ]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        {
+            let schema = agent.schema().read();
+            let tests = schema
+                .tables
+                .get("tests")
+                .expect("no tests table in schema");
+
+            let id_col = tests.columns.get("id").unwrap();
+            assert_eq!(id_col.name, "id");
+            assert_eq!(id_col.sql_type(), (SqliteType::Integer, Some("BIGINT")));
+            assert!(!id_col.nullable);
+            assert!(id_col.primary_key);
+
+            let foo_col = tests.columns.get("foo").unwrap();
+            assert_eq!(foo_col.name, "foo");
+            assert_eq!(foo_col.sql_type(), (SqliteType::Text, Some("TEXT")));
+            assert!(foo_col.nullable);
+            assert!(!foo_col.primary_key);
+
+            let tests = schema
+                .tables
+                .get("tests2")
+                .expect("no tests2 table in schema");
+
+            let id_col = tests.columns.get("id").unwrap();
+            assert_eq!(id_col.name, "id");
+            assert_eq!(id_col.sql_type(), (SqliteType::Integer, Some("BIGINT")));
+            assert!(!id_col.nullable);
+            assert!(id_col.primary_key);
+
+            let foo_col = tests.columns.get("foo").unwrap();
+            assert_eq!(foo_col.name, "foo");
+            assert_eq!(foo_col.sql_type(), (SqliteType::Text, Some("TEXT")));
+            assert!(foo_col.nullable);
+            assert!(!foo_col.primary_key);
+        }
+
+        // w/ existing table!
+
+        let create_stmt = "CREATE TABLE tests3 (id BIGINT NOT NULL PRIMARY KEY, foo TEXT, updated_at INTEGER NOT NULL DEFAULT 0) WITHOUT ROWID;";
+
+        {
+            // adding the table and an index
+            let conn = agent.pool().write_priority().await?;
+            conn.execute_batch(create_stmt)?;
+            conn.execute_batch("CREATE INDEX tests3_updated_at ON tests3 (updated_at);")?;
+            assert_eq!(
+                conn.execute(
+                    "INSERT INTO tests3 VALUES (123, 'some foo text', 123456789);",
+                    ()
+                )?,
+                1
+            );
+            assert_eq!(
+                conn.execute(
+                    "INSERT INTO tests3 VALUES (1234, 'some foo text 2', 1234567890);",
+                    ()
+                )?,
+                1
+            );
+        }
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![create_stmt.into()]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        {
+            let schema = agent.schema().read();
+
+            // check that the tests table is still there!
+            let tests = schema
+                .tables
+                .get("tests")
+                .expect("no tests table in schema");
+
+            let id_col = tests.columns.get("id").unwrap();
+            assert_eq!(id_col.name, "id");
+            assert_eq!(id_col.sql_type(), (SqliteType::Integer, Some("BIGINT")));
+            assert!(!id_col.nullable);
+            assert!(id_col.primary_key);
+
+            let foo_col = tests.columns.get("foo").unwrap();
+            assert_eq!(foo_col.name, "foo");
+            assert_eq!(foo_col.sql_type(), (SqliteType::Text, Some("TEXT")));
+            assert!(foo_col.nullable);
+            assert!(!foo_col.primary_key);
+
+            let tests = schema
+                .tables
+                .get("tests3")
+                .expect("no tests3 table in schema");
+
+            let id_col = tests.columns.get("id").unwrap();
+            assert_eq!(id_col.name, "id");
+            assert_eq!(id_col.sql_type(), (SqliteType::Integer, Some("BIGINT")));
+            assert!(!id_col.nullable);
+            assert!(id_col.primary_key);
+
+            let foo_col = tests.columns.get("foo").unwrap();
+            assert_eq!(foo_col.name, "foo");
+            assert_eq!(foo_col.sql_type(), (SqliteType::Text, Some("TEXT")));
+            assert!(foo_col.nullable);
+            assert!(!foo_col.primary_key);
+
+            let updated_at_col = tests.columns.get("updated_at").unwrap();
+            assert_eq!(updated_at_col.name, "updated_at");
+            assert_eq!(
+                updated_at_col.sql_type(),
+                (SqliteType::Integer, Some("INTEGER"))
+            );
+            assert!(!updated_at_col.nullable);
+            assert!(!updated_at_col.primary_key);
+
+            let updated_at_idx = tests.indexes.get("tests3_updated_at").unwrap();
+            assert_eq!(updated_at_idx.name, "tests3_updated_at");
+            assert_eq!(updated_at_idx.tbl_name, "tests3");
+            assert_eq!(updated_at_idx.columns.len(), 1);
+            assert!(updated_at_idx.where_clause.is_none());
+        }
+
+        // tables missing from new statements are kept, schemas are merged
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![
+                "CREATE TABLE tests (id BIGINT NOT NULL PRIMARY KEY, foo TEXT) WITHOUT ROWID;"
+                    .into(),
+            ]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert!(agent.schema().read().tables.contains_key("tests2"));
+
+        // removing a column takes the destructive flag
+        let no_foo = "CREATE TABLE tests2 (id BIGINT NOT NULL PRIMARY KEY) WITHOUT ROWID;";
+        let (status_code, body) =
+            api_v1_db_schema(Extension(agent.clone()), axum::Json(vec![no_foo.into()])).await;
+        assert_eq!(status_code, StatusCode::BAD_REQUEST);
+        assert!(matches!(
+            &body.0.results[..],
+            [ExecResult::Error { error }] if error.contains("without the destructive flag")
+        ));
+
+        agent.update_config(|conf| conf.db.allow_destructive_schema = true);
+
+        let (status_code, _body) =
+            api_v1_db_schema(Extension(agent.clone()), axum::Json(vec![no_foo.into()])).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert!(!agent.schema().read().tables["tests2"]
+            .columns
+            .contains_key("foo"));
+
+        // even then, tables are never dropped
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![
+                "CREATE TABLE tests (id BIGINT NOT NULL PRIMARY KEY, foo TEXT) WITHOUT ROWID;"
+                    .into(),
+            ]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert!(agent.schema().read().tables.contains_key("tests2"));
+
+        let conn = agent.pool().read().await?;
+        let count: usize =
+            conn.query_row("SELECT COUNT(*) FROM tests3__crsql_clock;", (), |row| {
+                row.get(0)
+            })?;
+        // should've created a specific qty of clock table rows, just a sanity check!
+        assert_eq!(count, 4);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_db_schema_waits_for_schema_lock() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        // e.g. a schema reload reading its files
+        let guard = agent.lock_schema().await;
+
+        let schema = tokio::spawn(api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        ));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!schema.is_finished());
+        assert!(agent.schema().read().tables.is_empty());
+
+        drop(guard);
+
+        let (status_code, _body) = schema.await?;
+        assert_eq!(status_code, StatusCode::OK);
+        assert!(agent.schema().read().tables.contains_key("tests"));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_db_schema_record_failure() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        {
+            // recording the change for other nodes can't succeed anymore
+            let conn = agent.pool().write_priority().await?;
+            conn.execute_batch(&format!("DROP TABLE {SCHEMA_CHANGES_TABLE}_applied;"))?;
+        }
+
+        let (status_code, body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+
+        // the change isn't silently kept to this node
+        assert_eq!(status_code, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(matches!(
+            body.0.results.as_slice(),
+            [ExecResult::Error { error }] if error.contains("could not be recorded")
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_db_schema_rename_table() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![
+                "CREATE TABLE foo (id BIGINT NOT NULL PRIMARY KEY, text TEXT) WITHOUT ROWID;"
+                    .into(),
+            ]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        agent
+            .pool()
+            .write_priority()
+            .await?
+            .execute("INSERT INTO foo (id, text) VALUES (1, 'hello')", ())?;
 
-    use super::*;
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![
+                "-- corro:rename foo -> bar\nCREATE TABLE bar (id BIGINT NOT NULL PRIMARY KEY, text TEXT) WITHOUT ROWID;".into(),
+            ]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
 
-    use crate::agent::setup;
+        {
+            let schema = agent.schema().read();
+            assert!(!schema.tables.contains_key("foo"));
+            assert!(schema.tables.contains_key("bar"));
+        }
 
-    struct UnsyncBodyStream(std::pin::Pin<Box<UnsyncBoxBody<Bytes, axum::Error>>>);
+        let conn = agent.pool().read().await?;
+        let text: String =
+            conn.query_row("SELECT text FROM bar WHERE id = 1", (), |row| row.get(0))?;
+        assert_eq!(text, "hello");
 
-    impl Stream for UnsyncBodyStream {
-        type Item = Result<Bytes, axum::Error>;
+        let tbl_names: Vec<String> = conn
+            .prepare("SELECT DISTINCT tbl_name FROM __corro_schema")?
+            .query_map((), |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        assert_eq!(tbl_names, vec!["bar".to_string()]);
 
-        fn poll_next(
-            mut self: std::pin::Pin<&mut Self>,
-            cx: &mut std::task::Context<'_>,
-        ) -> std::task::Poll<Option<Self::Item>> {
-            self.0.as_mut().poll_data(cx)
-        }
+        Ok(())
     }
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
-    async fn test_api_db_execute() -> eyre::Result<()> {
+    async fn test_api_db_schema_rowid_table() -> eyre::Result<()> {
         _ = tracing_subscriber::fmt::try_init();
-
         let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
 
         let dir = tempfile::tempdir()?;
 
-        let (agent, mut agent_options) = setup(
+        let (agent, _agent_options) = setup(
             Config::builder()
                 .db_path(dir.path().join("corrosion.db").display().to_string())
                 .gossip_addr("127.0.0.1:0".parse()?)
@@ -656,84 +3813,261 @@ mod tests {
         )
         .await?;
 
-        let rx_bcast = &mut agent_options.rx_bcast;
+        let (status_code, body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec!["CREATE TABLE foo (text TEXT);".into()]),
+        )
+        .await;
 
+        assert_eq!(status_code, StatusCode::BAD_REQUEST);
+        assert!(matches!(
+            &body.0.results[..],
+            [ExecResult::Error { error }] if error.contains("needs a PRIMARY KEY")
+        ));
+
+        // statements that don't parse are refused the same way
         let (status_code, _body) = api_v1_db_schema(
             Extension(agent.clone()),
-            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+            axum::Json(vec![
+                "CREATE TABLEE foo (id BIGINT NOT NULL PRIMARY KEY);".into()
+            ]),
         )
         .await;
+        assert_eq!(status_code, StatusCode::BAD_REQUEST);
 
+        assert!(agent.schema().read().tables.is_empty());
+        let conn = agent.pool().read().await?;
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_schema WHERE name = 'foo'",
+            (),
+            |row| row.get(0),
+        )?;
+        assert_eq!(count, 0);
+
+        // new rowid tables are refused too, before anything gets created
+        let (status_code, body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![
+                "CREATE TABLE bar (id BIGINT NOT NULL PRIMARY KEY) WITHOUT ROWID;".into(),
+                "CREATE TABLE foo (id BIGINT NOT NULL PRIMARY KEY, text TEXT);".into(),
+            ]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::BAD_REQUEST);
+        assert!(matches!(
+            &body.0.results[..],
+            [ExecResult::Error { error }] if error.contains("must be declared WITHOUT ROWID")
+        ));
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_schema WHERE name IN ('foo', 'bar')",
+            (),
+            |row| row.get(0),
+        )?;
+        assert_eq!(count, 0);
+
+        // unless they're already in the database, then it's only a warning
+        let create_foo = "CREATE TABLE foo (id BIGINT NOT NULL PRIMARY KEY, text TEXT);";
+        agent
+            .pool()
+            .write_priority()
+            .await?
+            .execute_batch(create_foo)?;
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![create_foo.into()]),
+        )
+        .await;
         assert_eq!(status_code, StatusCode::OK);
+        assert!(agent.schema().read().tables.contains_key("foo"));
 
-        let (status_code, body) = api_v1_transactions(
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_db_schema_views_and_triggers() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let foo = "CREATE TABLE foo (id BIGINT NOT NULL PRIMARY KEY, text TEXT) WITHOUT ROWID;";
+        let foo_count = "CREATE TABLE foo_count (id BIGINT NOT NULL PRIMARY KEY, n BIGINT NOT NULL DEFAULT 0) WITHOUT ROWID;";
+        let foo_texts = "CREATE VIEW foo_texts AS SELECT id, text FROM foo;";
+
+        // a trigger on a table would also write for merged changes
+        let (status_code, body) = api_v1_db_schema(
             Extension(agent.clone()),
-            axum::Json(vec![Statement::WithParams(
-                "insert into tests (id, text) values (?,?)".into(),
-                vec!["service-id".into(), "service-name".into()],
-            )]),
+            axum::Json(vec![
+                foo.into(),
+                foo_count.into(),
+                "CREATE TRIGGER foo_inserted AFTER INSERT ON foo BEGIN INSERT INTO foo_count (id, n) VALUES (1, 1) ON CONFLICT (id) DO UPDATE SET n = n + 1; END;".into(),
+            ]),
         )
         .await;
+        assert_eq!(status_code, StatusCode::BAD_REQUEST);
+        assert!(matches!(
+            &body.0.results[..],
+            [ExecResult::Error { error }] if error.contains("can't write to 'foo_count'")
+        ));
+        assert!(agent.schema().read().tables.is_empty());
 
-        println!("{body:?}");
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![
+                foo.into(),
+                foo_count.into(),
+                foo_texts.into(),
+                "CREATE TRIGGER foo_texts_insert INSTEAD OF INSERT ON foo_texts BEGIN INSERT INTO foo (id, text) VALUES (NEW.id, NEW.text); INSERT INTO foo_count (id, n) VALUES (1, 1) ON CONFLICT (id) DO UPDATE SET n = n + 1; END;".into(),
+            ]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        agent
+            .pool()
+            .write_priority()
+            .await?
+            .execute("INSERT INTO foo_texts (id, text) VALUES (1, 'hello')", ())?;
+
+        {
+            let conn = agent.pool().read().await?;
+            let text: String =
+                conn.query_row("SELECT text FROM foo WHERE id = 1", (), |row| row.get(0))?;
+            assert_eq!(text, "hello");
+            let n: i64 =
+                conn.query_row("SELECT n FROM foo_count WHERE id = 1", (), |row| row.get(0))?;
+            assert_eq!(n, 1);
+
+            // tracked, so they survive a restart
+            let schema = init_schema(&conn)?;
+            assert!(schema.views.contains_key("foo_texts"));
+            assert_eq!(schema.triggers["foo_texts_insert"].tbl_name, "foo_texts");
+        }
 
+        // resubmitting the view without its trigger drops it
+        let (status_code, _body) =
+            api_v1_db_schema(Extension(agent.clone()), axum::Json(vec![foo_texts.into()])).await;
         assert_eq!(status_code, StatusCode::OK);
 
-        assert!(body.0.results.len() == 1);
+        {
+            let schema = agent.schema().read();
+            assert!(schema.views.contains_key("foo_texts"));
+            assert!(schema.triggers.is_empty());
+        }
 
-        let msg = rx_bcast
-            .recv()
-            .await
-            .expect("not msg received on bcast channel");
+        let conn = agent.pool().read().await?;
+        let schema = init_schema(&conn)?;
+        assert!(schema.views.contains_key("foo_texts"));
+        assert!(schema.triggers.is_empty());
 
-        assert!(matches!(
-            msg,
-            BroadcastInput::AddBroadcast(BroadcastV1::Change(ChangeV1 {
-                changeset: Changeset::Full {
-                    version: Version(1),
-                    ..
-                },
-                ..
-            }))
-        ));
+        let kinds: Vec<(String, String)> = conn
+            .prepare("SELECT type, name FROM sqlite_schema WHERE type IN ('view', 'trigger') AND name NOT LIKE '%crsql%'")?
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        assert_eq!(kinds, vec![("view".to_string(), "foo_texts".to_string())]);
 
-        assert_eq!(
-            agent
-                .bookie()
-                .write("test")
-                .await
-                .for_actor(agent.actor_id())
-                .read("test")
-                .await
-                .last(),
-            Some(Version(1))
-        );
+        Ok(())
+    }
 
-        println!("second req...");
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_db_schema_reserved_names() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
 
-        let (status_code, body) = api_v1_transactions(
-            Extension(agent.clone()),
-            axum::Json(vec![Statement::WithParams(
-                "update tests SET text = ? where id = ?".into(),
-                vec!["service-name".into(), "service-id".into()],
-            )]),
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
         )
-        .await;
+        .await?;
 
-        println!("{body:?}");
+        for statements in [
+            vec!["CREATE TABLE __corro_evil (id BIGINT NOT NULL PRIMARY KEY, foo TEXT);"],
+            vec!["CREATE TABLE evil_crsql (id BIGINT NOT NULL PRIMARY KEY, foo TEXT);"],
+            vec![
+                "CREATE TABLE tests (id BIGINT NOT NULL PRIMARY KEY, foo TEXT) WITHOUT ROWID;",
+                "CREATE INDEX __corro_evil_idx ON tests (foo);",
+            ],
+        ] {
+            let (status_code, body) = api_v1_db_schema(
+                Extension(agent.clone()),
+                axum::Json(statements.into_iter().map(String::from).collect()),
+            )
+            .await;
+
+            assert_eq!(status_code, StatusCode::BAD_REQUEST);
+            assert!(matches!(
+                &body.0.results[..],
+                [ExecResult::Error { error }] if error.contains("reserved name")
+            ));
+        }
+
+        // nothing was applied
+        assert!(agent.schema().read().tables.is_empty());
+        let conn = agent.pool().read().await?;
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_schema WHERE name IN ('__corro_evil', 'evil_crsql', 'tests')",
+            (),
+            |row| row.get(0),
+        )?;
+        assert_eq!(count, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_health_ready() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
 
+        let (status_code, body) = api_v1_health(Extension(agent.clone())).await;
         assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(body.0.actor_id, agent.actor_id());
+        assert!(body.0.db_version.is_some());
+        assert!(!body.0.ready);
 
-        assert!(body.0.results.len() == 1);
+        let (status_code, _body) = api_v1_ready(Extension(agent.clone())).await;
+        assert_eq!(status_code, StatusCode::SERVICE_UNAVAILABLE);
 
-        // no actual changes!
-        assert!(matches!(rx_bcast.try_recv(), Err(TryRecvError::Empty)));
+        agent.set_ready();
+
+        let (status_code, body) = api_v1_ready(Extension(agent.clone())).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert!(body.0.ready);
 
         Ok(())
     }
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
-    async fn test_api_db_query() -> eyre::Result<()> {
+    async fn test_api_db_versions() -> eyre::Result<()> {
         _ = tracing_subscriber::fmt::try_init();
 
         let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
@@ -750,106 +4084,228 @@ mod tests {
         )
         .await?;
 
+        let body = api_v1_db_versions(Extension(agent.clone()))
+            .await
+            .map_err(|(_, e)| eyre::eyre!(e))?;
+        assert_eq!(body.0.db_version, CrsqlDbVersion(0));
+        assert!(body.0.heads.is_empty());
+
         let (status_code, _body) = api_v1_db_schema(
             Extension(agent.clone()),
             axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
         )
         .await;
-
         assert_eq!(status_code, StatusCode::OK);
 
-        let (status_code, body) = api_v1_transactions(
+        let (status_code, _body) = api_v1_transactions(
             Extension(agent.clone()),
-            axum::Json(vec![
-                Statement::WithParams(
-                    "insert into tests (id, text) values (?,?)".into(),
-                    vec!["service-id".into(), "service-name".into()],
-                ),
-                Statement::WithParams(
-                    "insert into tests (id, text) values (?,?)".into(),
-                    vec!["service-id-2".into(), "service-name-2".into()],
-                ),
-            ]),
+            HeaderMap::new(),
+            ApiBody(vec![Statement::WithParams(
+                "insert into tests (id, text) values (?,?)".into(),
+                vec!["service-id".into(), "service-name".into()],
+            )]),
         )
         .await;
-
-        // println!("{body:?}");
-
         assert_eq!(status_code, StatusCode::OK);
 
-        assert!(body.0.results.len() == 2);
-
-        println!("transaction body: {body:?}");
+        let body = api_v1_db_versions(Extension(agent.clone()))
+            .await
+            .map_err(|(_, e)| eyre::eyre!(e))?;
+        // version 1 is the schema change
+        assert_eq!(body.0.db_version, CrsqlDbVersion(2));
+        assert_eq!(body.0.heads.get(&agent.actor_id()), Some(&Version(2)));
 
-        let res = api_v1_queries(
-            Extension(agent.clone()),
-            axum::Json(Statement::Simple("select * from tests".into())),
-        )
-        .await
-        .into_response();
+        Ok(())
+    }
 
-        assert_eq!(res.status(), StatusCode::OK);
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_admin_bookkeeping() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
 
-        let mut body = res.into_body();
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
 
-        let mut lines = LinesCodec::new();
+        let dir = tempfile::tempdir()?;
 
-        let mut buf = BytesMut::new();
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
 
-        buf.extend_from_slice(&body.data().await.unwrap()?);
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
 
-        let s = lines.decode(&mut buf).unwrap().unwrap();
+        for id in 1i64..=2 {
+            let (status_code, _body) = api_v1_transactions(
+                Extension(agent.clone()),
+                HeaderMap::new(),
+                ApiBody(vec![Statement::WithParams(
+                    "insert into tests (id, text) values (?,?)".into(),
+                    vec![id.into(), "hello".into()],
+                )]),
+            )
+            .await;
+            assert_eq!(status_code, StatusCode::OK);
+        }
 
-        let cols: QueryEvent = serde_json::from_str(&s).unwrap();
+        let bookkeeping = |actor_id: Option<ActorId>| {
+            let agent = agent.clone();
+            async move {
+                let res = api_v1_admin_bookkeeping(
+                    Extension(agent),
+                    axum::extract::Query(BookkeepingParams { actor_id }),
+                )
+                .await
+                .map_err(|(_, e)| eyre::eyre!(e))?;
+                let status = res.status();
+                let body = hyper::body::to_bytes(res.into_body()).await?;
+                Ok::<_, eyre::Report>((status, serde_json::from_slice::<serde_json::Value>(&body)?))
+            }
+        };
 
-        assert_eq!(cols, QueryEvent::Columns(vec!["id".into(), "text".into()]));
+        let (status, body) = bookkeeping(None).await?;
+        assert_eq!(status, StatusCode::OK);
+        let heads = generate_sync(agent.bookie(), agent.actor_id()).await.heads;
+        assert_eq!(body, serde_json::json!({ "heads": heads }));
+        // version 1 is the schema change
+        assert_eq!(heads.get(&agent.actor_id()), Some(&Version(3)));
+
+        let (status, body) = bookkeeping(Some(agent.actor_id())).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["last"], 3);
+        assert_eq!(body["current"][1]["version"], 2);
+        assert_eq!(body["current"][1]["db_version"], 2);
+        assert_eq!(body["current"][2]["version"], 3);
+        assert_eq!(body["current"][2]["db_version"], 3);
+        assert_eq!(body["partials"], serde_json::json!([]));
+        assert_eq!(body["needed"], serde_json::json!([]));
+
+        let res = api_v1_admin_bookkeeping(
+            Extension(agent.clone()),
+            axum::extract::Query(BookkeepingParams {
+                actor_id: Some(ActorId(uuid::Uuid::new_v4())),
+            }),
+        )
+        .await;
+        assert!(matches!(res, Err((StatusCode::NOT_FOUND, _))));
 
-        buf.extend_from_slice(&body.data().await.unwrap()?);
+        Ok(())
+    }
 
-        let s = lines.decode(&mut buf).unwrap().unwrap();
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_backup() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
 
-        let row: QueryEvent = serde_json::from_str(&s).unwrap();
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
 
-        assert_eq!(
-            row,
-            QueryEvent::Row(RowId(1), vec!["service-id".into(), "service-name".into()])
-        );
+        let dir = tempfile::tempdir()?;
 
-        buf.extend_from_slice(&body.data().await.unwrap()?);
+        let backup_dir = dir.path().join("backups");
+        std::fs::create_dir(&backup_dir)?;
 
-        let s = lines.decode(&mut buf).unwrap().unwrap();
+        let mut conf = Config::builder()
+            .db_path(dir.path().join("corrosion.db").display().to_string())
+            .gossip_addr("127.0.0.1:0".parse()?)
+            .api_addr("127.0.0.1:0".parse()?)
+            .build()?;
+        conf.api.backup_dir = Some(backup_dir.clone().try_into()?);
 
-        let row: QueryEvent = serde_json::from_str(&s).unwrap();
+        let (agent, _agent_options) = setup(conf, tripwire).await?;
 
-        assert_eq!(
-            row,
-            QueryEvent::Row(
-                RowId(2),
-                vec!["service-id-2".into(), "service-name-2".into()]
-            )
-        );
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
 
-        buf.extend_from_slice(&body.data().await.unwrap()?);
+        let (status_code, _body) = api_v1_transactions(
+            Extension(agent.clone()),
+            HeaderMap::new(),
+            ApiBody(
+                (0..10i64)
+                    .map(|i| {
+                        Statement::WithParams(
+                            "insert into tests (id, text) values (?,?)".into(),
+                            vec![i.into(), format!("hello {i}").into()],
+                        )
+                    })
+                    .collect(),
+            ),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
 
-        let s = lines.decode(&mut buf).unwrap().unwrap();
+        let path = PathBuf::from("backup.db");
 
-        let query_evt: QueryEvent = serde_json::from_str(&s).unwrap();
+        let body = api_v1_backup(
+            Extension(agent.clone()),
+            axum::Json(BackupRequest { path: path.clone() }),
+        )
+        .await
+        .map_err(|(_, e)| eyre::eyre!(e))?;
+        assert!(body.0.size > 0);
+
+        let count: i64 = Connection::open(backup_dir.join(&path))?.query_row(
+            "SELECT COUNT(*) FROM tests",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(count, 10);
+
+        // never clobber an existing file
+        let res = api_v1_backup(
+            Extension(agent.clone()),
+            axum::Json(BackupRequest { path: path.clone() }),
+        )
+        .await;
+        assert!(matches!(res, Err((StatusCode::BAD_REQUEST, _))));
+
+        // nothing is written outside of the backup directory
+        for path in [
+            dir.path().join("elsewhere.db"),
+            PathBuf::from("../elsewhere.db"),
+        ] {
+            let res =
+                api_v1_backup(Extension(agent.clone()), axum::Json(BackupRequest { path })).await;
+            assert!(matches!(res, Err((StatusCode::BAD_REQUEST, _))));
+        }
+        assert!(!dir.path().join("elsewhere.db").exists());
 
-        assert!(matches!(query_evt, QueryEvent::EndOfQuery { .. }));
+        // backups are disabled without a backup directory
+        let mut conf = agent.config().as_ref().clone();
+        conf.api.backup_dir = None;
+        agent.set_config(conf);
 
-        assert!(body.data().await.is_none());
+        let res = api_v1_backup(
+            Extension(agent.clone()),
+            axum::Json(BackupRequest {
+                path: PathBuf::from("other.db"),
+            }),
+        )
+        .await;
+        assert!(matches!(res, Err((StatusCode::FORBIDDEN, _))));
 
         Ok(())
     }
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
-    async fn test_api_db_schema() -> eyre::Result<()> {
+    async fn test_api_drain() -> eyre::Result<()> {
         _ = tracing_subscriber::fmt::try_init();
+
         let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
 
         let dir = tempfile::tempdir()?;
 
-        let (agent, _agent_options) = setup(
+        let (agent, mut agent_options) = setup(
             Config::builder()
                 .db_path(dir.path().join("corrosion.db").display().to_string())
                 .gossip_addr("127.0.0.1:0".parse()?)
@@ -861,177 +4317,270 @@ mod tests {
 
         let (status_code, _body) = api_v1_db_schema(
             Extension(agent.clone()),
-            axum::Json(vec![
-                "CREATE TABLE tests (id BIGINT NOT NULL PRIMARY KEY, foo TEXT);".into(),
-            ]),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
         )
         .await;
-
         assert_eq!(status_code, StatusCode::OK);
 
-        // scope the schema reader in here
-        {
-            let schema = agent.schema().read();
-            let tests = schema
-                .tables
-                .get("tests")
-                .expect("no tests table in schema");
+        // nothing is consuming broadcasts, empty the queue
+        while agent_options.rx_bcast.try_recv().is_ok() {}
 
-            let id_col = tests.columns.get("id").unwrap();
-            assert_eq!(id_col.name, "id");
-            assert_eq!(id_col.sql_type(), (SqliteType::Integer, Some("BIGINT")));
-            assert!(!id_col.nullable);
-            assert!(id_col.primary_key);
+        // stand in for the sync loop
+        let mut rx_sync_now = agent_options.rx_sync_now;
+        tokio::spawn(async move {
+            while let Some(req) = rx_sync_now.recv().await {
+                _ = req.res_tx.send(Ok(3));
+            }
+        });
 
-            let foo_col = tests.columns.get("foo").unwrap();
-            assert_eq!(foo_col.name, "foo");
-            assert_eq!(foo_col.sql_type(), (SqliteType::Text, Some("TEXT")));
-            assert!(foo_col.nullable);
-            assert!(!foo_col.primary_key);
-        }
+        agent.set_ready();
+        assert!(health_response(&agent).await.ready);
+
+        // stands in for a write that got in before draining started
+        let in_flight = agent.pool().write_normal().await?;
+
+        let drain = tokio::spawn(api_v1_drain(Extension(agent.clone())));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(agent.is_draining());
+        assert!(!drain.is_finished());
+
+        drop(in_flight);
+
+        let body = drain.await?.map_err(|(_, e)| eyre::eyre!(e))?;
+        assert_eq!(body.0.synced, 3);
+
+        let health = health_response(&agent).await;
+        assert!(health.draining);
+        assert!(!health.ready);
+
+        let (status_code, _body) = api_v1_transactions(
+            Extension(agent.clone()),
+            HeaderMap::new(),
+            ApiBody(vec![Statement::Simple(
+                "insert into tests (id, text) values (1, 'hello')".into(),
+            )]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::SERVICE_UNAVAILABLE);
 
         let (status_code, _body) = api_v1_db_schema(
             Extension(agent.clone()),
             axum::Json(vec![
-                "CREATE TABLE tests2 (id BIGINT NOT NULL PRIMARY KEY, foo TEXT);".into(),
-                "CREATE TABLE tests (id BIGINT NOT NULL PRIMARY KEY, foo TEXT);".into(),
+                "CREATE TABLE foo (id INTEGER NOT NULL PRIMARY KEY);".into()
             ]),
         )
         .await;
+        assert_eq!(status_code, StatusCode::SERVICE_UNAVAILABLE);
 
-        assert_eq!(status_code, StatusCode::OK);
+        let res = api_v1_import(
+            Extension(agent.clone()),
+            axum::Json(ImportRequest {
+                path: "changes".into(),
+            }),
+        )
+        .await;
+        assert!(matches!(res, Err((StatusCode::SERVICE_UNAVAILABLE, _))));
 
-        {
-            let schema = agent.schema().read();
-            let tests = schema
-                .tables
-                .get("tests")
-                .expect("no tests table in schema");
+        // reads still work
+        let res = api_v1_db_versions(Extension(agent.clone())).await;
+        assert!(res.is_ok());
 
-            let id_col = tests.columns.get("id").unwrap();
-            assert_eq!(id_col.name, "id");
-            assert_eq!(id_col.sql_type(), (SqliteType::Integer, Some("BIGINT")));
-            assert!(!id_col.nullable);
-            assert!(id_col.primary_key);
+        let res = api_v1_drain(Extension(agent.clone())).await;
+        assert!(matches!(res, Err((StatusCode::CONFLICT, _))));
 
-            let foo_col = tests.columns.get("foo").unwrap();
-            assert_eq!(foo_col.name, "foo");
-            assert_eq!(foo_col.sql_type(), (SqliteType::Text, Some("TEXT")));
-            assert!(foo_col.nullable);
-            assert!(!foo_col.primary_key);
+        Ok(())
+    }
 
-            let tests = schema
-                .tables
-                .get("tests2")
-                .expect("no tests2 table in schema");
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_export_import() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
 
-            let id_col = tests.columns.get("id").unwrap();
-            assert_eq!(id_col.name, "id");
-            assert_eq!(id_col.sql_type(), (SqliteType::Integer, Some("BIGINT")));
-            assert!(!id_col.nullable);
-            assert!(id_col.primary_key);
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
 
-            let foo_col = tests.columns.get("foo").unwrap();
-            assert_eq!(foo_col.name, "foo");
-            assert_eq!(foo_col.sql_type(), (SqliteType::Text, Some("TEXT")));
-            assert!(foo_col.nullable);
-            assert!(!foo_col.primary_key);
-        }
+        let dir = tempfile::tempdir()?;
 
-        // w/ existing table!
+        let backup_dir = dir.path().join("backups");
+        std::fs::create_dir(&backup_dir)?;
+
+        let mut agents = vec![];
+        for name in ["a", "b"] {
+            let mut conf = Config::builder()
+                .db_path(
+                    dir.path()
+                        .join(name)
+                        .join("corrosion.db")
+                        .display()
+                        .to_string(),
+                )
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?;
+            conf.api.backup_dir = Some(backup_dir.clone().try_into()?);
 
-        let create_stmt = "CREATE TABLE tests3 (id BIGINT NOT NULL PRIMARY KEY, foo TEXT, updated_at INTEGER NOT NULL DEFAULT 0);";
+            let (agent, agent_options) = setup(conf, tripwire.clone()).await?;
 
-        {
-            // adding the table and an index
-            let conn = agent.pool().write_priority().await?;
-            conn.execute_batch(create_stmt)?;
-            conn.execute_batch("CREATE INDEX tests3_updated_at ON tests3 (updated_at);")?;
-            assert_eq!(
-                conn.execute(
-                    "INSERT INTO tests3 VALUES (123, 'some foo text', 123456789);",
-                    ()
-                )?,
-                1
-            );
-            assert_eq!(
-                conn.execute(
-                    "INSERT INTO tests3 VALUES (1234, 'some foo text 2', 1234567890);",
-                    ()
-                )?,
-                1
-            );
+            let (status_code, _body) = api_v1_db_schema(
+                Extension(agent.clone()),
+                axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+            )
+            .await;
+            assert_eq!(status_code, StatusCode::OK);
+
+            agents.push((agent, agent_options));
         }
 
-        let (status_code, _body) = api_v1_db_schema(
-            Extension(agent.clone()),
-            axum::Json(vec![create_stmt.into()]),
+        let (ta1, _) = &agents[0];
+        let (ta2, _) = &agents[1];
+
+        for i in 0..3i64 {
+            let (status_code, _body) = api_v1_transactions(
+                Extension(ta1.clone()),
+                HeaderMap::new(),
+                ApiBody(vec![Statement::WithParams(
+                    "insert into tests (id, text) values (?,?)".into(),
+                    vec![i.into(), format!("hello {i}").into()],
+                )]),
+            )
+            .await;
+            assert_eq!(status_code, StatusCode::OK);
+        }
+
+        let path = PathBuf::from("changes.bin");
+
+        let body = api_v1_export(
+            Extension(ta1.clone()),
+            axum::Json(ExportRequest {
+                actor_id: None,
+                // version 1 is the schema change
+                from_version: Version(2),
+                to_version: Version(4),
+                path: path.clone(),
+            }),
+        )
+        .await
+        .map_err(|(_, e)| eyre::eyre!(e))?;
+        assert_eq!(body.0.changes, 3);
+
+        // importing the same file twice doesn't apply anything new
+        for _ in 0..2 {
+            let body = api_v1_import(
+                Extension(ta2.clone()),
+                axum::Json(ImportRequest { path: path.clone() }),
+            )
+            .await
+            .map_err(|(_, e)| eyre::eyre!(e))?;
+            assert_eq!(body.0.changes, 3);
+
+            let count: i64 =
+                ta2.pool()
+                    .read()
+                    .await?
+                    .query_row("SELECT COUNT(*) FROM tests", [], |row| row.get(0))?;
+            assert_eq!(count, 3);
+
+            let heads = generate_sync(ta2.bookie(), ta2.actor_id()).await.heads;
+            assert_eq!(heads.get(&ta1.actor_id()), Some(&Version(4)));
+        }
+        assert!(backup_dir.join(&path).exists());
+
+        // files outside of the backup directory can't be read or written
+        let outside = dir.path().join("a").join("corrosion.db");
+        let res = api_v1_import(
+            Extension(ta2.clone()),
+            axum::Json(ImportRequest {
+                path: outside.clone(),
+            }),
         )
         .await;
+        assert!(matches!(res, Err((StatusCode::BAD_REQUEST, _))));
+
+        let res = api_v1_export(
+            Extension(ta1.clone()),
+            axum::Json(ExportRequest {
+                actor_id: None,
+                from_version: Version(2),
+                to_version: Version(4),
+                path: PathBuf::from("../changes.bin"),
+            }),
+        )
+        .await;
+        assert!(matches!(res, Err((StatusCode::BAD_REQUEST, _))));
+        assert!(!dir.path().join("changes.bin").exists());
 
-        assert_eq!(status_code, StatusCode::OK);
+        Ok(())
+    }
 
-        {
-            let schema = agent.schema().read();
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_info() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
 
-            // check that the tests table is still there!
-            let tests = schema
-                .tables
-                .get("tests")
-                .expect("no tests table in schema");
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
 
-            let id_col = tests.columns.get("id").unwrap();
-            assert_eq!(id_col.name, "id");
-            assert_eq!(id_col.sql_type(), (SqliteType::Integer, Some("BIGINT")));
-            assert!(!id_col.nullable);
-            assert!(id_col.primary_key);
+        let dir = tempfile::tempdir()?;
 
-            let foo_col = tests.columns.get("foo").unwrap();
-            assert_eq!(foo_col.name, "foo");
-            assert_eq!(foo_col.sql_type(), (SqliteType::Text, Some("TEXT")));
-            assert!(foo_col.nullable);
-            assert!(!foo_col.primary_key);
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
 
-            let tests = schema
-                .tables
-                .get("tests3")
-                .expect("no tests3 table in schema");
+        let body = serde_json::to_value(api_v1_info(Extension(agent.clone())).await.0)?;
 
-            let id_col = tests.columns.get("id").unwrap();
-            assert_eq!(id_col.name, "id");
-            assert_eq!(id_col.sql_type(), (SqliteType::Integer, Some("BIGINT")));
-            assert!(!id_col.nullable);
-            assert!(id_col.primary_key);
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert!(!body["git_sha"].as_str().unwrap().is_empty());
+        assert_eq!(body["actor_id"], serde_json::json!(agent.actor_id()));
+        assert_eq!(body["gossip_addr"], agent.gossip_addr().to_string());
+        assert_eq!(body["api_addr"], agent.api_addr().to_string());
+        assert!(body["uptime_secs"].is_u64());
 
-            let foo_col = tests.columns.get("foo").unwrap();
-            assert_eq!(foo_col.name, "foo");
-            assert_eq!(foo_col.sql_type(), (SqliteType::Text, Some("TEXT")));
-            assert!(foo_col.nullable);
-            assert!(!foo_col.primary_key);
+        Ok(())
+    }
 
-            let updated_at_col = tests.columns.get("updated_at").unwrap();
-            assert_eq!(updated_at_col.name, "updated_at");
-            assert_eq!(
-                updated_at_col.sql_type(),
-                (SqliteType::Integer, Some("INTEGER"))
-            );
-            assert!(!updated_at_col.nullable);
-            assert!(!updated_at_col.primary_key);
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_cluster_members() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
 
-            let updated_at_idx = tests.indexes.get("tests3_updated_at").unwrap();
-            assert_eq!(updated_at_idx.name, "tests3_updated_at");
-            assert_eq!(updated_at_idx.tbl_name, "tests3");
-            assert_eq!(updated_at_idx.columns.len(), 1);
-            assert!(updated_at_idx.where_clause.is_none());
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire,
+        )
+        .await?;
+
+        let (status_code, body) = api_v1_cluster_members(Extension(agent.clone())).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert!(body.0.is_empty());
+
+        let actor_id = ActorId(uuid::Uuid::new_v4());
+        let addr: SocketAddr = "127.0.0.1:4242".parse()?;
+        {
+            let mut members = agent.members().write();
+            members.add_member(&corro_types::actor::Actor::new(
+                actor_id,
+                addr,
+                agent.clock().new_timestamp().into(),
+            ));
+            members.add_rtt(addr, Duration::from_millis(2));
+            members.add_rtt(addr, Duration::from_millis(4));
         }
 
-        let conn = agent.pool().read().await?;
-        let count: usize =
-            conn.query_row("SELECT COUNT(*) FROM tests3__crsql_clock;", (), |row| {
-                row.get(0)
-            })?;
-        // should've created a specific qty of clock table rows, just a sanity check!
-        assert_eq!(count, 4);
+        let (status_code, body) = api_v1_cluster_members(Extension(agent.clone())).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(body.0.len(), 1);
+        assert_eq!(body.0[0].actor_id, actor_id);
+        assert_eq!(body.0[0].addr, addr);
+        assert_eq!(body.0[0].rtt_ms, Some(3));
 
         Ok(())
     }
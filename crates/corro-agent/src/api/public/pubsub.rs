@@ -1,17 +1,35 @@
-use std::{collections::HashMap, io::Write, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::Infallible,
+    io::Write,
+    sync::Arc,
+    time::Duration,
+};
 
-use axum::{http::StatusCode, response::IntoResponse, Extension};
+use axum::{
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    Extension,
+};
 use bytes::{BufMut, Bytes, BytesMut};
 use compact_str::{format_compact, ToCompactString};
 use corro_types::{
     agent::Agent,
-    api::{ChangeId, QueryEvent, QueryEventMeta, Statement},
-    pubsub::{MatcherCreated, MatcherError, MatcherHandle, NormalizeStatementError, SubsManager},
+    api::{ChangeId, ColumnName, QueryEvent, QueryEventMeta, RowId, Statement},
+    pubsub::{
+        parse_select, ChangeType, MatcherCreated, MatcherError, MatcherHandle,
+        NormalizeStatementError, SubsManager,
+    },
     sqlite::SqlitePoolError,
 };
-use futures::future::poll_fn;
+use futures::{future::poll_fn, StreamExt};
+use metrics::increment_counter;
 use rusqlite::Connection;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tokio::{
     sync::{
         broadcast,
@@ -20,6 +38,7 @@ use tokio::{
     },
     task::{block_in_place, JoinError},
 };
+use tokio_stream::wrappers::ReceiverStream;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 use tripwire::Tripwire;
@@ -31,6 +50,12 @@ pub struct SubParams {
     from: Option<ChangeId>,
     #[serde(default)]
     skip_rows: bool,
+    /// Also send the previous values of updated rows
+    #[serde(default)]
+    old_values: bool,
+    /// Send change events that arrive together as a single JSON array
+    #[serde(default)]
+    batch: bool,
 }
 
 pub async fn api_v1_sub_by_id(
@@ -40,16 +65,103 @@ pub async fn api_v1_sub_by_id(
     axum::extract::Path(id): axum::extract::Path<Uuid>,
     axum::extract::Query(params): axum::extract::Query<SubParams>,
 ) -> impl IntoResponse {
-    sub_by_id(agent.subs_manager(), id, params, &bcast_cache, tripwire).await
+    sub_by_id(&agent, id, params, &bcast_cache, tripwire).await
 }
 
-async fn sub_by_id(
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SubInfo {
+    pub id: Uuid,
+    pub sql: String,
+    pub hash: String,
+    /// Number of clients currently receiving this subscription's events
+    pub subscribers: usize,
+}
+
+async fn list_subs(
     subs: &SubsManager,
+    bcast_cache: &SharedMatcherBroadcastCache,
+) -> axum::Json<Vec<SubInfo>> {
+    let bcast_read = bcast_cache.read().await;
+    axum::Json(
+        subs.handles()
+            .into_iter()
+            .map(|handle| SubInfo {
+                id: handle.id(),
+                sql: handle.sql().to_owned(),
+                hash: handle.hash().to_owned(),
+                subscribers: bcast_read
+                    .get(&handle.id())
+                    .map(|tx| tx.receiver_count())
+                    .unwrap_or_default(),
+            })
+            .collect(),
+    )
+}
+
+/// Lists active subscriptions, or subscribes over a WebSocket when the
+/// request asks for an upgrade (see [api_v1_subs_ws]).
+pub async fn api_v1_subs_get(
+    Extension(agent): Extension<Agent>,
+    Extension(bcast_cache): Extension<SharedMatcherBroadcastCache>,
+    Extension(tripwire): Extension<Tripwire>,
+    axum::extract::Query(params): axum::extract::Query<SubParams>,
+    ws: Option<WebSocketUpgrade>,
+) -> Response {
+    match ws {
+        Some(ws) => api_v1_subs_ws(
+            Extension(agent),
+            Extension(bcast_cache),
+            Extension(tripwire),
+            axum::extract::Query(params),
+            ws,
+        )
+        .await
+        .into_response(),
+        None => list_subs(agent.subs_manager(), &bcast_cache)
+            .await
+            .into_response(),
+    }
+}
+
+/// Cancels a subscription: its clients' streams end and its state is
+/// removed from disk.
+pub async fn api_v1_sub_delete(
+    Extension(agent): Extension<Agent>,
+    Extension(bcast_cache): Extension<SharedMatcherBroadcastCache>,
+    axum::extract::Path(id): axum::extract::Path<Uuid>,
+) -> hyper::Response<hyper::Body> {
+    bcast_cache.write().await.remove(&id);
+
+    match agent.subs_manager().remove(&id) {
+        Some(handle) => {
+            info!(sub_id = %id, "Removed subscription from api_v1_sub_delete");
+            handle.cleanup().await;
+            hyper::Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(hyper::Body::empty())
+                .expect("could not build delete response")
+        }
+        None => hyper::Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(
+                serde_json::to_vec(&QueryEvent::Error(format_compact!(
+                    "could not find subscription with id {id}"
+                )))
+                .expect("could not serialize queries stream error")
+                .into(),
+            )
+            .expect("could not build error response"),
+    }
+}
+
+async fn sub_by_id(
+    agent: &Agent,
     id: Uuid,
     params: SubParams,
     bcast_cache: &SharedMatcherBroadcastCache,
     tripwire: Tripwire,
 ) -> hyper::Response<hyper::Body> {
+    let subs = agent.subs_manager();
     let (matcher, rx) = match bcast_cache.read().await.get(&id).and_then(|tx| {
         subs.get(&id).map(|matcher| {
             debug!("found matcher by id {id}");
@@ -78,7 +190,7 @@ async fn sub_by_id(
         }
     };
 
-    let (evt_tx, evt_rx) = mpsc::channel(512);
+    let (evt_tx, evt_rx) = mpsc::channel(agent.config().api.subscriber_buffer);
 
     tokio::spawn(catch_up_sub(matcher, params, rx, evt_tx));
 
@@ -589,7 +701,7 @@ pub async fn catch_up_sub(
         }
     };
 
-    forward_sub_to_sender(matcher, sub_rx, evt_tx, params.skip_rows).await
+    forward_sub_to_sender(matcher, sub_rx, evt_tx, params).await
 }
 
 pub async fn upsert_sub(
@@ -607,12 +719,7 @@ pub async fn upsert_sub(
 
         let (sub_tx, sub_rx) = broadcast::channel(10240);
 
-        tokio::spawn(forward_sub_to_sender(
-            handle.clone(),
-            sub_rx,
-            tx,
-            params.skip_rows,
-        ));
+        tokio::spawn(forward_sub_to_sender(handle.clone(), sub_rx, tx, params));
 
         bcast_write.insert(handle.id(), sub_tx.clone());
 
@@ -638,17 +745,16 @@ pub async fn upsert_sub(
     }
 }
 
-pub async fn api_v1_subs(
-    Extension(agent): Extension<Agent>,
-    Extension(bcast_cache): Extension<SharedMatcherBroadcastCache>,
-    Extension(tripwire): Extension<Tripwire>,
-    axum::extract::Query(params): axum::extract::Query<SubParams>,
-    axum::extract::Json(stmt): axum::extract::Json<Statement>,
-) -> impl IntoResponse {
-    let stmt = match expand_sql(&agent, &stmt).await {
-        Ok(stmt) => stmt,
-        Err(e) => return hyper::Response::<hyper::Body>::from(e),
-    };
+// creates or reuses a matcher for the statement, events are sent to the returned receiver
+// until it's dropped
+async fn subscribe(
+    agent: &Agent,
+    bcast_cache: &SharedMatcherBroadcastCache,
+    tripwire: Tripwire,
+    params: SubParams,
+    stmt: &Statement,
+) -> Result<(Uuid, mpsc::Receiver<(Bytes, QueryEventMeta)>), MatcherUpsertError> {
+    let stmt = expand_sql(agent, stmt).await?;
 
     info!("Received subscription request for query: {stmt}");
 
@@ -656,30 +762,17 @@ pub async fn api_v1_subs(
 
     let subs = agent.subs_manager();
 
-    let upsert_res = subs.get_or_insert(
+    let (handle, maybe_created) = subs.get_or_insert(
         &stmt,
         &agent.config().db.subscriptions_path(),
         &agent.schema().read(),
         agent.pool(),
-        tripwire.clone(),
-    );
-
-    let (handle, maybe_created) = match upsert_res {
-        Ok(res) => res,
-        Err(e) => return hyper::Response::<hyper::Body>::from(MatcherUpsertError::from(e)),
-    };
-
-    let (tx, body) = hyper::Body::channel();
-    let (forward_tx, forward_rx) = mpsc::channel(10240);
-
-    tokio::spawn(forward_bytes_to_body_sender(
-        handle.id(),
-        forward_rx,
-        tx,
         tripwire,
-    ));
+    )?;
+
+    let (forward_tx, forward_rx) = mpsc::channel(agent.config().api.subscriber_buffer);
 
-    let matcher_id = match upsert_sub(
+    let matcher_id = upsert_sub(
         handle,
         maybe_created,
         subs,
@@ -687,11 +780,29 @@ pub async fn api_v1_subs(
         params,
         forward_tx,
     )
-    .await
-    {
-        Ok(id) => id,
-        Err(e) => return hyper::Response::<hyper::Body>::from(e),
-    };
+    .await?;
+
+    Ok((matcher_id, forward_rx))
+}
+
+pub async fn api_v1_subs(
+    Extension(agent): Extension<Agent>,
+    Extension(bcast_cache): Extension<SharedMatcherBroadcastCache>,
+    Extension(tripwire): Extension<Tripwire>,
+    axum::extract::Query(params): axum::extract::Query<SubParams>,
+    axum::extract::Json(stmt): axum::extract::Json<Statement>,
+) -> impl IntoResponse {
+    let (matcher_id, forward_rx) =
+        match subscribe(&agent, &bcast_cache, tripwire.clone(), params, &stmt).await {
+            Ok(res) => res,
+            Err(e) => return hyper::Response::<hyper::Body>::from(e),
+        };
+
+    let (tx, body) = hyper::Body::channel();
+
+    tokio::spawn(forward_bytes_to_body_sender(
+        matcher_id, forward_rx, tx, tripwire,
+    ));
 
     hyper::Response::builder()
         .status(StatusCode::OK)
@@ -700,32 +811,264 @@ pub async fn api_v1_subs(
         .expect("could not generate ok http response for query request")
 }
 
+/// Same as [api_v1_subs], over a WebSocket. The statement is expected as
+/// the first text message, each event is then sent as a text message.
+pub async fn api_v1_subs_ws(
+    Extension(agent): Extension<Agent>,
+    Extension(bcast_cache): Extension<SharedMatcherBroadcastCache>,
+    Extension(tripwire): Extension<Tripwire>,
+    axum::extract::Query(params): axum::extract::Query<SubParams>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| handle_sub_ws(agent, bcast_cache, tripwire, params, socket))
+}
+
+async fn handle_sub_ws(
+    agent: Agent,
+    bcast_cache: SharedMatcherBroadcastCache,
+    mut tripwire: Tripwire,
+    params: SubParams,
+    mut socket: WebSocket,
+) {
+    let stmt: Statement = loop {
+        match socket.recv().await {
+            Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                Ok(stmt) => break stmt,
+                Err(e) => {
+                    send_ws_error(&mut socket, e).await;
+                    return;
+                }
+            },
+            Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+            Some(Ok(_)) => {
+                send_ws_error(&mut socket, "expected a statement as a text message").await;
+                return;
+            }
+            Some(Err(_)) | None => return,
+        }
+    };
+
+    let (sub_id, mut rx) =
+        match subscribe(&agent, &bcast_cache, tripwire.clone(), params, &stmt).await {
+            Ok(res) => res,
+            Err(e) => {
+                send_ws_error(&mut socket, e).await;
+                return;
+            }
+        };
+
+    loop {
+        tokio::select! {
+            maybe_event = rx.recv() => match maybe_event {
+                Some((event_buf, _meta)) => {
+                    let text = String::from_utf8_lossy(&event_buf).trim_end().to_owned();
+                    if let Err(e) = socket.send(Message::Text(text)).await {
+                        warn!(%sub_id, "could not send subscription event over websocket: {e}");
+                        break;
+                    }
+                }
+                None => break,
+            },
+            maybe_msg = socket.recv() => match maybe_msg {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                // nothing else is expected from the client
+                Some(Ok(_)) => continue,
+            },
+            _ = &mut tripwire => break,
+        }
+    }
+
+    // dropping the receiver is enough, the subscription gets cleaned
+    // up once it has no more listeners
+    debug!(%sub_id, "done forwarding subscription events over websocket");
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SseSubParams {
+    query: String,
+    #[serde(default)]
+    from: Option<ChangeId>,
+    #[serde(default)]
+    skip_rows: bool,
+    #[serde(default)]
+    old_values: bool,
+    #[serde(default)]
+    batch: bool,
+}
+
+const SSE_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Same as [api_v1_subs], as Server-Sent Events for clients that can't keep
+/// a streaming body or a WebSocket open through their proxies.
+pub async fn api_v1_subs_sse(
+    Extension(agent): Extension<Agent>,
+    Extension(bcast_cache): Extension<SharedMatcherBroadcastCache>,
+    Extension(tripwire): Extension<Tripwire>,
+    axum::extract::Query(sse_params): axum::extract::Query<SseSubParams>,
+) -> Response {
+    let params = SubParams {
+        from: sse_params.from,
+        skip_rows: sse_params.skip_rows,
+        old_values: sse_params.old_values,
+        batch: sse_params.batch,
+    };
+    let stmt = Statement::Simple(sse_params.query);
+
+    let (matcher_id, forward_rx) =
+        match subscribe(&agent, &bcast_cache, tripwire.clone(), params, &stmt).await {
+            Ok(res) => res,
+            Err(e) => return hyper::Response::<hyper::Body>::from(e).into_response(),
+        };
+
+    // the receiver is dropped along with the stream when the client goes away
+    let events = ReceiverStream::new(forward_rx)
+        .map(|(event_buf, _meta)| {
+            Ok::<_, Infallible>(
+                Event::default().data(String::from_utf8_lossy(&event_buf).trim_end()),
+            )
+        })
+        .take_until(tripwire);
+
+    (
+        [("corro-query-id", matcher_id.to_string())],
+        Sse::new(events).keep_alive(
+            KeepAlive::new()
+                .interval(SSE_KEEPALIVE_INTERVAL)
+                .text("keepalive"),
+        ),
+    )
+        .into_response()
+}
+
+async fn send_ws_error<E: ToCompactString>(socket: &mut WebSocket, e: E) {
+    let text = serde_json::to_string(&QueryEvent::Error(e.to_compact_string()))
+        .expect("could not serialize queries stream error");
+    _ = socket.send(Message::Text(text)).await;
+    _ = socket.send(Message::Close(None)).await;
+}
+
 const MAX_EVENTS_BUFFER_SIZE: usize = 1024;
 
+const MAX_BATCH_EVENTS: usize = 1024;
+const BATCH_WINDOW: Duration = Duration::from_millis(10);
+
+/// Serializes change events into a single JSON array line, see
+/// [coalesce_changes]. Returns `None` if nothing is left to send.
+fn make_batch_bytes(events: &[Bytes]) -> serde_json::Result<Option<(Bytes, QueryEventMeta)>> {
+    let events = coalesce_changes(
+        events
+            .iter()
+            .map(|event_buf| serde_json::from_slice(event_buf))
+            .collect::<serde_json::Result<Vec<QueryEvent>>>()?,
+    );
+
+    let change_ids = events.iter().filter_map(|event| match event {
+        QueryEvent::Change(_, _, _, change_id) => Some(*change_id),
+        _ => None,
+    });
+    let (Some(first), Some(last)) = (change_ids.clone().min(), change_ids.max()) else {
+        return Ok(None);
+    };
+
+    let mut buf = serde_json::to_vec(&events)?;
+    buf.push(b'\n');
+
+    Ok(Some((buf.into(), QueryEventMeta::Batch(first, last))))
+}
+
+/// Merges the change events of a row into one carrying its latest values.
+/// An insert stays an insert, an insert followed by a delete cancels out,
+/// and the `old` values of a row are the ones it had before the batch.
+fn coalesce_changes(events: Vec<QueryEvent>) -> Vec<QueryEvent> {
+    let mut coalesced: Vec<Option<QueryEvent>> = Vec::with_capacity(events.len());
+    // position of the latest change and old values of each row
+    let mut changes: BTreeMap<RowId, usize> = BTreeMap::new();
+    let mut olds: BTreeMap<RowId, usize> = BTreeMap::new();
+
+    for event in events {
+        match event {
+            QueryEvent::Old(rowid, cells, change_id) => {
+                // values from before the batch win
+                if !changes.contains_key(&rowid) && !olds.contains_key(&rowid) {
+                    olds.insert(rowid, coalesced.len());
+                    coalesced.push(Some(QueryEvent::Old(rowid, cells, change_id)));
+                }
+            }
+            QueryEvent::Change(change_type, rowid, cells, change_id) => {
+                let prev_type =
+                    changes
+                        .remove(&rowid)
+                        .and_then(|idx| match coalesced[idx].take() {
+                            Some(QueryEvent::Change(prev_type, ..)) => Some(prev_type),
+                            _ => None,
+                        });
+                let old = olds.remove(&rowid).and_then(|idx| coalesced[idx].take());
+
+                let change_type = match (prev_type, change_type) {
+                    (Some(ChangeType::Insert), ChangeType::Delete) => continue,
+                    (Some(ChangeType::Insert), _) => ChangeType::Insert,
+                    (_, change_type) => change_type,
+                };
+
+                if let Some(QueryEvent::Old(_, old_cells, _)) = old {
+                    if change_type == ChangeType::Update {
+                        olds.insert(rowid, coalesced.len());
+                        coalesced.push(Some(QueryEvent::Old(rowid, old_cells, change_id)));
+                    }
+                }
+                changes.insert(rowid, coalesced.len());
+                coalesced.push(Some(QueryEvent::Change(
+                    change_type,
+                    rowid,
+                    cells,
+                    change_id,
+                )));
+            }
+            event => coalesced.push(Some(event)),
+        }
+    }
+
+    coalesced.into_iter().flatten().collect()
+}
+
 async fn forward_sub_to_sender(
     handle: MatcherHandle,
     mut sub_rx: broadcast::Receiver<(Bytes, QueryEventMeta)>,
     tx: mpsc::Sender<(Bytes, QueryEventMeta)>,
-    skip_rows: bool,
+    params: SubParams,
 ) {
     info!(sub_id = %handle.id(), "forwarding subscription events to a sender");
 
+    let _old_values = params.old_values.then(|| handle.want_old_values());
+
+    // an event received while filling a batch, but not part of it
+    let mut pending = None;
+
     loop {
-        let (event_buf, meta) = tokio::select! {
-            Ok((event_buf, meta)) = sub_rx.recv() => {
-                (event_buf, meta)
+        let (event_buf, meta) = if let Some(event) = pending.take() {
+            event
+        } else {
+            tokio::select! {
+            res = sub_rx.recv() => match res {
+                Ok((event_buf, meta)) => (event_buf, meta),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!(sub_id = %handle.id(), "subscriber lagged behind by {skipped} events, dropping it");
+                    increment_counter!("corro.subs.dropped.slow", "sql_hash" => handle.hash().to_owned());
+                    return;
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    info!(sub_id = %handle.id(), "events subcription ran out");
+                    return;
+                }
             },
             _ = handle.cancelled() => {
                 info!(sub_id = %handle.id(), "subscription cancelled, aborting forwarding bytes to subscriber");
                 return;
             },
-            else => {
-                info!(sub_id = %handle.id(), "events subcription ran out");
-                return;
             }
         };
 
-        if skip_rows
+        if params.skip_rows
             && matches!(
                 meta,
                 QueryEventMeta::Columns | QueryEventMeta::Row(_) | QueryEventMeta::EndOfQuery(_)
@@ -733,9 +1076,64 @@ async fn forward_sub_to_sender(
         {
             continue;
         }
-        if let Err(e) = tx.send((event_buf, meta)).await {
-            warn!(sub_id = %handle.id(), "could not send subscription event to channel: {e}");
-            return;
+        if !params.old_values && matches!(meta, QueryEventMeta::Old(_)) {
+            continue;
+        }
+
+        let (event_buf, meta) = if params.batch
+            && matches!(meta, QueryEventMeta::Change(_) | QueryEventMeta::Old(_))
+        {
+            // a subscription sends the changes of a write back-to-back,
+            // gather whatever follows within the batch window
+            let mut batch = vec![event_buf];
+            let deadline = tokio::time::Instant::now() + BATCH_WINDOW;
+            while batch.len() < MAX_BATCH_EVENTS {
+                match tokio::time::timeout_at(deadline, sub_rx.recv()).await {
+                    Ok(Ok((_, QueryEventMeta::Old(_)))) if !params.old_values => {}
+                    Ok(Ok((event_buf, QueryEventMeta::Change(_) | QueryEventMeta::Old(_)))) => {
+                        batch.push(event_buf)
+                    }
+                    Ok(Ok(event)) => {
+                        pending = Some(event);
+                        break;
+                    }
+                    Ok(Err(broadcast::error::RecvError::Lagged(skipped))) => {
+                        warn!(sub_id = %handle.id(), "subscriber lagged behind by {skipped} events, dropping it");
+                        increment_counter!("corro.subs.dropped.slow", "sql_hash" => handle.hash().to_owned());
+                        return;
+                    }
+                    // closed or out of time
+                    Ok(Err(broadcast::error::RecvError::Closed)) | Err(_) => break,
+                }
+            }
+            match make_batch_bytes(&batch) {
+                Ok(Some(batch)) => batch,
+                // everything cancelled out
+                Ok(None) => continue,
+                Err(e) => {
+                    error!(sub_id = %handle.id(), "could not batch subscription events: {e}");
+                    (
+                        error_to_query_event_bytes(&mut BytesMut::new(), e),
+                        QueryEventMeta::Error,
+                    )
+                }
+            }
+        } else {
+            (event_buf, meta)
+        };
+
+        // never wait on a subscriber, a full buffer means it can't keep up
+        match tx.try_send((event_buf, meta)) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                warn!(sub_id = %handle.id(), "subscriber is not keeping up with events, dropping it");
+                increment_counter!("corro.subs.dropped.slow", "sql_hash" => handle.hash().to_owned());
+                return;
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                warn!(sub_id = %handle.id(), "subscriber is gone, stopping event forwarding");
+                return;
+            }
         }
     }
 }
@@ -756,22 +1154,26 @@ async fn forward_bytes_to_body_sender(
     loop {
         let to_send = tokio::select! {
             biased;
-            Some((event_buf, meta)) = rx.recv() => {
-                match meta {
+            res = rx.recv() => {
+                // the subscription is gone (cancelled or errored), end the body
+                let Some((event_buf, meta)) = res else {
+                    break;
+                };
+                let change_ids = match meta {
                     QueryEventMeta::EndOfQuery(Some(change_id)) |
-                    QueryEventMeta::Change(change_id) => {
-                        if !last_change_id.is_zero() && change_id > last_change_id + 1 {
-                            warn!(%sub_id, "non-contiguous change id (> + 1) received: {change_id:?}, last seen: {last_change_id:?}");
-                        } else if !last_change_id.is_zero() && change_id == last_change_id {
-                            warn!(%sub_id, "duplicate change id received: {change_id:?}, last seen: {last_change_id:?}");
-                        } else if change_id < last_change_id {
-                            warn!(%sub_id, "smaller change id received: {change_id:?}, last seen: {last_change_id:?}");
-                        }
-                        last_change_id = change_id;
-                    },
-                    _ => {
-                        // do nothing
+                    QueryEventMeta::Change(change_id) => Some((change_id, change_id)),
+                    QueryEventMeta::Batch(first, last) => Some((first, last)),
+                    _ => None,
+                };
+                if let Some((change_id, last)) = change_ids {
+                    if !last_change_id.is_zero() && change_id > last_change_id + 1 {
+                        warn!(%sub_id, "non-contiguous change id (> + 1) received: {change_id:?}, last seen: {last_change_id:?}");
+                    } else if !last_change_id.is_zero() && change_id == last_change_id {
+                        warn!(%sub_id, "duplicate change id received: {change_id:?}, last seen: {last_change_id:?}");
+                    } else if change_id < last_change_id {
+                        warn!(%sub_id, "smaller change id received: {change_id:?}, last seen: {last_change_id:?}");
                     }
+                    last_change_id = last;
                 }
                 buf.extend_from_slice(&event_buf);
                 if buf.len() >= 64*1024 {
@@ -810,20 +1212,63 @@ async fn forward_bytes_to_body_sender(
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct SubTestRequest {
+    pub statement: Statement,
+    pub change: SubTestChange,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubTestChange {
+    pub table: String,
+    pub column: ColumnName,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SubTestResponse {
+    pub matched: bool,
+}
+
+/// Validates a subscription's statement without subscribing, and tells if a
+/// change to the given table and column would be considered by it.
+pub async fn api_v1_subs_test(
+    Extension(agent): Extension<Agent>,
+    axum::extract::Json(req): axum::extract::Json<SubTestRequest>,
+) -> Response {
+    let sql = match expand_sql(&agent, &req.statement).await {
+        Ok(sql) => sql,
+        Err(e) => return hyper::Response::<hyper::Body>::from(e).into_response(),
+    };
+
+    let parsed = match parse_select(&sql, &agent.schema().read()) {
+        Ok((_stmt, parsed)) => parsed,
+        Err(e) => {
+            return hyper::Response::<hyper::Body>::from(MatcherUpsertError::from(e))
+                .into_response()
+        }
+    };
+
+    axum::Json(SubTestResponse {
+        matched: parsed.matches_column(&req.change.table, &req.change.column),
+    })
+    .into_response()
+}
+
 #[cfg(test)]
 mod tests {
     use corro_types::{
         api::{ChangeId, RowId},
         config::Config,
-        pubsub::ChangeType,
+        pubsub::{ChangeType, Matcher},
     };
     use http_body::Body;
+    use hyper::HeaderMap;
     use tokio_util::codec::{Decoder, LinesCodec};
     use tripwire::Tripwire;
 
     use crate::{
         agent::setup,
-        api::public::{api_v1_db_schema, api_v1_transactions},
+        api::public::{api_v1_db_schema, api_v1_transactions, ApiBody},
     };
 
     use super::*;
@@ -856,7 +1301,8 @@ mod tests {
 
         let (status_code, body) = api_v1_transactions(
             Extension(agent.clone()),
-            axum::Json(vec![
+            HeaderMap::new(),
+            ApiBody(vec![
                 Statement::WithParams(
                     "insert into tests (id, text) values (?,?)".into(),
                     vec!["service-id".into(), "service-name".into()],
@@ -895,7 +1341,8 @@ mod tests {
 
             let (status_code, _) = api_v1_transactions(
                 Extension(agent.clone()),
-                axum::Json(vec![Statement::WithParams(
+                HeaderMap::new(),
+                ApiBody(vec![Statement::WithParams(
                     "insert into tests (id, text) values (?,?)".into(),
                     vec!["service-id-3".into(), "service-name-3".into()],
                 )]),
@@ -946,7 +1393,8 @@ mod tests {
 
             let (status_code, _) = api_v1_transactions(
                 Extension(agent.clone()),
-                axum::Json(vec![Statement::WithParams(
+                HeaderMap::new(),
+                ApiBody(vec![Statement::WithParams(
                     "insert into tests (id, text) values (?,?)".into(),
                     vec!["service-id-4".into(), "service-name-4".into()],
                 )]),
@@ -1004,7 +1452,8 @@ mod tests {
 
             let (status_code, _) = api_v1_transactions(
                 Extension(agent.clone()),
-                axum::Json(vec![Statement::WithParams(
+                HeaderMap::new(),
+                ApiBody(vec![Statement::WithParams(
                     "insert into tests (id, text) values (?,?)".into(),
                     vec!["service-id-5".into(), "service-name-5".into()],
                 )]),
@@ -1173,7 +1622,8 @@ mod tests {
 
         let (status_code, _) = api_v1_transactions(
             Extension(agent.clone()),
-            axum::Json(vec![Statement::WithParams(
+            HeaderMap::new(),
+            ApiBody(vec![Statement::WithParams(
                 "insert into tests (id, text) values (?,?)".into(),
                 vec!["service-id-6".into(), "service-name-6".into()],
             )]),
@@ -1201,6 +1651,7 @@ mod tests {
             axum::extract::Query(SubParams {
                 skip_rows: true,
                 from: Some(ChangeId(3)),
+                ..Default::default()
             }),
             axum::Json(Statement::Simple("select * from tests".into())),
         )
@@ -1243,6 +1694,10 @@ mod tests {
 
     impl RowsIter {
         async fn recv(&mut self) -> Option<eyre::Result<QueryEvent>> {
+            self.recv_as().await
+        }
+
+        async fn recv_as<T: serde::de::DeserializeOwned>(&mut self) -> Option<eyre::Result<T>> {
             if self.done {
                 return None;
             }
@@ -1282,4 +1737,811 @@ mod tests {
             }
         }
     }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_v1_subs_old_values() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire.clone(),
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let (status_code, _body) = api_v1_transactions(
+            Extension(agent.clone()),
+            HeaderMap::new(),
+            ApiBody(vec![Statement::Simple(
+                "insert into tests (id, text) values (1, 'before')".into(),
+            )]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let bcast_cache: SharedMatcherBroadcastCache = Default::default();
+
+        let subscribe = |old_values| {
+            api_v1_subs(
+                Extension(agent.clone()),
+                Extension(bcast_cache.clone()),
+                Extension(tripwire.clone()),
+                axum::extract::Query(SubParams {
+                    skip_rows: true,
+                    old_values,
+                    ..Default::default()
+                }),
+                axum::Json(Statement::Simple("select id, text from tests".into())),
+            )
+        };
+
+        let mut with_old = RowsIter {
+            body: subscribe(true).await.into_response().into_body(),
+            codec: LinesCodec::new(),
+            buf: BytesMut::new(),
+            done: false,
+        };
+        let mut without_old = RowsIter {
+            body: subscribe(false).await.into_response().into_body(),
+            codec: LinesCodec::new(),
+            buf: BytesMut::new(),
+            done: false,
+        };
+
+        let (status_code, _body) = api_v1_transactions(
+            Extension(agent.clone()),
+            HeaderMap::new(),
+            ApiBody(vec![Statement::Simple(
+                "update tests set text = 'after' where id = 1".into(),
+            )]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        assert_eq!(
+            with_old.recv().await.unwrap().unwrap(),
+            QueryEvent::Old(RowId(1), vec![1i64.into(), "before".into()], ChangeId(1))
+        );
+        let change = QueryEvent::Change(
+            ChangeType::Update,
+            RowId(1),
+            vec![1i64.into(), "after".into()],
+            ChangeId(1),
+        );
+        assert_eq!(with_old.recv().await.unwrap().unwrap(), change);
+
+        assert_eq!(without_old.recv().await.unwrap().unwrap(), change);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_v1_subs_batch() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire.clone(),
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let (status_code, _body) = api_v1_transactions(
+            Extension(agent.clone()),
+            HeaderMap::new(),
+            ApiBody(vec![Statement::Simple(
+                "insert into tests (id, text) values (1, 'before')".into(),
+            )]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let bcast_cache: SharedMatcherBroadcastCache = Default::default();
+
+        let mut rows = RowsIter {
+            body: api_v1_subs(
+                Extension(agent.clone()),
+                Extension(bcast_cache.clone()),
+                Extension(tripwire.clone()),
+                axum::extract::Query(SubParams {
+                    skip_rows: true,
+                    old_values: true,
+                    batch: true,
+                    ..Default::default()
+                }),
+                axum::Json(Statement::Simple("select id, text from tests".into())),
+            )
+            .await
+            .into_response()
+            .into_body(),
+            codec: LinesCodec::new(),
+            buf: BytesMut::new(),
+            done: false,
+        };
+
+        // two updates to the same row in a single write
+        let (status_code, _body) = api_v1_transactions(
+            Extension(agent.clone()),
+            HeaderMap::new(),
+            ApiBody(vec![
+                Statement::Simple("update tests set text = 'during' where id = 1".into()),
+                Statement::Simple("update tests set text = 'after' where id = 1".into()),
+                Statement::Simple("insert into tests (id, text) values (2, 'new')".into()),
+            ]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let batch: Vec<QueryEvent> = rows.recv_as().await.unwrap()?;
+        assert_eq!(batch.len(), 3, "unexpected batch: {batch:?}");
+
+        let update_id = batch
+            .iter()
+            .find_map(|event| match event {
+                QueryEvent::Change(ChangeType::Update, RowId(1), cells, change_id) => {
+                    assert_eq!(cells, &vec![1i64.into(), "after".into()]);
+                    Some(*change_id)
+                }
+                _ => None,
+            })
+            .expect("no update for the first row");
+        assert!(batch.contains(&QueryEvent::Old(
+            RowId(1),
+            vec![1i64.into(), "before".into()],
+            update_id
+        )));
+        assert!(batch.iter().any(|event| matches!(
+            event,
+            QueryEvent::Change(ChangeType::Insert, _, cells, _) if cells == &vec![2i64.into(), "new".into()]
+        )));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_coalesce_changes() {
+        let change = |change_type, rowid, text: &str, change_id| {
+            QueryEvent::Change(
+                change_type,
+                RowId(rowid),
+                vec![text.into()],
+                ChangeId(change_id),
+            )
+        };
+        let old = |rowid, text: &str, change_id| {
+            QueryEvent::Old(RowId(rowid), vec![text.into()], ChangeId(change_id))
+        };
+
+        assert_eq!(
+            coalesce_changes(vec![
+                change(ChangeType::Insert, 1, "a", 1),
+                old(2, "x", 2),
+                change(ChangeType::Update, 2, "y", 2),
+                change(ChangeType::Update, 1, "b", 3),
+                old(2, "y", 4),
+                change(ChangeType::Update, 2, "z", 4),
+                change(ChangeType::Insert, 3, "c", 5),
+                change(ChangeType::Delete, 3, "c", 6),
+                change(ChangeType::Update, 4, "d", 7),
+                change(ChangeType::Delete, 4, "d", 8),
+            ]),
+            vec![
+                change(ChangeType::Insert, 1, "b", 3),
+                old(2, "x", 4),
+                change(ChangeType::Update, 2, "z", 4),
+                change(ChangeType::Delete, 4, "d", 8),
+            ]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_v1_subs_from_purged() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire.clone(),
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let bcast_cache: SharedMatcherBroadcastCache = Default::default();
+
+        let subscribe = |from| {
+            api_v1_subs(
+                Extension(agent.clone()),
+                Extension(bcast_cache.clone()),
+                Extension(tripwire.clone()),
+                axum::extract::Query(SubParams {
+                    from,
+                    ..Default::default()
+                }),
+                axum::Json(Statement::Simple("select * from tests".into())),
+            )
+        };
+
+        let res = subscribe(None).await.into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+        let id: Uuid = res.headers()["corro-query-id"].to_str()?.parse()?;
+
+        let mut rows = RowsIter {
+            body: res.into_body(),
+            codec: LinesCodec::new(),
+            buf: BytesMut::new(),
+            done: false,
+        };
+        assert!(matches!(
+            rows.recv().await.unwrap()?,
+            QueryEvent::Columns(_)
+        ));
+        assert!(matches!(
+            rows.recv().await.unwrap()?,
+            QueryEvent::EndOfQuery { .. }
+        ));
+
+        for i in 1..=3i64 {
+            let (status_code, _) = api_v1_transactions(
+                Extension(agent.clone()),
+                HeaderMap::new(),
+                ApiBody(vec![Statement::WithParams(
+                    "insert into tests (id, text) values (?,?)".into(),
+                    vec![i.into(), format!("service-name-{i}").into()],
+                )]),
+            )
+            .await;
+            assert_eq!(status_code, StatusCode::OK);
+            assert!(matches!(
+                rows.recv().await.unwrap()?,
+                QueryEvent::Change(ChangeType::Insert, _, _, change_id) if change_id == ChangeId(i as u64)
+            ));
+        }
+
+        // as if the first change had been purged
+        rusqlite::Connection::open(Matcher::sub_db_path(
+            &agent.config().db.subscriptions_path(),
+            id,
+        ))?
+        .execute("DELETE FROM changes WHERE id = 1", [])?;
+
+        let mut resumed = RowsIter {
+            body: subscribe(Some(ChangeId(1)))
+                .await
+                .into_response()
+                .into_body(),
+            codec: LinesCodec::new(),
+            buf: BytesMut::new(),
+            done: false,
+        };
+        for i in 2..=3i64 {
+            assert_eq!(
+                resumed.recv().await.unwrap()?,
+                QueryEvent::Change(
+                    ChangeType::Insert,
+                    RowId(i as u64),
+                    vec![i.into(), format!("service-name-{i}").into()],
+                    ChangeId(i as u64)
+                )
+            );
+        }
+
+        let mut too_old = RowsIter {
+            body: subscribe(Some(ChangeId(0)))
+                .await
+                .into_response()
+                .into_body(),
+            codec: LinesCodec::new(),
+            buf: BytesMut::new(),
+            done: false,
+        };
+        assert!(matches!(
+            too_old.recv().await.unwrap()?,
+            QueryEvent::Error(e) if e.contains("purged")
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_v1_subs_list_delete() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire.clone(),
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let bcast_cache: SharedMatcherBroadcastCache = Default::default();
+
+        let list = || async {
+            let res = api_v1_subs_get(
+                Extension(agent.clone()),
+                Extension(bcast_cache.clone()),
+                Extension(tripwire.clone()),
+                axum::extract::Query(SubParams::default()),
+                None,
+            )
+            .await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            serde_json::from_slice::<Vec<SubInfo>>(&body).unwrap()
+        };
+
+        assert!(list().await.is_empty());
+
+        let res = api_v1_subs(
+            Extension(agent.clone()),
+            Extension(bcast_cache.clone()),
+            Extension(tripwire.clone()),
+            axum::extract::Query(SubParams::default()),
+            axum::Json(Statement::Simple("select * from tests".into())),
+        )
+        .await
+        .into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+        let id: Uuid = res.headers()["corro-query-id"].to_str()?.parse()?;
+
+        let mut rows = RowsIter {
+            body: res.into_body(),
+            codec: LinesCodec::new(),
+            buf: BytesMut::new(),
+            done: false,
+        };
+        assert!(matches!(
+            rows.recv().await.unwrap().unwrap(),
+            QueryEvent::Columns(_)
+        ));
+
+        let subs = list().await;
+        assert_eq!(subs.len(), 1);
+        assert_eq!(subs[0].id, id);
+        assert_eq!(subs[0].sql, "select * from tests");
+        assert_eq!(subs[0].subscribers, 1);
+
+        let res = api_v1_sub_delete(
+            Extension(agent.clone()),
+            Extension(bcast_cache.clone()),
+            axum::extract::Path(id),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+        // the subscriber's stream ends
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(evt) = rows.recv().await {
+                evt.unwrap();
+            }
+        })
+        .await?;
+
+        assert!(list().await.is_empty());
+        assert!(agent.subs_manager().get(&id).is_none());
+
+        let res = api_v1_sub_delete(
+            Extension(agent.clone()),
+            Extension(bcast_cache.clone()),
+            axum::extract::Path(id),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_api_v1_subs_drops_slow_subscriber() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let mut conf = Config::builder()
+            .db_path(dir.path().join("corrosion.db").display().to_string())
+            .gossip_addr("127.0.0.1:0".parse()?)
+            .api_addr("127.0.0.1:0".parse()?)
+            .build()?;
+        conf.api.subscriber_buffer = 4;
+
+        let (agent, _agent_options) = setup(conf, tripwire.clone()).await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let bcast_cache: SharedMatcherBroadcastCache = Default::default();
+
+        let res = api_v1_subs(
+            Extension(agent.clone()),
+            Extension(bcast_cache.clone()),
+            Extension(tripwire.clone()),
+            axum::extract::Query(SubParams::default()),
+            axum::Json(Statement::Simple("select * from tests".into())),
+        )
+        .await
+        .into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+        let id: Uuid = res.headers()["corro-query-id"].to_str()?.parse()?;
+
+        // nothing reads the body while changes are applied
+        let mut rows = RowsIter {
+            body: res.into_body(),
+            codec: LinesCodec::new(),
+            buf: BytesMut::new(),
+            done: false,
+        };
+
+        let subscribers = || async {
+            bcast_cache
+                .read()
+                .await
+                .get(&id)
+                .map(|tx| tx.receiver_count())
+                .unwrap_or_default()
+        };
+        assert_eq!(subscribers().await, 1);
+
+        for i in 0..100i64 {
+            let (status_code, _) = tokio::time::timeout(
+                Duration::from_secs(5),
+                api_v1_transactions(
+                    Extension(agent.clone()),
+                    HeaderMap::new(),
+                    ApiBody(vec![Statement::WithParams(
+                        "insert into tests (id, text) values (?,?)".into(),
+                        vec![i.into(), format!("service-name-{i}").into()],
+                    )]),
+                ),
+            )
+            .await?;
+            assert_eq!(status_code, StatusCode::OK);
+        }
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while subscribers().await > 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await?;
+
+        // the subscription itself is still around for others
+        assert!(agent.subs_manager().get(&id).is_some());
+
+        // the dropped subscriber gets what was buffered, then its stream ends
+        let mut changes = 0;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(evt) = rows.recv().await {
+                if let QueryEvent::Change(..) = evt.unwrap() {
+                    changes += 1;
+                }
+            }
+        })
+        .await?;
+        assert!(changes < 100);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_v1_subs_test() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire.clone(),
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+
+        let test = |statement: &str, table: &str, column: &str| {
+            api_v1_subs_test(
+                Extension(agent.clone()),
+                axum::Json(SubTestRequest {
+                    statement: Statement::Simple(statement.into()),
+                    change: SubTestChange {
+                        table: table.into(),
+                        column: ColumnName(column.into()),
+                    },
+                }),
+            )
+        };
+
+        for (table, column, matched) in [
+            ("tests", "text", true),
+            ("tests", "nope", false),
+            ("tests2", "text", false),
+        ] {
+            let res = test("SELECT id, text FROM tests", table, column).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = hyper::body::to_bytes(res.into_body()).await?;
+            let res: SubTestResponse = serde_json::from_slice(&body)?;
+            assert_eq!(res.matched, matched, "{table}.{column}");
+        }
+
+        for statement in [
+            "SELEC id FROM tests",
+            "SELECT id FROM nope",
+            "DELETE FROM tests",
+        ] {
+            let res = test(statement, "tests", "text").await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{statement}");
+            let body = hyper::body::to_bytes(res.into_body()).await?;
+            assert!(matches!(
+                serde_json::from_slice(&body)?,
+                QueryEvent::Error(_)
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_v1_subs_ws() -> eyre::Result<()> {
+        use futures::{SinkExt, StreamExt};
+        use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire.clone(),
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        let bcast_cache: SharedMatcherBroadcastCache = Default::default();
+
+        let app = axum::Router::new()
+            .route("/v1/subscriptions", axum::routing::get(api_v1_subs_ws))
+            .layer(Extension(agent.clone()))
+            .layer(Extension(bcast_cache))
+            .layer(Extension(tripwire.clone()));
+
+        let server = axum::Server::bind(&"127.0.0.1:0".parse()?).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let (mut ws, _) =
+            tokio_tungstenite::connect_async(format!("ws://{addr}/v1/subscriptions")).await?;
+
+        ws.send(WsMessage::Text(serde_json::to_string(&Statement::Simple(
+            "select * from tests".into(),
+        ))?))
+        .await?;
+
+        async fn next_event<S>(ws: &mut S) -> serde_json::Result<QueryEvent>
+        where
+            S: futures::Stream<Item = tokio_tungstenite::tungstenite::Result<WsMessage>> + Unpin,
+        {
+            match ws.next().await {
+                Some(Ok(WsMessage::Text(text))) => serde_json::from_str(&text),
+                other => panic!("unexpected websocket message: {other:?}"),
+            }
+        }
+
+        assert_eq!(
+            next_event(&mut ws).await?,
+            QueryEvent::Columns(vec!["id".into(), "text".into()])
+        );
+        assert!(matches!(
+            next_event(&mut ws).await?,
+            QueryEvent::EndOfQuery { .. }
+        ));
+
+        let (status_code, _) = api_v1_transactions(
+            Extension(agent.clone()),
+            HeaderMap::new(),
+            ApiBody(vec![Statement::WithParams(
+                "insert into tests (id, text) values (?,?)".into(),
+                vec!["service-id".into(), "service-name".into()],
+            )]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        assert_eq!(
+            next_event(&mut ws).await?,
+            QueryEvent::Change(
+                ChangeType::Insert,
+                RowId(1),
+                vec!["service-id".into(), "service-name".into()],
+                ChangeId(1)
+            )
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_api_v1_subs_sse() -> eyre::Result<()> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let (tripwire, _tripwire_worker, _tripwire_tx) = Tripwire::new_simple();
+
+        let dir = tempfile::tempdir()?;
+
+        let (agent, _agent_options) = setup(
+            Config::builder()
+                .db_path(dir.path().join("corrosion.db").display().to_string())
+                .gossip_addr("127.0.0.1:0".parse()?)
+                .api_addr("127.0.0.1:0".parse()?)
+                .build()?,
+            tripwire.clone(),
+        )
+        .await?;
+
+        let (status_code, _body) = api_v1_db_schema(
+            Extension(agent.clone()),
+            axum::Json(vec![corro_tests::TEST_SCHEMA.into()]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        let bcast_cache: SharedMatcherBroadcastCache = Default::default();
+
+        let res = api_v1_subs_sse(
+            Extension(agent.clone()),
+            Extension(bcast_cache.clone()),
+            Extension(tripwire.clone()),
+            axum::extract::Query(SseSubParams {
+                query: "select * from tests".into(),
+                from: None,
+                skip_rows: false,
+                old_values: false,
+                batch: false,
+            }),
+        )
+        .await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().contains_key("corro-query-id"));
+
+        let mut body = res.into_body();
+        let mut buf = String::new();
+
+        // reads the next `data:` line, skipping keepalives
+        async fn next_event(
+            body: &mut axum::body::BoxBody,
+            buf: &mut String,
+        ) -> eyre::Result<QueryEvent> {
+            loop {
+                if let Some(idx) = buf.find("\n\n") {
+                    let event: String = buf.drain(..idx + 2).collect();
+                    if let Some(data) = event.trim_end().strip_prefix("data:") {
+                        return Ok(serde_json::from_str(data.trim_start())?);
+                    }
+                    continue;
+                }
+                let chunk = body
+                    .data()
+                    .await
+                    .ok_or_else(|| eyre::eyre!("body ended"))??;
+                buf.push_str(std::str::from_utf8(&chunk)?);
+            }
+        }
+
+        assert_eq!(
+            next_event(&mut body, &mut buf).await?,
+            QueryEvent::Columns(vec!["id".into(), "text".into()])
+        );
+        assert!(matches!(
+            next_event(&mut body, &mut buf).await?,
+            QueryEvent::EndOfQuery { .. }
+        ));
+
+        let (status_code, _) = api_v1_transactions(
+            Extension(agent.clone()),
+            HeaderMap::new(),
+            ApiBody(vec![Statement::WithParams(
+                "insert into tests (id, text) values (?,?)".into(),
+                vec!["service-id".into(), "service-name".into()],
+            )]),
+        )
+        .await;
+
+        assert_eq!(status_code, StatusCode::OK);
+
+        assert_eq!(
+            next_event(&mut body, &mut buf).await?,
+            QueryEvent::Change(
+                ChangeType::Insert,
+                RowId(1),
+                vec!["service-id".into(), "service-name".into()],
+                ChangeId(1)
+            )
+        );
+
+        Ok(())
+    }
 }
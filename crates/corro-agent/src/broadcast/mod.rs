@@ -1,5 +1,6 @@
 use std::{
-    collections::{hash_map::Entry, HashMap},
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    hash::Hash,
     net::SocketAddr,
     num::NonZeroU32,
     pin::Pin,
@@ -14,7 +15,7 @@ use futures::{
     stream::{FusedStream, FuturesUnordered},
     Future,
 };
-use metrics::{counter, gauge};
+use metrics::{counter, gauge, increment_counter};
 use parking_lot::RwLock;
 use rand::{rngs::StdRng, seq::IteratorRandom, SeedableRng};
 use rusqlite::params;
@@ -22,7 +23,7 @@ use spawn::spawn_counted;
 use speedy::Writable;
 use strum::EnumDiscriminants;
 use tokio::{
-    sync::mpsc::{self, channel, Receiver, Sender},
+    sync::mpsc::{self, channel, error::TrySendError, Receiver, Sender},
     task::{block_in_place, LocalSet},
     time::interval,
 };
@@ -35,6 +36,7 @@ use corro_types::{
     actor::{Actor, ActorId},
     agent::Agent,
     broadcast::{BroadcastInput, DispatchRuntime, FocaCmd, FocaInput, UniPayload, UniPayloadV1},
+    signing::SIGNATURE_LEN,
 };
 
 use crate::transport::Transport;
@@ -127,7 +129,20 @@ pub fn runtime_loop(
     let rng = StdRng::from_entropy();
     let actor_id = actor.id();
 
-    let config = Arc::new(RwLock::new(make_foca_config(1.try_into().unwrap())));
+    let max_packet_size = {
+        let config = agent.config();
+        let mut size = config.gossip.max_datagram_size;
+        // leave room for the signature
+        if agent.signer().is_some() {
+            size -= 1 + SIGNATURE_LEN;
+        }
+        size
+    };
+
+    let config = Arc::new(RwLock::new(make_foca_config(
+        1.try_into().unwrap(),
+        max_packet_size,
+    )));
 
     let mut foca = Foca::with_custom_broadcast(
         actor,
@@ -232,7 +247,7 @@ pub fn runtime_loop(
 
                             if size != last_cluster_size {
                                 debug!("Adjusting cluster size to {size}");
-                                let new_config = make_foca_config(size);
+                                let new_config = make_foca_config(size, max_packet_size);
                                 if let Err(e) = foca.set_config(new_config.clone()) {
                                     error!("foca set_config error: {e}");
                                 } else {
@@ -449,10 +464,13 @@ pub fn runtime_loop(
                     }
                     trace!("ser buf len: {}", ser_buf.len());
 
+                    let ser = match agent.signer() {
+                        Some(signer) => signer.sign(&ser_buf.split()),
+                        None => ser_buf.split().freeze(),
+                    };
+
                     if is_local {
-                        if let Err(e) =
-                            bcast_codec.encode(ser_buf.split().freeze(), &mut single_bcast_buf)
-                        {
+                        if let Err(e) = bcast_codec.encode(ser, &mut single_bcast_buf) {
                             error!("could not encode local broadcast: {e}");
                             single_bcast_buf.clear();
                             continue;
@@ -462,8 +480,12 @@ pub fn runtime_loop(
 
                         local_bcast_buf.extend_from_slice(&payload);
 
+                        let config = agent.config();
                         let members = agent.members().read();
-                        for addr in members.ring0() {
+                        for addr in members
+                            .ring0()
+                            .filter(|addr| config.gossip.is_peer_allowed(addr.ip()))
+                        {
                             // this spawns, so we won't be holding onto the read lock for long
                             tokio::spawn(transmit_broadcast(
                                 payload.clone(),
@@ -478,8 +500,7 @@ pub fn runtime_loop(
                             ));
                         }
                     } else {
-                        if let Err(e) = bcast_codec.encode(ser_buf.split().freeze(), &mut bcast_buf)
-                        {
+                        if let Err(e) = bcast_codec.encode(ser, &mut bcast_buf) {
                             error!("could not encode broadcast: {e}");
                             bcast_buf.clear();
                             continue;
@@ -520,6 +541,7 @@ pub fn runtime_loop(
                 };
 
                 let broadcast_to = {
+                    let config = agent.config();
                     agent
                         .members()
                         .read()
@@ -527,7 +549,10 @@ pub fn runtime_loop(
                         .iter()
                         .filter_map(|(member_id, state)| {
                             // don't broadcast to ourselves... or ring0 if local broadcast
-                            if *member_id == actor_id || (pending.is_local && state.is_ring0()) {
+                            if *member_id == actor_id
+                                || (pending.is_local && state.is_ring0())
+                                || !config.gossip.is_peer_allowed(state.addr.ip())
+                            {
                                 None
                             } else {
                                 Some(state.addr)
@@ -701,17 +726,67 @@ fn diff_member_states(
     }))
 }
 
-fn make_foca_config(cluster_size: NonZeroU32) -> foca::Config {
+fn make_foca_config(cluster_size: NonZeroU32, max_packet_size: usize) -> foca::Config {
     let mut config = foca::Config::new_wan(cluster_size);
     config.remove_down_after = Duration::from_secs(2 * 24 * 60 * 60);
 
-    // max payload size for udp datagrams, use a safe value here...
+    // max payload size for udp datagrams, validated when loading the config
     // TODO: calculate from smallest max datagram size for all QUIC conns
-    config.max_packet_size = 1178.try_into().unwrap();
+    config.max_packet_size = max_packet_size.try_into().unwrap();
 
     config
 }
 
+/// Bounded set of recently seen keys, each remembered for a limited time.
+/// Entries are evicted oldest-first when full or expired.
+pub(crate) struct SeenCache<K> {
+    keys: HashSet<K>,
+    order: VecDeque<(K, Instant)>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl<K: Clone + Hash + Eq> SeenCache<K> {
+    pub(crate) fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            keys: HashSet::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+            capacity,
+            ttl,
+        }
+    }
+
+    /// Records the key, returns true if it was already seen within the ttl
+    pub(crate) fn check_and_insert(&mut self, key: K) -> bool {
+        let now = Instant::now();
+        while let Some((oldest, at)) = self.order.front() {
+            if now.duration_since(*at) < self.ttl {
+                break;
+            }
+            self.keys.remove(oldest);
+            self.order.pop_front();
+        }
+
+        if self.keys.contains(&key) {
+            return true;
+        }
+
+        if self.order.len() >= self.capacity {
+            if let Some((oldest, _)) = self.order.pop_front() {
+                self.keys.remove(&oldest);
+            }
+        }
+
+        self.keys.insert(key.clone());
+        self.order.push_back((key, now));
+        false
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.order.len()
+    }
+}
+
 #[derive(Debug)]
 struct PendingBroadcast {
     payload: Bytes,
@@ -754,3 +829,104 @@ async fn transmit_broadcast(payload: Bytes, transport: Transport, addr: SocketAd
         }
     }
 }
+
+/// Queues a broadcast without waiting for room in the channel. Broadcasts
+/// that don't fit are dropped and counted: stalling the caller, often the
+/// gossip receive path, is worse than losing a rebroadcast.
+pub fn try_send_broadcast<T>(tx: &Sender<T>, msg: T, channel: &'static str) -> bool {
+    gauge!("corro.broadcast.channel.depth", (tx.max_capacity() - tx.capacity()) as f64, "channel" => channel);
+    match tx.try_send(msg) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+            increment_counter!("corro.broadcast.dropped.count", "channel" => channel);
+            false
+        }
+        Err(TrySendError::Closed(_)) => {
+            debug!("{channel} broadcasts channel is closed");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsigned_payloads_never_look_signed() -> eyre::Result<()> {
+        use corro_types::{
+            base::Version,
+            broadcast::{BroadcastV1, ChangeV1, Changeset, Timestamp},
+            signing::KIND_MARKER,
+        };
+        use foca::{Codec, Header, Message};
+
+        let actor = Actor::new(
+            ActorId(uuid::Uuid::new_v4()),
+            "127.0.0.1:8787".parse()?,
+            Timestamp::zero(),
+        );
+        let mut datagram = BytesMut::new();
+        BincodeCodec(DefaultOptions::new()).encode_header(
+            &Header {
+                src: actor.clone(),
+                src_incarnation: 0,
+                dst: actor,
+                message: Message::Ping(1),
+            },
+            &mut datagram,
+        )?;
+
+        let broadcast = UniPayload::V1(UniPayloadV1::Broadcast(BroadcastV1::Change(ChangeV1 {
+            actor_id: ActorId(uuid::Uuid::new_v4()),
+            changeset: Changeset::Empty {
+                versions: Version(1)..=Version(5),
+            },
+        })))
+        .write_to_vec()?;
+
+        // signed payloads are told apart by their first byte alone
+        for payload in [&datagram[..], &broadcast[..]] {
+            assert_ne!(payload[0] & KIND_MARKER, KIND_MARKER);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn try_send_broadcast_drops_when_full() {
+        let (tx, mut rx) = channel(1);
+
+        assert!(try_send_broadcast(&tx, 1, "test"));
+        // full: dropped instead of waiting for room
+        assert!(!try_send_broadcast(&tx, 2, "test"));
+
+        assert_eq!(rx.recv().await, Some(1));
+        assert!(try_send_broadcast(&tx, 3, "test"));
+        assert_eq!(rx.recv().await, Some(3));
+
+        drop(rx);
+        assert!(!try_send_broadcast(&tx, 4, "test"));
+    }
+
+    #[test]
+    fn seen_cache() {
+        let mut seen = SeenCache::new(2, Duration::from_secs(60));
+
+        assert!(!seen.check_and_insert(1));
+        assert!(seen.check_and_insert(1));
+        assert!(!seen.check_and_insert(2));
+
+        // evicts the oldest key when full
+        assert!(!seen.check_and_insert(3));
+        assert_eq!(seen.len(), 2);
+        assert!(!seen.check_and_insert(1));
+        assert!(seen.check_and_insert(3));
+
+        let mut seen = SeenCache::new(10, Duration::from_millis(10));
+        assert!(!seen.check_and_insert(1));
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!seen.check_and_insert(1));
+        assert_eq!(seen.len(), 1);
+    }
+}
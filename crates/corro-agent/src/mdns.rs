@@ -0,0 +1,107 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::Arc,
+};
+
+use corro_types::actor::ActorId;
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use parking_lot::RwLock;
+use tracing::{debug, info, warn};
+use tripwire::Tripwire;
+
+pub const MDNS_SERVICE_TYPE: &str = "_corrosion._udp.local.";
+
+/// Advertises our gossip address over mDNS and keeps track of the
+/// other corrosion nodes found on the local network.
+#[derive(Clone, Default)]
+pub struct MdnsDiscovery {
+    // service fullname -> addresses
+    peers: Arc<RwLock<HashMap<String, HashSet<SocketAddr>>>>,
+}
+
+impl MdnsDiscovery {
+    pub fn start(
+        actor_id: ActorId,
+        gossip_addr: SocketAddr,
+        mut tripwire: Tripwire,
+    ) -> Result<Self, mdns_sd::Error> {
+        let daemon = ServiceDaemon::new()?;
+
+        let instance_name = actor_id.to_string();
+
+        // let the daemon figure out our addresses if we're listening on all interfaces
+        let ip = if gossip_addr.ip().is_unspecified() {
+            String::new()
+        } else {
+            gossip_addr.ip().to_string()
+        };
+
+        let mut service = ServiceInfo::new(
+            MDNS_SERVICE_TYPE,
+            &instance_name,
+            &format!("{instance_name}.local."),
+            ip,
+            gossip_addr.port(),
+            None::<HashMap<String, String>>,
+        )?;
+
+        if gossip_addr.ip().is_unspecified() {
+            service = service.enable_addr_auto();
+        }
+
+        let our_fullname = service.get_fullname().to_string();
+
+        daemon.register(service)?;
+        let receiver = daemon.browse(MDNS_SERVICE_TYPE)?;
+
+        info!("Advertising gossip address over mDNS as {our_fullname}");
+
+        let discovery = Self::default();
+
+        tokio::spawn({
+            let peers = discovery.peers.clone();
+            async move {
+                loop {
+                    let event = tokio::select! {
+                        res = receiver.recv_async() => match res {
+                            Ok(event) => event,
+                            Err(_) => break,
+                        },
+                        _ = &mut tripwire => break,
+                    };
+
+                    match event {
+                        ServiceEvent::ServiceResolved(info) => {
+                            if info.get_fullname() == our_fullname {
+                                continue;
+                            }
+                            let addrs = info
+                                .get_addresses()
+                                .iter()
+                                .map(|ip| SocketAddr::new(*ip, info.get_port()))
+                                .collect::<HashSet<_>>();
+                            debug!("discovered {} over mDNS: {addrs:?}", info.get_fullname());
+                            peers.write().insert(info.get_fullname().to_string(), addrs);
+                        }
+                        ServiceEvent::ServiceRemoved(_, fullname) => {
+                            debug!("{fullname} is gone from mDNS");
+                            peers.write().remove(&fullname);
+                        }
+                        _ => {}
+                    }
+                }
+
+                if let Err(e) = daemon.shutdown() {
+                    warn!("could not shutdown mDNS daemon: {e}");
+                }
+            }
+        });
+
+        Ok(discovery)
+    }
+
+    pub fn peers(&self) -> HashSet<SocketAddr> {
+        self.peers.read().values().flatten().copied().collect()
+    }
+}
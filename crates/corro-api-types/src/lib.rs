@@ -7,7 +7,7 @@ use std::{
 };
 
 use compact_str::CompactString;
-use corro_base_types::{CrsqlDbVersion, CrsqlSeq};
+use corro_base_types::{CrsqlDbVersion, CrsqlSeq, Version};
 use rusqlite::{
     types::{FromSql, FromSqlError, ToSqlOutput, Value, ValueRef},
     Row, ToSql,
@@ -32,6 +32,9 @@ pub enum QueryEvent {
         change_id: Option<ChangeId>,
     },
     Change(ChangeType, RowId, Vec<SqliteValue>, ChangeId),
+    /// Values of an updated row before its change with the same id, only
+    /// sent to subscribers that asked for them
+    Old(RowId, Vec<SqliteValue>, ChangeId),
     Error(CompactString),
 }
 
@@ -42,6 +45,7 @@ impl QueryEvent {
             QueryEvent::Row(rowid, _) => QueryEventMeta::Row(*rowid),
             QueryEvent::EndOfQuery { change_id, .. } => QueryEventMeta::EndOfQuery(*change_id),
             QueryEvent::Change(_, _, _, id) => QueryEventMeta::Change(*id),
+            QueryEvent::Old(_, _, id) => QueryEventMeta::Old(*id),
             QueryEvent::Error(_) => QueryEventMeta::Error,
         }
     }
@@ -53,6 +57,9 @@ pub enum QueryEventMeta {
     Row(RowId),
     EndOfQuery(Option<ChangeId>),
     Change(ChangeId),
+    Old(ChangeId),
+    /// Several change events sent as one, covering this range of change ids
+    Batch(ChangeId, ChangeId),
     Error,
 }
 
@@ -187,6 +194,16 @@ impl Statement {
     }
 }
 
+/// A query template registered in the config, run with the given parameters
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NamedQuery {
+    pub name: String,
+    #[serde(default)]
+    pub params: Option<Vec<SqliteParam>>,
+    #[serde(default)]
+    pub named_params: Option<HashMap<String, SqliteParam>>,
+}
+
 impl From<&str> for Statement {
     fn from(value: &str) -> Self {
         Statement::Simple(value.into())
@@ -197,13 +214,30 @@ impl From<&str> for Statement {
 pub struct ExecResponse {
     pub results: Vec<ExecResult>,
     pub time: f64,
+    /// Version assigned to the changes made by the transaction, if there were any
+    #[serde(default)]
+    pub version: Option<Version>,
+    /// Column-level changes (`crsql_changes` rows) produced by the
+    /// transaction, which can differ from the rows affected by its statements
+    #[serde(default)]
+    pub changes: usize,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum ExecResult {
-    Execute { rows_affected: usize, time: f64 },
-    Error { error: String },
+    Execute {
+        rows_affected: usize,
+        time: f64,
+        /// Columns of the rows returned by the statement (e.g. with a `RETURNING` clause)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        columns: Option<Vec<ColumnName>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rows: Option<Vec<Vec<SqliteValue>>>,
+    },
+    Error {
+        error: String,
+    },
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize, Readable, Writable, PartialEq)]
@@ -356,6 +390,93 @@ impl FromSql for ColumnType {
     }
 }
 
+/// JSON has no bytes type, blobs are represented as `{"$blob": "<base64>"}`.
+/// Arrays of bytes are still accepted on input. Binary formats (MessagePack)
+/// carry blobs as native bytes.
+mod blob_json {
+    use std::fmt;
+
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+    use serde::{
+        de::{Error, SeqAccess, Visitor},
+        ser::SerializeMap,
+        Deserialize, Deserializer, Serializer,
+    };
+    use smallvec::SmallVec;
+
+    pub const BLOB_KEY: &str = "$blob";
+
+    pub fn serialize<S: Serializer>(blob: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return serializer.serialize_bytes(blob);
+        }
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry(BLOB_KEY, &BASE64.encode(blob))?;
+        map.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<SmallVec<[u8; 512]>, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Base64Blob {
+            #[serde(rename = "$blob")]
+            blob: String,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Blob {
+            Base64(Base64Blob),
+            Bytes(Bytes),
+        }
+
+        match Blob::deserialize(deserializer)? {
+            Blob::Base64(Base64Blob { blob }) => BASE64
+                .decode(blob)
+                .map(SmallVec::from_vec)
+                .map_err(D::Error::custom),
+            Blob::Bytes(Bytes(bytes)) => Ok(SmallVec::from_vec(bytes)),
+        }
+    }
+
+    /// Raw bytes, either as a native bytes value or as an array of integers
+    struct Bytes(Vec<u8>);
+
+    impl<'de> Deserialize<'de> for Bytes {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct BytesVisitor;
+
+            impl<'de> Visitor<'de> for BytesVisitor {
+                type Value = Bytes;
+
+                fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    f.write_str("bytes or an array of bytes")
+                }
+
+                fn visit_bytes<E: Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+                    Ok(Bytes(v.to_vec()))
+                }
+
+                fn visit_byte_buf<E: Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+                    Ok(Bytes(v))
+                }
+
+                fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                    let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+                    while let Some(b) = seq.next_element()? {
+                        bytes.push(b);
+                    }
+                    Ok(Bytes(bytes))
+                }
+            }
+
+            deserializer.deserialize_byte_buf(BytesVisitor)
+        }
+    }
+}
+
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
@@ -366,6 +487,7 @@ pub enum SqliteParam {
     Integer(i64),
     Real(f64),
     Text(CompactString),
+    #[serde(with = "blob_json")]
     Blob(SmallVec<[u8; 512]>),
     Json(Box<RawValue>),
 }
@@ -435,6 +557,7 @@ pub enum SqliteValue {
     Integer(i64),
     Real(Real),
     Text(CompactString),
+    #[serde(with = "blob_json")]
     Blob(SmallVec<[u8; 512]>),
 }
 
@@ -900,4 +1023,23 @@ mod tests {
         let stmts: Vec<Statement> = serde_json::from_str(json).unwrap();
         println!("stmts: {stmts:?}");
     }
+
+    #[test]
+    fn test_blob_serialization() {
+        let value = SqliteValue::Blob(b"\x00\x01binary\xff".to_smallvec());
+        let s = serde_json::to_string(&value).unwrap();
+        assert_eq!(s, r#"{"$blob":"AAFiaW5hcnn/"}"#);
+        assert_eq!(serde_json::from_str::<SqliteValue>(&s).unwrap(), value);
+
+        let param: SqliteParam = serde_json::from_str(&s).unwrap();
+        assert!(matches!(param, SqliteParam::Blob(b) if b.as_slice() == b"\x00\x01binary\xff"));
+
+        // byte arrays still work
+        let param: SqliteParam = serde_json::from_str("[1,2,3]").unwrap();
+        assert!(matches!(param, SqliteParam::Blob(b) if b.as_slice() == [1, 2, 3]));
+
+        // not a blob
+        assert!(serde_json::from_str::<SqliteValue>(r#"{"$blob":"AA==","a":1}"#).is_err());
+        assert!(serde_json::from_str::<SqliteValue>(r#"{"$blob":"not base64!"}"#).is_err());
+    }
 }
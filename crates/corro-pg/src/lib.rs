@@ -1824,6 +1824,8 @@ enum QueryError {
     BackendResponseSendFailed,
     #[error("could not acquire write permit")]
     PermitAcquire(#[from] AcquireError),
+    #[error("node is read-only, not accepting writes")]
+    ReadOnly,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -1848,11 +1850,21 @@ impl TryFrom<QueryError> for PgWireBackendMessage {
             e @ QueryError::PermitAcquire(_) => {
                 ErrorInfo::new("FATAL".to_owned(), "XX000".to_owned(), e.to_string()).into()
             }
+            e @ QueryError::ReadOnly => {
+                ErrorInfo::new("ERROR".to_owned(), "25006".to_owned(), e.to_string()).into()
+            }
             QueryError::BackendResponseSendFailed => return Err(ChannelClosed),
         }))
     }
 }
 
+fn write_permit(agent: &Agent) -> Result<OwnedSemaphorePermit, QueryError> {
+    if agent.config().node.read_only {
+        return Err(QueryError::ReadOnly);
+    }
+    Ok(agent.write_permit_blocking()?)
+}
+
 #[allow(clippy::too_many_arguments)]
 fn handle_execute<'conn>(
     agent: &Agent,
@@ -1907,7 +1919,7 @@ fn handle_execute<'conn>(
     } else {
         if !open_tx.is_writing() && !prepped.readonly() {
             trace!("statement writes, acquiring permit...");
-            open_tx.set_write_permit(agent.write_permit_blocking()?);
+            open_tx.set_write_permit(write_permit(agent)?);
         }
         let mut rows = prepped.raw_query();
         loop {
@@ -2151,7 +2163,7 @@ fn handle_query(
 
         if !open_tx.is_writing() && !prepped.readonly() {
             trace!("query statement writes, acquiring permit...");
-            open_tx.set_write_permit(agent.write_permit_blocking()?);
+            open_tx.set_write_permit(write_permit(agent)?);
         }
 
         let mut rows = prepped.raw_query();
@@ -3169,7 +3181,7 @@ mod tests {
                 id BIGINT PRIMARY KEY NOT NULL,
                 other_ts DATETIME,
                 updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
-            );
+            ) WITHOUT ROWID;
         ",
         )
         .await?;
@@ -3251,11 +3263,20 @@ mod tests {
 
             assert!(exec_elapsed > sema_elapsed);
 
-            let row = client.query_one("SELECT * FROM crsql_changes", &[]).await?;
+            // the schema change comes first
+            let row = client
+                .query_one(
+                    "SELECT * FROM crsql_changes ORDER BY db_version DESC LIMIT 1",
+                    &[],
+                )
+                .await?;
             println!("CHANGE ROW: {row:?}");
 
             let row = client
-                .query_one("SELECT * FROM __corro_bookkeeping", &[])
+                .query_one(
+                    "SELECT * FROM __corro_bookkeeping ORDER BY start_version DESC LIMIT 1",
+                    &[],
+                )
                 .await?;
             println!("BK ROW: {row:?}");
 
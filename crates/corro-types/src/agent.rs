@@ -5,7 +5,7 @@ use std::{
     ops::{Deref, DerefMut, RangeInclusive},
     path::{Path, PathBuf},
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc,
     },
     time::{Duration, Instant},
@@ -15,13 +15,15 @@ use arc_swap::ArcSwap;
 use camino::Utf8PathBuf;
 use compact_str::CompactString;
 use indexmap::IndexMap;
-use metrics::{gauge, histogram};
-use parking_lot::RwLock;
+use metrics::{gauge, histogram, increment_counter};
+use parking_lot::{Mutex, MutexGuard, RwLock};
+use rand::{rngs::StdRng, SeedableRng};
 use rangemap::RangeInclusiveSet;
 use rusqlite::{Connection, Transaction};
 use serde::{Deserialize, Serialize};
 use tokio::sync::{
-    AcquireError, OwnedRwLockWriteGuard as OwnedTokioRwLockWriteGuard, OwnedSemaphorePermit,
+    AcquireError, Mutex as TokioMutex, MutexGuard as TokioMutexGuard,
+    OwnedRwLockWriteGuard as OwnedTokioRwLockWriteGuard, OwnedSemaphorePermit,
     RwLock as TokioRwLock, RwLockReadGuard as TokioRwLockReadGuard,
     RwLockWriteGuard as TokioRwLockWriteGuard,
 };
@@ -43,7 +45,12 @@ use crate::{
     config::Config,
     pubsub::SubsManager,
     schema::Schema,
-    sqlite::{rusqlite_to_crsqlite, setup_conn, CrConn, Migration, SqlitePool, SqlitePoolError},
+    signing::GossipSigner,
+    sqlite::{
+        apply_pragmas, rusqlite_to_crsqlite, setup_conn, CrConn, Migration, SqlitePool,
+        SqlitePoolError,
+    },
+    sync::{SyncNowRequest, SyncRangeRequest},
 };
 
 use super::members::Members;
@@ -67,6 +74,11 @@ pub struct AgentConfig {
     pub tx_clear_buf: Sender<(ActorId, RangeInclusive<Version>)>,
     pub tx_changes: Sender<(ChangeV1, ChangeSource)>,
     pub tx_foca: Sender<FocaInput>,
+    pub tx_sync_range: Sender<SyncRangeRequest>,
+    pub tx_sync_now: Sender<SyncNowRequest>,
+    pub tx_schema_change: Sender<()>,
+
+    pub signer: Option<GossipSigner>,
 
     pub write_sema: Arc<Semaphore>,
 
@@ -92,10 +104,19 @@ pub struct AgentInner {
     tx_clear_buf: Sender<(ActorId, RangeInclusive<Version>)>,
     tx_changes: Sender<(ChangeV1, ChangeSource)>,
     tx_foca: Sender<FocaInput>,
+    tx_sync_range: Sender<SyncRangeRequest>,
+    tx_sync_now: Sender<SyncNowRequest>,
+    tx_schema_change: Sender<()>,
+    signer: Option<GossipSigner>,
     write_sema: Arc<Semaphore>,
     schema: RwLock<Schema>,
+    schema_lock: TokioMutex<()>,
     limits: Limits,
     subs_manager: SubsManager,
+    ready: AtomicBool,
+    draining: AtomicBool,
+    started_at: Instant,
+    rng: Mutex<StdRng>,
 }
 
 #[derive(Debug, Clone)]
@@ -104,7 +125,23 @@ pub struct Limits {
 }
 
 impl Agent {
+    /// Creates an agent, seeding its rng from `sync.rng_seed` if set or from
+    /// entropy otherwise
     pub fn new(config: AgentConfig) -> Self {
+        let rng = match config.config.load().sync.rng_seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self::with_rng(config, rng)
+    }
+
+    /// Creates an agent whose rng is seeded from `seed`, regardless of config
+    pub fn with_seed(config: AgentConfig, seed: u64) -> Self {
+        Self::with_rng(config, StdRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(config: AgentConfig, rng: StdRng) -> Self {
+        let sync_concurrency = config.config.load().limits.sync_concurrency;
         Self(Arc::new(AgentInner {
             actor_id: config.actor_id,
             pool: config.pool,
@@ -120,12 +157,21 @@ impl Agent {
             tx_clear_buf: config.tx_clear_buf,
             tx_changes: config.tx_changes,
             tx_foca: config.tx_foca,
+            tx_sync_range: config.tx_sync_range,
+            tx_sync_now: config.tx_sync_now,
+            tx_schema_change: config.tx_schema_change,
+            signer: config.signer,
             write_sema: config.write_sema,
             schema: config.schema,
+            schema_lock: TokioMutex::new(()),
             limits: Limits {
-                sync: Arc::new(Semaphore::new(3)),
+                sync: Arc::new(Semaphore::new(sync_concurrency)),
             },
             subs_manager: config.subs_manager,
+            ready: AtomicBool::new(false),
+            draining: AtomicBool::new(false),
+            started_at: Instant::now(),
+            rng: Mutex::new(rng),
         }))
     }
 
@@ -149,6 +195,11 @@ impl Agent {
         self.0.api_addr
     }
 
+    /// Time elapsed since the agent was set up
+    pub fn uptime(&self) -> Duration {
+        self.0.started_at.elapsed()
+    }
+
     pub fn tx_bcast(&self) -> &Sender<BroadcastInput> {
         &self.0.tx_bcast
     }
@@ -173,6 +224,24 @@ impl Agent {
         &self.0.tx_foca
     }
 
+    pub fn tx_sync_range(&self) -> &Sender<SyncRangeRequest> {
+        &self.0.tx_sync_range
+    }
+
+    pub fn tx_sync_now(&self) -> &Sender<SyncNowRequest> {
+        &self.0.tx_sync_now
+    }
+
+    /// Wakes up the task applying schema changes recorded by other nodes
+    pub fn tx_schema_change(&self) -> &Sender<()> {
+        &self.0.tx_schema_change
+    }
+
+    /// Signs and verifies gossip payloads, if `gossip.signing_key` is set
+    pub fn signer(&self) -> Option<&GossipSigner> {
+        self.0.signer.as_ref()
+    }
+
     pub fn write_sema(&self) -> &Arc<Semaphore> {
         &self.0.write_sema
     }
@@ -197,6 +266,13 @@ impl Agent {
         &self.0.schema
     }
 
+    /// Serializes schema changes (API, reloads and changes from other nodes)
+    /// for longer than the schema itself is locked, e.g. while reading schema
+    /// files before applying them
+    pub async fn lock_schema(&self) -> TokioMutexGuard<'_, ()> {
+        self.0.schema_lock.lock().await
+    }
+
     pub fn db_path(&self) -> Utf8PathBuf {
         self.0.config.load().db.path.clone()
     }
@@ -209,13 +285,48 @@ impl Agent {
         self.0.config.store(Arc::new(new_conf))
     }
 
+    /// Atomically applies `f` to a copy of the current config and stores it,
+    /// retrying if the config was replaced concurrently. Returns the previous
+    /// config.
+    pub fn update_config<F: Fn(&mut Config)>(&self, f: F) -> Arc<Config> {
+        self.0.config.rcu(|current| {
+            let mut conf = Config::clone(current);
+            f(&mut conf);
+            conf
+        })
+    }
+
     pub fn limits(&self) -> &Limits {
         &self.0.limits
     }
 
+    /// Random number generator used to pick which nodes to sync with
+    pub fn rng(&self) -> MutexGuard<'_, StdRng> {
+        self.0.rng.lock()
+    }
+
     pub fn subs_manager(&self) -> &SubsManager {
         &self.0.subs_manager
     }
+
+    /// Whether the agent has completed its first sync with the cluster
+    pub fn is_ready(&self) -> bool {
+        self.0.ready.load(Ordering::Acquire)
+    }
+
+    pub fn set_ready(&self) {
+        self.0.ready.store(true, Ordering::Release)
+    }
+
+    /// Whether the agent is refusing new writes ahead of a shutdown
+    pub fn is_draining(&self) -> bool {
+        self.0.draining.load(Ordering::Acquire)
+    }
+
+    /// Returns false if the agent was already draining
+    pub fn set_draining(&self) -> bool {
+        !self.0.draining.swap(true, Ordering::AcqRel)
+    }
 }
 
 pub fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
@@ -224,6 +335,8 @@ pub fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
         Box::new(v0_2_0_migration as fn(&Transaction) -> rusqlite::Result<()>),
         Box::new(v0_2_0_1_migration as fn(&Transaction) -> rusqlite::Result<()>),
         Box::new(v0_2_0_2_migration as fn(&Transaction) -> rusqlite::Result<()>),
+        Box::new(v0_2_0_3_migration as fn(&Transaction) -> rusqlite::Result<()>),
+        Box::new(v0_2_0_4_migration as fn(&Transaction) -> rusqlite::Result<()>),
     ];
 
     crate::sqlite::migrate(conn, migrations)
@@ -349,6 +462,45 @@ fn v0_2_0_2_migration(tx: &Transaction) -> rusqlite::Result<()> {
     )
 }
 
+fn v0_2_0_3_migration(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        -- last persisted HLC timestamp, restored on startup
+        CREATE TABLE __corro_clock (
+            id INTEGER PRIMARY KEY NOT NULL CHECK (id = 0),
+            ts TEXT NOT NULL
+        );
+    "#,
+    )
+}
+
+/// Schema changes applied through the API, replicated like any other table
+/// so nodes that were offline at the time get them through sync
+pub const SCHEMA_CHANGES_TABLE: &str = "__corro_schema_changes";
+
+fn v0_2_0_4_migration(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        -- schema statements (a JSON array) applied through the API on actor_id
+        CREATE TABLE __corro_schema_changes (
+            actor_id BLOB NOT NULL,
+            ts TEXT NOT NULL,
+            statements TEXT NOT NULL DEFAULT '[]',
+            PRIMARY KEY (actor_id, ts)
+        ) WITHOUT ROWID;
+        SELECT crsql_as_crr('__corro_schema_changes');
+        CREATE INDEX corro___corro_schema_changes__crsql_clock_site_id_dbv ON __corro_schema_changes__crsql_clock (site_id, db_version);
+
+        -- schema changes this node is done with, local only
+        CREATE TABLE __corro_schema_changes_applied (
+            actor_id BLOB NOT NULL,
+            ts TEXT NOT NULL,
+            PRIMARY KEY (actor_id, ts)
+        ) WITHOUT ROWID;
+    "#,
+    )
+}
+
 #[derive(Debug, Clone)]
 pub struct SplitPool(Arc<SplitPoolInner>);
 
@@ -363,6 +515,8 @@ struct SplitPoolInner {
     priority_tx: Sender<oneshot::Sender<CancellationToken>>,
     normal_tx: Sender<oneshot::Sender<CancellationToken>>,
     low_tx: Sender<oneshot::Sender<CancellationToken>>,
+
+    acquire_timeout: Duration,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -375,6 +529,17 @@ pub enum PoolError {
     CallbackClosed,
     #[error("could not acquire write permit")]
     Permit(#[from] AcquireError),
+    #[error("timed out waiting for a write connection")]
+    Timeout,
+}
+
+impl PoolError {
+    pub fn is_timeout(&self) -> bool {
+        matches!(
+            self,
+            PoolError::Timeout | PoolError::Pool(SqlitePoolError::Timeout(_))
+        )
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -383,6 +548,38 @@ pub enum ChangeError {
     Pool(#[from] PoolError),
     #[error("rusqlite: {0}")]
     Rusqlite(#[from] rusqlite::Error),
+    #[error("db_version {0} is out of range")]
+    DbVersionOutOfRange(i64),
+    #[error("db_version {next} is not greater than the last booked db_version {last}")]
+    NonMonotonicDbVersion {
+        last: CrsqlDbVersion,
+        next: CrsqlDbVersion,
+    },
+    #[error("transaction changed {rows} rows, more than the limits.max_change_size of {max}")]
+    TooLarge { rows: i64, max: i64 },
+    #[error("node is draining, not accepting writes")]
+    Draining,
+}
+
+/// Validates a db_version about to be booked: it has to be positive and
+/// strictly greater than the last one booked, or the version ranges built
+/// on top of it stop making sense.
+pub fn check_db_version(
+    last: Option<CrsqlDbVersion>,
+    next: i64,
+) -> Result<CrsqlDbVersion, ChangeError> {
+    if next <= 0 {
+        increment_counter!("corro.db_version.rejected", "reason" => "out_of_range");
+        return Err(ChangeError::DbVersionOutOfRange(next));
+    }
+    let next = CrsqlDbVersion(next as u64);
+    match last {
+        Some(last) if next <= last => {
+            increment_counter!("corro.db_version.rejected", "reason" => "non_monotonic");
+            Err(ChangeError::NonMonotonicDbVersion { last, next })
+        }
+        _ => Ok(next),
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -395,21 +592,36 @@ pub enum SplitPoolCreateError {
     Rusqlite(#[from] rusqlite::Error),
 }
 
+// loads cr-sqlite on each new pooled connection, then applies the user's pragmas
+fn crsqlite_transform(
+    pragmas: Vec<String>,
+) -> impl Fn(rusqlite::Connection) -> rusqlite::Result<CrConn> + Send + Sync + 'static {
+    move |conn| {
+        let conn = rusqlite_to_crsqlite(conn)?;
+        apply_pragmas(&conn, &pragmas)?;
+        Ok(conn)
+    }
+}
+
 impl SplitPool {
     pub async fn create<P: AsRef<Path>>(
         path: P,
         write_sema: Arc<Semaphore>,
+        rw_pool_size: usize,
+        ro_pool_size: usize,
+        pragmas: &[String],
+        acquire_timeout: Duration,
     ) -> Result<Self, SplitPoolCreateError> {
         let rw_pool = sqlite_pool::Config::new(path.as_ref())
-            .max_size(1)
-            .create_pool_transform(rusqlite_to_crsqlite)?;
+            .max_size(rw_pool_size)
+            .create_pool_transform(crsqlite_transform(pragmas.to_vec()))?;
 
         debug!("built RW pool");
 
         let ro_pool = sqlite_pool::Config::new(path.as_ref())
             .read_only()
-            .max_size(20)
-            .create_pool_transform(rusqlite_to_crsqlite)?;
+            .max_size(ro_pool_size)
+            .create_pool_transform(crsqlite_transform(pragmas.to_vec()))?;
         debug!("built RO pool");
 
         Ok(Self::new(
@@ -417,10 +629,17 @@ impl SplitPool {
             write_sema,
             ro_pool,
             rw_pool,
+            acquire_timeout,
         ))
     }
 
-    fn new(path: PathBuf, write_sema: Arc<Semaphore>, read: SqlitePool, write: SqlitePool) -> Self {
+    fn new(
+        path: PathBuf,
+        write_sema: Arc<Semaphore>,
+        read: SqlitePool,
+        write: SqlitePool,
+        acquire_timeout: Duration,
+    ) -> Self {
         let (priority_tx, mut priority_rx) = channel(256);
         let (normal_tx, mut normal_rx) = channel(512);
         let (low_tx, mut low_rx) = channel(1024);
@@ -447,6 +666,7 @@ impl SplitPool {
             priority_tx,
             normal_tx,
             low_tx,
+            acquire_timeout,
         }))
     }
 
@@ -480,7 +700,35 @@ impl SplitPool {
     // get a read-only connection
     #[tracing::instrument(skip(self), level = "debug")]
     pub async fn read(&self) -> Result<sqlite_pool::Connection<CrConn>, SqlitePoolError> {
-        self.0.read.get().await
+        let start = Instant::now();
+        let res = self.0.read.get().await;
+        record_acquire(
+            "read",
+            start,
+            matches!(res, Err(SqlitePoolError::Timeout(_))),
+        );
+        res
+    }
+
+    // get a read-only connection, giving up after `db.pool_acquire_timeout_ms`
+    // (e.g. client queries, which shouldn't pile up behind a saturated pool)
+    #[tracing::instrument(skip(self), level = "debug")]
+    pub async fn read_timeout(&self) -> Result<sqlite_pool::Connection<CrConn>, SqlitePoolError> {
+        let start = Instant::now();
+        let res = self
+            .0
+            .read
+            .timeout_get(&sqlite_pool::Timeouts {
+                wait: Some(self.0.acquire_timeout),
+                ..self.0.read.timeouts()
+            })
+            .await;
+        record_acquire(
+            "read",
+            start,
+            matches!(res, Err(SqlitePoolError::Timeout(_))),
+        );
+        res
     }
 
     #[tracing::instrument(skip(self), level = "debug")]
@@ -504,30 +752,68 @@ impl SplitPool {
     // get a high priority write connection (e.g. client input)
     #[tracing::instrument(skip(self), level = "debug")]
     pub async fn write_priority(&self) -> Result<WriteConn, PoolError> {
-        self.write_inner(&self.0.priority_tx, "priority").await
+        self.write_inner(&self.0.priority_tx, "priority", None)
+            .await
+    }
+
+    // get a high priority write connection, giving up after
+    // `db.pool_acquire_timeout_ms` (e.g. client transactions)
+    #[tracing::instrument(skip(self), level = "debug")]
+    pub async fn write_priority_timeout(&self) -> Result<WriteConn, PoolError> {
+        self.write_inner(
+            &self.0.priority_tx,
+            "priority",
+            Some(self.0.acquire_timeout),
+        )
+        .await
     }
 
     // get a normal priority write connection (e.g. sync process)
     #[tracing::instrument(skip(self), level = "debug")]
     pub async fn write_normal(&self) -> Result<WriteConn, PoolError> {
-        self.write_inner(&self.0.normal_tx, "normal").await
+        self.write_inner(&self.0.normal_tx, "normal", None).await
     }
 
     // get a low priority write connection (e.g. background tasks)
     #[tracing::instrument(skip(self), level = "debug")]
     pub async fn write_low(&self) -> Result<WriteConn, PoolError> {
-        self.write_inner(&self.0.low_tx, "low").await
+        self.write_inner(&self.0.low_tx, "low", None).await
     }
 
     async fn write_inner(
         &self,
         chan: &Sender<oneshot::Sender<CancellationToken>>,
         queue: &'static str,
+        timeout: Option<Duration>,
+    ) -> Result<WriteConn, PoolError> {
+        let start = Instant::now();
+        let res = match timeout {
+            Some(timeout) => {
+                match tokio::time::timeout(timeout, self.acquire_write(chan, queue)).await {
+                    Ok(res) => res,
+                    Err(_elapsed) => Err(PoolError::Timeout),
+                }
+            }
+            None => self.acquire_write(chan, queue).await,
+        };
+        record_acquire("write", start, matches!(&res, Err(e) if e.is_timeout()));
+        res
+    }
+
+    async fn acquire_write(
+        &self,
+        chan: &Sender<oneshot::Sender<CancellationToken>>,
+        queue: &'static str,
     ) -> Result<WriteConn, PoolError> {
         let (tx, rx) = oneshot::channel();
         chan.send(tx).await.map_err(|_| PoolError::QueueClosed)?;
         let start = Instant::now();
-        let token = rx.await.map_err(|_| PoolError::CallbackClosed)?;
+        // turned into a guard right away so the queue moves on even if we
+        // time out before getting a connection
+        let drop_guard = rx
+            .await
+            .map_err(|_| PoolError::CallbackClosed)?
+            .drop_guard();
         histogram!("corro.sqlite.pool.queue.seconds", start.elapsed().as_secs_f64(), "queue" => queue);
         let conn = self.0.write.get().await?;
 
@@ -540,17 +826,25 @@ impl SplitPool {
 
         Ok(WriteConn {
             conn,
-            _drop_guard: token.drop_guard(),
+            _drop_guard: drop_guard,
             _permit,
         })
     }
 }
 
+fn record_acquire(pool: &'static str, start: Instant, timed_out: bool) {
+    histogram!("corro.db.pool.acquire.seconds", start.elapsed().as_secs_f64(), "pool" => pool);
+    if timed_out {
+        increment_counter!("corro.db.pool.timeout.count", "pool" => pool);
+    }
+}
+
 async fn wait_conn_drop(tx: oneshot::Sender<CancellationToken>) {
     let cancel = CancellationToken::new();
 
     if let Err(_e) = tx.send(cancel.clone()) {
-        error!("could not send back drop guard for pooled conn, oneshot channel likely closed");
+        // the waiter gave up, most likely because it timed out
+        debug!("could not send back drop guard for pooled conn, oneshot channel likely closed");
         return;
     }
 
@@ -1002,6 +1296,13 @@ impl BookedVersions {
         self.last
     }
 
+    /// db_version of the highest current version
+    pub fn last_db_version(&self) -> Option<CrsqlDbVersion> {
+        self.current
+            .last_key_value()
+            .map(|(_, current)| current.db_version)
+    }
+
     pub fn insert(&mut self, version: Version, known_version: KnownDbVersion) {
         self.insert_many(version..=version, known_version);
     }
@@ -1168,3 +1469,40 @@ impl Bookie {
         self.0.registry()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_db_version_rejects_garbage() {
+        assert_eq!(check_db_version(None, 1).unwrap(), CrsqlDbVersion(1));
+        assert_eq!(
+            check_db_version(Some(CrsqlDbVersion(4)), 5).unwrap(),
+            CrsqlDbVersion(5)
+        );
+
+        // decreasing or repeated
+        assert!(matches!(
+            check_db_version(Some(CrsqlDbVersion(5)), 4),
+            Err(ChangeError::NonMonotonicDbVersion {
+                last: CrsqlDbVersion(5),
+                next: CrsqlDbVersion(4)
+            })
+        ));
+        assert!(matches!(
+            check_db_version(Some(CrsqlDbVersion(5)), 5),
+            Err(ChangeError::NonMonotonicDbVersion { .. })
+        ));
+
+        // negative, e.g. an i64 that rolled over
+        assert!(matches!(
+            check_db_version(None, i64::MIN),
+            Err(ChangeError::DbVersionOutOfRange(i64::MIN))
+        ));
+        assert!(matches!(
+            check_db_version(Some(CrsqlDbVersion(5)), 0),
+            Err(ChangeError::DbVersionOutOfRange(0))
+        ));
+    }
+}
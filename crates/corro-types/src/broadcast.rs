@@ -23,7 +23,7 @@ use uhlc::{ParseNTP64Error, NTP64};
 use crate::{
     actor::{Actor, ActorId},
     base::{CrsqlDbVersion, CrsqlSeq, Version},
-    sync::SyncTraceContextV1,
+    sync::{SyncCompressionV1, SyncTraceContextV1},
 };
 
 #[derive(Debug, Clone, Readable, Writable)]
@@ -47,6 +47,9 @@ pub enum BiPayloadV1 {
         actor_id: ActorId,
         #[speedy(default_on_eof)]
         trace_ctx: SyncTraceContextV1,
+        // compression the peer accepts for sync messages, if any
+        #[speedy(default_on_eof)]
+        compression: Option<SyncCompressionV1>,
     },
 }
 
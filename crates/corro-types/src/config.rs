@@ -1,10 +1,36 @@
-use std::net::SocketAddr;
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+};
 
 use camino::Utf8PathBuf;
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
 
+use crate::signing::{GossipSigner, SigningKeyError};
+
 pub const DEFAULT_GOSSIP_PORT: u16 = 4001;
 const DEFAULT_GOSSIP_IDLE_TIMEOUT: u32 = 30;
+const DEFAULT_BOOTSTRAP_INTERVAL_SECS: u64 = 300;
+// safe size for a SWIM payload sent as a QUIC datagram on most networks
+pub const DEFAULT_MAX_DATAGRAM_SIZE: usize = 1178;
+const MIN_DATAGRAM_SIZE: usize = 512;
+const MAX_DATAGRAM_SIZE: usize = 65507;
+pub const DEFAULT_BROADCAST_BUFFER: usize = 10240;
+pub const DEFAULT_ANNOUNCE_FANOUT: usize = 10;
+const DEFAULT_WAL_CHECKPOINT_INTERVAL_SECS: u64 = 60 * 15;
+pub const DEFAULT_RW_POOL_SIZE: usize = 1;
+pub const DEFAULT_RO_POOL_SIZE: usize = 20;
+pub const DEFAULT_POOL_ACQUIRE_TIMEOUT_MS: u64 = 30_000;
+pub const DEFAULT_MAX_BODY_BYTES: usize = 4 * 1024 * 1024;
+pub const DEFAULT_SUBSCRIBER_BUFFER: usize = 10240;
+pub const DEFAULT_DB_EXECUTE_CONCURRENCY: usize = 128;
+pub const DEFAULT_SYNC_CONCURRENCY: usize = 3;
+pub const DEFAULT_BROADCAST_CONCURRENCY: u32 = 256;
+const DEFAULT_SYNC_MIN_INTERVAL_SECS: u64 = 1;
+const DEFAULT_SYNC_MAX_INTERVAL_SECS: u64 = 15;
+const DEFAULT_SYNC_GAP_INTERVAL_SECS: u64 = 5;
+const DEFAULT_SYNC_APPLY_CHUNK_SIZE: usize = 1000;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -22,6 +48,142 @@ pub struct Config {
     pub log: LogConfig,
     #[serde(default)]
     pub consul: Option<ConsulConfig>,
+    #[serde(default)]
+    pub discovery: DiscoveryConfig,
+    #[serde(default)]
+    pub sync: SyncConfig,
+    #[serde(default)]
+    pub node: NodeConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub tables: TablesConfig,
+    /// Query templates that can be run by name through `/v1/queries/named`
+    #[serde(default)]
+    pub queries: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncConfig {
+    /// Lower bound for the backoff between syncs with other nodes
+    #[serde(default = "default_sync_min_interval")]
+    pub min_interval_secs: u64,
+    /// Upper bound for the backoff between syncs with other nodes
+    #[serde(default = "default_sync_max_interval")]
+    pub max_interval_secs: u64,
+    /// How often to look for holes in the versions we've recorded
+    #[serde(default = "default_sync_gap_interval")]
+    pub gap_interval_secs: u64,
+    /// How many received changes to accumulate before applying them in a single transaction
+    #[serde(default = "default_sync_apply_chunk_size")]
+    pub apply_chunk_size: usize,
+    /// Seed for picking which nodes to sync with, random if unset
+    #[serde(default)]
+    pub rng_seed: Option<u64>,
+    /// How many members to consider before picking the ones to sync with,
+    /// twice the number of syncs if unset
+    #[serde(default)]
+    pub candidate_pool: Option<usize>,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            min_interval_secs: default_sync_min_interval(),
+            max_interval_secs: default_sync_max_interval(),
+            gap_interval_secs: default_sync_gap_interval(),
+            apply_chunk_size: default_sync_apply_chunk_size(),
+            rng_seed: None,
+            candidate_pool: None,
+        }
+    }
+}
+
+fn default_sync_min_interval() -> u64 {
+    DEFAULT_SYNC_MIN_INTERVAL_SECS
+}
+
+fn default_sync_max_interval() -> u64 {
+    DEFAULT_SYNC_MAX_INTERVAL_SECS
+}
+
+fn default_sync_gap_interval() -> u64 {
+    DEFAULT_SYNC_GAP_INTERVAL_SECS
+}
+
+fn default_sync_apply_chunk_size() -> usize {
+    DEFAULT_SYNC_APPLY_CHUNK_SIZE
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LimitsConfig {
+    /// Concurrent requests to /v1/transactions and /v1/queries, each;
+    /// requests over the limit get a 503
+    #[serde(default = "default_db_execute_concurrency")]
+    pub db_execute_concurrency: usize,
+    /// Syncs served to other nodes at the same time, others wait their turn
+    #[serde(default = "default_sync_concurrency")]
+    pub sync_concurrency: usize,
+    /// Broadcast streams a peer connection may have open at the same time
+    #[serde(default = "default_broadcast_concurrency")]
+    pub broadcast_concurrency: u32,
+    /// Rows a single transaction may change, larger transactions are
+    /// rolled back. Can be changed at runtime through the admin API
+    #[serde(default)]
+    pub max_change_size: Option<i64>,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            db_execute_concurrency: default_db_execute_concurrency(),
+            sync_concurrency: default_sync_concurrency(),
+            broadcast_concurrency: default_broadcast_concurrency(),
+            max_change_size: None,
+        }
+    }
+}
+
+fn default_db_execute_concurrency() -> usize {
+    DEFAULT_DB_EXECUTE_CONCURRENCY
+}
+
+fn default_sync_concurrency() -> usize {
+    DEFAULT_SYNC_CONCURRENCY
+}
+
+fn default_broadcast_concurrency() -> u32 {
+    DEFAULT_BROADCAST_CONCURRENCY
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct NodeConfig {
+    /// Refuse writes and schema changes through the API, changes from
+    /// other nodes are still applied via gossip and sync
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct TablesConfig {
+    /// Tables whose changes are only applied locally, never broadcast or
+    /// served to other nodes when they sync
+    #[serde(default)]
+    pub no_broadcast: Vec<String>,
+}
+
+impl TablesConfig {
+    /// Whether changes to `table` are propagated to other nodes
+    pub fn broadcasts(&self, table: &str) -> bool {
+        !self.no_broadcast.iter().any(|t| t == table)
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct DiscoveryConfig {
+    /// Advertise and discover peers on the local network via mDNS
+    #[serde(default)]
+    pub mdns: bool,
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -63,8 +225,31 @@ pub struct DbConfig {
     pub path: Utf8PathBuf,
     #[serde(default)]
     pub schema_paths: Vec<Utf8PathBuf>,
+    /// Reload the schema when .sql files in the schema paths change
+    #[serde(default)]
+    pub watch_schema: bool,
     #[serde(default)]
     pub subscriptions_path: Option<Utf8PathBuf>,
+    /// Allow schema changes that drop tables or columns
+    #[serde(default)]
+    pub allow_destructive_schema: bool,
+    #[serde(default = "default_wal_checkpoint_interval")]
+    pub wal_checkpoint_interval_secs: u64,
+    /// Maximum number of read-write connections
+    #[serde(default = "default_rw_pool_size")]
+    pub rw_pool_size: usize,
+    /// Maximum number of read-only connections
+    #[serde(default = "default_ro_pool_size")]
+    pub ro_pool_size: usize,
+    /// Give up acquiring a pooled connection after this many milliseconds
+    #[serde(default = "default_pool_acquire_timeout_ms")]
+    pub pool_acquire_timeout_ms: u64,
+    /// Pragmas applied, in order, to every new pooled connection
+    #[serde(default)]
+    pub pragmas: Vec<String>,
+    /// Log statements taking longer than this many milliseconds
+    #[serde(default)]
+    pub slow_query_ms: Option<u64>,
 }
 
 impl DbConfig {
@@ -89,6 +274,23 @@ pub struct ApiConfig {
     pub authorization: Option<AuthzConfig>,
     #[serde(default)]
     pub pg: Option<PgConfig>,
+    /// Also serve the API on this unix domain socket
+    #[serde(default)]
+    pub uds_path: Option<Utf8PathBuf>,
+    /// Requests with a larger body are rejected with a 413
+    #[serde(default = "default_max_body_bytes")]
+    pub max_body_bytes: usize,
+    /// Origins allowed to make cross-origin requests, `*` allows any
+    #[serde(default)]
+    pub cors_allow_origins: Vec<String>,
+    /// Events buffered for each subscriber, a subscriber falling further
+    /// behind is dropped
+    #[serde(default = "default_subscriber_buffer")]
+    pub subscriber_buffer: usize,
+    /// Directory backups and change files are read from and written to,
+    /// `/v1/admin/backup`, `/export` and `/import` are disabled when unset
+    #[serde(default)]
+    pub backup_dir: Option<Utf8PathBuf>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -120,12 +322,85 @@ pub struct GossipConfig {
     pub idle_timeout_secs: u32,
     #[serde(default)]
     pub disable_gso: bool,
+    #[serde(default = "default_bootstrap_interval")]
+    pub bootstrap_interval_secs: u64,
+    #[serde(default = "default_max_datagram_size")]
+    pub max_datagram_size: usize,
+    /// Capacity of the queues holding broadcasts waiting to be sent or processed
+    #[serde(default = "default_broadcast_buffer")]
+    pub broadcast_buffer: usize,
+    /// How many of the bootstrap addresses to announce ourselves to each
+    /// time we bootstrap
+    #[serde(default = "default_announce_fanout")]
+    pub announce_fanout: usize,
+    /// Hex-encoded ed25519 seed shared by all the nodes of the cluster
+    #[serde(default)]
+    pub signing_key: Option<String>,
+    #[serde(default = "default_as_true")]
+    pub signing_required: bool,
+    /// Only gossip and sync with peers in these networks, if any are set
+    #[serde(default)]
+    pub allow_cidrs: Vec<IpNet>,
+    /// Never gossip or sync with peers in these networks
+    #[serde(default)]
+    pub deny_cidrs: Vec<IpNet>,
+}
+
+impl GossipConfig {
+    /// Whether we can talk to a peer with this IP, the deny list wins over
+    /// the allow list
+    pub fn is_peer_allowed(&self, ip: IpAddr) -> bool {
+        if self.deny_cidrs.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.allow_cidrs.is_empty() || self.allow_cidrs.iter().any(|net| net.contains(&ip))
+    }
 }
 
 fn default_gossip_idle_timeout() -> u32 {
     DEFAULT_GOSSIP_IDLE_TIMEOUT
 }
 
+fn default_bootstrap_interval() -> u64 {
+    DEFAULT_BOOTSTRAP_INTERVAL_SECS
+}
+
+fn default_max_datagram_size() -> usize {
+    DEFAULT_MAX_DATAGRAM_SIZE
+}
+
+fn default_wal_checkpoint_interval() -> u64 {
+    DEFAULT_WAL_CHECKPOINT_INTERVAL_SECS
+}
+
+fn default_rw_pool_size() -> usize {
+    DEFAULT_RW_POOL_SIZE
+}
+
+fn default_ro_pool_size() -> usize {
+    DEFAULT_RO_POOL_SIZE
+}
+
+fn default_pool_acquire_timeout_ms() -> u64 {
+    DEFAULT_POOL_ACQUIRE_TIMEOUT_MS
+}
+
+fn default_broadcast_buffer() -> usize {
+    DEFAULT_BROADCAST_BUFFER
+}
+
+fn default_announce_fanout() -> usize {
+    DEFAULT_ANNOUNCE_FANOUT
+}
+
+fn default_max_body_bytes() -> usize {
+    DEFAULT_MAX_BODY_BYTES
+}
+
+fn default_subscriber_buffer() -> usize {
+    DEFAULT_SUBSCRIBER_BUFFER
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TlsConfig {
     /// Certificate file
@@ -173,6 +448,24 @@ fn default_as_true() -> bool {
 pub enum ConfigError {
     #[error(transparent)]
     Config(#[from] config::ConfigError),
+    #[error("{0} must be greater than 0")]
+    MustBePositive(&'static str),
+    #[error("db.pragmas must not contain empty pragmas")]
+    EmptyPragma,
+    #[error("sync.min_interval_secs ({min}) must not exceed sync.max_interval_secs ({max})")]
+    SyncIntervalRange { min: u64, max: u64 },
+    #[error("gossip.max_datagram_size ({0}) must be between {MIN_DATAGRAM_SIZE} and {MAX_DATAGRAM_SIZE}")]
+    DatagramSizeRange(usize),
+    #[error("api.cors_allow_origins: invalid origin {0:?}, origins must be visible ASCII and `*` must be the only entry")]
+    CorsOrigin(String),
+    #[error("gossip.signing_key: {0}")]
+    SigningKey(#[from] SigningKeyError),
+    #[error("gossip.signing_key requires mutual TLS (gossip.tls with a ca_file and a client certificate): syncs aren't signed, only client certificates keep other nodes from sending changes through them")]
+    SigningWithoutMtls,
+    #[error("gossip.bootstrap: {0:?} has an IPv6 scope that isn't a numeric interface index, use e.g. \"[fe80::1%2]:8787\"")]
+    BootstrapScope(String),
+    #[error("queries.{0} must not be empty")]
+    EmptyQuery(String),
 }
 
 impl Config {
@@ -187,7 +480,114 @@ impl Config {
             .add_source(config::File::new(config_path, config::FileFormat::Toml))
             .add_source(config::Environment::default().separator("__"))
             .build()?;
-        Ok(config.try_deserialize()?)
+        let config: Self = config.try_deserialize()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.gossip.bootstrap_interval_secs == 0 {
+            return Err(ConfigError::MustBePositive(
+                "gossip.bootstrap_interval_secs",
+            ));
+        }
+        if !(MIN_DATAGRAM_SIZE..=MAX_DATAGRAM_SIZE).contains(&self.gossip.max_datagram_size) {
+            return Err(ConfigError::DatagramSizeRange(
+                self.gossip.max_datagram_size,
+            ));
+        }
+        if GossipSigner::from_config(&self.gossip)?.is_some()
+            && !self
+                .gossip
+                .tls
+                .as_ref()
+                .is_some_and(|tls| tls.ca_file.is_some() && tls.client.is_some())
+        {
+            return Err(ConfigError::SigningWithoutMtls);
+        }
+        if self.gossip.broadcast_buffer == 0 {
+            return Err(ConfigError::MustBePositive("gossip.broadcast_buffer"));
+        }
+        // std only parses numeric scope ids, `%eth0` would otherwise end up
+        // being looked up as a hostname
+        if let Some(entry) = self
+            .gossip
+            .bootstrap
+            .iter()
+            .find(|entry| entry.contains('%') && entry.parse::<SocketAddr>().is_err())
+        {
+            return Err(ConfigError::BootstrapScope(entry.clone()));
+        }
+        if self.gossip.announce_fanout == 0 {
+            return Err(ConfigError::MustBePositive("gossip.announce_fanout"));
+        }
+        if self.sync.candidate_pool == Some(0) {
+            return Err(ConfigError::MustBePositive("sync.candidate_pool"));
+        }
+        let origins = &self.api.cors_allow_origins;
+        if let Some(origin) = origins.iter().find(|origin| {
+            origin.is_empty()
+                || !origin.bytes().all(|b| b.is_ascii_graphic())
+                || (origin.as_str() == "*" && origins.len() > 1)
+        }) {
+            return Err(ConfigError::CorsOrigin(origin.clone()));
+        }
+        if self.api.subscriber_buffer == 0 {
+            return Err(ConfigError::MustBePositive("api.subscriber_buffer"));
+        }
+        if self.limits.db_execute_concurrency == 0 {
+            return Err(ConfigError::MustBePositive("limits.db_execute_concurrency"));
+        }
+        if self.limits.sync_concurrency == 0 {
+            return Err(ConfigError::MustBePositive("limits.sync_concurrency"));
+        }
+        if self.limits.broadcast_concurrency == 0 {
+            return Err(ConfigError::MustBePositive("limits.broadcast_concurrency"));
+        }
+        if matches!(self.limits.max_change_size, Some(size) if size <= 0) {
+            return Err(ConfigError::MustBePositive("limits.max_change_size"));
+        }
+        if let Some((name, _)) = self.queries.iter().find(|(_, sql)| sql.trim().is_empty()) {
+            return Err(ConfigError::EmptyQuery(name.clone()));
+        }
+        if self.db.wal_checkpoint_interval_secs == 0 {
+            return Err(ConfigError::MustBePositive(
+                "db.wal_checkpoint_interval_secs",
+            ));
+        }
+        if self.db.rw_pool_size == 0 {
+            return Err(ConfigError::MustBePositive("db.rw_pool_size"));
+        }
+        if self.db.ro_pool_size == 0 {
+            return Err(ConfigError::MustBePositive("db.ro_pool_size"));
+        }
+        if self.db.pool_acquire_timeout_ms == 0 {
+            return Err(ConfigError::MustBePositive("db.pool_acquire_timeout_ms"));
+        }
+        if self
+            .db
+            .pragmas
+            .iter()
+            .any(|pragma| pragma.trim().is_empty())
+        {
+            return Err(ConfigError::EmptyPragma);
+        }
+        if self.sync.min_interval_secs == 0 {
+            return Err(ConfigError::MustBePositive("sync.min_interval_secs"));
+        }
+        if self.sync.gap_interval_secs == 0 {
+            return Err(ConfigError::MustBePositive("sync.gap_interval_secs"));
+        }
+        if self.sync.apply_chunk_size == 0 {
+            return Err(ConfigError::MustBePositive("sync.apply_chunk_size"));
+        }
+        if self.sync.min_interval_secs > self.sync.max_interval_secs {
+            return Err(ConfigError::SyncIntervalRange {
+                min: self.sync.min_interval_secs,
+                max: self.sync.max_interval_secs,
+            });
+        }
+        Ok(())
     }
 }
 
@@ -204,6 +604,17 @@ pub struct ConfigBuilder {
     max_change_size: Option<i64>,
     consul: Option<ConsulConfig>,
     tls: Option<TlsConfig>,
+    allow_destructive_schema: bool,
+    mdns_discovery: bool,
+    signing_key: Option<String>,
+    allow_cidrs: Vec<IpNet>,
+    deny_cidrs: Vec<IpNet>,
+    read_only: bool,
+    sync_rng_seed: Option<u64>,
+    sync_candidate_pool: Option<usize>,
+    announce_fanout: Option<usize>,
+    no_broadcast_tables: Vec<String>,
+    pool_acquire_timeout_ms: Option<u64>,
 }
 
 impl ConfigBuilder {
@@ -242,6 +653,16 @@ impl ConfigBuilder {
         self
     }
 
+    pub fn allow_destructive_schema(mut self, allow: bool) -> Self {
+        self.allow_destructive_schema = allow;
+        self
+    }
+
+    pub fn mdns_discovery(mut self, enabled: bool) -> Self {
+        self.mdns_discovery = enabled;
+        self
+    }
+
     pub fn admin_path<S: Into<Utf8PathBuf>>(mut self, path: S) -> Self {
         self.admin_path = Some(path.into());
         self
@@ -252,6 +673,11 @@ impl ConfigBuilder {
         self
     }
 
+    pub fn pool_acquire_timeout_ms(mut self, timeout_ms: u64) -> Self {
+        self.pool_acquire_timeout_ms = Some(timeout_ms);
+        self
+    }
+
     pub fn consul(mut self, config: ConsulConfig) -> Self {
         self.consul = Some(config);
         self
@@ -262,6 +688,46 @@ impl ConfigBuilder {
         self
     }
 
+    pub fn signing_key<S: Into<String>>(mut self, key: S) -> Self {
+        self.signing_key = Some(key.into());
+        self
+    }
+
+    pub fn allow_cidrs<V: Into<Vec<IpNet>>>(mut self, cidrs: V) -> Self {
+        self.allow_cidrs = cidrs.into();
+        self
+    }
+
+    pub fn deny_cidrs<V: Into<Vec<IpNet>>>(mut self, cidrs: V) -> Self {
+        self.deny_cidrs = cidrs.into();
+        self
+    }
+
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    pub fn sync_rng_seed(mut self, seed: u64) -> Self {
+        self.sync_rng_seed = Some(seed);
+        self
+    }
+
+    pub fn sync_candidate_pool(mut self, size: usize) -> Self {
+        self.sync_candidate_pool = Some(size);
+        self
+    }
+
+    pub fn announce_fanout(mut self, fanout: usize) -> Self {
+        self.announce_fanout = Some(fanout);
+        self
+    }
+
+    pub fn no_broadcast_table<S: Into<String>>(mut self, table: S) -> Self {
+        self.no_broadcast_tables.push(table.into());
+        self
+    }
+
     pub fn build(self) -> Result<Config, ConfigBuilderError> {
         let db_path = self.db_path.ok_or(ConfigBuilderError::DbPathRequired)?;
 
@@ -276,12 +742,27 @@ impl ConfigBuilder {
             db: DbConfig {
                 path: db_path,
                 schema_paths: self.schema_paths,
+                watch_schema: false,
                 subscriptions_path: None,
+                allow_destructive_schema: self.allow_destructive_schema,
+                wal_checkpoint_interval_secs: default_wal_checkpoint_interval(),
+                rw_pool_size: default_rw_pool_size(),
+                ro_pool_size: default_ro_pool_size(),
+                pool_acquire_timeout_ms: self
+                    .pool_acquire_timeout_ms
+                    .unwrap_or_else(default_pool_acquire_timeout_ms),
+                pragmas: vec![],
+                slow_query_ms: None,
             },
             api: ApiConfig {
                 bind_addr: self.api_addr.ok_or(ConfigBuilderError::ApiAddrRequired)?,
                 authorization: None,
                 pg: None,
+                uds_path: None,
+                max_body_bytes: default_max_body_bytes(),
+                cors_allow_origins: vec![],
+                subscriber_buffer: default_subscriber_buffer(),
+                backup_dir: None,
             },
             gossip: GossipConfig {
                 bind_addr: self
@@ -293,6 +774,14 @@ impl ConfigBuilder {
                 idle_timeout_secs: default_gossip_idle_timeout(),
                 max_mtu: None, // TODO: add a builder function for it
                 disable_gso: false,
+                bootstrap_interval_secs: default_bootstrap_interval(),
+                max_datagram_size: default_max_datagram_size(),
+                broadcast_buffer: default_broadcast_buffer(),
+                announce_fanout: self.announce_fanout.unwrap_or_else(default_announce_fanout),
+                signing_key: self.signing_key,
+                signing_required: true,
+                allow_cidrs: self.allow_cidrs,
+                deny_cidrs: self.deny_cidrs,
             },
             admin: AdminConfig {
                 uds_path: self.admin_path.unwrap_or_else(default_admin_path),
@@ -301,6 +790,25 @@ impl ConfigBuilder {
             log: self.log.unwrap_or_default(),
 
             consul: self.consul,
+            discovery: DiscoveryConfig {
+                mdns: self.mdns_discovery,
+            },
+            sync: SyncConfig {
+                rng_seed: self.sync_rng_seed,
+                candidate_pool: self.sync_candidate_pool,
+                ..Default::default()
+            },
+            node: NodeConfig {
+                read_only: self.read_only,
+            },
+            limits: LimitsConfig {
+                max_change_size: self.max_change_size,
+                ..Default::default()
+            },
+            tables: TablesConfig {
+                no_broadcast: self.no_broadcast_tables,
+            },
+            queries: BTreeMap::new(),
         })
     }
 }
@@ -315,12 +823,13 @@ pub enum ConfigBuilderError {
     ApiAddrRequired,
 }
 
-/// Log format (JSON only)
+/// Log format, human readable text or one JSON object per line
 #[derive(Debug, Default, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 #[allow(missing_docs)]
 pub enum LogFormat {
     #[default]
+    #[serde(alias = "text")]
     Plaintext,
     Json,
 }
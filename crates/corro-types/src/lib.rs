@@ -8,6 +8,7 @@ pub mod config;
 pub mod members;
 pub mod pubsub;
 pub mod schema;
+pub mod signing;
 pub mod sqlite;
 pub mod sync;
 pub mod tls;
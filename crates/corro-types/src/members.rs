@@ -108,15 +108,20 @@ impl Members {
         self.recalculate_rings(addr)
     }
 
+    /// Average of the recently recorded round-trip times to an address, in milliseconds
+    pub fn avg_rtt(&self, addr: &SocketAddr) -> Option<u64> {
+        self.rtts.get(addr).and_then(|rtt| {
+            (!rtt.buf.is_empty()).then(|| {
+                (rtt.buf.as_slices().0.iter().sum::<u64>()
+                    + rtt.buf.as_slices().1.iter().sum::<u64>())
+                    / rtt.buf.len() as u64
+            })
+        })
+    }
+
     fn recalculate_rings(&mut self, addr: SocketAddr) {
         if let Some(actor_id) = self.by_addr.get(&addr) {
-            if let Some(avg) = self.rtts.get(&addr).and_then(|rtt| {
-                (!rtt.buf.is_empty()).then(|| {
-                    (rtt.buf.as_slices().0.iter().sum::<u64>()
-                        + rtt.buf.as_slices().1.iter().sum::<u64>())
-                        / rtt.buf.len() as u64
-                })
-            }) {
+            if let Some(avg) = self.avg_rtt(&addr) {
                 if let Some(state) = self.states.get_mut(actor_id) {
                     for (ring, n) in RING_BUCKETS.iter().enumerate() {
                         if n.contains(&avg) {
@@ -1,7 +1,10 @@
 use std::{
     cmp,
     collections::{BTreeMap, HashMap, HashSet},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 
@@ -64,6 +67,8 @@ pub struct MatcherCreated {
 }
 
 const SUB_EVENT_CHANNEL_CAP: usize = 512;
+/// Number of past changes kept around to resume subscriptions from
+pub const RETAINED_CHANGES: u64 = 500;
 
 impl SubsManager {
     pub fn get(&self, id: &Uuid) -> Option<MatcherHandle> {
@@ -159,6 +164,10 @@ impl SubsManager {
         inner.remove(id)
     }
 
+    pub fn handles(&self) -> Vec<MatcherHandle> {
+        self.0.read().handles.values().cloned().collect()
+    }
+
     pub fn match_changes(&self, changes: &[Change], db_version: CrsqlDbVersion) {
         trace!(
             %db_version,
@@ -190,6 +199,12 @@ impl SubsManager {
             for (table, pks) in candidates.iter() {
                 counter!("corro.subs.changes.matched.count", pks.len() as u64, "sql_hash" => handle.inner.hash.clone(), "table" => table.to_string());
             }
+            // only labeled by subscription to keep cardinality bounded, a
+            // matched count close to the evaluated one means a filter is too broad
+            counter!("corro.subs.changes.evaluated.count", changes.len() as u64, "sub_id" => id.to_string());
+            if match_count > 0 {
+                counter!("corro.subs.matched.count", match_count, "sub_id" => id.to_string());
+            }
 
             trace!(sub_id = %id, %db_version, "found {match_count} candidates");
 
@@ -277,6 +292,7 @@ struct InnerMatcherHandle {
     cancel: CancellationToken,
     changes_tx: mpsc::Sender<(MatchCandidates, CrsqlDbVersion)>,
     last_change_rx: watch::Receiver<ChangeId>,
+    old_values_subs: Arc<AtomicUsize>,
 }
 
 type MatchCandidates = IndexMap<TableName, IndexSet<Vec<u8>>>;
@@ -286,6 +302,21 @@ impl MatcherHandle {
         self.inner.id
     }
 
+    pub fn sql(&self) -> &str {
+        &self.inner.sql
+    }
+
+    pub fn hash(&self) -> &str {
+        &self.inner.hash
+    }
+
+    /// Makes the matcher read the previous values of updated rows and send
+    /// them as [`QueryEvent::Old`], for as long as the guard is held.
+    pub fn want_old_values(&self) -> OldValuesGuard {
+        self.inner.old_values_subs.fetch_add(1, Ordering::Relaxed);
+        OldValuesGuard(self.inner.old_values_subs.clone())
+    }
+
     pub fn parsed_columns(&self) -> &[ResultColumn] {
         &self.inner.parsed.columns
     }
@@ -337,8 +368,20 @@ impl MatcherHandle {
         since: ChangeId,
         conn: &Connection,
         tx: mpsc::Sender<QueryEvent>,
-    ) -> rusqlite::Result<ChangeId> {
+    ) -> Result<ChangeId, MatcherError> {
         self.wait_for_running_state();
+
+        // older changes are purged, replaying from before them would
+        // silently skip some
+        let oldest: Option<ChangeId> = conn
+            .prepare_cached("SELECT MIN(id) FROM changes")?
+            .query_row([], |row| row.get(0))?;
+        if let Some(oldest) = oldest {
+            if since + 1 < oldest {
+                return Err(MatcherError::ChangesPurged { since, oldest });
+            }
+        }
+
         let mut query_cols = vec![];
         for i in 0..(self.parsed_columns().len()) {
             query_cols.push(format!("col_{i}"));
@@ -456,10 +499,7 @@ impl MatcherHandle {
         if !self
             .inner
             .parsed
-            .table_columns
-            .get(change.table.as_str())
-            .map(|cols| change.column.is_crsql_sentinel() || cols.contains(change.column.as_str()))
-            .unwrap_or_default()
+            .matches_column(change.table.as_str(), change.column)
         {
             return false;
         }
@@ -473,6 +513,14 @@ impl MatcherHandle {
     }
 }
 
+pub struct OldValuesGuard(Arc<AtomicUsize>);
+
+impl Drop for OldValuesGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
 type StateLock = Arc<(Mutex<MatcherState>, Condvar)>;
 
 pub struct Matcher {
@@ -491,6 +539,7 @@ pub struct Matcher {
     state: StateLock,
     last_change_tx: watch::Sender<ChangeId>,
     changes_rx: mpsc::Receiver<(MatchCandidates, CrsqlDbVersion)>,
+    old_values_subs: Arc<AtomicUsize>,
 }
 
 #[derive(Debug, Clone)]
@@ -541,23 +590,7 @@ impl Matcher {
             "#,
         )?;
 
-        let mut parser = Parser::new(sql.as_bytes());
-
-        let (mut stmt, parsed) = match parser.next()?.ok_or(MatcherError::StatementRequired)? {
-            Cmd::Stmt(stmt) => {
-                let parsed = match stmt {
-                    Stmt::Select(ref select) => extract_select_columns(select, schema)?,
-                    _ => return Err(MatcherError::UnsupportedStatement),
-                };
-
-                (stmt, parsed)
-            }
-            _ => return Err(MatcherError::StatementRequired),
-        };
-
-        if parsed.table_columns.is_empty() {
-            return Err(MatcherError::TableRequired);
-        }
+        let (mut stmt, parsed) = parse_select(sql, schema)?;
 
         let mut statements = HashMap::new();
 
@@ -671,6 +704,8 @@ impl Matcher {
 
         let sql_hash = hex::encode(seahash::hash(sql.as_bytes()).to_be_bytes());
 
+        let old_values_subs = Arc::new(AtomicUsize::new(0));
+
         let handle = MatcherHandle {
             inner: Arc::new(InnerMatcherHandle {
                 id,
@@ -686,6 +721,7 @@ impl Matcher {
                 cancel: cancel.clone(),
                 last_change_rx,
                 changes_tx,
+                old_values_subs: old_values_subs.clone(),
             }),
             state: state.clone(),
         };
@@ -706,6 +742,7 @@ impl Matcher {
             state,
             last_change_tx,
             changes_rx,
+            old_values_subs,
         };
 
         Ok((matcher, handle))
@@ -1055,9 +1092,9 @@ impl Matcher {
 
                         let deleted = tx
                             .prepare_cached(
-                                "DELETE FROM changes WHERE id < (SELECT COALESCE(MAX(id),0) - 500 FROM changes)"
+                                "DELETE FROM changes WHERE id < (SELECT COALESCE(MAX(id),0) - ? FROM changes)"
                             )?
-                            .execute([])?;
+                            .execute([RETAINED_CHANGES])?;
 
                         tx.commit().map(|_| deleted)
                     });
@@ -1474,6 +1511,32 @@ impl Matcher {
 
                 let delete_prepped = tx.prepare_cached(&sql)?;
 
+                // previous values of the rows about to be updated, if anybody cares
+                let mut old_values: BTreeMap<RowId, Vec<SqliteValue>> = BTreeMap::new();
+                if self.old_values_subs.load(Ordering::Relaxed) > 0 {
+                    let sql = format!(
+                        "SELECT __corro_rowid,{return_cols} FROM query WHERE ({pks}) IN (SELECT {pks} FROM (
+                            SELECT * FROM state_results
+                            EXCEPT
+                            {query_query}
+                        ))",
+                        return_cols = query_cols.join(","),
+                        pks = pk_cols.join(","),
+                        query_query = stmt.temp_query,
+                    );
+                    let mut prepped = tx.prepare_cached(&sql)?;
+                    let col_count = prepped.column_count();
+                    let mut rows = prepped.raw_query();
+                    while let Some(row) = rows.next()? {
+                        old_values.insert(
+                            row.get(0)?,
+                            (1..col_count)
+                                .map(|i| row.get::<_, SqliteValue>(i))
+                                .collect::<rusqlite::Result<Vec<_>>>()?,
+                        );
+                    }
+                }
+
                 let mut change_insert_stmt = tx.prepare_cached(&format!(
                     "INSERT INTO changes (__corro_rowid, {CHANGE_TYPE_COL}, {}) VALUES (?, ?, {}) RETURNING {CHANGE_ID_COL}",
                     query_cols.join(","),
@@ -1525,6 +1588,18 @@ impl Matcher {
 
                                 trace!("got change id: {change_id}");
 
+                                if let Some(old) = old_values.remove(&rowid) {
+                                    if change_type == ChangeType::Update {
+                                        if let Err(e) = self
+                                            .evt_tx
+                                            .blocking_send(QueryEvent::Old(rowid, old, change_id))
+                                        {
+                                            debug!("could not send back old values to matcher sub sender: {e}");
+                                            return Err(MatcherError::EventReceiverClosed);
+                                        }
+                                    }
+                                }
+
                                 if let Err(e) = self.evt_tx.blocking_send(QueryEvent::Change(
                                     change_type,
                                     rowid,
@@ -1647,6 +1722,40 @@ pub struct ParsedSelect {
     children: Vec<ParsedSelect>,
 }
 
+impl ParsedSelect {
+    /// Whether a change to this table and column can affect the query's results
+    pub fn matches_column(&self, table: &str, column: &ColumnName) -> bool {
+        self.table_columns
+            .get(table)
+            .map(|cols| column.is_crsql_sentinel() || cols.contains(column.as_str()))
+            .unwrap_or_default()
+    }
+}
+
+/// Parses a subscription's SQL the way a [`Matcher`] would, without
+/// creating one.
+pub fn parse_select(sql: &str, schema: &Schema) -> Result<(Stmt, ParsedSelect), MatcherError> {
+    let mut parser = Parser::new(sql.as_bytes());
+
+    let (stmt, parsed) = match parser.next()?.ok_or(MatcherError::StatementRequired)? {
+        Cmd::Stmt(stmt) => {
+            let parsed = match stmt {
+                Stmt::Select(ref select) => extract_select_columns(select, schema)?,
+                _ => return Err(MatcherError::UnsupportedStatement),
+            };
+
+            (stmt, parsed)
+        }
+        _ => return Err(MatcherError::StatementRequired),
+    };
+
+    if parsed.table_columns.is_empty() {
+        return Err(MatcherError::TableRequired);
+    }
+
+    Ok((stmt, parsed))
+}
+
 fn extract_select_columns(select: &Select, schema: &Schema) -> Result<ParsedSelect, MatcherError> {
     let mut parsed = ParsedSelect::default();
 
@@ -1832,7 +1941,13 @@ fn extract_expr_columns(
             }
         }
 
-        Expr::Between { lhs, .. } => extract_expr_columns(lhs, schema, parsed)?,
+        Expr::Between {
+            lhs, start, end, ..
+        } => {
+            extract_expr_columns(lhs, schema, parsed)?;
+            extract_expr_columns(start, schema, parsed)?;
+            extract_expr_columns(end, schema, parsed)?;
+        }
         Expr::Binary(lhs, _, rhs) => {
             extract_expr_columns(lhs, schema, parsed)?;
             extract_expr_columns(rhs, schema, parsed)?;
@@ -2066,6 +2181,8 @@ pub enum MatcherError {
     NotRunning,
     #[error("subscription restore is missing SQL query")]
     MissingSql,
+    #[error("changes since {since} were purged, the oldest change available is {oldest}")]
+    ChangesPurged { since: ChangeId, oldest: ChangeId },
 }
 
 impl MatcherError {
@@ -2274,6 +2391,7 @@ mod tests {
     use crate::{
         actor::ActorId,
         agent::migrate,
+        config::{DEFAULT_POOL_ACQUIRE_TIMEOUT_MS, DEFAULT_RO_POOL_SIZE, DEFAULT_RW_POOL_SIZE},
         schema::{apply_schema, parse_sql},
         sqlite::{setup_conn, CrConn},
     };
@@ -2284,7 +2402,8 @@ mod tests {
     async fn test_matcher() -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
         _ = tracing_subscriber::fmt::try_init();
         let (tripwire, tripwire_worker, tripwire_tx) = Tripwire::new_simple();
-        let schema_sql = "CREATE TABLE sw (pk TEXT NOT NULL PRIMARY KEY, sandwich TEXT);";
+        let schema_sql =
+            "CREATE TABLE sw (pk TEXT NOT NULL PRIMARY KEY, sandwich TEXT) WITHOUT ROWID;";
         let mut schema = parse_sql(schema_sql)?;
 
         let sql = "SELECT sandwich FROM sw WHERE pk=\"mad\"";
@@ -2296,13 +2415,21 @@ mod tests {
         let subscriptions_path: Utf8PathBuf =
             tmpdir.path().join("subs").display().to_string().into();
 
-        let pool = SplitPool::create(db_path, Arc::new(Semaphore::new(1))).await?;
+        let pool = SplitPool::create(
+            db_path,
+            Arc::new(Semaphore::new(1)),
+            DEFAULT_RW_POOL_SIZE,
+            DEFAULT_RO_POOL_SIZE,
+            &[],
+            Duration::from_millis(DEFAULT_POOL_ACQUIRE_TIMEOUT_MS),
+        )
+        .await?;
         {
             let mut conn = pool.write_priority().await?;
             setup_conn(&mut conn)?;
             migrate(&mut conn)?;
             let tx = conn.transaction()?;
-            apply_schema(&tx, &Schema::default(), &mut schema)?;
+            apply_schema(&tx, &Schema::default(), &mut schema, Default::default())?;
             tx.commit()?;
         }
 
@@ -2325,6 +2452,59 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_parse_select_filter_operators() -> Result<(), Box<dyn std::error::Error>> {
+        let schema = parse_sql(
+            "CREATE TABLE sw (pk TEXT NOT NULL PRIMARY KEY, sandwich TEXT, price INTEGER, min_price INTEGER, max_price INTEGER);",
+        )?;
+
+        let columns = |sql: &str| -> Result<Vec<String>, MatcherError> {
+            let (_, parsed) = parse_select(sql, &schema)?;
+            let mut cols: Vec<String> = parsed.table_columns["sw"].iter().cloned().collect();
+            cols.sort();
+            Ok(cols)
+        };
+
+        // IN / NOT IN
+        assert_eq!(
+            columns("SELECT pk FROM sw WHERE sandwich IN ('ham', 'brie')")?,
+            ["pk", "sandwich"]
+        );
+        assert_eq!(
+            columns("SELECT pk FROM sw WHERE sandwich NOT IN ('ham', 'brie')")?,
+            ["pk", "sandwich"]
+        );
+
+        // numeric comparisons
+        for op in ["<", "<=", ">", ">="] {
+            assert_eq!(
+                columns(&format!("SELECT pk FROM sw WHERE price {op} 10"))?,
+                ["pk", "price"]
+            );
+        }
+
+        // negation
+        assert_eq!(
+            columns("SELECT pk FROM sw WHERE NOT (sandwich = 'ham' OR price > 10)")?,
+            ["pk", "price", "sandwich"]
+        );
+
+        // BETWEEN bounds can reference columns too
+        assert_eq!(
+            columns("SELECT pk FROM sw WHERE price NOT BETWEEN min_price AND max_price")?,
+            ["max_price", "min_price", "pk", "price"]
+        );
+
+        let (_, parsed) = parse_select(
+            "SELECT pk FROM sw WHERE price BETWEEN min_price AND max_price",
+            &schema,
+        )?;
+        assert!(parsed.matches_column("sw", &ColumnName("max_price".into())));
+        assert!(!parsed.matches_column("sw", &ColumnName("sandwich".into())));
+
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
     async fn test_diff() {
         _ = tracing_subscriber::fmt::try_init();
@@ -2376,7 +2556,7 @@ mod tests {
               ), protocol TEXT
           AS (JSON_EXTRACT(meta, '$.protocol')),
               PRIMARY KEY (node, id)
-          );
+          ) WITHOUT ROWID;
 
           CREATE TABLE machines (
               id TEXT NOT NULL PRIMARY KEY,
@@ -2387,7 +2567,7 @@ mod tests {
               organization_id INTEGER NOT NULL DEFAULT 0,
               network_id INTEGER NOT NULL DEFAULT 0,
               updated_at INTEGER NOT NULL DEFAULT 0
-          );
+          ) WITHOUT ROWID;
 
           CREATE TABLE machine_versions (
               machine_id TEXT NOT NULL,
@@ -2395,7 +2575,7 @@ mod tests {
               config TEXT NOT NULL DEFAULT '{}',
               updated_at INTEGER NOT NULL DEFAULT 0,
               PRIMARY KEY (machine_id, id)
-          );
+          ) WITHOUT ROWID;
 
           CREATE TABLE machine_version_statuses (
               machine_id TEXT NOT NULL,
@@ -2403,7 +2583,7 @@ mod tests {
               status TEXT NOT NULL DEFAULT '',
               updated_at INTEGER NOT NULL DEFAULT 0,
               PRIMARY KEY (machine_id, id)
-          );
+          ) WITHOUT ROWID;
           ";
 
         let mut schema = parse_sql(schema_sql).unwrap();
@@ -2414,16 +2594,23 @@ mod tests {
         let subscriptions_path: Utf8PathBuf =
             tmpdir.path().join("subs").display().to_string().into();
 
-        let pool = SplitPool::create(&db_path, Arc::new(Semaphore::new(1)))
-            .await
-            .unwrap();
+        let pool = SplitPool::create(
+            &db_path,
+            Arc::new(Semaphore::new(1)),
+            DEFAULT_RW_POOL_SIZE,
+            DEFAULT_RO_POOL_SIZE,
+            &[],
+            Duration::from_millis(DEFAULT_POOL_ACQUIRE_TIMEOUT_MS),
+        )
+        .await
+        .unwrap();
         let mut conn = pool.write_priority().await.unwrap();
 
         {
             setup_conn(&mut conn).unwrap();
             migrate(&mut conn).unwrap();
             let tx = conn.transaction().unwrap();
-            apply_schema(&tx, &Schema::default(), &mut schema).unwrap();
+            apply_schema(&tx, &Schema::default(), &mut schema, Default::default()).unwrap();
             tx.commit().unwrap();
         }
 
@@ -2461,7 +2648,7 @@ mod tests {
 
             {
                 let tx = conn2.transaction().unwrap();
-                apply_schema(&tx, &Schema::default(), &mut schema).unwrap();
+                apply_schema(&tx, &Schema::default(), &mut schema, Default::default()).unwrap();
                 tx.commit().unwrap();
             }
 
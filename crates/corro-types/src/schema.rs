@@ -11,9 +11,10 @@ use rusqlite::{Connection, Transaction};
 use serde::{Deserialize, Serialize};
 use sqlite3_parser::ast::{
     Cmd, ColumnConstraint, ColumnDefinition, CreateTableBody, Expr, Name, NamedTableConstraint,
-    QualifiedName, SortedColumn, Stmt, TableConstraint, TableOptions, ToTokens,
+    QualifiedName, SortedColumn, Stmt, TableConstraint, TableOptions, ToTokens, TriggerCmd,
 };
-use tracing::{debug, info, trace};
+use sqlite3_parser::lexer::{sql::Tokenizer, Scanner};
+use tracing::{debug, info, trace, warn};
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Column {
@@ -68,7 +69,7 @@ pub enum SqliteType {
     Blob,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Table {
     pub name: String,
     pub pk: IndexSet<String>,
@@ -77,6 +78,16 @@ pub struct Table {
     pub raw: CreateTableBody,
 }
 
+impl Table {
+    pub fn without_rowid(&self) -> bool {
+        matches!(
+            &self.raw,
+            CreateTableBody::ColumnsAndConstraints { options, .. }
+                if options.contains(TableOptions::WITHOUT_ROWID)
+        )
+    }
+}
+
 impl fmt::Display for Table {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         Cmd::Stmt(Stmt::CreateTable {
@@ -98,9 +109,37 @@ pub struct Index {
     pub unique: bool,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct View {
+    pub name: String,
+    pub raw: Stmt,
+}
+
+impl fmt::Display for View {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Cmd::Stmt(self.raw.clone()).to_fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Trigger {
+    pub name: String,
+    /// table or view the trigger is on
+    pub tbl_name: String,
+    pub raw: Stmt,
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Cmd::Stmt(self.raw.clone()).to_fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct Schema {
     pub tables: IndexMap<String, Table>,
+    pub views: IndexMap<String, View>,
+    pub triggers: IndexMap<String, Trigger>,
 }
 
 impl Schema {
@@ -110,6 +149,13 @@ impl Schema {
         });
 
         for (tbl_name, table) in self.tables.iter() {
+            if is_reserved_name(tbl_name) {
+                return Err(ConstrainedSchemaError::ReservedName(tbl_name.clone()));
+            }
+            if let Some(idx_name) = table.indexes.keys().find(|name| is_reserved_name(name)) {
+                return Err(ConstrainedSchemaError::ReservedName(idx_name.clone()));
+            }
+
             // this should always be the case...
             if let CreateTableBody::ColumnsAndConstraints {
                 columns: _,
@@ -160,10 +206,48 @@ impl Schema {
             }
         }
 
+        if let Some(name) = self
+            .views
+            .keys()
+            .chain(self.triggers.keys())
+            .find(|name| is_reserved_name(name))
+        {
+            return Err(ConstrainedSchemaError::ReservedName(name.clone()));
+        }
+
+        // triggers on tables also fire while changes from other nodes are
+        // merged, their writes would be made again on every node. triggers
+        // on views only fire for local writes to the view.
+        for trigger in self.triggers.values() {
+            if !self.tables.contains_key(&trigger.tbl_name) {
+                continue;
+            }
+            if let Stmt::CreateTrigger { commands, .. } = &trigger.raw {
+                for cmd in commands.iter() {
+                    let tbl_name = match cmd {
+                        TriggerCmd::Insert { tbl_name, .. }
+                        | TriggerCmd::Update { tbl_name, .. }
+                        | TriggerCmd::Delete { tbl_name, .. } => tbl_name,
+                        TriggerCmd::Select(_) => continue,
+                    };
+                    return Err(ConstrainedSchemaError::TriggerWrites {
+                        name: trigger.name.clone(),
+                        tbl_name: unquote(tbl_name.0.as_str())
+                            .unwrap_or_else(|_| tbl_name.0.clone()),
+                    });
+                }
+            }
+        }
+
         Ok(())
     }
 }
 
+// names used by corrosion and cr-sqlite for their own bookkeeping
+fn is_reserved_name(name: &str) -> bool {
+    name.starts_with("__corro_") || name.contains("crsql")
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum SchemaError {
     #[error(transparent)]
@@ -180,6 +264,22 @@ pub enum SchemaError {
     IndexWithoutTable { tbl_name: String, name: String },
     #[error("temporary tables are not supported: {0}")]
     TemporaryTable(Cmd),
+    #[error("temporary views are not supported: {0}")]
+    TemporaryView(Cmd),
+    #[error("temporary triggers are not supported: {0}")]
+    TemporaryTrigger(Cmd),
+    #[error("missing table or view for trigger (table: '{tbl_name}', trigger: '{name}')")]
+    TriggerWithoutTable { tbl_name: String, name: String },
+    #[error("invalid directive: {0}")]
+    InvalidDirective(String),
+}
+
+impl SchemaError {
+    /// Whether the statements themselves are invalid, rather than the
+    /// schema failing to load
+    pub fn is_invalid_schema(&self) -> bool {
+        !matches!(self, SchemaError::Io(_) | SchemaError::Rusqlite(_))
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -194,6 +294,10 @@ pub enum ConstrainedSchemaError {
     ForeignKey { tbl_name: String, name: String },
     #[error("expr used as primary")]
     PrimaryKeyExpr,
+    #[error("'{0}' is a reserved name (names can't start with '__corro_' or contain 'crsql')")]
+    ReservedName(String),
+    #[error("trigger '{name}' can't write to '{tbl_name}', triggers on tables also fire while changes from other nodes are merged (use a trigger on a view instead)")]
+    TriggerWrites { name: String, tbl_name: String },
 }
 
 #[allow(clippy::result_large_err)]
@@ -224,9 +328,65 @@ pub fn init_schema(conn: &Connection) -> Result<Schema, SchemaError> {
         dump.push(';');
     }
 
+    // triggers can be on views, so views go first
+    for kind in ["view", "trigger"] {
+        let sqls: Vec<String> = conn
+            .prepare("SELECT sql FROM __corro_schema WHERE type = ? ORDER BY name")?
+            .query_map([kind], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+
+        for sql in sqls {
+            dump.push_str(sql.as_str());
+            dump.push(';');
+        }
+    }
+
     parse_sql(dump.as_str())
 }
 
+// what `crsql_as_crr` creates for a table, all of it is needed for changes
+// to the table to be captured
+const CRR_TABLE_SUFFIXES: [&str; 2] = ["__crsql_clock", "__crsql_pks"];
+const CRR_TRIGGER_SUFFIXES: [&str; 3] = ["__crsql_itrig", "__crsql_utrig", "__crsql_dtrig"];
+
+/// Re-runs `crsql_as_crr`, which is idempotent, for tables missing any of
+/// their clock tables or triggers. A table left like that (e.g. by a crash
+/// while applying a schema) would silently stop capturing changes.
+/// Returns the names of the tables that needed repairing.
+pub fn repair_crrs(conn: &Connection, schema: &Schema) -> rusqlite::Result<Vec<String>> {
+    let mut repaired = vec![];
+
+    for name in schema.tables.keys() {
+        let mut missing = vec![];
+        for (kind, suffixes) in [
+            ("table", &CRR_TABLE_SUFFIXES[..]),
+            ("trigger", &CRR_TRIGGER_SUFFIXES[..]),
+        ] {
+            for suffix in suffixes {
+                let artifact = format!("{name}{suffix}");
+                let exists: bool = conn
+                    .prepare_cached(
+                        "SELECT EXISTS(SELECT 1 FROM sqlite_schema WHERE type = ? AND name = ?)",
+                    )?
+                    .query_row((kind, &artifact), |row| row.get(0))?;
+                if !exists {
+                    missing.push(artifact);
+                }
+            }
+        }
+
+        if missing.is_empty() {
+            continue;
+        }
+
+        warn!("table '{name}' is missing CRR artifacts {missing:?}, re-running crsql_as_crr");
+        conn.execute_batch(&format!("SELECT crsql_as_crr('{name}'); CREATE INDEX IF NOT EXISTS corro_{name}__crsql_clock_site_id_dbv ON {name}__crsql_clock (site_id, db_version);"))?;
+        repaired.push(name.clone());
+    }
+
+    Ok(repaired)
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ApplySchemaError {
     #[error(transparent)]
@@ -235,14 +395,30 @@ pub enum ApplySchemaError {
     Schema(#[from] SchemaError),
     #[error(transparent)]
     ConstrainedSchema(#[from] ConstrainedSchemaError),
-    #[error("won't drop table without the destructive flag set (table: '{0}')")]
-    DropTableWithoutDestructiveFlag(String),
+    #[error("tables are never dropped, '{0}' has to stay in the schema")]
+    DropTable(String),
     #[error("won't remove column without the destructive flag set (table: '{0}', column: '{1}')")]
     RemoveColumnWithoutDestructiveFlag(String, String),
+    #[error("table '{0}' needs a PRIMARY KEY, it's how rows are identified across nodes")]
+    MissingPrimaryKey(String),
+    #[error("table '{0}' must be declared WITHOUT ROWID (e.g. `CREATE TABLE {0} (...) WITHOUT ROWID`) so its rows are keyed by their primary key on every node, not by a local rowid")]
+    RowidTable(String),
     #[error("can't add a primary key (table: '{0}', column: '{1}')")]
     AddPrimaryKey(String, String),
     #[error("can't modify primary keys (table: '{0}')")]
     ModifyPrimaryKeys(String),
+    #[error("can't rename table '{0}' to '{1}': table '{0}' does not exist")]
+    RenameTableNotFound(String, String),
+    #[error("can't rename table '{0}' to '{1}': table '{1}' already exists")]
+    RenameTableExists(String, String),
+    #[error("can't rename table '{0}' to '{1}': new schema must define '{1}' and not '{0}'")]
+    RenameTableDefinition(String, String),
+    #[error("can't rename column '{1}' to '{2}' on table '{0}': column '{1}' does not exist")]
+    RenameColumnNotFound(String, String, String),
+    #[error("can't rename column '{1}' to '{2}' on table '{0}': column '{2}' already exists")]
+    RenameColumnExists(String, String, String),
+    #[error("can't rename column '{1}' to '{2}' on table '{0}': new schema must define '{2}' and not '{1}'")]
+    RenameColumnDefinition(String, String, String),
 
     #[error("tried importing an existing schema for table '{0}' due to a failed CREATE TABLE but didn't find anything (this should never happen)")]
     ImportedSchemaNotFound(String),
@@ -262,23 +438,107 @@ pub enum ApplySchemaError {
     },
 }
 
+impl ApplySchemaError {
+    /// Whether the new schema was refused, rather than failing to apply
+    pub fn is_invalid_schema(&self) -> bool {
+        match self {
+            ApplySchemaError::Rusqlite(_) | ApplySchemaError::ImportedSchemaNotFound(_) => false,
+            ApplySchemaError::Schema(e) => e.is_invalid_schema(),
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ApplySchemaOptions {
+    /// Allow dropping columns missing from the new schema
+    pub allow_destructive: bool,
+    /// Tables and columns to rename before diffing the schemas
+    pub renames: Renames,
+    /// Refuse new tables not declared WITHOUT ROWID instead of warning about
+    /// them. Tables already in the database are only ever warned about
+    pub require_without_rowid: bool,
+}
+
+/// Renames requested through `corro:rename` schema directives
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Renames {
+    /// (old table name, new table name)
+    pub tables: Vec<(String, String)>,
+    /// (table name, old column name, new column name)
+    pub columns: Vec<(String, String, String)>,
+}
+
+impl Renames {
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty() && self.columns.is_empty()
+    }
+}
+
 #[allow(clippy::result_large_err)]
 pub fn apply_schema(
     tx: &Transaction,
     schema: &Schema,
     new_schema: &mut Schema,
+    opts: ApplySchemaOptions,
 ) -> Result<(), ApplySchemaError> {
+    // checked before anything is created or dropped. existing tables were
+    // accepted before this was enforced, leave them be
+    for (name, table) in new_schema.tables.iter() {
+        if schema.tables.contains_key(name)
+            || opts
+                .renames
+                .tables
+                .iter()
+                .any(|(_, new_name)| new_name == name)
+        {
+            continue;
+        }
+        if table.pk.is_empty() {
+            return Err(ApplySchemaError::MissingPrimaryKey(name.clone()));
+        }
+        // schema files written before this was checked have to keep working
+        // on new nodes, and tables already in the database are left be
+        if !table.without_rowid() {
+            let exists: bool = tx.query_row(
+                "SELECT EXISTS(SELECT 1 FROM sqlite_schema WHERE type = 'table' AND name = ?)",
+                [name],
+                |row| row.get(0),
+            )?;
+            if opts.require_without_rowid && !exists {
+                return Err(ApplySchemaError::RowidTable(name.clone()));
+            }
+            warn!("table '{name}' should be declared WITHOUT ROWID (e.g. `CREATE TABLE {name} (...) WITHOUT ROWID`) so its rows are keyed by their primary key on every node, not by a local rowid");
+        }
+    }
+
+    // views and triggers can refer to any table, get the affected ones out
+    // of the way while tables are renamed or rebuilt, they're recreated at the end
+    let (dropped_views, dropped_triggers) =
+        affected_views_and_triggers(schema, new_schema, &opts.renames);
+    for name in dropped_triggers.iter() {
+        tx.execute_batch(&format!("DROP TRIGGER IF EXISTS {name}"))?;
+    }
+    for name in dropped_views.iter() {
+        tx.execute_batch(&format!("DROP VIEW IF EXISTS {name}"))?;
+    }
+
+    let renamed_schema;
+    let schema = if opts.renames.is_empty() {
+        schema
+    } else {
+        renamed_schema = apply_renames(tx, schema, new_schema, &opts.renames)?;
+        &renamed_schema
+    };
+
+    // callers merge new statements into the current schema, tables can only
+    // go missing when a whole schema is applied directly
     if let Some(name) = schema
         .tables
         .keys()
-        .collect::<HashSet<_>>()
-        .difference(&new_schema.tables.keys().collect::<HashSet<_>>())
-        .next()
+        .find(|name| !new_schema.tables.contains_key(*name))
     {
-        // TODO: add options and check flag
-        return Err(ApplySchemaError::DropTableWithoutDestructiveFlag(
-            (*name).clone(),
-        ));
+        return Err(ApplySchemaError::DropTable(name.clone()));
     }
 
     let mut schema_to_merge = Schema::default();
@@ -399,7 +659,38 @@ pub fn apply_schema(
             new_table.columns.keys().collect::<Vec<&String>>()
         );
 
-        // 1. Check column drops... don't allow unless flag is passed
+        // 1. Check primary keys, they can't change no matter what else does
+
+        // a new primary key column is the most likely way to change them,
+        // report it as such whether or not the table would be rebuilt
+        if let Some(col) = new_table
+            .columns
+            .values()
+            .find(|col| col.primary_key && !table.columns.contains_key(&col.name))
+        {
+            return Err(ApplySchemaError::AddPrimaryKey(
+                name.clone(),
+                col.name.clone(),
+            ));
+        }
+
+        let primary_keys = table
+            .columns
+            .values()
+            .filter_map(|col| col.primary_key.then_some(&col.name))
+            .collect::<Vec<&String>>();
+
+        let new_primary_keys = new_table
+            .columns
+            .values()
+            .filter_map(|col| col.primary_key.then_some(&col.name))
+            .collect::<Vec<&String>>();
+
+        if primary_keys != new_primary_keys {
+            return Err(ApplySchemaError::ModifyPrimaryKeys(name.clone()));
+        }
+
+        // 2. Check column drops... don't allow unless flag is passed
 
         let dropped_cols = table
             .columns
@@ -411,14 +702,16 @@ pub fn apply_schema(
 
         debug!("dropped cols: {dropped_cols:?}");
 
-        if let Some(col_name) = dropped_cols.into_iter().next() {
-            return Err(ApplySchemaError::RemoveColumnWithoutDestructiveFlag(
-                name.clone(),
-                col_name.clone(),
-            ));
+        if !opts.allow_destructive {
+            if let Some(col_name) = dropped_cols.iter().next() {
+                return Err(ApplySchemaError::RemoveColumnWithoutDestructiveFlag(
+                    name.clone(),
+                    (*col_name).clone(),
+                ));
+            }
         }
 
-        // 2. check for changed columns
+        // 3. check for changed columns
 
         let changed_cols: HashMap<String, Column> = table
             .columns
@@ -446,8 +739,8 @@ pub fn apply_schema(
 
         info!("new columns: {new_col_names:?}");
 
-        if changed_cols.is_empty() {
-            // 2.1. no changed columns, add missing ones
+        if changed_cols.is_empty() && dropped_cols.is_empty() {
+            // 3.1. no changed or dropped columns, add missing ones
 
             if new_col_names.is_empty() {
                 // nothing to do
@@ -463,12 +756,6 @@ pub fn apply_schema(
 
                 for (col_name, col) in new_cols_iter {
                     info!("adding column '{col_name}'");
-                    if col.primary_key {
-                        return Err(ApplySchemaError::AddPrimaryKey(
-                            name.clone(),
-                            col_name.clone(),
-                        ));
-                    }
                     if !col.nullable && col.default_value.is_none() {
                         return Err(ConstrainedSchemaError::NotNullableColumnNeedsDefault {
                             tbl_name: name.clone(),
@@ -486,27 +773,11 @@ pub fn apply_schema(
                 );
             }
         } else {
-            // 2.2 we do have changed columns, try to do something about that
+            // 3.2 we do have changed (or dropped) columns, try to do something about that
 
             info!("Columns have changed... replacing table {}", table.name);
             let start = Instant::now();
 
-            let primary_keys = table
-                .columns
-                .values()
-                .filter_map(|col| col.primary_key.then_some(&col.name))
-                .collect::<Vec<&String>>();
-
-            let new_primary_keys = new_table
-                .columns
-                .values()
-                .filter_map(|col| col.primary_key.then_some(&col.name))
-                .collect::<Vec<&String>>();
-
-            if primary_keys != new_primary_keys {
-                return Err(ApplySchemaError::ModifyPrimaryKeys(name.clone()));
-            }
-
             // "12-step" process to modifying a table
 
             // first, create our new table with a temp name
@@ -525,15 +796,26 @@ pub fn apply_schema(
                 body: new_table.raw.clone(),
             });
 
-            tx.execute_batch("SELECT crsql_begin_alter('{name}');")?;
+            tx.execute_batch(&format!("SELECT crsql_begin_alter('{name}');"))?;
 
             info!("creating tmp table '{tmp_name}'");
             tx.execute_batch(&create_tmp_table.to_string())?;
 
+            // only carry over columns that still exist in the new table,
+            // generated columns can't be inserted into and get recomputed
             let col_names = table
                 .columns
-                .keys()
-                .cloned()
+                .iter()
+                .filter(|(col_name, col)| {
+                    !dropped_cols.contains(col_name)
+                        && col.generated.is_none()
+                        && new_table
+                            .columns
+                            .get(*col_name)
+                            .map(|new_col| new_col.generated.is_none())
+                            .unwrap_or(false)
+                })
+                .map(|(col_name, _)| col_name.clone())
                 .collect::<Vec<String>>()
                 .join(",");
 
@@ -622,9 +904,375 @@ pub fn apply_schema(
         }
     }
 
+    for name in schema.views.keys() {
+        if !new_schema.views.contains_key(name) {
+            info!("dropping view '{name}'");
+        }
+    }
+
+    for (name, view) in new_schema.views.iter() {
+        if schema.views.contains_key(name) && !dropped_views.contains(name) {
+            continue;
+        }
+        if schema.views.get(name) == Some(view) {
+            debug!("recreating view '{name}'");
+        } else {
+            info!("creating view '{name}'");
+        }
+        tx.execute_batch(&view.to_string())?;
+    }
+
+    for name in schema.triggers.keys() {
+        if !new_schema.triggers.contains_key(name) {
+            info!("dropping trigger '{name}'");
+        }
+    }
+
+    for (name, trigger) in new_schema.triggers.iter() {
+        if schema.triggers.contains_key(name) && !dropped_triggers.contains(name) {
+            continue;
+        }
+        if schema.triggers.get(name) == Some(trigger) {
+            debug!("recreating trigger '{name}'");
+        } else {
+            info!("creating trigger '{name}'");
+        }
+        tx.execute_batch(&trigger.to_string())?;
+    }
+
     Ok(())
 }
 
+// views and triggers to drop before applying `new_schema`: removed or changed
+// ones, and the ones referring to a table that is about to be renamed or
+// rebuilt, directly or through another view
+fn affected_views_and_triggers(
+    schema: &Schema,
+    new_schema: &Schema,
+    renames: &Renames,
+) -> (HashSet<String>, HashSet<String>) {
+    let mut restructured = HashSet::new();
+    for (old_name, new_name) in renames.tables.iter() {
+        restructured.insert(old_name.clone());
+        restructured.insert(new_name.clone());
+    }
+    for (tbl_name, _, _) in renames.columns.iter() {
+        restructured.insert(tbl_name.clone());
+    }
+    for (name, table) in schema.tables.iter() {
+        let rebuilt = match new_schema.tables.get(name) {
+            None => true,
+            Some(new_table) => table
+                .columns
+                .iter()
+                .any(|(col_name, col)| new_table.columns.get(col_name) != Some(col)),
+        };
+        if rebuilt {
+            restructured.insert(name.clone());
+        }
+    }
+
+    let mut views = HashSet::new();
+    loop {
+        let affected = schema
+            .views
+            .iter()
+            .filter(|(name, view)| {
+                !views.contains(*name)
+                    && (new_schema.views.get(*name) != Some(*view)
+                        || refers_to(&view.to_string(), &restructured))
+            })
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        if affected.is_empty() {
+            break;
+        }
+        // dropping a view also drops its triggers
+        restructured.extend(affected.iter().cloned());
+        views.extend(affected);
+    }
+
+    let triggers = schema
+        .triggers
+        .iter()
+        .filter(|(name, trigger)| {
+            new_schema.triggers.get(*name) != Some(*trigger)
+                || restructured.contains(&trigger.tbl_name)
+                || refers_to(&trigger.to_string(), &restructured)
+        })
+        .map(|(name, _)| name.clone())
+        .collect();
+
+    (views, triggers)
+}
+
+// whether any token of `sql` is one of `names`. that covers every table or
+// view it refers to, and maybe a few more, which only costs a recreation
+fn refers_to(sql: &str, names: &HashSet<String>) -> bool {
+    if names.is_empty() {
+        return false;
+    }
+    let input = sql.as_bytes();
+    let mut scanner = Scanner::new(Tokenizer::new());
+    loop {
+        match scanner.scan(input) {
+            Ok((_, Some((token, _)), _)) => {
+                let token = String::from_utf8_lossy(token);
+                let token = unquote(&token).unwrap_or_else(|_| token.into_owned());
+                if names.iter().any(|name| name.eq_ignore_ascii_case(&token)) {
+                    return true;
+                }
+            }
+            Ok((_, None, _)) => return false,
+            // can't tell, better safe than sorry
+            Err(_) => return true,
+        }
+    }
+}
+
+// renames tables and columns in place, carrying over their crsql bookkeeping
+// so the change history is kept, and returns the schema with the renames applied
+#[allow(clippy::result_large_err)]
+fn apply_renames(
+    tx: &Transaction,
+    schema: &Schema,
+    new_schema: &Schema,
+    renames: &Renames,
+) -> Result<Schema, ApplySchemaError> {
+    let mut schema = schema.clone();
+
+    for (old_name, new_name) in renames.tables.iter() {
+        match (
+            schema.tables.contains_key(old_name),
+            schema.tables.contains_key(new_name),
+        ) {
+            // already renamed, e.g. the directive is still in the schema files
+            (false, true) => continue,
+            (false, false) => {
+                return Err(ApplySchemaError::RenameTableNotFound(
+                    old_name.clone(),
+                    new_name.clone(),
+                ))
+            }
+            (true, true) => {
+                return Err(ApplySchemaError::RenameTableExists(
+                    old_name.clone(),
+                    new_name.clone(),
+                ))
+            }
+            (true, false) => {}
+        }
+
+        if new_schema.tables.contains_key(old_name) || !new_schema.tables.contains_key(new_name) {
+            return Err(ApplySchemaError::RenameTableDefinition(
+                old_name.clone(),
+                new_name.clone(),
+            ));
+        }
+
+        warn!("renaming table '{old_name}' to '{new_name}' on this node only, every node of the cluster has to apply the same rename");
+
+        // crsql triggers refer to the table by its name, drop them before renaming
+        let triggers: Vec<String> = tx
+            .prepare(
+                "SELECT name FROM sqlite_schema WHERE type = 'trigger' AND tbl_name = ? AND name LIKE ?",
+            )?
+            .query_map(
+                [old_name.clone(), format!("{old_name}__crsql_%")],
+                |row| row.get(0),
+            )?
+            .collect::<rusqlite::Result<_>>()?;
+        for trigger in triggers {
+            tx.execute_batch(&format!("DROP TRIGGER \"{trigger}\";"))?;
+        }
+
+        tx.execute_batch(&format!(
+            "ALTER TABLE {old_name} RENAME TO {new_name};
+             ALTER TABLE {old_name}__crsql_clock RENAME TO {new_name}__crsql_clock;
+             DROP INDEX IF EXISTS {old_name}__crsql_clock_dbv_idx;
+             DROP INDEX IF EXISTS corro_{old_name}__crsql_clock_site_id_dbv;"
+        ))?;
+
+        let has_pks_table: bool = tx.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_schema WHERE type = 'table' AND name = ?)",
+            [format!("{old_name}__crsql_pks")],
+            |row| row.get(0),
+        )?;
+        if has_pks_table {
+            tx.execute_batch(&format!(
+                "ALTER TABLE {old_name}__crsql_pks RENAME TO {new_name}__crsql_pks;
+                 DROP INDEX IF EXISTS {old_name}__crsql_pks_pks;"
+            ))?;
+        }
+
+        // sets the crsql triggers and indexes back up under the new name,
+        // existing clock entries are kept as-is
+        tx.execute_batch(&format!(
+            "SELECT crsql_as_crr('{new_name}');
+             CREATE INDEX IF NOT EXISTS corro_{new_name}__crsql_clock_site_id_dbv ON {new_name}__crsql_clock (site_id, db_version);"
+        ))?;
+
+        // indexes follow the table, only their table name changes
+        if let Some(mut table) = schema.tables.shift_remove(old_name) {
+            table.name = new_name.clone();
+            for index in table.indexes.values_mut() {
+                index.tbl_name = new_name.clone();
+            }
+            schema.tables.insert(new_name.clone(), table);
+        }
+    }
+
+    for (tbl_name, old_name, new_name) in renames.columns.iter() {
+        let err_args = || (tbl_name.clone(), old_name.clone(), new_name.clone());
+
+        let table = match schema.tables.get_mut(tbl_name) {
+            Some(table) => table,
+            None => {
+                let (tbl_name, old_name, new_name) = err_args();
+                return Err(ApplySchemaError::RenameColumnNotFound(
+                    tbl_name, old_name, new_name,
+                ));
+            }
+        };
+
+        match (
+            table.columns.get(old_name),
+            table.columns.contains_key(new_name),
+        ) {
+            // already renamed, e.g. the directive is still in the schema files
+            (None, true) => continue,
+            (None, false) => {
+                let (tbl_name, old_name, new_name) = err_args();
+                return Err(ApplySchemaError::RenameColumnNotFound(
+                    tbl_name, old_name, new_name,
+                ));
+            }
+            (Some(_), true) => {
+                let (tbl_name, old_name, new_name) = err_args();
+                return Err(ApplySchemaError::RenameColumnExists(
+                    tbl_name, old_name, new_name,
+                ));
+            }
+            (Some(col), false) if col.primary_key => {
+                return Err(ApplySchemaError::ModifyPrimaryKeys(tbl_name.clone()));
+            }
+            (Some(_), false) => {}
+        }
+
+        if new_schema
+            .tables
+            .get(tbl_name)
+            .map(|new_table| {
+                new_table.columns.contains_key(old_name)
+                    || !new_table.columns.contains_key(new_name)
+            })
+            .unwrap_or(true)
+        {
+            let (tbl_name, old_name, new_name) = err_args();
+            return Err(ApplySchemaError::RenameColumnDefinition(
+                tbl_name, old_name, new_name,
+            ));
+        }
+
+        warn!("renaming column '{old_name}' to '{new_name}' on table '{tbl_name}' on this node only, every node of the cluster has to apply the same rename");
+        tx.execute_batch(&format!(
+            "SELECT crsql_begin_alter('{tbl_name}');
+             ALTER TABLE {tbl_name} RENAME COLUMN {old_name} TO {new_name};"
+        ))?;
+        // clock entries are keyed by column name, move them over to keep the versions
+        tx.execute(
+            &format!("UPDATE {tbl_name}__crsql_clock SET col_name = ? WHERE col_name = ?"),
+            [new_name, old_name],
+        )?;
+        tx.execute_batch(&format!("SELECT crsql_commit_alter('{tbl_name}');"))?;
+
+        // keep the column in place so it's not seen as a changed column
+        table.columns = std::mem::take(&mut table.columns)
+            .into_iter()
+            .map(|(name, mut col)| {
+                if name == *old_name {
+                    col.name = new_name.clone();
+                    col.raw.col_name = Name(new_name.clone());
+                    (new_name.clone(), col)
+                } else {
+                    (name, col)
+                }
+            })
+            .collect();
+        if let CreateTableBody::ColumnsAndConstraints { columns, .. } = &mut table.raw {
+            for def in columns.iter_mut() {
+                if unquote(def.col_name.0.as_str()).unwrap_or_else(|_| def.col_name.0.clone())
+                    == *old_name
+                {
+                    def.col_name = Name(new_name.clone());
+                }
+            }
+        }
+    }
+
+    Ok(schema)
+}
+
+/// Parses rename directives out of schema SQL comments:
+/// `-- corro:rename old_table -> new_table` and
+/// `-- corro:rename-col table.old_column -> table.new_column`
+#[allow(clippy::result_large_err)]
+pub fn parse_renames(sql: &str) -> Result<Renames, SchemaError> {
+    let mut renames = Renames::default();
+
+    for line in sql.lines() {
+        let comment = match line.trim().strip_prefix("--") {
+            Some(comment) => comment.trim(),
+            None => continue,
+        };
+
+        let invalid = || SchemaError::InvalidDirective(line.trim().to_owned());
+
+        if let Some(directive) = comment.strip_prefix("corro:rename-col") {
+            let ((tbl_name, old_name), (new_tbl_name, new_name)) = directive
+                .split_once("->")
+                .and_then(|(old, new)| {
+                    Some((old.trim().split_once('.')?, new.trim().split_once('.')?))
+                })
+                .filter(|((tbl_name, old_name), (new_tbl_name, new_name))| {
+                    [tbl_name, old_name, new_tbl_name, new_name]
+                        .into_iter()
+                        .all(|name| is_directive_name(name))
+                })
+                .ok_or_else(invalid)?;
+
+            if tbl_name != new_tbl_name {
+                return Err(invalid());
+            }
+
+            renames.columns.push((
+                tbl_name.to_owned(),
+                old_name.to_owned(),
+                new_name.to_owned(),
+            ));
+        } else if let Some(directive) = comment.strip_prefix("corro:rename") {
+            let (old_name, new_name) = directive
+                .split_once("->")
+                .map(|(old_name, new_name)| (old_name.trim(), new_name.trim()))
+                .filter(|(old_name, new_name)| {
+                    is_directive_name(old_name) && is_directive_name(new_name)
+                })
+                .ok_or_else(invalid)?;
+
+            renames
+                .tables
+                .push((old_name.to_owned(), new_name.to_owned()));
+        }
+    }
+
+    Ok(renames)
+}
+
+fn is_directive_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains(|c: char| c.is_whitespace() || c == '\'' || c == '"' || c == '.')
+}
+
 #[allow(clippy::result_large_err)]
 pub fn parse_sql_to_schema(schema: &mut Schema, sql: &str) -> Result<(), SchemaError> {
     trace!("parsing {sql}");
@@ -690,6 +1338,77 @@ pub fn parse_sql_to_schema(schema: &mut Schema, sql: &str) -> Result<(), SchemaE
                         });
                     }
                 }
+                Stmt::CreateView {
+                    temporary: true, ..
+                } => return Err(SchemaError::TemporaryView(cmd.clone())),
+                Stmt::CreateView {
+                    temporary: false,
+                    view_name,
+                    columns,
+                    select,
+                    ..
+                } => {
+                    let name = unquote(view_name.name.0.as_str())
+                        .unwrap_or_else(|_| view_name.name.0.clone());
+                    schema.views.insert(
+                        name.clone(),
+                        View {
+                            name,
+                            raw: Stmt::CreateView {
+                                temporary: false,
+                                if_not_exists: false,
+                                view_name: view_name.clone(),
+                                columns: columns.clone(),
+                                select: select.clone(),
+                            },
+                        },
+                    );
+                }
+                Stmt::CreateTrigger {
+                    temporary: true, ..
+                } => return Err(SchemaError::TemporaryTrigger(cmd.clone())),
+                Stmt::CreateTrigger {
+                    temporary: false,
+                    trigger_name,
+                    time,
+                    event,
+                    tbl_name,
+                    for_each_row,
+                    when_clause,
+                    commands,
+                    ..
+                } => {
+                    let name = unquote(trigger_name.name.0.as_str())
+                        .unwrap_or_else(|_| trigger_name.name.0.clone());
+                    let target = unquote(tbl_name.name.0.as_str())
+                        .unwrap_or_else(|_| tbl_name.name.0.clone());
+                    // only on tables (or views) of the schema, never on
+                    // cr-sqlite's own tables
+                    if !schema.tables.contains_key(&target) && !schema.views.contains_key(&target) {
+                        return Err(SchemaError::TriggerWithoutTable {
+                            tbl_name: target,
+                            name,
+                        });
+                    }
+                    schema.triggers.insert(
+                        name.clone(),
+                        Trigger {
+                            name,
+                            tbl_name: target,
+                            raw: Stmt::CreateTrigger {
+                                temporary: false,
+                                if_not_exists: false,
+                                trigger_name: trigger_name.clone(),
+                                time: *time,
+                                event: event.clone(),
+                                tbl_name: tbl_name.clone(),
+                                for_each_row: *for_each_row,
+                                when_clause: when_clause.clone(),
+                                commands: commands.clone(),
+                            },
+                        },
+                    );
+                }
                 _ => return Err(SchemaError::UnsupportedCmd(cmd.clone())),
             },
             Ok(Some(cmd)) => return Err(SchemaError::UnsupportedCmd(cmd)),
@@ -835,3 +1554,736 @@ fn prepare_table(
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use super::*;
+    use crate::sqlite::CrConn;
+
+    fn table_exists(conn: &Connection, name: &str) -> rusqlite::Result<bool> {
+        conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_schema WHERE type = 'table' AND name = ?)",
+            [name],
+            |row| row.get(0),
+        )
+    }
+
+    #[test]
+    fn crr_repair() -> Result<(), Box<dyn std::error::Error>> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let mut conn = CrConn::init(Connection::open_in_memory()?)?;
+
+        let mut schema = parse_sql(
+            "
+            CREATE TABLE foo (id INTEGER NOT NULL PRIMARY KEY, a TEXT) WITHOUT ROWID;
+            CREATE TABLE bar (id INTEGER NOT NULL PRIMARY KEY, b TEXT) WITHOUT ROWID;
+        ",
+        )?;
+
+        {
+            let tx = conn.transaction()?;
+            apply_schema(&tx, &Schema::default(), &mut schema, Default::default())?;
+            tx.commit()?;
+        }
+
+        assert!(repair_crrs(&conn, &schema)?.is_empty());
+
+        // as if a crash left `foo` half-converted
+        conn.execute_batch(
+            "DROP TRIGGER foo__crsql_itrig;
+             DROP TRIGGER foo__crsql_utrig;",
+        )?;
+
+        conn.execute("INSERT INTO foo (id, a) VALUES (1, 'lost')", ())?;
+        let changes: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM crsql_changes WHERE \"table\" = 'foo'",
+            (),
+            |row| row.get(0),
+        )?;
+        assert_eq!(changes, 0);
+
+        assert_eq!(repair_crrs(&conn, &schema)?, vec!["foo".to_string()]);
+        assert!(repair_crrs(&conn, &schema)?.is_empty());
+
+        conn.execute("INSERT INTO foo (id, a) VALUES (2, 'captured')", ())?;
+        let captured: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM crsql_changes WHERE \"table\" = 'foo'",
+            (),
+            |row| row.get(0),
+        )?;
+        assert!(captured > 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn schema_application_changed_column() -> Result<(), Box<dyn std::error::Error>> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let mut conn = CrConn::init(Connection::open_in_memory()?)?;
+
+        let mut schema = parse_sql(
+            "CREATE TABLE foo (id INTEGER NOT NULL PRIMARY KEY, a TEXT, b TEXT) WITHOUT ROWID;",
+        )?;
+
+        {
+            let tx = conn.transaction()?;
+            apply_schema(&tx, &Schema::default(), &mut schema, Default::default())?;
+            tx.commit()?;
+        }
+
+        conn.execute("INSERT INTO foo (id, a, b) VALUES (1, '1', 'b')", ())?;
+        conn.execute("INSERT INTO foo (id, a, b) VALUES (2, '2', 'c')", ())?;
+
+        // changing the type of a column forces the table to be rebuilt
+        let mut new_schema = parse_sql(
+            "CREATE TABLE foo (id INTEGER NOT NULL PRIMARY KEY, a INTEGER, b TEXT) WITHOUT ROWID;",
+        )?;
+
+        {
+            let tx = conn.transaction()?;
+            apply_schema(&tx, &schema, &mut new_schema, Default::default())?;
+            tx.commit()?;
+        }
+
+        let rows = conn
+            .prepare("SELECT id, a, b FROM foo ORDER BY id")?
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<Result<Vec<(i64, i64, String)>, _>>()?;
+        assert_eq!(rows, vec![(1, 1, "b".to_string()), (2, 2, "c".to_string())]);
+
+        // existing rows are still part of the changeset
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(DISTINCT pk) FROM crsql_changes WHERE \"table\" = 'foo'",
+            (),
+            |row| row.get(0),
+        )?;
+        assert_eq!(count, 2);
+
+        // new writes are still tracked
+        let db_version: i64 = conn.query_row("SELECT crsql_db_version()", (), |row| row.get(0))?;
+        conn.execute("UPDATE foo SET a = 3 WHERE id = 1", ())?;
+        let changed: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM crsql_changes WHERE \"table\" = 'foo' AND db_version > ?",
+            [db_version],
+            |row| row.get(0),
+        )?;
+        assert_eq!(changed, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn schema_application_rename_table() -> Result<(), Box<dyn std::error::Error>> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let mut conn = CrConn::init(Connection::open_in_memory()?)?;
+
+        let mut schema = parse_sql(
+            "
+            CREATE TABLE foo (id INTEGER NOT NULL PRIMARY KEY, a TEXT) WITHOUT ROWID;
+            CREATE INDEX foo_a ON foo (a);
+        ",
+        )?;
+
+        {
+            let tx = conn.transaction()?;
+            apply_schema(&tx, &Schema::default(), &mut schema, Default::default())?;
+            tx.commit()?;
+        }
+
+        conn.execute("INSERT INTO foo (id, a) VALUES (1, 'a')", ())?;
+        conn.execute("INSERT INTO foo (id, a) VALUES (2, 'b')", ())?;
+        conn.execute("UPDATE foo SET a = 'c' WHERE id = 2", ())?;
+
+        let changes_before: Vec<(String, i64, i64)> = conn
+            .prepare(
+                "SELECT cid, col_version, db_version FROM crsql_changes ORDER BY db_version, cid",
+            )?
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<Result<_, _>>()?;
+        assert!(!changes_before.is_empty());
+
+        let sql = "
+            -- corro:rename foo -> bar
+            CREATE TABLE bar (id INTEGER NOT NULL PRIMARY KEY, a TEXT) WITHOUT ROWID;
+            CREATE INDEX foo_a ON bar (a);
+        ";
+        let renames = parse_renames(sql)?;
+        assert_eq!(renames.tables, vec![("foo".to_string(), "bar".to_string())]);
+
+        let mut new_schema = parse_sql(sql)?;
+        {
+            let tx = conn.transaction()?;
+            apply_schema(
+                &tx,
+                &schema,
+                &mut new_schema,
+                ApplySchemaOptions {
+                    renames: renames.clone(),
+                    ..Default::default()
+                },
+            )?;
+            tx.commit()?;
+        }
+
+        // nothing is left under the old name, except for user-defined indexes
+        let leftovers: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_schema WHERE name LIKE '%foo%' AND name != 'foo_a'",
+            (),
+            |row| row.get(0),
+        )?;
+        assert_eq!(leftovers, 0);
+
+        let rows = conn
+            .prepare("SELECT id, a FROM bar ORDER BY id")?
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(i64, String)>, _>>()?;
+        assert_eq!(rows, vec![(1, "a".to_string()), (2, "c".to_string())]);
+
+        // the change history moved along with the table
+        let changes_after: Vec<(String, i64, i64)> = conn
+            .prepare("SELECT cid, col_version, db_version FROM crsql_changes WHERE \"table\" = 'bar' ORDER BY db_version, cid")?
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<Result<_, _>>()?;
+        assert_eq!(changes_before, changes_after);
+
+        let index_table: String = conn.query_row(
+            "SELECT tbl_name FROM sqlite_schema WHERE type = 'index' AND name = 'foo_a'",
+            (),
+            |row| row.get(0),
+        )?;
+        assert_eq!(index_table, "bar");
+
+        // new writes are still tracked
+        conn.execute("UPDATE bar SET a = 'd' WHERE id = 1", ())?;
+        let changed: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM crsql_changes WHERE \"table\" = 'bar' AND db_version > ?",
+            [changes_before.last().unwrap().2],
+            |row| row.get(0),
+        )?;
+        assert_eq!(changed, 1);
+
+        // applying the same directive again is a no-op
+        let mut again_schema = parse_sql(sql)?;
+        {
+            let tx = conn.transaction()?;
+            apply_schema(
+                &tx,
+                &new_schema,
+                &mut again_schema,
+                ApplySchemaOptions {
+                    renames,
+                    ..Default::default()
+                },
+            )?;
+            tx.commit()?;
+        }
+
+        assert!(matches!(
+            parse_renames("-- corro:rename foo bar"),
+            Err(SchemaError::InvalidDirective(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn schema_application_rename_column() -> Result<(), Box<dyn std::error::Error>> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let mut conn = CrConn::init(Connection::open_in_memory()?)?;
+
+        let mut schema = parse_sql(
+            "CREATE TABLE foo (id INTEGER NOT NULL PRIMARY KEY, a TEXT, b TEXT) WITHOUT ROWID;",
+        )?;
+
+        {
+            let tx = conn.transaction()?;
+            apply_schema(&tx, &Schema::default(), &mut schema, Default::default())?;
+            tx.commit()?;
+        }
+
+        conn.execute("INSERT INTO foo (id, a, b) VALUES (1, 'a', 'b')", ())?;
+        conn.execute("UPDATE foo SET a = 'c' WHERE id = 1", ())?;
+
+        let versions = |conn: &Connection, cid: &str| -> rusqlite::Result<(i64, i64)> {
+            conn.query_row(
+                "SELECT col_version, db_version FROM crsql_changes WHERE \"table\" = 'foo' AND cid = ?",
+                [cid],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+        };
+        let a_versions = versions(&conn, "a")?;
+        assert_eq!(a_versions.0, 2);
+
+        let sql = "
+            -- corro:rename-col foo.a -> foo.renamed
+            CREATE TABLE foo (id INTEGER NOT NULL PRIMARY KEY, renamed TEXT, b TEXT) WITHOUT ROWID;
+        ";
+        let renames = parse_renames(sql)?;
+        assert_eq!(
+            renames.columns,
+            vec![("foo".to_string(), "a".to_string(), "renamed".to_string())]
+        );
+
+        let mut new_schema = parse_sql(sql)?;
+        {
+            let tx = conn.transaction()?;
+            apply_schema(
+                &tx,
+                &schema,
+                &mut new_schema,
+                ApplySchemaOptions {
+                    renames: renames.clone(),
+                    ..Default::default()
+                },
+            )?;
+            tx.commit()?;
+        }
+
+        let renamed: String =
+            conn.query_row("SELECT renamed FROM foo WHERE id = 1", (), |row| row.get(0))?;
+        assert_eq!(renamed, "c");
+
+        // the column kept its versions
+        assert_eq!(versions(&conn, "renamed")?, a_versions);
+        assert!(matches!(
+            versions(&conn, "a"),
+            Err(rusqlite::Error::QueryReturnedNoRows)
+        ));
+
+        // new writes are still tracked
+        conn.execute("UPDATE foo SET renamed = 'd' WHERE id = 1", ())?;
+        assert_eq!(versions(&conn, "renamed")?.0, 3);
+
+        // applying the same directive again is a no-op
+        let mut again_schema = parse_sql(sql)?;
+        {
+            let tx = conn.transaction()?;
+            apply_schema(
+                &tx,
+                &new_schema,
+                &mut again_schema,
+                ApplySchemaOptions {
+                    renames,
+                    ..Default::default()
+                },
+            )?;
+            tx.commit()?;
+        }
+
+        assert!(matches!(
+            parse_renames("-- corro:rename-col foo.a -> bar.a"),
+            Err(SchemaError::InvalidDirective(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn schema_application_modified_primary_keys() -> Result<(), Box<dyn std::error::Error>> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let mut conn = CrConn::init(Connection::open_in_memory()?)?;
+
+        let mut schema =
+            parse_sql("CREATE TABLE foo (id INTEGER NOT NULL PRIMARY KEY, a TEXT) WITHOUT ROWID;")?;
+
+        {
+            let tx = conn.transaction()?;
+            apply_schema(&tx, &Schema::default(), &mut schema, Default::default())?;
+            tx.commit()?;
+        }
+
+        // only additive changes besides the primary key
+        let mut new_schema = parse_sql(
+            "CREATE TABLE foo (
+                id INTEGER NOT NULL,
+                a TEXT,
+                b TEXT NOT NULL DEFAULT '',
+                PRIMARY KEY (id, b)
+            ) WITHOUT ROWID;",
+        )?;
+
+        {
+            let tx = conn.transaction()?;
+            assert!(matches!(
+                apply_schema(&tx, &schema, &mut new_schema, Default::default()),
+                Err(ApplySchemaError::AddPrimaryKey(name, col)) if name == "foo" && col == "b"
+            ));
+        }
+
+        // same error when another column changes and the table would be rebuilt
+        let mut new_schema = parse_sql(
+            "CREATE TABLE foo (
+                id INTEGER NOT NULL,
+                a INTEGER,
+                b TEXT NOT NULL DEFAULT '',
+                PRIMARY KEY (id, b)
+            ) WITHOUT ROWID;",
+        )?;
+
+        {
+            let tx = conn.transaction()?;
+            assert!(matches!(
+                apply_schema(&tx, &schema, &mut new_schema, Default::default()),
+                Err(ApplySchemaError::AddPrimaryKey(name, col)) if name == "foo" && col == "b"
+            ));
+        }
+
+        // changing existing primary keys is still reported as such
+        let mut new_schema = parse_sql(
+            "CREATE TABLE foo (id INTEGER NOT NULL, a TEXT NOT NULL PRIMARY KEY) WITHOUT ROWID;",
+        )?;
+
+        let tx = conn.transaction()?;
+        assert!(matches!(
+            apply_schema(&tx, &schema, &mut new_schema, Default::default()),
+            Err(ApplySchemaError::ModifyPrimaryKeys(name)) if name == "foo"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn schema_application_generated_column() -> Result<(), Box<dyn std::error::Error>> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let mut conn = CrConn::init(Connection::open_in_memory()?)?;
+
+        let mut schema = parse_sql(
+            "CREATE TABLE foo (
+                id INTEGER NOT NULL PRIMARY KEY,
+                a TEXT,
+                b TEXT,
+                a_upper TEXT AS (upper(a))
+            ) WITHOUT ROWID;",
+        )?;
+
+        {
+            let tx = conn.transaction()?;
+            apply_schema(&tx, &Schema::default(), &mut schema, Default::default())?;
+            tx.commit()?;
+        }
+
+        conn.execute("INSERT INTO foo (id, a, b) VALUES (1, 'a', '1')", ())?;
+
+        // changing the type of a normal column rebuilds the table
+        let mut new_schema = parse_sql(
+            "CREATE TABLE foo (
+                id INTEGER NOT NULL PRIMARY KEY,
+                a TEXT,
+                b INTEGER,
+                a_upper TEXT AS (upper(a))
+            ) WITHOUT ROWID;",
+        )?;
+
+        {
+            let tx = conn.transaction()?;
+            apply_schema(&tx, &schema, &mut new_schema, Default::default())?;
+            tx.commit()?;
+        }
+
+        let row = conn.query_row("SELECT a, b, a_upper FROM foo WHERE id = 1", (), |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?;
+        assert_eq!(row, ("a".to_string(), 1i64, "A".to_string()));
+
+        conn.execute("UPDATE foo SET a = 'b' WHERE id = 1", ())?;
+        let a_upper: String =
+            conn.query_row("SELECT a_upper FROM foo WHERE id = 1", (), |row| row.get(0))?;
+        assert_eq!(a_upper, "B");
+
+        Ok(())
+    }
+
+    #[test]
+    fn schema_application_partial_indexes() -> Result<(), Box<dyn std::error::Error>> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        // unique indexes conflict with merging changes from other nodes
+        assert!(matches!(
+            parse_sql(
+                "
+                CREATE TABLE foo (id INTEGER NOT NULL PRIMARY KEY, a TEXT) WITHOUT ROWID;
+                CREATE UNIQUE INDEX foo_a ON foo (a);
+            "
+            )?
+            .constrain(),
+            Err(ConstrainedSchemaError::UniqueIndex(name)) if name == "foo_a"
+        ));
+
+        let mut conn = CrConn::init(Connection::open_in_memory()?)?;
+
+        let mut schema = parse_sql(
+            "
+            CREATE TABLE foo (id INTEGER NOT NULL PRIMARY KEY, a TEXT, b TEXT) WITHOUT ROWID;
+            CREATE INDEX foo_b ON foo (b) WHERE b IS NOT NULL;
+        ",
+        )?;
+        schema.constrain()?;
+        {
+            let tx = conn.transaction()?;
+            apply_schema(&tx, &Schema::default(), &mut schema, Default::default())?;
+            tx.commit()?;
+        }
+
+        let is_partial = |conn: &Connection| {
+            conn.query_row(
+                "SELECT partial FROM pragma_index_list('foo') WHERE name = 'foo_b'",
+                (),
+                |row| row.get::<_, bool>(0),
+            )
+        };
+        assert!(is_partial(&conn)?);
+
+        // a changed index keeps its where clause when recreated
+        let mut new_schema = parse_sql(
+            "
+            CREATE TABLE foo (id INTEGER NOT NULL PRIMARY KEY, a TEXT, b TEXT) WITHOUT ROWID;
+            CREATE INDEX foo_b ON foo (b, a) WHERE b IS NOT NULL;
+        ",
+        )?;
+        new_schema.constrain()?;
+        {
+            let tx = conn.transaction()?;
+            apply_schema(&tx, &schema, &mut new_schema, Default::default())?;
+            tx.commit()?;
+        }
+        assert!(is_partial(&conn)?);
+
+        let columns: Vec<String> = conn
+            .prepare("SELECT name FROM pragma_index_info('foo_b') ORDER BY seqno")?
+            .query_map((), |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        assert_eq!(columns, vec!["b".to_string(), "a".to_string()]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn schema_application_rowid_tables() -> Result<(), Box<dyn std::error::Error>> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let mut conn = CrConn::init(Connection::open_in_memory()?)?;
+
+        let mut schema = parse_sql(
+            "
+            CREATE TABLE foo (id INTEGER NOT NULL PRIMARY KEY, a TEXT) WITHOUT ROWID;
+            CREATE TABLE bar (id INTEGER NOT NULL PRIMARY KEY, a TEXT);
+        ",
+        )?;
+        // rowid tables with a primary key are only warned about
+        {
+            let tx = conn.transaction()?;
+            apply_schema(&tx, &Schema::default(), &mut schema, Default::default())?;
+            assert!(table_exists(&tx, "foo")?);
+            assert!(table_exists(&tx, "bar")?);
+        }
+
+        let mut schema = parse_sql(
+            "
+            CREATE TABLE foo (id INTEGER NOT NULL PRIMARY KEY, a TEXT) WITHOUT ROWID;
+            CREATE TABLE bar (a TEXT);
+        ",
+        )?;
+        {
+            let tx = conn.transaction()?;
+            assert!(matches!(
+                apply_schema(&tx, &Schema::default(), &mut schema, Default::default()),
+                Err(ApplySchemaError::MissingPrimaryKey(name)) if name == "bar"
+            ));
+            // rejected before any table got created
+            assert!(!table_exists(&tx, "foo")?);
+        }
+
+        let strict = || ApplySchemaOptions {
+            require_without_rowid: true,
+            ..Default::default()
+        };
+
+        let mut schema = parse_sql(
+            "
+            CREATE TABLE foo (id INTEGER NOT NULL PRIMARY KEY, a TEXT) WITHOUT ROWID;
+            CREATE TABLE bar (id INTEGER NOT NULL PRIMARY KEY, a TEXT);
+        ",
+        )?;
+        {
+            let tx = conn.transaction()?;
+            assert!(matches!(
+                apply_schema(&tx, &Schema::default(), &mut schema, strict()),
+                Err(ApplySchemaError::RowidTable(name)) if name == "bar"
+            ));
+            assert!(!table_exists(&tx, "foo")?);
+        }
+
+        // tables created before this was enforced can still be changed
+        let sql = "CREATE TABLE bar (id INTEGER NOT NULL PRIMARY KEY, a TEXT);";
+        conn.execute_batch(&format!("{sql} SELECT crsql_as_crr('bar');"))?;
+        let schema = parse_sql(sql)?;
+
+        // or imported, they're only warned about
+        {
+            let tx = conn.transaction()?;
+            let mut imported = parse_sql(sql)?;
+            apply_schema(&tx, &Schema::default(), &mut imported, strict())?;
+        }
+
+        let mut new_schema =
+            parse_sql("CREATE TABLE bar (id INTEGER NOT NULL PRIMARY KEY, a TEXT, b TEXT);")?;
+        {
+            let tx = conn.transaction()?;
+            apply_schema(&tx, &schema, &mut new_schema, Default::default())?;
+            tx.commit()?;
+        }
+
+        conn.execute("INSERT INTO bar (id, a, b) VALUES (1, 'a', 'b')", ())?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn schema_application_views_and_triggers() -> Result<(), Box<dyn std::error::Error>> {
+        _ = tracing_subscriber::fmt::try_init();
+
+        let exists = |conn: &Connection, kind: &str, name: &str| -> rusqlite::Result<bool> {
+            conn.query_row(
+                "SELECT EXISTS(SELECT 1 FROM sqlite_schema WHERE type = ? AND name = ?)",
+                [kind, name],
+                |row| row.get(0),
+            )
+        };
+
+        // triggers can only be on tables or views of the schema
+        assert!(matches!(
+            parse_sql(
+                "CREATE TRIGGER foo_clock AFTER INSERT ON foo__crsql_clock BEGIN SELECT 1; END;"
+            ),
+            Err(SchemaError::TriggerWithoutTable { tbl_name, .. }) if tbl_name == "foo__crsql_clock"
+        ));
+
+        // triggers on tables would run again for merged changes
+        assert!(matches!(
+            parse_sql(
+                "
+                CREATE TABLE foo (id INTEGER NOT NULL PRIMARY KEY, a TEXT) WITHOUT ROWID;
+                CREATE TABLE foo_log (id INTEGER NOT NULL PRIMARY KEY, a TEXT) WITHOUT ROWID;
+                CREATE TRIGGER foo_insert AFTER INSERT ON foo BEGIN
+                    INSERT INTO foo_log (id, a) VALUES (NEW.id, NEW.a);
+                END;
+            "
+            )?
+            .constrain(),
+            Err(ConstrainedSchemaError::TriggerWrites { name, tbl_name }) if name == "foo_insert" && tbl_name == "foo_log"
+        ));
+
+        let mut conn = CrConn::init(Connection::open_in_memory()?)?;
+
+        let schema_sql = "
+            CREATE TABLE foo (id INTEGER NOT NULL PRIMARY KEY, a TEXT) WITHOUT ROWID;
+            CREATE TABLE foo_log (id INTEGER NOT NULL PRIMARY KEY, a TEXT) WITHOUT ROWID;
+            CREATE TRIGGER foo_check BEFORE INSERT ON foo WHEN NEW.a = 'bad' BEGIN
+                SELECT RAISE(ABORT, 'bad a');
+            END;
+            CREATE VIEW foo_a AS SELECT id, a FROM foo WHERE a IS NOT NULL;
+            CREATE VIEW foo_log_a AS SELECT id, a FROM foo_log;
+            CREATE TRIGGER foo_a_insert INSTEAD OF INSERT ON foo_a BEGIN
+                INSERT INTO foo (id, a) VALUES (NEW.id, NEW.a);
+                INSERT INTO foo_log (id, a) VALUES (NEW.id, NEW.a);
+            END;
+        ";
+        let mut schema = parse_sql(schema_sql)?;
+        schema.constrain()?;
+
+        assert_eq!(
+            schema.views.keys().collect::<Vec<_>>(),
+            vec!["foo_a", "foo_log_a"]
+        );
+        assert_eq!(schema.triggers["foo_check"].tbl_name, "foo");
+        assert_eq!(schema.triggers["foo_a_insert"].tbl_name, "foo_a");
+
+        {
+            let tx = conn.transaction()?;
+            apply_schema(&tx, &Schema::default(), &mut schema, Default::default())?;
+            tx.commit()?;
+        }
+
+        assert!(exists(&conn, "view", "foo_a")?);
+        assert!(exists(&conn, "trigger", "foo_a_insert")?);
+
+        conn.execute("INSERT INTO foo_a (id, a) VALUES (1, 'one')", ())?;
+        assert!(conn
+            .execute("INSERT INTO foo (id, a) VALUES (2, 'bad')", ())
+            .is_err());
+
+        let a: String = conn.query_row("SELECT a FROM foo_a WHERE id = 1", (), |row| row.get(0))?;
+        assert_eq!(a, "one");
+
+        // the trigger's writes are captured like any other
+        let logged: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM crsql_changes WHERE \"table\" = 'foo_log'",
+            (),
+            |row| row.get(0),
+        )?;
+        assert!(logged > 0);
+
+        let schema_version = |conn: &Connection| -> rusqlite::Result<i64> {
+            conn.query_row("PRAGMA schema_version", (), |row| row.get(0))
+        };
+        let rowid = |conn: &Connection, name: &str| -> rusqlite::Result<i64> {
+            conn.query_row(
+                "SELECT rowid FROM sqlite_schema WHERE name = ?",
+                [name],
+                |row| row.get(0),
+            )
+        };
+
+        // applying the same schema again leaves everything in place
+        {
+            let before = schema_version(&conn)?;
+            let mut same_schema = parse_sql(schema_sql)?;
+            let tx = conn.transaction()?;
+            apply_schema(&tx, &schema, &mut same_schema, Default::default())?;
+            tx.commit()?;
+            assert_eq!(schema_version(&conn)?, before);
+        }
+
+        let foo_log_a = rowid(&conn, "foo_log_a")?;
+
+        // rebuilding the table recreates its trigger, the view (and its
+        // trigger) goes away, the view on another table is left alone
+        let mut new_schema = parse_sql(
+            "
+            CREATE TABLE foo (id INTEGER NOT NULL PRIMARY KEY, a TEXT DEFAULT 'none') WITHOUT ROWID;
+            CREATE TABLE foo_log (id INTEGER NOT NULL PRIMARY KEY, a TEXT) WITHOUT ROWID;
+            CREATE TRIGGER foo_check BEFORE INSERT ON foo WHEN NEW.a = 'bad' BEGIN
+                SELECT RAISE(ABORT, 'bad a');
+            END;
+            CREATE VIEW foo_log_a AS SELECT id, a FROM foo_log;
+        ",
+        )?;
+        {
+            let tx = conn.transaction()?;
+            apply_schema(&tx, &schema, &mut new_schema, Default::default())?;
+            tx.commit()?;
+        }
+
+        assert!(!exists(&conn, "view", "foo_a")?);
+        assert!(!exists(&conn, "trigger", "foo_a_insert")?);
+        assert!(exists(&conn, "trigger", "foo_check")?);
+        assert_eq!(rowid(&conn, "foo_log_a")?, foo_log_a);
+
+        conn.execute("INSERT INTO foo (id) VALUES (2)", ())?;
+        let a: String = conn.query_row("SELECT a FROM foo WHERE id = 2", (), |row| row.get(0))?;
+        assert_eq!(a, "none");
+        assert!(conn
+            .execute("INSERT INTO foo (id, a) VALUES (3, 'bad')", ())
+            .is_err());
+
+        Ok(())
+    }
+}
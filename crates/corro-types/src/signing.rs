@@ -0,0 +1,188 @@
+use std::sync::Arc;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use ring::{
+    rand::{SecureRandom, SystemRandom},
+    signature::{self, Ed25519KeyPair, KeyPair, UnparsedPublicKey},
+};
+
+use crate::config::GossipConfig;
+
+/// Signed gossip payloads start with a kind byte, followed by the signature:
+///
+/// ```text
+/// | kind (1) | signature (64) | payload |
+/// ```
+///
+/// The high bits of the kind byte are always `KIND_MARKER`, which unsigned
+/// payloads never start with: broadcasts start with a speedy enum tag (0x00)
+/// and SWIM datagrams with the bincode length of the sender's actor id (0x10).
+/// The low bits are flags.
+pub const KIND_MARKER: u8 = 0xc0;
+/// Set in the kind byte when the payload is preceded by its signature
+pub const SIGNED_FLAG: u8 = 0x01;
+pub const SIGNATURE_LEN: usize = 64;
+const SEED_LEN: usize = 32;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SigningKeyError {
+    #[error("signing key is not valid hex: {0}")]
+    Hex(#[from] hex::FromHexError),
+    #[error("signing key must be {SEED_LEN} bytes, got {0}")]
+    Length(usize),
+    #[error("rejected signing key: {0}")]
+    Rejected(ring::error::KeyRejected),
+    #[error("could not generate a random signing key")]
+    Rng,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum SignatureError {
+    #[error("payload is not signed")]
+    Unsigned,
+    #[error("invalid payload signature")]
+    Invalid,
+}
+
+impl SignatureError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SignatureError::Unsigned => "unsigned",
+            SignatureError::Invalid => "invalid",
+        }
+    }
+}
+
+/// Generates a new random signing key, hex-encoded for `gossip.signing_key`
+pub fn generate_signing_key() -> Result<String, SigningKeyError> {
+    let mut seed = [0u8; SEED_LEN];
+    SystemRandom::new()
+        .fill(&mut seed)
+        .map_err(|_| SigningKeyError::Rng)?;
+    Ok(hex::encode(seed))
+}
+
+/// Signs and verifies gossip payloads with the cluster-wide ed25519 key
+#[derive(Clone)]
+pub struct GossipSigner {
+    key_pair: Arc<Ed25519KeyPair>,
+    required: bool,
+}
+
+impl GossipSigner {
+    pub fn new(hex_key: &str, required: bool) -> Result<Self, SigningKeyError> {
+        let seed = hex::decode(hex_key.trim())?;
+        if seed.len() != SEED_LEN {
+            return Err(SigningKeyError::Length(seed.len()));
+        }
+        let key_pair =
+            Ed25519KeyPair::from_seed_unchecked(&seed).map_err(SigningKeyError::Rejected)?;
+        Ok(Self {
+            key_pair: Arc::new(key_pair),
+            required,
+        })
+    }
+
+    pub fn from_config(config: &GossipConfig) -> Result<Option<Self>, SigningKeyError> {
+        config
+            .signing_key
+            .as_deref()
+            .map(|key| Self::new(key, config.signing_required))
+            .transpose()
+    }
+
+    pub fn sign(&self, payload: &[u8]) -> Bytes {
+        let sig = self.key_pair.sign(payload);
+        let mut buf = BytesMut::with_capacity(1 + SIGNATURE_LEN + payload.len());
+        buf.put_u8(KIND_MARKER | SIGNED_FLAG);
+        buf.put_slice(sig.as_ref());
+        buf.put_slice(payload);
+        buf.freeze()
+    }
+
+    /// Returns the payload without its signature. Unsigned payloads are only
+    /// let through if signatures aren't required.
+    pub fn verify(&self, data: Bytes) -> Result<Bytes, SignatureError> {
+        let signed = data
+            .first()
+            .is_some_and(|kind| kind & KIND_MARKER == KIND_MARKER && kind & SIGNED_FLAG != 0);
+        if !signed {
+            return if self.required {
+                Err(SignatureError::Unsigned)
+            } else {
+                Ok(data)
+            };
+        }
+
+        if data.len() <= SIGNATURE_LEN {
+            return Err(SignatureError::Invalid);
+        }
+
+        let sig = &data[1..=SIGNATURE_LEN];
+        let payload = data.slice(1 + SIGNATURE_LEN..);
+
+        UnparsedPublicKey::new(&signature::ED25519, self.key_pair.public_key().as_ref())
+            .verify(&payload, sig)
+            .map_err(|_| SignatureError::Invalid)?;
+
+        Ok(payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_and_verify() -> Result<(), Box<dyn std::error::Error>> {
+        let key = generate_signing_key()?;
+        let signer = GossipSigner::new(&key, true)?;
+
+        let payload = Bytes::from_static(b"some gossip");
+        let signed = signer.sign(&payload);
+        assert_eq!(signer.verify(signed.clone()), Ok(payload.clone()));
+
+        let mut tampered = signed.to_vec();
+        *tampered.last_mut().unwrap() ^= 0xff;
+        assert_eq!(signer.verify(tampered.into()), Err(SignatureError::Invalid));
+
+        assert_eq!(
+            signer.verify(payload.clone()),
+            Err(SignatureError::Unsigned)
+        );
+
+        // signed by another cluster
+        let other = GossipSigner::new(&generate_signing_key()?, true)?;
+        assert_eq!(
+            signer.verify(other.sign(&payload)),
+            Err(SignatureError::Invalid)
+        );
+
+        // let unsigned payloads through while rolling out signing
+        let lenient = GossipSigner::new(&key, false)?;
+        assert_eq!(lenient.verify(payload.clone()), Ok(payload.clone()));
+        assert_eq!(lenient.verify(signed.clone()), Ok(payload.clone()));
+
+        // including the ones starting with the signed flag alone
+        let mut unsigned = vec![SIGNED_FLAG];
+        unsigned.extend_from_slice(&[0x2a; SIGNATURE_LEN + 16]);
+        let unsigned = Bytes::from(unsigned);
+        assert_eq!(lenient.verify(unsigned.clone()), Ok(unsigned.clone()));
+        assert_eq!(signer.verify(unsigned), Err(SignatureError::Unsigned));
+
+        // but a bad signature is never mistaken for an unsigned payload
+        let mut tampered = signed.to_vec();
+        *tampered.last_mut().unwrap() ^= 0xff;
+        assert_eq!(
+            lenient.verify(tampered.into()),
+            Err(SignatureError::Invalid)
+        );
+
+        assert!(matches!(
+            GossipSigner::new("abcd", true),
+            Err(SigningKeyError::Length(2))
+        ));
+
+        Ok(())
+    }
+}
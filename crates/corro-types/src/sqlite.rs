@@ -117,6 +117,19 @@ pub fn setup_conn(conn: &mut Connection) -> Result<(), rusqlite::Error> {
     Ok(())
 }
 
+/// Runs each configured pragma (without the leading `PRAGMA` keyword), in order
+pub fn apply_pragmas(conn: &Connection, pragmas: &[String]) -> Result<(), rusqlite::Error> {
+    for pragma in pragmas {
+        trace!("applying pragma: {pragma}");
+        let mut prepped = conn.prepare(&format!("PRAGMA {pragma}"))?;
+        // some pragmas return their new value, drain those rows
+        let mut rows = prepped.query([])?;
+        while rows.next()?.is_some() {}
+    }
+
+    Ok(())
+}
+
 pub trait Migration {
     fn migrate(&self, tx: &Transaction) -> rusqlite::Result<()>;
 }
@@ -234,6 +247,25 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_apply_pragmas() -> rusqlite::Result<()> {
+        let conn = Connection::open_in_memory()?;
+
+        apply_pragmas(
+            &conn,
+            &["busy_timeout = 1234".into(), "cache_size = -4000".into()],
+        )?;
+
+        let busy_timeout: i64 = conn.query_row("PRAGMA busy_timeout", [], |row| row.get(0))?;
+        assert_eq!(busy_timeout, 1234);
+        let cache_size: i64 = conn.query_row("PRAGMA cache_size", [], |row| row.get(0))?;
+        assert_eq!(cache_size, -4000);
+
+        assert!(apply_pragmas(&conn, &["not a pragma".into()]).is_err());
+
+        Ok(())
+    }
+
     #[derive(Debug, thiserror::Error)]
     enum TestError {
         #[error(transparent)]
@@ -1,10 +1,11 @@
-use std::{cmp, collections::HashMap, io, ops::RangeInclusive};
+use std::{cmp, collections::HashMap, io, net::SocketAddr, ops::RangeInclusive};
 
 use bytes::BytesMut;
 use opentelemetry::propagation::{Extractor, Injector};
 use rangemap::RangeInclusiveSet;
 use serde::{Deserialize, Serialize};
 use speedy::{Readable, Writable};
+use tokio::sync::oneshot;
 use tokio_util::codec::{Decoder, LengthDelimitedCodec};
 use tracing::warn;
 
@@ -27,8 +28,23 @@ pub enum SyncMessageV1 {
     Clock(Timestamp),
     Rejection(SyncRejectionV1),
     Request(SyncRequestV1),
+    Compressed {
+        compression: SyncCompressionV1,
+        data: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Readable, Writable)]
+pub enum SyncCompressionV1 {
+    Zstd,
 }
 
+const ZSTD_COMPRESSION_LEVEL: i32 = 3;
+
+/// Largest sync frame, the default of the `LengthDelimitedCodec` sync streams
+/// are read with. Compressed messages can't decompress to more than this.
+pub const MAX_SYNC_FRAME_LENGTH: usize = 8 * 1024 * 1024;
+
 #[derive(Debug, Default, Clone, PartialEq, Readable, Writable)]
 pub struct SyncTraceContextV1 {
     pub traceparent: Option<String>,
@@ -68,6 +84,24 @@ impl Extractor for SyncTraceContextV1 {
 
 pub type SyncRequestV1 = Vec<(ActorId, Vec<SyncNeedV1>)>;
 
+/// Request to sync a specific range of an actor's versions from a peer,
+/// bypassing the usual diffing of sync states
+#[derive(Debug)]
+pub struct SyncRangeRequest {
+    pub actor_id: ActorId,
+    pub versions: RangeInclusive<Version>,
+    pub peer: SocketAddr,
+    /// number of changes received, or why the sync failed
+    pub res_tx: oneshot::Sender<Result<usize, String>>,
+}
+
+/// Request to run a sync with peers right away, outside of the sync loop's schedule
+#[derive(Debug)]
+pub struct SyncNowRequest {
+    /// number of changes received, or why the sync failed
+    pub res_tx: oneshot::Sender<Result<usize, String>>,
+}
+
 #[derive(Debug, thiserror::Error, Clone, PartialEq, Readable, Writable)]
 pub enum SyncRejectionV1 {
     #[error("max concurrency reached")]
@@ -336,6 +370,8 @@ pub enum SyncMessageDecodeError {
     Decode(#[from] speedy::Error),
     #[error("corrupted message, crc mismatch (got: {0}, expected {1})")]
     Corrupted(u32, u32),
+    #[error("decompressed message is larger than {MAX_SYNC_FRAME_LENGTH} bytes")]
+    TooLarge,
     #[error(transparent)]
     Io(#[from] io::Error),
 }
@@ -353,7 +389,41 @@ impl SyncMessage {
     }
 
     pub fn from_buf(buf: &mut BytesMut) -> Result<Self, SyncMessageDecodeError> {
-        Ok(Self::from_slice(buf)?)
+        Self::from_slice(buf)?.decompress()
+    }
+
+    /// Wraps the encoded message in a compressed `SyncMessageV1::Compressed`
+    pub fn compress(&self, compression: SyncCompressionV1) -> Result<Self, SyncMessageEncodeError> {
+        let buf = self.write_to_vec()?;
+        let data = match compression {
+            SyncCompressionV1::Zstd => zstd::bulk::compress(&buf, ZSTD_COMPRESSION_LEVEL)?,
+        };
+        Ok(SyncMessage::V1(SyncMessageV1::Compressed {
+            compression,
+            data,
+        }))
+    }
+
+    /// Unwraps a `SyncMessageV1::Compressed` message, anything else is returned as-is
+    pub fn decompress(self) -> Result<Self, SyncMessageDecodeError> {
+        match self {
+            SyncMessage::V1(SyncMessageV1::Compressed { compression, data }) => {
+                let buf = match compression {
+                    SyncCompressionV1::Zstd => {
+                        // bounded, a tiny frame from a peer could inflate to anything
+                        match zstd::zstd_safe::get_frame_content_size(&data) {
+                            Ok(Some(size)) if size > MAX_SYNC_FRAME_LENGTH as u64 => {
+                                return Err(SyncMessageDecodeError::TooLarge)
+                            }
+                            _ => {}
+                        }
+                        zstd::bulk::decompress(&data, MAX_SYNC_FRAME_LENGTH)?
+                    }
+                };
+                Ok(Self::from_slice(buf)?)
+            }
+            msg => Ok(msg),
+        }
     }
 
     pub fn decode(
@@ -488,4 +558,63 @@ mod tests {
             .into()
         );
     }
+
+    #[test]
+    fn test_compressed_sync_message() -> Result<(), Box<dyn std::error::Error>> {
+        let actor1 = ActorId(Uuid::new_v4());
+
+        let mut state = SyncStateV1::default();
+        state.heads.insert(actor1, Version(10));
+        let msg = SyncMessage::V1(SyncMessageV1::State(state));
+
+        let compressed = msg.compress(SyncCompressionV1::Zstd)?;
+        assert!(matches!(
+            compressed,
+            SyncMessage::V1(SyncMessageV1::Compressed {
+                compression: SyncCompressionV1::Zstd,
+                ..
+            })
+        ));
+
+        let mut buf = BytesMut::from(compressed.write_to_vec()?.as_slice());
+        assert_eq!(SyncMessage::from_buf(&mut buf)?, msg);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_sync_message_size_limit() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(
+            LengthDelimitedCodec::new().max_frame_length(),
+            MAX_SYNC_FRAME_LENGTH
+        );
+
+        // a few KiB that inflate past the frame limit
+        let bomb = SyncMessage::V1(SyncMessageV1::Compressed {
+            compression: SyncCompressionV1::Zstd,
+            data: zstd::bulk::compress(
+                &vec![0u8; MAX_SYNC_FRAME_LENGTH + 1],
+                ZSTD_COMPRESSION_LEVEL,
+            )?,
+        });
+        let mut buf = BytesMut::from(bomb.write_to_vec()?.as_slice());
+        assert!(buf.len() < 64 * 1024);
+        assert!(matches!(
+            SyncMessage::from_buf(&mut buf),
+            Err(SyncMessageDecodeError::TooLarge)
+        ));
+
+        // same, without the content size in the frame header
+        let mut encoder = zstd::stream::Encoder::new(vec![], ZSTD_COMPRESSION_LEVEL)?;
+        encoder.include_contentsize(false)?;
+        io::Write::write_all(&mut encoder, &vec![0u8; MAX_SYNC_FRAME_LENGTH + 1])?;
+        let bomb = SyncMessage::V1(SyncMessageV1::Compressed {
+            compression: SyncCompressionV1::Zstd,
+            data: encoder.finish()?,
+        });
+        let mut buf = BytesMut::from(bomb.write_to_vec()?.as_slice());
+        assert!(SyncMessage::from_buf(&mut buf).is_err());
+
+        Ok(())
+    }
 }
@@ -575,7 +575,7 @@ mod tests {
                 app_id INTEGER AS (CAST(JSON_EXTRACT(meta, '$.app_id') AS INTEGER)),        
 
                 PRIMARY KEY (node, id)
-            );
+            ) WITHOUT ROWID;
 
             CREATE TABLE consul_checks (
                 node TEXT NOT NULL,
@@ -587,7 +587,7 @@ mod tests {
                 output TEXT NOT NULL DEFAULT '',
                 updated_at INTEGER NOT NULL DEFAULT 0,
                 PRIMARY KEY (node, id)
-            );
+            ) WITHOUT ROWID;
         ").await?;
 
         let ta1 = launch_test_agent(|conf| conf.add_schema_path(tmpdir.path().display().to_string()).build(), tripwire.clone()).await?;
@@ -15,8 +15,10 @@ use command::{
 use corro_api_types::SqliteParam;
 use corro_client::CorrosionApiClient;
 use corro_types::{
+    actor::ActorId,
     api::{ExecResult, QueryEvent, Statement},
-    config::{default_admin_path, Config, ConfigError, LogFormat, OtelConfig},
+    base::Version,
+    config::{default_admin_path, Config, ConfigError, LogConfig, LogFormat, OtelConfig},
 };
 use futures::StreamExt;
 use once_cell::sync::OnceCell;
@@ -32,11 +34,16 @@ use opentelemetry::{
 use opentelemetry_otlp::WithExportConfig;
 use rusqlite::{Connection, OptionalExtension};
 use tokio_util::codec::{Decoder, LinesCodec};
+use tracing::Subscriber;
 use tracing::{debug, error, info, warn};
 use tracing_subscriber::{
-    fmt::format::Format, prelude::__tracing_subscriber_SubscriberExt, util::SubscriberInitExt,
-    EnvFilter,
+    fmt::{format::Format, MakeWriter},
+    prelude::__tracing_subscriber_SubscriberExt,
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+    EnvFilter, Layer,
 };
+use uuid::Uuid;
 
 pub mod admin;
 pub mod command;
@@ -51,6 +58,27 @@ build_info::build_info!(pub fn version);
 #[global_allocator]
 static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 
+/// Formats events according to `log.format`. JSON output keeps span fields
+/// (e.g. `actor_id`) as structured fields of every span in the current scope.
+fn fmt_layer<S, W>(log: &LogConfig, make_writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    match log.format {
+        LogFormat::Plaintext => tracing_subscriber::fmt::Layer::new()
+            .with_ansi(log.colors)
+            .with_writer(make_writer)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::Layer::new()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .with_writer(make_writer)
+            .boxed(),
+    }
+}
+
 fn init_tracing(cli: &Cli) -> Result<(), ConfigError> {
     if matches!(cli.command, Command::Agent) {
         let config = cli.config()?;
@@ -101,35 +129,9 @@ fn init_tracing(cli: &Cli) -> Result<(), ConfigError> {
                 .expect("Failed to initialize OpenTelemetry OTLP exporter.");
 
             let sub = sub.with(tracing_opentelemetry::layer().with_tracer(tracer));
-            match config.log.format {
-                LogFormat::Plaintext => {
-                    sub.with(tracing_subscriber::fmt::Layer::new().with_ansi(config.log.colors))
-                        .init();
-                }
-                LogFormat::Json => {
-                    sub.with(
-                        tracing_subscriber::fmt::Layer::new()
-                            .json()
-                            .with_span_list(false),
-                    )
-                    .init();
-                }
-            }
+            sub.with(fmt_layer(&config.log, std::io::stdout)).init();
         } else {
-            match config.log.format {
-                LogFormat::Plaintext => {
-                    sub.with(tracing_subscriber::fmt::Layer::new().with_ansi(config.log.colors))
-                        .init();
-                }
-                LogFormat::Json => {
-                    sub.with(
-                        tracing_subscriber::fmt::Layer::new()
-                            .json()
-                            .with_span_list(false),
-                    )
-                    .init();
-                }
-            }
+            sub.with(fmt_layer(&config.log, std::io::stdout)).init();
         }
     } else {
         tracing_subscriber::registry()
@@ -353,7 +355,7 @@ async fn process_cli(cli: Cli) -> eyre::Result<()> {
                             println!("time: {time}s");
                         }
                     }
-                    QueryEvent::Change(_, _, _, _) => {
+                    QueryEvent::Change(_, _, _, _) | QueryEvent::Old(_, _, _) => {
                         break;
                     }
                     QueryEvent::Error(e) => {
@@ -383,7 +385,19 @@ async fn process_cli(cli: Cli) -> eyre::Result<()> {
                     ExecResult::Execute {
                         rows_affected,
                         time,
+                        rows,
+                        ..
                     } => {
+                        for cells in rows.iter().flatten() {
+                            println!(
+                                "{}",
+                                cells
+                                    .iter()
+                                    .map(|v| v.to_string())
+                                    .collect::<Vec<_>>()
+                                    .join("|")
+                            );
+                        }
                         info!("Rows affected: {rows_affected}");
                         if *timer {
                             println!("Run Time: real {time}");
@@ -405,6 +419,23 @@ async fn process_cli(cli: Cli) -> eyre::Result<()> {
             ))
             .await?;
         }
+        Command::Sync(SyncCommand::Range {
+            actor_id,
+            start,
+            end,
+            peer,
+        }) => {
+            let mut conn = AdminConn::connect(cli.admin_path()).await?;
+            conn.send_command(corro_admin::Command::Sync(
+                corro_admin::SyncCommand::Range {
+                    actor_id: ActorId(*actor_id),
+                    start: Version(*start),
+                    end: Version(*end),
+                    peer: *peer,
+                },
+            ))
+            .await?;
+        }
         Command::Locks { top } => {
             let mut conn = AdminConn::connect(cli.admin_path()).await?;
             conn.send_command(corro_admin::Command::Locks { top: *top })
@@ -424,6 +455,9 @@ async fn process_cli(cli: Cli) -> eyre::Result<()> {
                 generate_client_cert(ca_cert, ca_key).await?
             }
         },
+        Command::SigningKey(SigningKeyCommand::Generate) => {
+            println!("{}", corro_types::signing::generate_signing_key()?);
+        }
     }
 
     Ok(())
@@ -578,6 +612,16 @@ enum Command {
     /// Tls-related commands
     #[command(subcommand)]
     Tls(TlsCommand),
+
+    /// Gossip signing key commands
+    #[command(subcommand)]
+    SigningKey(SigningKeyCommand),
+}
+
+#[derive(Subcommand)]
+enum SigningKeyCommand {
+    /// Generate a key for `gossip.signing_key`
+    Generate,
 }
 
 #[derive(Subcommand)]
@@ -596,6 +640,15 @@ enum ConsulCommand {
 enum SyncCommand {
     /// Generate a sync message from the current agent
     Generate,
+    /// Request a range of versions for an actor from a specific peer
+    Range {
+        actor_id: Uuid,
+        start: u64,
+        end: u64,
+        /// Gossip address of the peer to sync from
+        #[arg(long)]
+        peer: SocketAddr,
+    },
 }
 
 #[derive(Subcommand)]
@@ -639,3 +692,65 @@ enum TlsClientCommand {
         ca_cert: Utf8PathBuf,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tracing::info_span;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Captured {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn json_logs_keep_span_fields() {
+        let captured = Captured::default();
+        let log = LogConfig {
+            format: LogFormat::Json,
+            colors: false,
+        };
+        let sub = tracing_subscriber::registry().with(fmt_layer(&log, captured.clone()));
+
+        let actor_id = ActorId(Uuid::new_v4());
+        tracing::subscriber::with_default(sub, || {
+            let _agent = info_span!("agent", actor_id = %actor_id).entered();
+            let _sync = info_span!("sync", peers = 3).entered();
+            info!(rows = 10, "synced");
+        });
+
+        let output = captured.0.lock().unwrap().clone();
+        let line: serde_json::Value = serde_json::from_slice(&output).unwrap();
+
+        assert_eq!(line["fields"]["message"], "synced");
+        assert_eq!(line["fields"]["rows"], 10);
+        assert_eq!(line["span"]["peers"], 3);
+        assert_eq!(line["spans"][0]["name"], "agent");
+        assert_eq!(line["spans"][0]["actor_id"], actor_id.to_string());
+    }
+
+    #[test]
+    fn log_format_text_alias() {
+        let log: LogConfig = serde_json::from_str(r#"{"format": "text"}"#).unwrap();
+        assert_eq!(log.format, LogFormat::Plaintext);
+    }
+}
@@ -80,6 +80,11 @@ impl Config {
         self
     }
 
+    pub fn wait_timeout(mut self, value: Duration) -> Self {
+        self.pool.timeouts.wait = Some(value);
+        self
+    }
+
     pub fn create_pool(&self) -> Result<RusqlitePool, CreatePoolError> {
         self.builder(noop_transform)
             .map_err(CreatePoolError::Config)?
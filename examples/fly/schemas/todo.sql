@@ -2,4 +2,4 @@ CREATE TABLE todos (
     id BLOB NOT NULL PRIMARY KEY,
     title TEXT NOT NULL DEFAULT '',
     completed_at INTEGER
-);
\ No newline at end of file
+) WITHOUT ROWID;
\ No newline at end of file